@@ -39,8 +39,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     init_app_by_mongodb().await;
     let log_store = LogsStore::new("./logs")?;
     let control = Control::new(log_store);
+    if let Ok(core) = AppCore::app() {
+        core.metrics.attach_task_monitor(control.task_mon.clone());
+    }
+    let http = HttpServer::new("0.0.0.0".to_string(), 3000);
+    let http_server = http.clone();
     let http_handle = control.spawn(async move {
-        if let Err(e) = HttpServer::new("0.0.0.0".to_string(), 3000).run().await {
+        if let Err(e) = http_server.run().await {
             error!("Control error: {}", e);
         } else {
             info!("HTTP server exited.");
@@ -55,6 +60,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             info!("Metrics logs server task completed.");
         }
         _ = tokio::signal::ctrl_c() => {
+            http.shutdown();
             control.stop().await;
             info!("Shutdown signal received.");
         }
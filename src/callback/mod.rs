@@ -1,8 +1,10 @@
 use crate::callback::sidebend::SideBend;
+use crate::error::GitInnerError;
 use bytes::{BufMut, Bytes, BytesMut};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{Mutex, OwnedMutexGuard};
 
 #[derive(Clone)]
 pub struct CallBack {
@@ -18,27 +20,115 @@ impl CallBack {
             receive: Arc::new(Mutex::new(rx)),
         }
     }
-    pub async fn send(&self, kind: Bytes) {
-        self.callback.send(kind).await.ok();
+    /// Take the receiving end. Dropping the returned receiver closes the channel, so once
+    /// the client goes away every further send fails with
+    /// [`GitInnerError::ClientDisconnected`].
+    pub async fn subscribe(&self) -> CallBackReceiver {
+        CallBackReceiver(self.receive.clone().lock_owned().await)
     }
-    pub async fn send_pkt_line(&self, line: Bytes) {
+    /// Whether the receiving end has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.callback.is_closed()
+    }
+    /// Queue `kind` for the client, waiting for room only when the channel is full.
+    pub async fn send(&self, kind: Bytes) -> Result<(), GitInnerError> {
+        match self.callback.try_send(kind) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(kind)) => self
+                .callback
+                .send(kind)
+                .await
+                .map_err(|_| GitInnerError::ClientDisconnected),
+            Err(TrySendError::Closed(_)) => Err(GitInnerError::ClientDisconnected),
+        }
+    }
+    pub async fn send_pkt_line(&self, line: Bytes) -> Result<(), GitInnerError> {
         let len = line.len();
         let mut result = BytesMut::from(format!("{:04x}", len + 4).as_bytes());
         result.extend_from_slice(&line);
-        self.send(result.freeze()).await;
+        self.send(result.freeze()).await
     }
-    pub async fn send_side_pkt_line(&self, line: Bytes, side: SideBend) {
-        if side == SideBend::SidebandFlush {
-            let result = BytesMut::from(format!("{:04x}", 1).as_bytes());
-            self.send(result.freeze()).await;
-            return;
+    pub async fn send_side_pkt_line(
+        &self,
+        line: Bytes,
+        side: SideBend,
+    ) -> Result<(), GitInnerError> {
+        match side {
+            SideBend::SidebandFlush => return self.send(Bytes::from_static(b"0000")).await,
+            SideBend::SidebandDelim => return self.send(Bytes::from_static(b"0001")).await,
+            _ => {}
         }
         let len = line.len().saturating_add(1);
         let mut result = BytesMut::from(format!("{:04x}", len + 4).as_bytes());
         result.put_u8(side.to_u32() as u8);
         result.extend_from_slice(&line);
-        self.send(result.freeze()).await;
+        self.send(result.freeze()).await
+    }
+}
+
+/// Receiving end of a [`CallBack`]; closes the channel when dropped.
+pub struct CallBackReceiver(OwnedMutexGuard<Receiver<Bytes>>);
+
+impl CallBackReceiver {
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        self.0.recv().await
+    }
+}
+
+impl Drop for CallBackReceiver {
+    fn drop(&mut self) {
+        self.0.close();
     }
 }
 
 pub mod sidebend;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send_after_receiver_dropped() {
+        let call_back = CallBack::new(1);
+        let mut receiver = call_back.subscribe().await;
+        call_back.send(Bytes::from_static(b"0000")).await.unwrap();
+        // 通道已满，第二次发送需要等待接收方
+        let sender = call_back.clone();
+        let pending = tokio::spawn(async move { sender.send(Bytes::from_static(b"0001")).await });
+        assert_eq!(receiver.recv().await.unwrap(), "0000");
+        pending.await.unwrap().unwrap();
+        assert!(!call_back.is_closed());
+
+        drop(receiver);
+        assert!(call_back.is_closed());
+        assert!(matches!(
+            call_back.send(Bytes::from_static(b"0000")).await,
+            Err(GitInnerError::ClientDisconnected)
+        ));
+        assert!(matches!(
+            call_back.send_pkt_line(Bytes::from_static(b"done\n")).await,
+            Err(GitInnerError::ClientDisconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_side_pkt_line_control_packets() {
+        let call_back = CallBack::new(4);
+        let mut receiver = call_back.subscribe().await;
+        call_back
+            .send_side_pkt_line(Bytes::new(), SideBend::SidebandFlush)
+            .await
+            .unwrap();
+        call_back
+            .send_side_pkt_line(Bytes::new(), SideBend::SidebandDelim)
+            .await
+            .unwrap();
+        call_back
+            .send_side_pkt_line(Bytes::from_static(b"ok"), SideBend::SidebandPrimary)
+            .await
+            .unwrap();
+        assert_eq!(receiver.recv().await.unwrap(), "0000");
+        assert_eq!(receiver.recv().await.unwrap(), "0001");
+        assert_eq!(receiver.recv().await.unwrap(), "0007\x01ok");
+    }
+}
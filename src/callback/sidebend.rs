@@ -1,9 +1,14 @@
+use crate::error::GitInnerError;
+
+/// Sideband channel of a pkt-line. `SidebandFlush` and `SidebandDelim` are not bands: they
+/// stand for the `0000` flush packet ending the stream and the `0001` delimiter packet.
 #[derive(Clone, Debug, PartialEq)]
 pub enum SideBend {
     SidebandFlush = 0,
     SidebandPrimary = 1,
     SidebandMessage = 2,
     SidebandRemoteError = 3,
+    SidebandDelim = 4,
 }
 
 impl SideBend {
@@ -13,8 +18,10 @@ impl SideBend {
             SideBend::SidebandFlush => 0,
             SideBend::SidebandPrimary => 1,
             SideBend::SidebandMessage => 2,
+            SideBend::SidebandDelim => 4,
         }
     }
+    /// The band of a received sideband packet; the delimiter is never a band number.
     pub fn from_u32(u: u32) -> Option<SideBend> {
         match u {
             3 => Some(SideBend::SidebandRemoteError),
@@ -38,10 +45,97 @@ pub fn bend_pkt_flush() -> Vec<u8> {
     head.into_iter().chain(bend.into_iter()).collect()
 }
 
+/// Channels of a sideband multiplexed server response, as split by [`demux`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DemuxOutput {
+    /// Band 1: pack data.
+    pub pack: Vec<u8>,
+    /// Band 2: progress messages.
+    pub progress: Vec<u8>,
+    /// Band 3: fatal error messages.
+    pub error: Vec<u8>,
+    /// Payload of every data pkt-line in order, band byte included. Flush, delimiter and
+    /// response-end packets are left out.
+    pub lines: Vec<Vec<u8>>,
+}
+
+/// Split a pkt-line stream into its sideband channels. Packets whose payload does not start
+/// with a band byte, such as v2 section headers, only show up in `lines`.
+pub fn demux(input: &[u8]) -> Result<DemuxOutput, GitInnerError> {
+    let mut output = DemuxOutput::default();
+    let mut pos = 0;
+    while pos < input.len() {
+        let head = input
+            .get(pos..pos + 4)
+            .ok_or(GitInnerError::UnexpectedEof)?;
+        let len = std::str::from_utf8(head)
+            .ok()
+            .and_then(|x| usize::from_str_radix(x, 16).ok())
+            .ok_or(GitInnerError::InvalidData)?;
+        // 0000 flush、0001 delim、0002 response-end 没有内容
+        if len <= 2 {
+            pos += 4;
+            continue;
+        }
+        if len < 4 {
+            return Err(GitInnerError::InvalidData);
+        }
+        let payload = input
+            .get(pos + 4..pos + len)
+            .ok_or(GitInnerError::UnexpectedEof)?;
+        match payload.first().and_then(|x| SideBend::from_u32(*x as u32)) {
+            Some(SideBend::SidebandPrimary) => output.pack.extend_from_slice(&payload[1..]),
+            Some(SideBend::SidebandMessage) => output.progress.extend_from_slice(&payload[1..]),
+            Some(SideBend::SidebandRemoteError) => output.error.extend_from_slice(&payload[1..]),
+            _ => {}
+        }
+        output.lines.push(payload.to_vec());
+        pos += len;
+    }
+    Ok(output)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_demux_channels() {
+        let mut input = Vec::new();
+        input.extend_from_slice(b"000dpackfile\n");
+        input.extend_from_slice(b"000b\x02count\n");
+        input.extend_from_slice(b"0009\x01PACK");
+        input.extend_from_slice(b"0007\x01xy");
+        input.extend_from_slice(b"0001");
+        input.extend_from_slice(b"0009\x03boom");
+        input.extend_from_slice(b"0000");
+        let output = demux(&input).unwrap();
+        assert_eq!(output.pack, b"PACKxy");
+        assert_eq!(output.progress, b"count\n");
+        assert_eq!(output.error, b"boom");
+        assert_eq!(
+            output.lines,
+            vec![
+                b"packfile\n".to_vec(),
+                b"\x02count\n".to_vec(),
+                b"\x01PACK".to_vec(),
+                b"\x01xy".to_vec(),
+                b"\x03boom".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_demux_malformed() {
+        assert!(matches!(
+            demux(b"000a\x01PA"),
+            Err(GitInnerError::UnexpectedEof)
+        ));
+        assert!(matches!(demux(b"zzzz"), Err(GitInnerError::InvalidData)));
+        assert!(matches!(demux(b"0003"), Err(GitInnerError::InvalidData)));
+        assert!(matches!(demux(b"00"), Err(GitInnerError::UnexpectedEof)));
+    }
+
     #[test]
     fn test_sidebend_to_u32() {
         assert_eq!(SideBend::SidebandRemoteError.to_u32(), 3);
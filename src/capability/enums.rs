@@ -1,3 +1,8 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::sha::HashVersion;
+
 /// Git 协议能力枚举
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum GitCapability {
@@ -25,6 +30,8 @@ pub enum GitCapability {
     IncludeTag,
     /// 报告状态支持
     ReportStatus,
+    /// 报告状态 v2 支持
+    ReportStatusV2,
     /// 删除引用支持
     DeleteRefs,
     /// 静默模式
@@ -36,11 +43,11 @@ pub enum GitCapability {
     /// Agent 信息
     Agent(String),
     /// 对象格式
-    ObjectFormat(String),
+    ObjectFormat(HashVersion),
     /// 符号引用
     Symref(String, String),
-    /// 其他未知能力
-    Other(String),
+    /// 未识别的能力，原样保留以便回传
+    Unknown(String),
 }
 
 impl GitCapability {
@@ -65,6 +72,7 @@ impl GitCapability {
             "no-progress" => Self::NoProgress,
             "include-tag" => Self::IncludeTag,
             "report-status" => Self::ReportStatus,
+            "report-status-v2" => Self::ReportStatusV2,
             "delete-refs" => Self::DeleteRefs,
             "quiet" => Self::Quiet,
             "atomic" => Self::Atomic,
@@ -72,47 +80,24 @@ impl GitCapability {
             _ => {
                 if let Some(agent) = s.strip_prefix("agent=") {
                     Self::Agent(agent.to_string())
-                } else if let Some(format) = s.strip_prefix("object-format=") {
-                    Self::ObjectFormat(format.to_string())
+                } else if let Some(format) = s
+                    .strip_prefix("object-format=")
+                    .and_then(HashVersion::from_name)
+                {
+                    Self::ObjectFormat(format)
                 } else if let Some(symref) = s.strip_prefix("symref=") {
                     if let Some((from, to)) = symref.split_once(':') {
                         Self::Symref(from.to_string(), to.to_string())
                     } else {
-                        Self::Other(s.to_string())
+                        Self::Unknown(s.to_string())
                     }
                 } else {
-                    Self::Other(s.to_string())
+                    Self::Unknown(s.to_string())
                 }
             }
         }
     }
 
-    /// 转换为字符串表示
-    pub fn to_string(&self) -> String {
-        match self {
-            Self::MultiAck => "multi_ack".to_string(),
-            Self::MultiAckDetailed => "multi_ack_detailed".to_string(),
-            Self::NoDone => "no-done".to_string(),
-            Self::ThinPack => "thin-pack".to_string(),
-            Self::SideBand => "side-band".to_string(),
-            Self::SideBand64k => "side-band-64k".to_string(),
-            Self::OfsDelta => "ofs-delta".to_string(),
-            Self::Shallow => "shallow".to_string(),
-            Self::DeferredFetch => "deferred-fetch".to_string(),
-            Self::NoProgress => "no-progress".to_string(),
-            Self::IncludeTag => "include-tag".to_string(),
-            Self::ReportStatus => "report-status".to_string(),
-            Self::DeleteRefs => "delete-refs".to_string(),
-            Self::Quiet => "quiet".to_string(),
-            Self::Atomic => "atomic".to_string(),
-            Self::PushOptions => "push-options".to_string(),
-            Self::Agent(agent) => format!("agent={}", agent),
-            Self::ObjectFormat(format) => format!("object-format={}", format),
-            Self::Symref(from, to) => format!("symref={}:{}", from, to),
-            Self::Other(s) => s.clone(),
-        }
-    }
-
     pub fn basic() -> Vec<GitCapability> {
         vec![
             GitCapability::SideBand,
@@ -140,14 +125,57 @@ impl GitCapability {
         let mut capabilities = Self::basic();
         capabilities.extend(vec![
             // GitCapability::OfsDelta,
+            // 只发 v1 的状态行，删除引用也还不支持，不能声明 report-status-v2 和 delete-refs
             GitCapability::Atomic,
             GitCapability::PushOptions,
-            GitCapability::DeleteRefs,
         ]);
         capabilities
     }
 }
 
+impl Display for GitCapability {
+    /// 转换为字符串表示
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MultiAck => write!(f, "multi_ack"),
+            Self::MultiAckDetailed => write!(f, "multi_ack_detailed"),
+            Self::NoDone => write!(f, "no-done"),
+            Self::ThinPack => write!(f, "thin-pack"),
+            Self::SideBand => write!(f, "side-band"),
+            Self::SideBand64k => write!(f, "side-band-64k"),
+            Self::OfsDelta => write!(f, "ofs-delta"),
+            Self::Shallow => write!(f, "shallow"),
+            Self::DeferredFetch => write!(f, "deferred-fetch"),
+            Self::NoProgress => write!(f, "no-progress"),
+            Self::IncludeTag => write!(f, "include-tag"),
+            Self::ReportStatus => write!(f, "report-status"),
+            Self::ReportStatusV2 => write!(f, "report-status-v2"),
+            Self::DeleteRefs => write!(f, "delete-refs"),
+            Self::Quiet => write!(f, "quiet"),
+            Self::Atomic => write!(f, "atomic"),
+            Self::PushOptions => write!(f, "push-options"),
+            Self::Agent(agent) => write!(f, "agent={}", agent),
+            Self::ObjectFormat(format) => write!(f, "object-format={}", format.name()),
+            Self::Symref(from, to) => write!(f, "symref={}:{}", from, to),
+            Self::Unknown(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// 解析以空格分隔的能力列表
+pub fn parse_capability_list(s: &str) -> Vec<GitCapability> {
+    s.split_whitespace().map(GitCapability::from_str).collect()
+}
+
+/// 将能力列表渲染为以空格分隔的字符串
+pub fn render_capability_list(capabilities: &[GitCapability]) -> String {
+    capabilities
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,4 +216,23 @@ mod tests {
             "agent=git/2.40.0"
         );
     }
+
+    #[test]
+    fn test_capability_list_round_trip() {
+        let line = "multi_ack_detailed side-band-64k thin-pack ofs-delta no-progress \
+                    agent=git/2.45.0 object-format=sha256 symref=HEAD:refs/heads/main x-custom=1";
+        let capabilities = parse_capability_list(line);
+        assert!(capabilities.contains(&GitCapability::ObjectFormat(HashVersion::Sha256)));
+        assert_eq!(
+            capabilities.last(),
+            Some(&GitCapability::Unknown("x-custom=1".to_string()))
+        );
+        let rendered = render_capability_list(&capabilities);
+        assert_eq!(
+            rendered,
+            line.split_whitespace().collect::<Vec<_>>().join(" ")
+        );
+        assert_eq!(parse_capability_list(&rendered), capabilities);
+        assert!(parse_capability_list("  ").is_empty());
+    }
 }
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// 每个仓库在内存中缓存的提交数，0 表示不缓存
+    pub commit_capacity: usize,
+    /// 同时保留 odb 与 refs 句柄的仓库数，超出时淘汰最久未用的仓库及其提交缓存
+    pub repo_capacity: usize,
+}
+
+impl Default for CacheConfig {
+    /// Keeps the handles of the 1000 most recently used repositories, each with its 10000
+    /// most recently read commits.
+    fn default() -> Self {
+        Self {
+            commit_capacity: 10_000,
+            repo_capacity: 1000,
+        }
+    }
+}
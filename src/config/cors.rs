@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// 是否为 HTTP git 接口添加 CORS 响应头
+    pub enabled: bool,
+    /// 允许的来源，例如 `https://example.com`；为空时允许任意来源
+    pub allowed_origins: Vec<String>,
+    /// 预检结果的缓存时间（秒）
+    pub max_age_secs: usize,
+}
+
+impl Default for CorsConfig {
+    /// CORS is off by default; once enabled, any origin is allowed and preflights are cached
+    /// for an hour.
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allowed_origins: vec![],
+            max_age_secs: 3600,
+        }
+    }
+}
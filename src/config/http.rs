@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// 为不支持智能协议的客户端开启只读的哑协议（dumb protocol）路由
+    pub dumb_protocol: bool,
+    /// 优雅关闭时等待进行中请求（例如推送）完成的最长时间（秒）
+    pub shutdown_timeout_secs: u64,
+    /// 访问日志格式：`text` 为原有的单行文本，`json` 为每个请求一个 JSON 对象
+    pub access_log: AccessLogFormat,
+    /// gzip/deflate 请求体解压后的最大字节数，超过则中止请求
+    pub max_decoded_body_bytes: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Default for HttpConfig {
+    /// Dumb protocol routes are off, a graceful shutdown waits up to 30 seconds, the
+    /// access log is plain text and a compressed request body may inflate to 4 GiB.
+    fn default() -> Self {
+        Self {
+            dumb_protocol: false,
+            shutdown_timeout_secs: 30,
+            access_log: AccessLogFormat::Text,
+            max_decoded_body_bytes: 4 << 30,
+        }
+    }
+}
@@ -1,4 +1,9 @@
+use crate::config::cache::CacheConfig;
+use crate::config::cors::CorsConfig;
+use crate::config::http::HttpConfig;
 use crate::config::ssh::SshConfig;
+use crate::config::storage::StorageBackend;
+use crate::config::transaction::TransactionConfig;
 use serde::{Deserialize, Serialize};
 use std::env::var;
 
@@ -6,17 +11,57 @@ lazy_static::lazy_static! {
     pub static ref CFG: AppConfig = AppConfig::load();
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct AppConfig {
     pub(crate) ssh: SshConfig,
+    #[serde(default)]
+    pub(crate) transaction: TransactionConfig,
+    #[serde(default)]
+    pub(crate) storage: StorageBackend,
+    #[serde(default)]
+    pub(crate) http: HttpConfig,
+    #[serde(default)]
+    pub(crate) cors: CorsConfig,
+    #[serde(default)]
+    pub(crate) cache: CacheConfig,
+    /// 相同内容的 blob 只在共享目录中存一份
+    #[serde(default)]
+    pub(crate) shared_blobs: bool,
+    /// 存储和传输对象时的 zlib 压缩级别（0–9）
+    #[serde(default = "default_compression_level")]
+    pub(crate) compression_level: u32,
+}
+
+fn default_compression_level() -> u32 {
+    6
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            ssh: SshConfig::default(),
+            transaction: TransactionConfig::default(),
+            storage: StorageBackend::default(),
+            http: HttpConfig::default(),
+            cors: CorsConfig::default(),
+            cache: CacheConfig::default(),
+            shared_blobs: false,
+            compression_level: default_compression_level(),
+        }
+    }
 }
 
 pub mod auth;
+pub mod cache;
+pub mod cors;
+pub mod http;
 pub mod logger;
 pub mod rpc;
 pub mod socket;
 pub mod ssh;
+pub mod storage;
 pub mod tls;
+pub mod transaction;
 
 impl AppConfig {
     /// Loads the application configuration from the configured file or the default path.
@@ -98,4 +143,32 @@ impl AppConfig {
     pub fn ssh() -> &'static SshConfig {
         &CFG.ssh
     }
+    /// Accesses the global upload/receive pack timeout configuration.
+    pub fn transaction() -> &'static TransactionConfig {
+        &CFG.transaction
+    }
+    /// Accesses the global blob storage backend configuration.
+    pub fn storage() -> &'static StorageBackend {
+        &CFG.storage
+    }
+    /// Accesses the global HTTP server configuration.
+    pub fn http() -> &'static HttpConfig {
+        &CFG.http
+    }
+    /// Accesses the global CORS configuration of the HTTP server.
+    pub fn cors() -> &'static CorsConfig {
+        &CFG.cors
+    }
+    /// Accesses the global in-memory cache configuration.
+    pub fn cache() -> &'static CacheConfig {
+        &CFG.cache
+    }
+    /// Whether new blobs go to the object store pool shared by all repositories.
+    pub fn shared_blobs() -> bool {
+        CFG.shared_blobs
+    }
+    /// The zlib level objects are compressed with, clamped to 0–9.
+    pub fn compression_level() -> u32 {
+        CFG.compression_level.min(9)
+    }
 }
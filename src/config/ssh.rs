@@ -7,13 +7,33 @@ pub struct SshConfig {
     pub port: u16,
     pub user: String,
     pub server_public_key: Option<String>,
+    /// Additional host keys, each a base64 encoded private key PEM like `server_public_key`.
+    /// All configured keys are offered, so clients can pick the algorithm they support.
+    #[serde(default)]
+    pub host_keys: Vec<String>,
+    /// Host key algorithms to negotiate, most preferred first (e.g. `ssh-ed25519`,
+    /// `ecdsa-sha2-nistp256`, `rsa-sha2-512`). Empty keeps the russh defaults.
+    #[serde(default)]
+    pub key_algorithms: Vec<String>,
+    /// Messages queued per channel before the session stops reading from the client. This is
+    /// the backpressure bound of a connection, so it caps the memory a slow reader can pin.
+    #[serde(default = "default_buffer_size")]
+    pub channel_buffer_size: usize,
+    /// Events queued per session before the session stops reading from the socket.
+    #[serde(default = "default_buffer_size")]
+    pub event_buffer_size: usize,
+}
+
+fn default_buffer_size() -> usize {
+    2048
 }
 
 impl Default for SshConfig {
     /// Creates the default SSH configuration.
     ///
     /// The default configuration has `enabled` set to `false`, `host` set to `"0.0.0.0"`,
-    /// `port` set to `22`, an empty `user`, and `server_public_key` set to `None`.
+    /// `port` set to `22`, an empty `user`, `server_public_key` set to `None` and no extra host
+    /// keys or algorithm preferences; both buffer sizes default to 2048.
     ///
     /// # Examples
     ///
@@ -32,6 +52,10 @@ impl Default for SshConfig {
             port: 22,
             user: "".to_string(),
             server_public_key: None,
+            host_keys: vec![],
+            key_algorithms: vec![],
+            channel_buffer_size: default_buffer_size(),
+            event_buffer_size: default_buffer_size(),
         }
     }
 }
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+/// Where blob contents are stored.
+///
+/// Credentials left unset here are picked up from the provider's usual environment
+/// variables (`AWS_*`, `GOOGLE_*`) when the store is built.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// 本地目录
+    Local { path: String },
+    S3 {
+        bucket: String,
+        region: Option<String>,
+        /// 兼容 S3 的服务（如 MinIO）的地址
+        endpoint: Option<String>,
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+        /// 原子的“不存在才复制”的实现方式，即 object_store 的 `aws_copy_if_not_exists`，
+        /// 如 `multipart` 或 `header:<name>:<value>`；不设置时使用 `multipart`
+        copy_if_not_exists: Option<String>,
+    },
+    Gcs {
+        bucket: String,
+        /// 服务账号 JSON 密钥文件路径
+        service_account_path: Option<String>,
+    },
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        StorageBackend::Local {
+            path: "./data".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_s3_backend() {
+        let backend: StorageBackend = toml::from_str(
+            r#"
+            type = "s3"
+            bucket = "git-objects"
+            region = "us-east-1"
+            endpoint = "http://localhost:9000"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            backend,
+            StorageBackend::S3 {
+                bucket: "git-objects".to_string(),
+                region: Some("us-east-1".to_string()),
+                endpoint: Some("http://localhost:9000".to_string()),
+                access_key_id: None,
+                secret_access_key: None,
+                copy_if_not_exists: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_local_backend() {
+        let backend: StorageBackend =
+            toml::from_str("type = \"local\"\npath = \"/srv/git\"").unwrap();
+        assert_eq!(
+            backend,
+            StorageBackend::Local {
+                path: "/srv/git".to_string()
+            }
+        );
+    }
+}
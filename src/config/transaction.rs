@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct TransactionConfig {
+    /// 单次读取客户端数据的最长等待时间（秒）
+    pub read_timeout_secs: u64,
+    /// 整个 upload-pack / receive-pack 事务的最长时间（秒）
+    pub deadline_secs: u64,
+    /// receive-pack 单个 pack 允许声明的最大对象数
+    pub max_pack_objects: usize,
+    /// receive-pack 单个 pack 解压后的最大字节数
+    pub max_pack_bytes: u64,
+    /// receive-pack 单个 blob 解压后的最大字节数，超过则拒绝整个推送
+    pub max_blob_bytes: u64,
+    /// upload-pack 及仓库历史、目录遍历单次最多访问的对象数
+    pub max_walk_nodes: usize,
+    /// 一次 v2 upload-pack 会话最多处理的命令数（ls-refs、fetch）
+    pub max_session_commands: usize,
+    /// 一次 v2 upload-pack 会话最多读取的请求字节数
+    pub max_session_bytes: u64,
+    /// receive-pack 在 pack 之前的命令与 push-option 最多占用的字节数
+    pub max_command_bytes: usize,
+    /// 拒绝分支的非快进更新，对应 git 的 receive.denyNonFastForwards
+    pub deny_non_fast_forwards: bool,
+    /// 同一仓库同时进行的 receive-pack 事务数
+    pub max_concurrent_pushes: usize,
+    /// 事务暂存目录超过该时间（秒）仍未提交或回滚，视为遗留并清理
+    pub quarantine_max_age_secs: u64,
+    /// 清理遗留暂存目录的间隔（秒），0 表示不清理
+    pub quarantine_sweep_secs: u64,
+    /// 每个客户端 IP 在一个周期内最多发起的事务数，0 表示不限制
+    pub rate_limit_transactions: u32,
+    /// 限流周期（秒），令牌在一个周期内匀速补满
+    pub rate_limit_interval_secs: u64,
+}
+
+impl Default for TransactionConfig {
+    /// Creates the default transaction configuration: a 60 second read timeout, a one hour
+    /// overall deadline, pushes of at most 10 million objects / 4 GiB uncompressed with no
+    /// separate per-blob limit and at most 16 MiB of commands, fetch walks of at most 10
+    /// million objects, v2 sessions of at most 100 commands / 64 MiB of requests,
+    /// non-fast-forward pushes allowed, one push at a time per repository, and a sweep
+    /// every 10 minutes of quarantines left for over two hours, twice the deadline. Per-IP
+    /// rate limiting is off, with a one minute interval once enabled.
+    fn default() -> Self {
+        Self {
+            read_timeout_secs: 60,
+            deadline_secs: 3600,
+            max_pack_objects: 10_000_000,
+            max_pack_bytes: 4 << 30,
+            max_blob_bytes: 4 << 30,
+            max_walk_nodes: 10_000_000,
+            max_session_commands: 100,
+            max_session_bytes: 64 << 20,
+            max_command_bytes: 16 << 20,
+            deny_non_fast_forwards: false,
+            max_concurrent_pushes: 1,
+            quarantine_max_age_secs: 7200,
+            quarantine_sweep_secs: 600,
+            rate_limit_transactions: 0,
+            rate_limit_interval_secs: 60,
+        }
+    }
+}
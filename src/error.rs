@@ -1,5 +1,7 @@
+use crate::serve::maintenance::MAINTENANCE_MESSAGE;
 use crate::sha::HashValue;
 use mongodb::bson;
+use std::fmt::{Display, Formatter};
 
 #[derive(Clone, Debug)]
 pub enum GitInnerError {
@@ -36,6 +38,8 @@ pub enum GitInnerError {
     UnsupportedOfsDelta,
     InvalidHash,
     UnsupportedVersion,
+    /// A pushed pack declares a version this server cannot read.
+    UnsupportedProtocolVersion(u32),
     ZlibError,
     Payload(String),
     NotSupportCommand,
@@ -44,6 +48,246 @@ pub enum GitInnerError {
     SshServerStartError(String),
     AppInitError,
     AppNotInit,
+    InvalidRevision(String),
+    AmbiguousRevision(String),
+    RevisionNotFound(String),
+    PathNotFound(String),
+    InvalidArgument(String),
+    RefAlreadyExists(String),
+    RepoAlreadyExists(String),
+    PermissionDenied,
+    /// A pre-receive hook refused the push with this reason.
+    HookDeclined(String),
+    ObjectFormatMismatch(String),
+    Timeout,
+    PackTooLarge,
+    /// A pushed blob is larger than the configured per-blob limit.
+    BlobTooLarge,
+    GraphTooLarge,
+    /// A protocol v2 session sent more commands or request bytes than allowed.
+    SessionTooLarge,
+    /// The commands and push options in front of a pushed pack exceed the configured limit.
+    CommandsTooLarge,
+    ReadOnly,
+    /// The client opened more transactions than its rate limit allows.
+    RateLimited,
+    Io(String),
+    ClientDisconnected,
+    /// A path reaches into a submodule; `path` is the gitlink entry and `commit` the commit
+    /// it points at in the submodule's repository.
+    SubmoduleBoundary {
+        path: String,
+        commit: HashValue,
+    },
+}
+
+/// Broad category of a [`GitInnerError`], for callers that report errors across a
+/// service boundary and need to tell a bad request from a server fault.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The requested object, ref, revision or path does not exist.
+    NotFound,
+    /// The request itself is malformed or outside configured limits.
+    InvalidInput,
+    /// The caller may not perform the operation.
+    Unauthorized,
+    /// The request is valid but clashes with the current state, e.g. an existing ref.
+    Conflict,
+    /// The caller exceeded its request rate and should retry later.
+    RateLimited,
+    /// Storage, transport or other server-side failure.
+    Internal,
+}
+
+impl GitInnerError {
+    /// The category this error belongs to.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            GitInnerError::ObjectNotFound(_)
+            | GitInnerError::RevisionNotFound(_)
+            | GitInnerError::PathNotFound(_)
+            | GitInnerError::MissingBaseObject
+            | GitInnerError::SubmoduleBoundary { .. } => ErrorCode::NotFound,
+            GitInnerError::InvalidSha1String
+            | GitInnerError::InvalidSha256String
+            | GitInnerError::DeltaBaseSizeMismatch
+            | GitInnerError::DeltaInvalidInstruction
+            | GitInnerError::DeltaResultSizeMismatch
+            | GitInnerError::UnexpectedEof
+            | GitInnerError::InvalidUtf8
+            | GitInnerError::InvalidData
+            | GitInnerError::ConversionError(_)
+            | GitInnerError::InvalidSignatureType(_)
+            | GitInnerError::InvalidSignature
+            | GitInnerError::InvalidTimestamp
+            | GitInnerError::MissingField(_)
+            | GitInnerError::InvalidTreeItem(_)
+            | GitInnerError::InvalidDelta
+            | GitInnerError::MissingAuthor
+            | GitInnerError::MissingCommitter
+            | GitInnerError::TreeParseError
+            | GitInnerError::TagParseError
+            | GitInnerError::CommitParseError
+            | GitInnerError::NotSupportVersion
+            | GitInnerError::DecompressionError
+            | GitInnerError::UnsupportedOfsDelta
+            | GitInnerError::InvalidHash
+            | GitInnerError::UnsupportedVersion
+            | GitInnerError::UnsupportedProtocolVersion(_)
+            | GitInnerError::ZlibError
+            | GitInnerError::Payload(_)
+            | GitInnerError::NotSupportCommand
+            | GitInnerError::InvalidRevision(_)
+            | GitInnerError::AmbiguousRevision(_)
+            | GitInnerError::InvalidArgument(_)
+            | GitInnerError::ObjectFormatMismatch(_)
+            | GitInnerError::PackTooLarge
+            | GitInnerError::BlobTooLarge
+            | GitInnerError::GraphTooLarge
+            | GitInnerError::SessionTooLarge
+            | GitInnerError::CommandsTooLarge => ErrorCode::InvalidInput,
+            GitInnerError::PermissionDenied | GitInnerError::HookDeclined(_) => {
+                ErrorCode::Unauthorized
+            }
+            GitInnerError::DefaultBranchCannotBeDeleted
+            | GitInnerError::RefAlreadyExists(_)
+            | GitInnerError::RepoAlreadyExists(_)
+            | GitInnerError::ReadOnly => ErrorCode::Conflict,
+            GitInnerError::RateLimited => ErrorCode::RateLimited,
+            GitInnerError::MongodbError(_)
+            | GitInnerError::BJSONERROR(_)
+            | GitInnerError::ObjectStoreError(_)
+            | GitInnerError::HashVersionError
+            | GitInnerError::UuidError
+            | GitInnerError::Other(_)
+            | GitInnerError::RusshError(_)
+            | GitInnerError::SshServerStartError(_)
+            | GitInnerError::AppInitError
+            | GitInnerError::AppNotInit
+            | GitInnerError::Timeout
+            | GitInnerError::Io(_)
+            | GitInnerError::ClientDisconnected => ErrorCode::Internal,
+        }
+    }
+}
+
+impl Display for GitInnerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GitInnerError::InvalidSha1String => write!(f, "invalid SHA-1 hash string"),
+            GitInnerError::InvalidSha256String => write!(f, "invalid SHA-256 hash string"),
+            GitInnerError::MissingBaseObject => write!(f, "delta base object is missing"),
+            GitInnerError::DeltaBaseSizeMismatch => {
+                write!(f, "delta base size does not match the base object")
+            }
+            GitInnerError::DeltaInvalidInstruction => write!(f, "invalid delta instruction"),
+            GitInnerError::DeltaResultSizeMismatch => {
+                write!(f, "delta result size does not match the declared size")
+            }
+            GitInnerError::UnexpectedEof => write!(f, "unexpected end of input"),
+            GitInnerError::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            GitInnerError::InvalidData => write!(f, "invalid data"),
+            GitInnerError::ConversionError(msg) => write!(f, "conversion error: {}", msg),
+            GitInnerError::InvalidSignatureType(kind) => {
+                write!(f, "invalid signature type: {}", kind)
+            }
+            GitInnerError::InvalidSignature => write!(f, "invalid signature"),
+            GitInnerError::InvalidTimestamp => write!(f, "invalid timestamp"),
+            GitInnerError::MongodbError(msg) => write!(f, "mongodb error: {}", msg),
+            GitInnerError::DefaultBranchCannotBeDeleted => {
+                write!(f, "the default branch cannot be deleted")
+            }
+            GitInnerError::BJSONERROR(err) => write!(f, "bson serialization error: {}", err),
+            GitInnerError::ObjectNotFound(hash) => write!(f, "object not found: {}", hash),
+            GitInnerError::MissingField(field) => write!(f, "missing field: {}", field),
+            GitInnerError::InvalidTreeItem(msg) => write!(f, "invalid tree item: {}", msg),
+            GitInnerError::InvalidDelta => write!(f, "invalid delta"),
+            GitInnerError::MissingAuthor => write!(f, "commit has no author"),
+            GitInnerError::MissingCommitter => write!(f, "commit has no committer"),
+            GitInnerError::ObjectStoreError(msg) => write!(f, "object store error: {}", msg),
+            GitInnerError::HashVersionError => write!(f, "unsupported hash version"),
+            GitInnerError::UuidError => write!(f, "invalid uuid"),
+            GitInnerError::TreeParseError => write!(f, "failed to parse tree"),
+            GitInnerError::TagParseError => write!(f, "failed to parse tag"),
+            GitInnerError::CommitParseError => write!(f, "failed to parse commit"),
+            GitInnerError::NotSupportVersion => write!(f, "protocol version not supported"),
+            GitInnerError::DecompressionError => write!(f, "failed to decompress data"),
+            GitInnerError::UnsupportedOfsDelta => write!(f, "OFS_DELTA is not supported"),
+            GitInnerError::InvalidHash => write!(f, "invalid hash"),
+            GitInnerError::UnsupportedVersion => write!(f, "unsupported version"),
+            GitInnerError::UnsupportedProtocolVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            GitInnerError::ZlibError => write!(f, "zlib error"),
+            GitInnerError::Payload(msg) => write!(f, "request body error: {}", msg),
+            GitInnerError::NotSupportCommand => write!(f, "command not supported"),
+            GitInnerError::Other(msg) => write!(f, "{}", msg),
+            GitInnerError::RusshError(msg) => write!(f, "ssh error: {}", msg),
+            GitInnerError::SshServerStartError(msg) => {
+                write!(f, "failed to start ssh server: {}", msg)
+            }
+            GitInnerError::AppInitError => write!(f, "application is already initialized"),
+            GitInnerError::AppNotInit => write!(f, "application is not initialized"),
+            GitInnerError::InvalidRevision(rev) => write!(f, "invalid revision: {}", rev),
+            GitInnerError::AmbiguousRevision(rev) => write!(f, "ambiguous revision: {}", rev),
+            GitInnerError::RevisionNotFound(rev) => write!(f, "revision not found: {}", rev),
+            GitInnerError::PathNotFound(path) => write!(f, "path not found: {}", path),
+            GitInnerError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            GitInnerError::RefAlreadyExists(name) => write!(f, "ref already exists: {}", name),
+            GitInnerError::RepoAlreadyExists(path) => {
+                write!(f, "repository already exists: {}", path)
+            }
+            GitInnerError::PermissionDenied => write!(f, "permission denied"),
+            GitInnerError::HookDeclined(reason) => write!(f, "push declined: {}", reason),
+            GitInnerError::ObjectFormatMismatch(msg) => write!(f, "{}", msg),
+            GitInnerError::Timeout => write!(f, "timed out waiting for the client"),
+            GitInnerError::PackTooLarge => write!(f, "pack exceeds the configured size limit"),
+            GitInnerError::BlobTooLarge => write!(f, "blob exceeds size limit"),
+            GitInnerError::GraphTooLarge => {
+                write!(f, "object graph exceeds the configured walk limit")
+            }
+            GitInnerError::SessionTooLarge => {
+                write!(f, "session exceeds the configured command or request limit")
+            }
+            GitInnerError::CommandsTooLarge => {
+                write!(f, "push commands exceed the configured size limit")
+            }
+            GitInnerError::ReadOnly => write!(f, "{}", MAINTENANCE_MESSAGE),
+            GitInnerError::RateLimited => write!(f, "too many requests, try again later"),
+            GitInnerError::Io(msg) => write!(f, "io error: {}", msg),
+            GitInnerError::ClientDisconnected => write!(f, "client disconnected"),
+            GitInnerError::SubmoduleBoundary { path, commit } => {
+                write!(f, "path crosses submodule {} at {}", path, commit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GitInnerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GitInnerError::BJSONERROR(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for GitInnerError {
+    fn from(e: std::io::Error) -> Self {
+        GitInnerError::Io(e.to_string())
+    }
+}
+
+impl From<mongodb::error::Error> for GitInnerError {
+    fn from(e: mongodb::error::Error) -> Self {
+        GitInnerError::MongodbError(e.to_string())
+    }
+}
+
+impl From<object_store::Error> for GitInnerError {
+    fn from(e: object_store::Error) -> Self {
+        GitInnerError::ObjectStoreError(e.to_string())
+    }
 }
 
 impl From<bson::ser::Error> for GitInnerError {
@@ -80,3 +324,296 @@ impl From<russh::Error> for GitInnerError {
         GitInnerError::RusshError(format!("{}", e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha::HashVersion;
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(
+            GitInnerError::ObjectNotFound(HashVersion::Sha1.default()).code(),
+            ErrorCode::NotFound
+        );
+        assert_eq!(
+            GitInnerError::InvalidArgument("bad".to_string()).code(),
+            ErrorCode::InvalidInput
+        );
+        assert_eq!(
+            GitInnerError::PermissionDenied.code(),
+            ErrorCode::Unauthorized
+        );
+        assert_eq!(
+            GitInnerError::RefAlreadyExists("refs/heads/main".to_string()).code(),
+            ErrorCode::Conflict
+        );
+        assert_eq!(
+            GitInnerError::RepoAlreadyExists("ns/repo".to_string()).code(),
+            ErrorCode::Conflict
+        );
+        assert_eq!(GitInnerError::RateLimited.code(), ErrorCode::RateLimited);
+        assert_eq!(
+            GitInnerError::MongodbError("down".to_string()).code(),
+            ErrorCode::Internal
+        );
+    }
+
+    #[test]
+    fn test_display_every_variant() {
+        let hash = HashVersion::Sha1.default();
+        let bson_err = bson::to_document(&1u8).unwrap_err();
+        let cases = vec![
+            (
+                GitInnerError::InvalidSha1String,
+                "invalid SHA-1 hash string".to_string(),
+            ),
+            (
+                GitInnerError::InvalidSha256String,
+                "invalid SHA-256 hash string".to_string(),
+            ),
+            (
+                GitInnerError::MissingBaseObject,
+                "delta base object is missing".to_string(),
+            ),
+            (
+                GitInnerError::DeltaBaseSizeMismatch,
+                "delta base size does not match the base object".to_string(),
+            ),
+            (
+                GitInnerError::DeltaInvalidInstruction,
+                "invalid delta instruction".to_string(),
+            ),
+            (
+                GitInnerError::DeltaResultSizeMismatch,
+                "delta result size does not match the declared size".to_string(),
+            ),
+            (
+                GitInnerError::UnexpectedEof,
+                "unexpected end of input".to_string(),
+            ),
+            (GitInnerError::InvalidUtf8, "invalid UTF-8".to_string()),
+            (GitInnerError::InvalidData, "invalid data".to_string()),
+            (
+                GitInnerError::ConversionError("x".to_string()),
+                "conversion error: x".to_string(),
+            ),
+            (
+                GitInnerError::InvalidSignatureType("x".to_string()),
+                "invalid signature type: x".to_string(),
+            ),
+            (
+                GitInnerError::InvalidSignature,
+                "invalid signature".to_string(),
+            ),
+            (
+                GitInnerError::InvalidTimestamp,
+                "invalid timestamp".to_string(),
+            ),
+            (
+                GitInnerError::MongodbError("x".to_string()),
+                "mongodb error: x".to_string(),
+            ),
+            (
+                GitInnerError::DefaultBranchCannotBeDeleted,
+                "the default branch cannot be deleted".to_string(),
+            ),
+            (
+                GitInnerError::BJSONERROR(bson_err.clone()),
+                format!("bson serialization error: {}", bson_err),
+            ),
+            (
+                GitInnerError::ObjectNotFound(hash.clone()),
+                format!("object not found: {}", hash),
+            ),
+            (
+                GitInnerError::MissingField("tree"),
+                "missing field: tree".to_string(),
+            ),
+            (
+                GitInnerError::InvalidTreeItem("x".to_string()),
+                "invalid tree item: x".to_string(),
+            ),
+            (GitInnerError::InvalidDelta, "invalid delta".to_string()),
+            (
+                GitInnerError::MissingAuthor,
+                "commit has no author".to_string(),
+            ),
+            (
+                GitInnerError::MissingCommitter,
+                "commit has no committer".to_string(),
+            ),
+            (
+                GitInnerError::ObjectStoreError("x".to_string()),
+                "object store error: x".to_string(),
+            ),
+            (
+                GitInnerError::HashVersionError,
+                "unsupported hash version".to_string(),
+            ),
+            (GitInnerError::UuidError, "invalid uuid".to_string()),
+            (
+                GitInnerError::TreeParseError,
+                "failed to parse tree".to_string(),
+            ),
+            (
+                GitInnerError::TagParseError,
+                "failed to parse tag".to_string(),
+            ),
+            (
+                GitInnerError::CommitParseError,
+                "failed to parse commit".to_string(),
+            ),
+            (
+                GitInnerError::NotSupportVersion,
+                "protocol version not supported".to_string(),
+            ),
+            (
+                GitInnerError::DecompressionError,
+                "failed to decompress data".to_string(),
+            ),
+            (
+                GitInnerError::UnsupportedOfsDelta,
+                "OFS_DELTA is not supported".to_string(),
+            ),
+            (GitInnerError::InvalidHash, "invalid hash".to_string()),
+            (
+                GitInnerError::UnsupportedVersion,
+                "unsupported version".to_string(),
+            ),
+            (
+                GitInnerError::UnsupportedProtocolVersion(3),
+                "unsupported protocol version 3".to_string(),
+            ),
+            (GitInnerError::ZlibError, "zlib error".to_string()),
+            (
+                GitInnerError::Payload("x".to_string()),
+                "request body error: x".to_string(),
+            ),
+            (
+                GitInnerError::NotSupportCommand,
+                "command not supported".to_string(),
+            ),
+            (GitInnerError::Other("x".to_string()), "x".to_string()),
+            (
+                GitInnerError::RusshError("x".to_string()),
+                "ssh error: x".to_string(),
+            ),
+            (
+                GitInnerError::SshServerStartError("x".to_string()),
+                "failed to start ssh server: x".to_string(),
+            ),
+            (
+                GitInnerError::AppInitError,
+                "application is already initialized".to_string(),
+            ),
+            (
+                GitInnerError::AppNotInit,
+                "application is not initialized".to_string(),
+            ),
+            (
+                GitInnerError::InvalidRevision("x".to_string()),
+                "invalid revision: x".to_string(),
+            ),
+            (
+                GitInnerError::AmbiguousRevision("x".to_string()),
+                "ambiguous revision: x".to_string(),
+            ),
+            (
+                GitInnerError::RevisionNotFound("x".to_string()),
+                "revision not found: x".to_string(),
+            ),
+            (
+                GitInnerError::PathNotFound("x".to_string()),
+                "path not found: x".to_string(),
+            ),
+            (
+                GitInnerError::InvalidArgument("x".to_string()),
+                "invalid argument: x".to_string(),
+            ),
+            (
+                GitInnerError::RefAlreadyExists("x".to_string()),
+                "ref already exists: x".to_string(),
+            ),
+            (
+                GitInnerError::RepoAlreadyExists("x".to_string()),
+                "repository already exists: x".to_string(),
+            ),
+            (
+                GitInnerError::PermissionDenied,
+                "permission denied".to_string(),
+            ),
+            (
+                GitInnerError::HookDeclined("x".to_string()),
+                "push declined: x".to_string(),
+            ),
+            (
+                GitInnerError::ObjectFormatMismatch("x".to_string()),
+                "x".to_string(),
+            ),
+            (
+                GitInnerError::Timeout,
+                "timed out waiting for the client".to_string(),
+            ),
+            (
+                GitInnerError::PackTooLarge,
+                "pack exceeds the configured size limit".to_string(),
+            ),
+            (
+                GitInnerError::BlobTooLarge,
+                "blob exceeds size limit".to_string(),
+            ),
+            (
+                GitInnerError::GraphTooLarge,
+                "object graph exceeds the configured walk limit".to_string(),
+            ),
+            (
+                GitInnerError::SessionTooLarge,
+                "session exceeds the configured command or request limit".to_string(),
+            ),
+            (
+                GitInnerError::CommandsTooLarge,
+                "push commands exceed the configured size limit".to_string(),
+            ),
+            (
+                GitInnerError::ReadOnly,
+                "repository is in read-only maintenance mode".to_string(),
+            ),
+            (
+                GitInnerError::RateLimited,
+                "too many requests, try again later".to_string(),
+            ),
+            (
+                GitInnerError::Io("x".to_string()),
+                "io error: x".to_string(),
+            ),
+            (
+                GitInnerError::ClientDisconnected,
+                "client disconnected".to_string(),
+            ),
+            (
+                GitInnerError::SubmoduleBoundary {
+                    path: "vendor/lib".to_string(),
+                    commit: hash.clone(),
+                },
+                format!("path crosses submodule vendor/lib at {}", hash),
+            ),
+        ];
+        for (err, expected) in cases {
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_from_underlying_errors() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "gone");
+        assert!(matches!(GitInnerError::from(io), GitInnerError::Io(msg) if msg == "gone"));
+        let store = object_store::Error::NotImplemented;
+        assert!(matches!(
+            GitInnerError::from(store),
+            GitInnerError::ObjectStoreError(_)
+        ));
+        let boxed: Box<dyn std::error::Error> = Box::new(GitInnerError::PermissionDenied);
+        assert_eq!(boxed.to_string(), "permission denied");
+    }
+}
@@ -1 +1,27 @@
+use crate::transaction::receive::command::ReceiveCommand;
+use async_trait::async_trait;
+use uuid::Uuid;
 
+/// A push as seen by receive hooks.
+#[derive(Clone, Debug)]
+pub struct PushEvent {
+    pub repository: Uuid,
+    /// Authenticated user who pushed, if any.
+    pub actor: Option<String>,
+    pub commands: Vec<ReceiveCommand>,
+    /// `git push -o` values, in the order the client sent them.
+    pub push_options: Vec<String>,
+}
+
+/// Server-side hooks around receive-pack, the in-process counterpart of git's
+/// `pre-receive` and `post-receive` scripts.
+#[async_trait]
+pub trait ReceiveHook: Send + Sync + 'static {
+    /// Runs once the pack is unpacked and before anything is committed. `Err(reason)`
+    /// refuses the push: every ref is reported as `ng <ref> <reason>` and no object is kept.
+    async fn pre_receive(&self, _event: &PushEvent) -> Result<(), String> {
+        Ok(())
+    }
+    /// Runs after the ref updates have been applied.
+    async fn post_receive(&self, _event: &PushEvent) {}
+}
@@ -0,0 +1,216 @@
+use crate::http::git_protocol_version;
+use crate::transaction::GitProtoVersion;
+use actix_web::Error;
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::header::Header;
+use actix_web_httpauth::headers::authorization::{Authorization, Basic};
+use bytes::Bytes;
+use futures_util::future::{LocalBoxFuture, Ready, ready};
+use serde::Serialize;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+type Sink = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// One request of the JSON access log.
+#[derive(Serialize)]
+struct AccessLogEntry {
+    method: String,
+    path: String,
+    status: u16,
+    bytes: u64,
+    duration_us: u128,
+    git_protocol: u8,
+    namespace: Option<String>,
+    repo: Option<String>,
+    /// User named by the Basic credentials, whether or not they were accepted.
+    user: Option<String>,
+}
+
+/// Access log middleware writing one JSON object per request. The line is written once the
+/// response body has been sent, so `bytes` and `duration_us` cover streamed bodies too.
+#[derive(Clone)]
+pub struct JsonAccessLog {
+    sink: Sink,
+}
+
+impl Default for JsonAccessLog {
+    /// Log through `tracing` with the `access_log` target.
+    fn default() -> Self {
+        Self::with_sink(|line| tracing::info!(target: "access_log", "{}", line))
+    }
+}
+
+impl JsonAccessLog {
+    /// Hand every log line to `sink` instead.
+    pub fn with_sink(sink: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for JsonAccessLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<LoggedBody>;
+    type Error = Error;
+    type Transform = JsonAccessLogService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(JsonAccessLogService {
+            service: Rc::new(service),
+            sink: self.sink.clone(),
+        }))
+    }
+}
+
+pub struct JsonAccessLogService<S> {
+    service: Rc<S>,
+    sink: Sink,
+}
+
+impl<S, B> Service<ServiceRequest> for JsonAccessLogService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<LoggedBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let git_protocol = match git_protocol_version(req.request()) {
+            GitProtoVersion::V2 => 2,
+            _ => 1,
+        };
+        let user = Authorization::<Basic>::parse(req.request())
+            .ok()
+            .map(|x| x.into_scheme().user_id().to_string());
+        let sink = self.sink.clone();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            // 路由匹配后才能拿到仓库路径参数
+            let match_info = res.request().match_info();
+            let entry = AccessLogEntry {
+                method,
+                path,
+                status: res.status().as_u16(),
+                bytes: 0,
+                duration_us: 0,
+                git_protocol,
+                namespace: match_info.get("namespace").map(str::to_string),
+                repo: match_info.get("repo_name").map(str::to_string),
+                user,
+            };
+            Ok(res.map_body(|_, body| LoggedBody {
+                body: body.boxed(),
+                entry: Some(entry),
+                start,
+                sink,
+            }))
+        })
+    }
+}
+
+/// Response body that counts the bytes sent and writes the log line when dropped.
+pub struct LoggedBody {
+    body: BoxBody,
+    entry: Option<AccessLogEntry>,
+    start: Instant,
+    sink: Sink,
+}
+
+impl MessageBody for LoggedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let next = Pin::new(&mut this.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &next
+            && let Some(entry) = this.entry.as_mut()
+        {
+            entry.bytes += chunk.len() as u64;
+        }
+        next
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.duration_us = self.start.elapsed().as_micros();
+            if let Ok(line) = serde_json::to_string(&entry) {
+                (self.sink)(&line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use actix_web::web::scope;
+    use actix_web::{App, HttpResponse, web};
+    use std::sync::Mutex;
+
+    #[actix_web::test]
+    async fn test_json_line() {
+        let lines = Arc::new(Mutex::new(Vec::<String>::new()));
+        let captured = lines.clone();
+        let app = init_service(
+            App::new()
+                .wrap(JsonAccessLog::with_sink(move |line| {
+                    captured.lock().unwrap().push(line.to_string())
+                }))
+                .service(scope("/{namespace}/{repo_name}.git").route(
+                    "/info/refs",
+                    web::get().to(|| async { HttpResponse::Ok().body("hello") }),
+                )),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/team/project.git/info/refs?service=git-upload-pack")
+            .insert_header(("Git-Protocol", "version=2"))
+            // alice:secret
+            .insert_header(("Authorization", "Basic YWxpY2U6c2VjcmV0"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(read_body(resp).await, "hello");
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 1);
+        let entry: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/team/project.git/info/refs");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["bytes"], 5);
+        assert!(entry["duration_us"].is_u64());
+        assert_eq!(entry["git_protocol"], 2);
+        assert_eq!(entry["namespace"], "team");
+        assert_eq!(entry["repo"], "project");
+        assert_eq!(entry["user"], "alice");
+    }
+}
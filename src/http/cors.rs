@@ -0,0 +1,114 @@
+use crate::config::cors::CorsConfig;
+use actix_cors::Cors;
+use actix_web::http::Method;
+use actix_web::http::header::{AUTHORIZATION, CONTENT_TYPE, HeaderName};
+
+/// CORS layer for the smart HTTP endpoints, so browser based git clients can fetch and push.
+/// Preflight `OPTIONS` requests are answered by the layer itself.
+pub fn cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allowed_headers([
+            CONTENT_TYPE,
+            AUTHORIZATION,
+            HeaderName::from_static("git-protocol"),
+        ])
+        .expose_headers([CONTENT_TYPE])
+        .max_age(config.max_age_secs);
+    if config.allowed_origins.is_empty() {
+        cors = cors.allow_any_origin();
+    }
+    for origin in &config.allowed_origins {
+        cors = cors.allowed_origin(origin);
+    }
+    cors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpResponse, web};
+
+    fn config() -> CorsConfig {
+        CorsConfig {
+            enabled: true,
+            allowed_origins: vec!["https://example.com".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_preflight() {
+        let app = init_service(App::new().wrap(cors(&config())).route(
+            "/ns/repo.git/git-upload-pack",
+            web::post().to(HttpResponse::Ok),
+        ))
+        .await;
+        let req = TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/ns/repo.git/git-upload-pack")
+            .insert_header(("Origin", "https://example.com"))
+            .insert_header(("Access-Control-Request-Method", "POST"))
+            .insert_header((
+                "Access-Control-Request-Headers",
+                "content-type,git-protocol",
+            ))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let headers = resp.headers();
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin").unwrap(),
+            "https://example.com"
+        );
+        let allowed = headers
+            .get("Access-Control-Allow-Headers")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_lowercase();
+        for header in ["content-type", "git-protocol", "authorization"] {
+            assert!(allowed.contains(header), "missing {}", header);
+        }
+        assert_eq!(headers.get("Access-Control-Max-Age").unwrap(), "3600");
+    }
+
+    #[actix_web::test]
+    async fn test_actual_response() {
+        let app = init_service(
+            App::new()
+                .wrap(cors(&config()))
+                .route("/ns/repo.git/info/refs", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/ns/repo.git/info/refs?service=git-upload-pack")
+            .insert_header(("Origin", "https://example.com"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let headers = resp.headers();
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin").unwrap(),
+            "https://example.com"
+        );
+        assert!(
+            headers
+                .get("Access-Control-Expose-Headers")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_lowercase()
+                .contains("content-type")
+        );
+
+        // 不在白名单里的来源拿不到 CORS 头
+        let req = TestRequest::get()
+            .uri("/ns/repo.git/info/refs?service=git-upload-pack")
+            .insert_header(("Origin", "https://evil.example"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert!(resp.headers().get("Access-Control-Allow-Origin").is_none());
+    }
+}
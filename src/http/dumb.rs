@@ -0,0 +1,173 @@
+use crate::config::AppConfig;
+use crate::repository::Repository;
+use crate::serve::AppCore;
+use crate::sha::HashValue;
+use actix_web::http::header::Header;
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use actix_web_httpauth::headers::authorization::{Authorization, Basic};
+use flate2::Compression;
+use tracing::error;
+
+/// Look up the repository for a dumb protocol request. Private repositories require HTTP
+/// Basic credentials with at least read access, as for upload-pack.
+async fn readable_repo(
+    req: &HttpRequest,
+    app: &AppCore,
+    namespace: &str,
+    repo_name: &str,
+) -> Result<Repository, HttpResponse> {
+    let repo = app
+        .repo_store
+        .repo(namespace.to_string(), repo_name.to_string())
+        .await
+        .map_err(|_| HttpResponse::NotFound().body("Repo not found"))?;
+    if let Some(auth) = app.auth.clone()
+        && !repo.is_public
+    {
+        let unauthorized = || {
+            HttpResponse::Unauthorized()
+                .insert_header(("WWW-Authenticate", r#"Basic realm="Restricted""#))
+                .body("Unauthorized")
+        };
+        let scheme = Authorization::<Basic>::parse(req)
+            .map_err(|_| unauthorized())?
+            .into_scheme();
+        let password = scheme.password().unwrap_or("").to_string();
+        auth.authenticate(scheme.user_id(), &password, namespace, repo_name)
+            .await
+            .map_err(|_| {
+                app.metrics.record_auth_failure();
+                unauthorized()
+            })?;
+    }
+    Ok(repo)
+}
+
+/// `info/refs` without a `?service=` query: the plain ref list read by dumb clients.
+pub async fn info_refs(
+    req: HttpRequest,
+    app: Data<AppCore>,
+    namespace: String,
+    repo_name: String,
+) -> HttpResponse {
+    let repo = match readable_repo(&req, &app, &namespace, &repo_name).await {
+        Ok(repo) => repo,
+        Err(resp) => return resp,
+    };
+    match repo.dumb_info_refs().await {
+        Ok(body) => HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache, max-age=0, must-revalidate"))
+            .content_type("text/plain")
+            .body(body),
+        Err(err) => {
+            error!("info/refs error: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// `HEAD`: the symbolic ref naming the default branch.
+pub async fn head(
+    req: HttpRequest,
+    path: Path<(String, String)>,
+    app: Data<AppCore>,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    let repo = match readable_repo(&req, &app, &namespace, &repo_name).await {
+        Ok(repo) => repo,
+        Err(resp) => return resp,
+    };
+    match repo.dumb_head().await {
+        Ok(body) => HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache, max-age=0, must-revalidate"))
+            .content_type("text/plain")
+            .body(body),
+        Err(err) => {
+            error!("HEAD error: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// `objects/info/packs`: objects are only ever served loose, so the pack list is empty.
+pub async fn info_packs(
+    req: HttpRequest,
+    path: Path<(String, String)>,
+    app: Data<AppCore>,
+) -> impl Responder {
+    let (namespace, repo_name) = path.into_inner();
+    if let Err(resp) = readable_repo(&req, &app, &namespace, &repo_name).await {
+        return resp;
+    }
+    HttpResponse::Ok().content_type("text/plain").body("")
+}
+
+/// The object id named by a loose object path `objects/<xx>/<rest>`.
+fn loose_object_id(dir: &str, file: &str) -> Option<HashValue> {
+    if dir.len() != 2 {
+        return None;
+    }
+    HashValue::from_str(&format!("{}{}", dir, file))
+}
+
+/// Objects never change, so they can be cached for long; only a public repository's may be
+/// kept by shared caches, which would otherwise hand private objects to anyone.
+fn loose_object_cache_control(repo: &Repository) -> &'static str {
+    if repo.is_public {
+        "public, max-age=31536000, immutable"
+    } else {
+        "private, max-age=31536000, immutable"
+    }
+}
+
+/// `objects/<xx>/<rest>`: a single zlib-compressed loose object.
+pub async fn loose_object(
+    req: HttpRequest,
+    path: Path<(String, String, String, String)>,
+    app: Data<AppCore>,
+) -> impl Responder {
+    let (namespace, repo_name, dir, file) = path.into_inner();
+    let Some(hash) = loose_object_id(&dir, &file) else {
+        return HttpResponse::NotFound().body("Object not found");
+    };
+    let repo = match readable_repo(&req, &app, &namespace, &repo_name).await {
+        Ok(repo) => repo,
+        Err(resp) => return resp,
+    };
+    match repo
+        .loose_object(&hash, Compression::new(AppConfig::compression_level()))
+        .await
+    {
+        Ok(body) => HttpResponse::Ok()
+            .insert_header(("Cache-Control", loose_object_cache_control(&repo)))
+            .content_type("application/x-git-loose-object")
+            .body(body),
+        Err(_) => HttpResponse::NotFound().body("Object not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::memory_repository;
+
+    #[test]
+    fn test_loose_object_id() {
+        let hex = "ce013625030ba8dba906f756967f9e9ca394464a";
+        assert_eq!(
+            loose_object_id(&hex[..2], &hex[2..]).unwrap().to_string(),
+            hex
+        );
+        assert!(loose_object_id(&hex[..3], &hex[3..]).is_none());
+        assert!(loose_object_id("ce", "zz").is_none());
+    }
+
+    #[test]
+    fn test_private_objects_not_publicly_cached() {
+        let mut repo = memory_repository();
+        assert!(loose_object_cache_control(&repo).starts_with("public,"));
+        repo.is_public = false;
+        assert!(loose_object_cache_control(&repo).starts_with("private,"));
+    }
+}
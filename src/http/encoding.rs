@@ -0,0 +1,260 @@
+use crate::error::GitInnerError;
+use actix_web::HttpRequest;
+use async_stream::stream;
+use bytes::Bytes;
+use flate2::write::{GzDecoder, ZlibDecoder};
+use std::fmt::Display;
+use std::io::Write;
+use std::pin::Pin;
+use tokio_stream::{Stream, StreamExt};
+
+/// `Content-Encoding` of a smart HTTP request body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    /// HTTP `deflate`, i.e. a zlib stream.
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// Encoding of `req`'s body, or `None` when it uses one we can't decode.
+    pub fn from_request(req: &HttpRequest) -> Option<Self> {
+        let Some(header) = req.headers().get("Content-Encoding") else {
+            return Some(ContentEncoding::Identity);
+        };
+        match header.to_str().ok()?.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(ContentEncoding::Identity),
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "deflate" => Some(ContentEncoding::Deflate),
+            _ => None,
+        }
+    }
+}
+
+/// Most compressed bytes fed to the decoder at once. Deflate expands data at most about
+/// 1032 times, so each inflated piece stays near 1 MiB however large the body chunks are.
+const INPUT_PIECE: usize = 1024;
+
+enum Decoder {
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+}
+
+impl Decoder {
+    /// Feed `data` in and return whatever has been inflated so far.
+    fn decode(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Decoder::Gzip(decoder) => {
+                decoder.write_all(data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+            Decoder::Deflate(decoder) => {
+                decoder.write_all(data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.finish(),
+            Decoder::Deflate(decoder) => decoder.finish(),
+        }
+    }
+}
+
+/// Adapt a request body into the stream the transactions consume, inflating it piece by
+/// piece when it is compressed so the body is never buffered in full. A compressed body
+/// that inflates past `max_decoded` bytes ends the stream with
+/// [`GitInnerError::DecompressionError`].
+pub fn decode_payload<S, E>(
+    encoding: ContentEncoding,
+    payload: S,
+    max_decoded: u64,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>>>>
+where
+    S: Stream<Item = Result<Bytes, E>> + 'static,
+    E: Display,
+{
+    let mut decoder = match encoding {
+        ContentEncoding::Identity => {
+            return Box::pin(
+                payload.map(|next| next.map_err(|err| GitInnerError::Payload(err.to_string()))),
+            );
+        }
+        ContentEncoding::Gzip => Decoder::Gzip(GzDecoder::new(Vec::new())),
+        ContentEncoding::Deflate => Decoder::Deflate(ZlibDecoder::new(Vec::new())),
+    };
+    Box::pin(stream! {
+        let mut payload = Box::pin(payload);
+        let mut decoded = 0u64;
+        while let Some(next) = payload.next().await {
+            let chunk = match next {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    yield Err(GitInnerError::Payload(err.to_string()));
+                    return;
+                }
+            };
+            for piece in chunk.chunks(INPUT_PIECE) {
+                match decoder.decode(piece) {
+                    Ok(out) if out.is_empty() => {}
+                    Ok(out) => {
+                        decoded += out.len() as u64;
+                        if decoded > max_decoded {
+                            yield Err(GitInnerError::DecompressionError);
+                            return;
+                        }
+                        yield Ok(Bytes::from(out));
+                    }
+                    Err(_) => {
+                        yield Err(GitInnerError::DecompressionError);
+                        return;
+                    }
+                }
+            }
+        }
+        match decoder.finish() {
+            Ok(out) if out.is_empty() => {}
+            Ok(out) if decoded + out.len() as u64 > max_decoded => {
+                yield Err(GitInnerError::DecompressionError);
+            }
+            Ok(out) => yield Ok(Bytes::from(out)),
+            Err(_) => yield Err(GitInnerError::DecompressionError),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{
+        memory_repository, output, put_blob, put_commit, put_tree, transaction,
+    };
+    use actix_web::test::TestRequest;
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use tokio_stream::wrappers::ReceiverStream;
+
+    fn chunks(data: Vec<u8>) -> impl Stream<Item = Result<Bytes, GitInnerError>> {
+        let chunks = data
+            .chunks(7)
+            .map(|x| Ok(Bytes::copy_from_slice(x)))
+            .collect::<Vec<_>>();
+        tokio_stream::iter(chunks)
+    }
+
+    #[test]
+    fn test_content_encoding_from_request() {
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(
+            ContentEncoding::from_request(&req),
+            Some(ContentEncoding::Identity)
+        );
+        let req = TestRequest::default()
+            .insert_header(("Content-Encoding", "gzip"))
+            .to_http_request();
+        assert_eq!(
+            ContentEncoding::from_request(&req),
+            Some(ContentEncoding::Gzip)
+        );
+        let req = TestRequest::default()
+            .insert_header(("Content-Encoding", "br"))
+            .to_http_request();
+        assert_eq!(ContentEncoding::from_request(&req), None);
+    }
+
+    #[tokio::test]
+    async fn test_gzip_fetch_request() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+
+        let body = "0014command=ls-refs\n00010000";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+        let mut decoded = decode_payload(
+            ContentEncoding::Gzip,
+            chunks(encoder.finish().unwrap()),
+            u64::MAX,
+        );
+
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        while let Some(next) = decoded.next().await {
+            tx.send(next).await.unwrap();
+        }
+        drop(tx);
+        let txn = transaction(&repo);
+        txn.upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+        assert!(
+            output(&txn)
+                .await
+                .contains(&format!("{} refs/heads/main", commit))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deflate_and_corrupt_bodies() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"0009done\n0000").unwrap();
+        let decoded = decode_payload(
+            ContentEncoding::Deflate,
+            chunks(encoder.finish().unwrap()),
+            u64::MAX,
+        )
+        .collect::<Vec<_>>()
+        .await;
+        let decoded = decoded
+            .into_iter()
+            .flat_map(|x| x.unwrap().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, b"0009done\n0000");
+
+        let mut corrupt = decode_payload(
+            ContentEncoding::Gzip,
+            chunks(b"not gzip at all".to_vec()),
+            u64::MAX,
+        );
+        assert!(matches!(
+            corrupt.next().await,
+            Some(Err(GitInnerError::DecompressionError))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decoded_size_limit() {
+        // 16 MiB 的零压缩后只有十几 KiB
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![0u8; 16 << 20]).unwrap();
+        let body = Bytes::from(encoder.finish().unwrap());
+        let mut decoded = decode_payload(
+            ContentEncoding::Gzip,
+            tokio_stream::iter(vec![Ok::<_, GitInnerError>(body)]),
+            4 << 20,
+        );
+        let mut total = 0;
+        loop {
+            match decoded.next().await {
+                Some(Ok(piece)) => {
+                    assert!(piece.len() <= 2 << 20, "piece of {} bytes", piece.len());
+                    total += piece.len();
+                }
+                Some(Err(err)) => {
+                    assert!(matches!(err, GitInnerError::DecompressionError));
+                    break;
+                }
+                None => panic!("limit not enforced"),
+            }
+        }
+        assert!(total <= 4 << 20);
+        assert!(decoded.next().await.is_none());
+    }
+}
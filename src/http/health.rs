@@ -0,0 +1,96 @@
+use crate::serve::AppCore;
+use crate::serve::health::DependencyCheck;
+use actix_web::web::Data;
+use actix_web::{HttpResponse, Responder};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    dependencies: Vec<DependencyCheck>,
+}
+
+/// Liveness: answers 200 as soon as the server is accepting requests.
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().content_type("text/plain").body("ok")
+}
+
+/// Readiness: probes the repository store's dependencies and answers 503, listing the
+/// dependencies that failed, when any of them is unusable.
+pub async fn readyz(app: Data<AppCore>) -> impl Responder {
+    let dependencies = app.repo_store.readiness().await;
+    let failed = dependencies
+        .iter()
+        .filter(|x| !x.is_ok())
+        .cloned()
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        HttpResponse::Ok().json(Readiness {
+            status: "ready",
+            dependencies,
+        })
+    } else {
+        HttpResponse::ServiceUnavailable().json(Readiness {
+            status: "unavailable",
+            dependencies: failed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::GitInnerError;
+    use crate::repository::Repository;
+    use crate::serve::RepoStore;
+    use crate::serve::testing::app_core;
+    use crate::sha::HashVersion;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use actix_web::{App, web};
+
+    /// Store whose database is down while its object store answers.
+    struct DatabaseDown;
+
+    #[async_trait::async_trait]
+    impl RepoStore for DatabaseDown {
+        async fn repo(&self, _: String, _: String) -> Result<Repository, GitInnerError> {
+            Err(GitInnerError::ObjectNotFound(HashVersion::Sha1.default()))
+        }
+        async fn readiness(&self) -> Vec<DependencyCheck> {
+            vec![
+                DependencyCheck::failed("mongodb", "connection refused"),
+                DependencyCheck::ok("object_store"),
+            ]
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_healthz() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(app_core(DatabaseDown)))
+                .route("/healthz", web::get().to(healthz)),
+        )
+        .await;
+        let resp = call_service(&app, TestRequest::get().uri("/healthz").to_request()).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_readyz_reports_failed_database() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(app_core(DatabaseDown)))
+                .route("/readyz", web::get().to(readyz)),
+        )
+        .await;
+        let resp = call_service(&app, TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(resp.status(), 503);
+        let body: serde_json::Value = serde_json::from_slice(&read_body(resp).await).unwrap();
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(
+            body["dependencies"],
+            serde_json::json!([{ "name": "mongodb", "error": "connection refused" }])
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use crate::serve::AppCore;
+use actix_web::web::Data;
+use actix_web::{HttpResponse, Responder};
+
+/// Prometheus scrape endpoint.
+pub async fn metrics(app: Data<AppCore>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(app.metrics.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{
+        memory_repository, pack, put_blob, put_commit, put_tree, receive_transaction,
+    };
+    use crate::serve::testing::{EmptyStore, app_core};
+    use crate::transaction::Transaction;
+    use crate::write_pkt_line;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use actix_web::{App, web};
+
+    #[actix_web::test]
+    async fn test_push_counted() {
+        let core = app_core(EmptyStore);
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status",
+            repo.hash_version.default(),
+            commit
+        ));
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&pack(&[]));
+        let mut txn = Transaction {
+            metrics: core.metrics.clone(),
+            ..receive_transaction(&repo)
+        };
+        txn.receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body.freeze())])))
+            .await
+            .unwrap();
+
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(core))
+                .route("/metrics", web::get().to(metrics)),
+        )
+        .await;
+        let resp = call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let body = String::from_utf8(read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("# TYPE git_inner_pushes_total counter\n"));
+        assert!(body.contains("\ngit_inner_pushes_total 1\n"));
+        assert!(body.contains("\ngit_inner_fetches_total 0\n"));
+    }
+}
@@ -1,14 +1,28 @@
+use crate::config::AppConfig;
+use crate::config::cors::CorsConfig;
+use crate::config::http::{AccessLogFormat, HttpConfig};
+use crate::error::GitInnerError;
+use crate::http::access_log::JsonAccessLog;
 use crate::serve::AppCore;
-use actix_web::App;
+use crate::transaction::GitProtoVersion;
+use actix_web::dev::ServerHandle;
+use actix_web::middleware::Condition;
 use actix_web::web::{Data, scope};
+use actix_web::{App, HttpRequest, HttpResponse};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use tokio::sync::Notify;
 
 #[derive(Clone)]
 pub struct HttpServer {
     pub addr: String,
     pub port: u16,
     pub core: AppCore,
+    pub http: HttpConfig,
+    pub cors: CorsConfig,
+    shutdown: Arc<Notify>,
+    handle: Arc<Mutex<Option<ServerHandle>>>,
 }
 
 impl HttpServer {
@@ -26,19 +40,71 @@ impl HttpServer {
     /// ```
     pub fn new(addr: String, port: u16) -> Self {
         let core = AppCore::app().expect("App Not Initialized");
-        Self { addr, port, core }
+        Self::with_core(
+            addr,
+            port,
+            core,
+            AppConfig::http().clone(),
+            AppConfig::cors().clone(),
+        )
+    }
+    /// Like [`HttpServer::new`], with an explicit core and configuration instead of the
+    /// global ones.
+    pub fn with_core(
+        addr: String,
+        port: u16,
+        core: AppCore,
+        http: HttpConfig,
+        cors: CorsConfig,
+    ) -> Self {
+        Self {
+            addr,
+            port,
+            core,
+            http,
+            cors,
+            shutdown: Arc::new(Notify::new()),
+            handle: Arc::new(Mutex::new(None)),
+        }
+    }
+    /// Handle of the running server, `None` until [`HttpServer::run`] has bound its socket.
+    pub fn handle(&self) -> Option<ServerHandle> {
+        self.handle.lock().unwrap().clone()
+    }
+    /// Ask a running server to stop gracefully: it stops accepting connections and lets
+    /// in-flight requests finish for up to `http.shutdown_timeout_secs` before dropping them.
+    /// A push dropped this way never reaches its ref updates.
+    pub fn shutdown(&self) {
+        self.shutdown.notify_one();
     }
     pub fn bind_addr(&self) -> String {
         format!("{}:{}", self.addr, self.port)
     }
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
         let core = self.core.clone();
-        actix_web::HttpServer::new(move || {
+        let dumb_protocol = self.http.dumb_protocol;
+        let cors_config = self.cors.clone();
+        let access_log = self.http.access_log;
+        let server = actix_web::HttpServer::new(move || {
             App::new()
                 .app_data(Data::new(core.clone()))
-                .wrap(actix_web::middleware::Logger::new(
-                    "%a %r %s %b bytes in %D microseconds %{git-protocol}i",
+                .wrap(Condition::new(
+                    cors_config.enabled,
+                    cors::cors(&cors_config),
+                ))
+                .wrap(Condition::new(
+                    access_log == AccessLogFormat::Text,
+                    actix_web::middleware::Logger::new(
+                        "%a %r %s %b bytes in %D microseconds %{git-protocol}i",
+                    ),
+                ))
+                .wrap(Condition::new(
+                    access_log == AccessLogFormat::Json,
+                    JsonAccessLog::default(),
                 ))
+                .route("/healthz", actix_web::web::get().to(health::healthz))
+                .route("/readyz", actix_web::web::get().to(health::readyz))
+                .route("/metrics", actix_web::web::get().to(metrics::metrics))
                 .service(
                     scope("/{namespace}/{repo_name}.git")
                         .route("/info/refs", actix_web::web::get().to(refs::refs))
@@ -49,16 +115,72 @@ impl HttpServer {
                         .route(
                             "/git-upload-pack",
                             actix_web::web::post().to(upload::upload_pack),
-                        ),
+                        )
+                        .configure(|cfg| {
+                            if dumb_protocol {
+                                cfg.route("/HEAD", actix_web::web::get().to(dumb::head))
+                                    .route(
+                                        "/objects/info/packs",
+                                        actix_web::web::get().to(dumb::info_packs),
+                                    )
+                                    .route(
+                                        "/objects/{dir}/{file}",
+                                        actix_web::web::get().to(dumb::loose_object),
+                                    );
+                            }
+                        }),
                 )
         })
+        .shutdown_timeout(self.http.shutdown_timeout_secs)
         .bind(self.bind_addr())?
-        .run()
-        .await?;
+        .run();
+        let handle = server.handle();
+        *self.handle.lock().unwrap() = Some(handle.clone());
+        let shutdown = self.shutdown.clone();
+        let watcher = tokio::spawn(async move {
+            shutdown.notified().await;
+            handle.stop(true).await;
+        });
+        let result = server.await;
+        watcher.abort();
+        self.handle.lock().unwrap().take();
+        result?;
         Ok(())
     }
 }
 
+/// Protocol version requested through the `Git-Protocol` header, a colon separated list
+/// of `key=value` parameters. Requests without `version=2` get protocol v1.
+pub fn git_protocol_version(req: &HttpRequest) -> GitProtoVersion {
+    let Some(header) = req
+        .headers()
+        .get("Git-Protocol")
+        .and_then(|x| x.to_str().ok())
+    else {
+        return GitProtoVersion::V1;
+    };
+    if header.split(':').any(|param| param.trim() == "version=2") {
+        GitProtoVersion::V2
+    } else {
+        GitProtoVersion::V1
+    }
+}
+
+/// `429 Too Many Requests` when the client behind `req` has used up its transaction rate,
+/// `None` when the transaction may go ahead.
+pub fn rate_limited(app: &AppCore, req: &HttpRequest) -> Option<HttpResponse> {
+    let ip = req.peer_addr()?.ip();
+    if app.rate_limiter.check(ip) {
+        return None;
+    }
+    let retry_after = app.rate_limiter.retry_after().as_secs().max(1);
+    Some(
+        HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.to_string()))
+            .body(GitInnerError::RateLimited.to_string()),
+    )
+}
+
 impl Future for HttpServer {
     type Output = Result<(), Box<dyn std::error::Error>>;
 
@@ -70,6 +192,78 @@ impl Future for HttpServer {
     }
 }
 
+pub mod access_log;
+pub mod cors;
+pub mod dumb;
+pub mod encoding;
+pub mod health;
+pub mod metrics;
 pub mod receive;
 pub mod refs;
 pub mod upload;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serve::limit::RateLimiter;
+    use crate::serve::testing::{EmptyStore, app_core};
+    use actix_web::test::TestRequest;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_shutdown_stops_run() {
+        let server = HttpServer::with_core(
+            "127.0.0.1".to_string(),
+            0,
+            app_core(EmptyStore),
+            HttpConfig::default(),
+            CorsConfig::default(),
+        );
+        let running = server.clone();
+        let task = tokio::spawn(async move { running.run().await.map_err(|e| e.to_string()) });
+        while server.handle().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        server.shutdown();
+        let result = tokio::time::timeout(Duration::from_secs(10), task)
+            .await
+            .expect("run did not return after shutdown")
+            .unwrap();
+        assert!(result.is_ok());
+        assert!(server.handle().is_none());
+    }
+
+    #[test]
+    fn test_git_protocol_version() {
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(git_protocol_version(&req), GitProtoVersion::V1);
+        let req = TestRequest::default()
+            .insert_header(("Git-Protocol", "version=2"))
+            .to_http_request();
+        assert_eq!(git_protocol_version(&req), GitProtoVersion::V2);
+        let req = TestRequest::default()
+            .insert_header(("Git-Protocol", "object-format=sha1:version=2"))
+            .to_http_request();
+        assert_eq!(git_protocol_version(&req), GitProtoVersion::V2);
+        let req = TestRequest::default()
+            .insert_header(("Git-Protocol", "version=1"))
+            .to_http_request();
+        assert_eq!(git_protocol_version(&req), GitProtoVersion::V1);
+    }
+
+    #[test]
+    fn test_rate_limited_per_peer() {
+        let mut app = app_core(EmptyStore);
+        app.rate_limiter = Arc::new(RateLimiter::new(1, Duration::from_secs(60)));
+        let request = |peer: &str| {
+            TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .to_http_request()
+        };
+        assert!(rate_limited(&app, &request("203.0.113.7:50000")).is_none());
+        let response = rate_limited(&app, &request("203.0.113.7:50001")).unwrap();
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers().get("Retry-After").unwrap(), "60");
+        assert!(rate_limited(&app, &request("203.0.113.8:50000")).is_none());
+    }
+}
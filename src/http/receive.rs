@@ -1,16 +1,34 @@
 use crate::auth::AccessLevel;
 use crate::callback::CallBack;
+use crate::config::AppConfig;
 use crate::error::GitInnerError;
+use crate::http::encoding::{ContentEncoding, decode_payload};
+use crate::http::rate_limited;
 use crate::serve::AppCore;
 use crate::transaction::TransactionService::ReceivePack;
+use crate::transaction::receive::ReceiveStream;
 use crate::transaction::{GitProtoVersion, ProtocolType, Transaction};
 use actix_web::http::header::Header;
 use actix_web::web::Payload;
 use actix_web::{HttpResponse, Responder, web};
 use actix_web_httpauth::headers::authorization::{Authorization, Basic};
 use async_stream::stream;
+use bytes::Bytes;
+use std::fmt::Display;
 use std::io;
-use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{Instrument, error};
+
+/// Adapt an uncompressed request body into the stream `receive_pack` consumes, one chunk at
+/// a time, so the pack is never buffered in full.
+pub fn payload_stream<S, E>(payload: S) -> ReceiveStream
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Display,
+{
+    Box::pin(payload.map(|next| next.map_err(|err| GitInnerError::Payload(err.to_string()))))
+}
 
 /// Handle an HTTP Git "receive-pack" request for a repository and stream the service result.
 ///
@@ -22,6 +40,7 @@ use tokio_stream::StreamExt;
 /// - `404 Not Found` when the repository cannot be located.
 /// - `401 Unauthorized` when authentication is required but missing or invalid.
 /// - `403 Forbidden` when authentication succeeds but grants only read access.
+/// - `429 Too Many Requests` when the client exceeded its per-IP transaction rate.
 ///
 /// # Examples
 ///
@@ -41,12 +60,18 @@ use tokio_stream::StreamExt;
 /// }
 /// ```
 pub async fn receive_pack(
-    mut payload: Payload,
+    payload: Payload,
     path: web::Path<(String, String)>,
     app: web::Data<AppCore>,
     req: actix_web::HttpRequest,
 ) -> impl Responder {
+    if let Some(response) = rate_limited(&app, &req) {
+        return response;
+    }
     let (namespace, repo_name) = path.into_inner();
+    let Some(encoding) = ContentEncoding::from_request(&req) else {
+        return HttpResponse::UnsupportedMediaType().body("Unsupported Content-Encoding");
+    };
     let repo = match app
         .repo_store
         .repo(namespace.clone(), repo_name.clone())
@@ -54,10 +79,11 @@ pub async fn receive_pack(
     {
         Ok(repo) => repo,
         Err(err) => {
-            dbg!(err);
+            error!("Repo lookup error: {:?}", err);
             return HttpResponse::NotFound().body("Repo not found");
         }
     };
+    let mut actor = None;
     if let Some(auth) = app.auth.clone() {
         match Authorization::<Basic>::parse(&req) {
             Ok(basic) => {
@@ -70,9 +96,10 @@ pub async fn receive_pack(
                 {
                     Ok(level) => match level {
                         AccessLevel::Read => return HttpResponse::Forbidden().body("Forbidden"),
-                        _ => {}
+                        _ => actor = Some(username),
                     },
                     Err(_) => {
+                        app.metrics.record_auth_failure();
                         return HttpResponse::Unauthorized()
                             .insert_header(("WWW-Authenticate", r#"Basic realm="Restricted""#))
                             .body("Unauthorized");
@@ -93,23 +120,41 @@ pub async fn receive_pack(
         version: GitProtoVersion::V1,
         call_back: call_back.clone(),
         protocol: ProtocolType::Http,
+        timeouts: AppConfig::transaction().into(),
+        pack_limits: AppConfig::cfg().into(),
+        actor,
+        maintenance: app.maintenance.clone(),
+        metrics: app.metrics.clone(),
+        fetch_resume: app.fetch_resume.clone(),
+        hooks: app.hooks.clone(),
+        push_limiter: app.transactions.clone(),
+        deny_non_fast_forwards: AppConfig::transaction().deny_non_fast_forwards,
     };
-    let (tx, rx) = tokio::sync::mpsc::channel(8);
+    // 请求体只能在当前线程读取，逐块转交给 receive-pack
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
     tokio::task::spawn_local(async move {
-        while let Some(next) = payload.next().await {
-            tx.send(next.map_err(|err| GitInnerError::Payload(err.to_string())))
-                .await
-                .ok();
+        let mut body = decode_payload(encoding, payload, AppConfig::http().max_decoded_body_bytes);
+        while let Some(next) = body.next().await {
+            if tx.send(next).await.is_err() {
+                break;
+            }
         }
     });
-    tokio::task::spawn_local(async move {
-        let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
-        let _result = transaction.receive_pack(Box::pin(stream)).await;
-        let _ = dbg!(_result);
-    });
+    let span = transaction.span(&namespace, &repo_name);
+    tokio::task::spawn_local(
+        async move {
+            let result = transaction
+                .receive_pack(Box::pin(ReceiverStream::new(rx)))
+                .await;
+            if let Err(err) = result {
+                error!("Receive pack error: {:?}", err);
+            }
+        }
+        .instrument(span),
+    );
 
     let stream = stream! {
-        let mut receiver = call_back.receive.lock().await;
+        let mut receiver = call_back.subscribe().await;
         while let Some(next) = receiver.recv().await {
              if next.is_empty() {
                 break;
@@ -122,3 +167,120 @@ pub async fn receive_pack(
         .content_type("application/x-git-receive-pack-result")
         .streaming(stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::ObjectTrait;
+    use crate::objects::blob::Blob;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{memory_repository, pack, put_tree, receive_transaction};
+    use crate::sha::HashVersion;
+    use crate::transaction::upload::recursion::Object;
+    use crate::write_pkt_line;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio_stream::StreamExt;
+
+    /// A body of `chunks` chunks that counts how many have been pulled from it.
+    fn counting_body(
+        data: Bytes,
+        chunk: usize,
+        pulled: Arc<AtomicUsize>,
+    ) -> impl Stream<Item = Result<Bytes, io::Error>> {
+        futures_util::stream::unfold(0, move |pos| {
+            let data = data.clone();
+            let pulled = pulled.clone();
+            async move {
+                if pos >= data.len() {
+                    return None;
+                }
+                pulled.fetch_add(1, Ordering::SeqCst);
+                let end = (pos + chunk).min(data.len());
+                Some((Ok(data.slice(pos..end)), end))
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_payload_stream_does_not_read_ahead() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let body = Bytes::from(vec![0u8; 64 * 1024 * 1024]);
+        let mut stream = payload_stream(counting_body(body, 64 * 1024, pulled.clone()));
+        for i in 1..=3 {
+            assert_eq!(stream.next().await.unwrap().unwrap().len(), 64 * 1024);
+            // 每次只从请求体取出一个分块，内存占用与请求大小无关
+            assert_eq!(pulled.load(Ordering::SeqCst), i);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payload_stream_maps_errors() {
+        let body = futures_util::stream::iter(vec![
+            Ok(Bytes::from("0000")),
+            Err(io::Error::other("connection reset")),
+        ]);
+        let mut stream = payload_stream(body);
+        assert!(stream.next().await.unwrap().is_ok());
+        assert!(matches!(
+            stream.next().await.unwrap(),
+            Err(GitInnerError::Payload(msg)) if msg == "connection reset"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_from_chunked_body() {
+        let repo = memory_repository();
+        let blobs = (0..32)
+            .map(|i| Blob::parse(Bytes::from(format!("file {}\n", i)), HashVersion::Sha1))
+            .collect::<Vec<_>>();
+        let tree = put_tree(
+            &repo,
+            blobs
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (TreeItemMode::Blob, format!("f{:02}", i), x.id.clone()))
+                .collect::<Vec<_>>()
+                .iter()
+                .map(|(mode, name, id)| (mode.clone(), name.as_str(), id.clone()))
+                .collect(),
+        )
+        .await;
+        let tree = repo.odb.get_tree(&tree).await.unwrap();
+        let mut objects = blobs.into_iter().map(Object::Blob).collect::<Vec<_>>();
+        objects.push(Object::Tree(tree.clone()));
+
+        let mut commands = write_pkt_line(format!(
+            "{} {} refs/heads/data\0report-status",
+            HashVersion::Sha1.default(),
+            tree.id
+        ));
+        commands.extend_from_slice(b"0000");
+
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let body = futures_util::stream::iter(vec![Ok(commands.freeze())]).chain(counting_body(
+            pack(&objects),
+            7,
+            pulled.clone(),
+        ));
+        let mut txn = receive_transaction(&repo);
+        txn.receive_pack(payload_stream(body)).await.unwrap();
+
+        assert!(pulled.load(Ordering::SeqCst) > 32);
+        for object in &objects {
+            if let Object::Blob(blob) = object {
+                assert_eq!(
+                    repo.odb.get_blob(&blob.id).await.unwrap().get_data(),
+                    blob.data
+                );
+            }
+        }
+        assert_eq!(
+            repo.refs
+                .get_value_refs("refs/heads/data".to_string())
+                .await
+                .unwrap(),
+            tree.id
+        );
+    }
+}
@@ -1,17 +1,30 @@
 use crate::auth::AccessLevel;
 use crate::callback::CallBack;
+use crate::config::AppConfig;
+use crate::http::{dumb, git_protocol_version};
 use crate::serve::AppCore;
-use crate::transaction::{GitProtoVersion, ProtocolType, Transaction, TransactionService};
-use actix_web::http::header::Header;
+use crate::transaction::{ProtocolType, Transaction, TransactionService};
+use actix_web::http::header::{ETAG, Header, HeaderValue, IF_NONE_MATCH, VARY};
 use actix_web::web::{Data, Path};
 use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use actix_web_httpauth::headers::authorization::{Authorization, Basic};
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
+use tracing::{Instrument, error};
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct RefsQuery {
-    service: TransactionService,
+    service: Option<String>,
+}
+
+/// The smart HTTP service named by `?service=`; only the two pack services may be
+/// requested over `info/refs`.
+fn advertised_service(name: Option<&str>) -> Option<TransactionService> {
+    match name? {
+        "git-upload-pack" => Some(TransactionService::UploadPack),
+        "git-receive-pack" => Some(TransactionService::ReceivePack),
+        _ => None,
+    }
 }
 /// Handle a refs advertisement request for a repository over HTTP.
 ///
@@ -35,6 +48,13 @@ pub async fn refs(
     query: web::Query<RefsQuery>,
 ) -> impl Responder {
     let (namespace, repo_name) = path.into_inner();
+    let Some(service) = advertised_service(query.service.as_deref()) else {
+        // 没有 service 参数的是哑协议客户端
+        if query.service.is_none() && AppConfig::http().dumb_protocol {
+            return dumb::info_refs(req, app, namespace, repo_name).await;
+        }
+        return HttpResponse::BadRequest().body("Unsupported service");
+    };
 
     let start = std::time::Instant::now();
     let repo = match app
@@ -44,13 +64,12 @@ pub async fn refs(
     {
         Ok(repo) => repo,
         Err(err) => {
-            dbg!(err);
-            dbg!(format!("{:?}", start.elapsed()));
+            error!("Repo lookup error after {:?}: {:?}", start.elapsed(), err);
             return HttpResponse::NotFound().body("Repo not found");
         }
     };
     if let Some(auth) = app.auth.clone() {
-        match query.service {
+        match service {
             TransactionService::UploadPack | TransactionService::UploadPackLs => {
                 if !repo.is_public {
                     match Authorization::<Basic>::parse(&req) {
@@ -66,6 +85,7 @@ pub async fn refs(
                                     _ => {}
                                 },
                                 Err(_) => {
+                                    app.metrics.record_auth_failure();
                                     return HttpResponse::Unauthorized()
                                         .insert_header((
                                             "WWW-Authenticate",
@@ -100,6 +120,7 @@ pub async fn refs(
                                 _ => {}
                             },
                             Err(_) => {
+                                app.metrics.record_auth_failure();
                                 return HttpResponse::Unauthorized()
                                     .insert_header((
                                         "WWW-Authenticate",
@@ -118,30 +139,79 @@ pub async fn refs(
             }
         }
     }
-    let version = match req.headers().get("Git-Protocol") {
-        Some(header) => {
-            if header.to_str().unwrap().contains("version=2") {
-                GitProtoVersion::V2
-            } else {
-                GitProtoVersion::V1
-            }
+    let version = git_protocol_version(&req);
+    // 同一组引用按服务和协议版本渲染出不同的广告
+    let variant = format!("{} version={}", service.to_string(), version.to_str());
+    let etag = match repo.refs_etag(&variant).await {
+        Ok(etag) => etag,
+        Err(err) => {
+            error!("Refs etag error: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
         }
-        None => GitProtoVersion::V1,
     };
-    let call_back = CallBack::new(20);
+    if if_none_match(&req, &etag) {
+        return HttpResponse::NotModified()
+            .insert_header((ETAG, etag))
+            .insert_header((VARY, "Git-Protocol"))
+            .finish();
+    }
     let transaction = Transaction {
-        service: query.service.clone(),
+        service,
         repository: repo,
         version,
-        call_back: call_back.clone(),
+        call_back: CallBack::new(20),
         protocol: ProtocolType::Http,
+        timeouts: AppConfig::transaction().into(),
+        pack_limits: AppConfig::cfg().into(),
+        actor: None,
+        maintenance: app.maintenance.clone(),
+        metrics: app.metrics.clone(),
+        fetch_resume: app.fetch_resume.clone(),
+        hooks: app.hooks.clone(),
+        push_limiter: app.transactions.clone(),
+        deny_non_fast_forwards: AppConfig::transaction().deny_non_fast_forwards,
     };
-    match transaction.advertise_refs().await {
-        Ok(_) => {}
-        Err(_) => {}
+    let span = transaction.span(&namespace, &repo_name);
+    let mut resp = advertise(transaction).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        resp.headers_mut().insert(ETAG, value);
     }
+    resp
+}
+
+/// Whether the `If-None-Match` header of `req` lists `etag`, compared weakly.
+fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    let Some(header) = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|x| x.to_str().ok())
+    else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    header
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// Run the ref advertisement of `transaction` and wrap its output, `# service=` line
+/// first, in a non-cacheable `application/x-<service>-advertisement` response.
+async fn advertise(transaction: Transaction) -> HttpResponse {
+    let call_back = transaction.call_back.clone();
+    let service = transaction.service.clone();
+    tokio::spawn(
+        async move {
+            if let Err(err) = transaction.advertise_refs().await {
+                error!("Advertise refs error: {:?}", err);
+                // 出错时也要让下面的读取循环结束
+                let _ = transaction.call_back.send(Bytes::new()).await;
+            }
+        }
+        .in_current_span(),
+    );
     let mut result = BytesMut::new();
-    let mut recv = call_back.receive.lock().await;
+    let mut recv = call_back.subscribe().await;
     while let Some(msg) = recv.recv().await {
         result.extend_from_slice(&msg);
         if msg.is_empty() {
@@ -152,9 +222,10 @@ pub async fn refs(
         .insert_header(("Pragma", "no-cache"))
         .insert_header(("Cache-Control", "no-cache, max-age=0, must-revalidate"))
         .insert_header(("Expires", "Fri, 01 Jan 1980 00:00:00 GMT"))
+        .insert_header((VARY, "Git-Protocol"))
         .insert_header((
             "Content-Type",
-            match query.service {
+            match service {
                 TransactionService::UploadPack | TransactionService::UploadPackLs => {
                     "application/x-git-upload-pack-advertisement"
                 }
@@ -165,3 +236,134 @@ pub async fn refs(
         ))
         .body(result.freeze())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{memory_repository, put_blob, transaction};
+    use crate::serve::testing::{SingleStore, app_core};
+    use crate::transaction::GitProtoVersion;
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_service, init_service};
+
+    async fn advertise_service(
+        service: TransactionService,
+        version: GitProtoVersion,
+    ) -> (String, String) {
+        let repo = memory_repository();
+        let txn = Transaction {
+            service,
+            version,
+            ..transaction(&repo)
+        };
+        let resp = advertise(txn).await;
+        let content_type = resp
+            .headers()
+            .get("Content-Type")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        (content_type, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[tokio::test]
+    async fn test_upload_pack_advertisement() {
+        let (content_type, body) =
+            advertise_service(TransactionService::UploadPack, GitProtoVersion::V1).await;
+        assert_eq!(content_type, "application/x-git-upload-pack-advertisement");
+        assert!(body.starts_with("001e# service=git-upload-pack\n0000000eversion 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_advertisement() {
+        let (content_type, body) =
+            advertise_service(TransactionService::ReceivePack, GitProtoVersion::V1).await;
+        assert_eq!(content_type, "application/x-git-receive-pack-advertisement");
+        assert!(body.starts_with("001f# service=git-receive-pack\n0000000eversion 1\n"));
+        assert!(body.contains(" capabilities^{}\0"));
+        assert!(body.ends_with("0000"));
+    }
+
+    #[tokio::test]
+    async fn test_upload_pack_v2_advertisement() {
+        let (content_type, body) =
+            advertise_service(TransactionService::UploadPack, GitProtoVersion::V2).await;
+        assert_eq!(content_type, "application/x-git-upload-pack-advertisement");
+        assert!(body.starts_with("001e# service=git-upload-pack\n0000000eversion 2\n"));
+        for capability in ["ls-refs", "fetch=", "object-format=sha1", "agent="] {
+            assert!(body.contains(capability), "missing {}", capability);
+        }
+        // v2 不在广告里列出引用
+        assert!(!body.contains(" HEAD"));
+        assert!(body.ends_with("0000"));
+    }
+
+    #[tokio::test]
+    async fn test_if_none_match() {
+        let repo = memory_repository();
+        let etag = repo.refs_etag("git-upload-pack version=1").await.unwrap();
+        let req = TestRequest::default().to_http_request();
+        assert!(!if_none_match(&req, &etag));
+        let req = TestRequest::default()
+            .insert_header(("If-None-Match", etag.as_str()))
+            .to_http_request();
+        assert!(if_none_match(&req, &etag));
+        // 强校验形式和多值列表同样命中
+        let req = TestRequest::default()
+            .insert_header((
+                "If-None-Match",
+                format!("\"other\", {}", etag.trim_start_matches("W/")),
+            ))
+            .to_http_request();
+        assert!(if_none_match(&req, &etag));
+        let req = TestRequest::default()
+            .insert_header(("If-None-Match", "W/\"stale\""))
+            .to_http_request();
+        assert!(!if_none_match(&req, &etag));
+    }
+
+    #[actix_web::test]
+    async fn test_refs_not_modified() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "x").await;
+        repo.refs_insert("refs/heads/main".to_string(), blob)
+            .await
+            .unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(app_core(SingleStore(repo.clone()))))
+                .route("/{namespace}/{repo}/info/refs", web::get().to(refs)),
+        )
+        .await;
+        let etag = repo.refs_etag("git-upload-pack version=2").await.unwrap();
+        let resp = call_service(
+            &app,
+            TestRequest::get()
+                .uri("/team/app/info/refs?service=git-upload-pack")
+                .insert_header(("Git-Protocol", "version=2"))
+                .insert_header(("If-None-Match", etag.as_str()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(resp.headers().get(ETAG).unwrap(), etag.as_str());
+        assert_eq!(resp.headers().get(VARY).unwrap(), "Git-Protocol");
+        // 其他服务或协议版本的广告有各自的 ETag
+        for variant in ["git-upload-pack version=1", "git-receive-pack version=2"] {
+            assert_ne!(repo.refs_etag(variant).await.unwrap(), etag);
+        }
+    }
+
+    #[test]
+    fn test_unknown_service_rejected() {
+        assert!(advertised_service(Some("git-upload-archive")).is_none());
+        assert!(advertised_service(Some("git-upload-pack-ls")).is_none());
+        assert!(advertised_service(None).is_none());
+        assert!(matches!(
+            advertised_service(Some("git-receive-pack")),
+            Some(TransactionService::ReceivePack)
+        ));
+    }
+}
@@ -1,8 +1,10 @@
 use crate::callback::CallBack;
-use crate::error::GitInnerError;
+use crate::config::AppConfig;
+use crate::http::encoding::{ContentEncoding, decode_payload};
+use crate::http::{git_protocol_version, rate_limited};
 use crate::serve::AppCore;
 use crate::transaction::TransactionService::UploadPack;
-use crate::transaction::{GitProtoVersion, ProtocolType, Transaction};
+use crate::transaction::{ProtocolType, Transaction};
 use actix_web::http::header::Header;
 use actix_web::web::Payload;
 use actix_web::{HttpResponse, Responder, web};
@@ -10,7 +12,7 @@ use actix_web_httpauth::headers::authorization::{Authorization, Basic};
 use async_stream::stream;
 use std::io;
 use tokio_stream::StreamExt;
-use tracing::error;
+use tracing::{Instrument, error};
 
 /// Handle an HTTP Git "upload-pack" request for a repository and stream the Git service response.
 ///
@@ -37,12 +39,18 @@ use tracing::error;
 /// }
 /// ```
 pub async fn upload_pack(
-    mut payload: Payload,
+    payload: Payload,
     path: web::Path<(String, String)>,
     app: web::Data<AppCore>,
     req: actix_web::HttpRequest,
 ) -> impl Responder {
+    if let Some(response) = rate_limited(&app, &req) {
+        return response;
+    }
     let (namespace, repo_name) = path.into_inner();
+    let Some(encoding) = ContentEncoding::from_request(&req) else {
+        return HttpResponse::UnsupportedMediaType().body("Unsupported Content-Encoding");
+    };
     let repo = match app
         .repo_store
         .repo(namespace.clone(), repo_name.clone())
@@ -50,7 +58,7 @@ pub async fn upload_pack(
     {
         Ok(repo) => repo,
         Err(err) => {
-            dbg!(err);
+            error!("Repo lookup error: {:?}", err);
             return HttpResponse::NotFound().body("Repo not found");
         }
     };
@@ -69,6 +77,7 @@ pub async fn upload_pack(
                             _ => {}
                         },
                         Err(_) => {
+                            app.metrics.record_auth_failure();
                             return HttpResponse::Unauthorized()
                                 .insert_header(("WWW-Authenticate", r#"Basic realm="Restricted""#))
                                 .body("Unauthorized");
@@ -84,43 +93,46 @@ pub async fn upload_pack(
         }
     }
     let call_back = CallBack::new(1024);
-    let version = match req.headers().get("Git-Protocol") {
-        Some(header) => {
-            if header.to_str().unwrap().contains("version=2") {
-                GitProtoVersion::V2
-            } else {
-                GitProtoVersion::V1
-            }
-        }
-        None => GitProtoVersion::V1,
-    };
+    let version = git_protocol_version(&req);
     let transaction = Transaction {
         service: UploadPack,
         repository: repo,
         version,
         call_back: call_back.clone(),
         protocol: ProtocolType::Http,
+        timeouts: AppConfig::transaction().into(),
+        pack_limits: AppConfig::cfg().into(),
+        actor: None,
+        maintenance: app.maintenance.clone(),
+        metrics: app.metrics.clone(),
+        fetch_resume: app.fetch_resume.clone(),
+        hooks: app.hooks.clone(),
+        push_limiter: app.transactions.clone(),
+        deny_non_fast_forwards: AppConfig::transaction().deny_non_fast_forwards,
     };
     let (tx, rx) = tokio::sync::mpsc::channel(8);
     tokio::task::spawn_local(async move {
-        while let Some(next) = payload.next().await {
-            tx.send(next.map_err(|err| GitInnerError::Payload(err.to_string())))
-                .await
-                .ok();
+        let mut body = decode_payload(encoding, payload, AppConfig::http().max_decoded_body_bytes);
+        while let Some(next) = body.next().await {
+            tx.send(next).await.ok();
         }
     });
-    tokio::task::spawn_local(async move {
-        let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
-        let result = transaction.upload_pack(&mut Box::pin(stream)).await;
-        match result {
-            Ok(_) => {}
-            Err(err) => {
-                error!("Receive pack error: {:?}", err);
+    let span = transaction.span(&namespace, &repo_name);
+    tokio::task::spawn_local(
+        async move {
+            let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
+            let result = transaction.upload_pack(&mut Box::pin(stream)).await;
+            match result {
+                Ok(_) => {}
+                Err(err) => {
+                    error!("Receive pack error: {:?}", err);
+                }
             }
         }
-    });
+        .instrument(span),
+    );
     let stream = stream! {
-        let mut receiver = call_back.receive.lock().await;
+        let mut receiver = call_back.subscribe().await;
         while let Some(next) = receiver.recv().await {
             yield Ok::<_, io::Error>(next);
         }
@@ -11,4 +11,7 @@ pub struct MongoRepository {
     pub hash_version: i32,
     pub default_branch: String,
     pub is_public: bool,
+    /// HEAD names `refs/heads/<default_branch>` even before that branch exists.
+    #[serde(default)]
+    pub symbolic_head: bool,
 }
@@ -1,7 +1,9 @@
+use crate::error::GitInnerError;
 use crate::objects::ObjectTrait;
 use crate::objects::types::ObjectType;
-use crate::sha::{HashValue, HashVersion, Sha};
+use crate::sha::{HashValue, HashVersion, Sha, hash_object};
 use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
 use std::fmt::Display;
 
 #[derive(Eq, Debug, Clone)]
@@ -39,15 +41,71 @@ impl ObjectTrait for Blob {
 
 impl Blob {
     pub fn parse(input: Bytes, version: HashVersion) -> Blob {
-        let mut hash = version.default();
-        hash.update(format!("blob {}\0", input.len()).as_bytes());
-        hash.update(&input);
-        hash.finalize();
         Blob {
-            id: hash,
+            id: hash_object(ObjectType::Blob, &input, version),
             data: input,
         }
     }
+
+    /// Hash a blob of `size` bytes while it streams through, handing every chunk to
+    /// `forward` (e.g. an object store upload) so the content is never held in memory as a
+    /// whole. `size` is needed up front because it is part of the hashed object header; a
+    /// stream that ends up shorter or longer fails with `InvalidData`.
+    pub async fn from_stream<S, F, Fut>(
+        version: HashVersion,
+        size: usize,
+        mut stream: S,
+        mut forward: F,
+    ) -> Result<HashValue, GitInnerError>
+    where
+        S: Stream<Item = Result<Bytes, GitInnerError>> + Unpin,
+        F: FnMut(Bytes) -> Fut,
+        Fut: Future<Output = Result<(), GitInnerError>>,
+    {
+        let mut hash = version.default();
+        hash.update(format!("{} {}\0", ObjectType::Blob, size).as_bytes());
+        let mut read = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            read += chunk.len();
+            if read > size {
+                return Err(GitInnerError::InvalidData);
+            }
+            hash.update(&chunk);
+            forward(chunk).await?;
+        }
+        if read != size {
+            return Err(GitInnerError::InvalidData);
+        }
+        hash.finalize();
+        Ok(hash)
+    }
+}
+
+/// Bytes inspected when guessing whether a blob is binary, same window git uses.
+pub const BINARY_CHECK_LEN: usize = 8000;
+
+impl Blob {
+    /// Git's heuristic: a NUL byte within the first [`BINARY_CHECK_LEN`] bytes means binary.
+    pub fn is_binary(&self) -> bool {
+        self.data[..self.data.len().min(BINARY_CHECK_LEN)].contains(&0)
+    }
+
+    /// Text encoding from the byte order mark, falling back to `UTF-8` for valid UTF-8
+    /// text without one. `None` means binary or unknown.
+    pub fn detect_encoding(&self) -> Option<&'static str> {
+        if self.data.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            Some("UTF-8")
+        } else if self.data.starts_with(&[0xFF, 0xFE]) {
+            Some("UTF-16LE")
+        } else if self.data.starts_with(&[0xFE, 0xFF]) {
+            Some("UTF-16BE")
+        } else if !self.is_binary() && std::str::from_utf8(&self.data).is_ok() {
+            Some("UTF-8")
+        } else {
+            None
+        }
+    }
 }
 
 #[cfg(test)]
@@ -60,4 +118,72 @@ mod tests {
         let blob = Blob::parse(Bytes::from("hello world"), HashVersion::Sha1);
         dbg!(blob);
     }
+
+    #[tokio::test]
+    async fn test_from_stream_matches_parse() {
+        let data = Bytes::from("hello streaming world\n".repeat(100));
+        let chunks = data
+            .chunks(37)
+            .map(|x| Ok(Bytes::copy_from_slice(x)))
+            .collect::<Vec<_>>();
+        let mut forwarded = Vec::new();
+        let id = Blob::from_stream(
+            HashVersion::Sha1,
+            data.len(),
+            futures_util::stream::iter(chunks.clone()),
+            |chunk| {
+                forwarded.extend_from_slice(&chunk);
+                async { Ok(()) }
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(id, Blob::parse(data.clone(), HashVersion::Sha1).id);
+        assert_eq!(forwarded, data);
+
+        // 声明的大小和实际内容不一致
+        let short = Blob::from_stream(
+            HashVersion::Sha1,
+            data.len() + 1,
+            futures_util::stream::iter(chunks),
+            |_| async { Ok(()) },
+        )
+        .await;
+        assert!(matches!(short, Err(GitInnerError::InvalidData)));
+    }
+
+    #[test]
+    fn test_utf8_text() {
+        let blob = Blob::parse(Bytes::from("héllo\nworld\n"), HashVersion::Sha1);
+        assert!(!blob.is_binary());
+        assert_eq!(blob.detect_encoding(), Some("UTF-8"));
+        let bom = Blob::parse(Bytes::from(&b"\xEF\xBB\xBFtext"[..]), HashVersion::Sha1);
+        assert_eq!(bom.detect_encoding(), Some("UTF-8"));
+    }
+
+    #[test]
+    fn test_embedded_nul() {
+        let blob = Blob::parse(Bytes::from(&b"PK\x03\x04\0\0data"[..]), HashVersion::Sha1);
+        assert!(blob.is_binary());
+        assert_eq!(blob.detect_encoding(), None);
+
+        // NUL bytes past the inspected window don't count.
+        let mut data = vec![b'a'; BINARY_CHECK_LEN];
+        data.push(0);
+        assert!(!Blob::parse(Bytes::from(data), HashVersion::Sha1).is_binary());
+    }
+
+    #[test]
+    fn test_utf16_bom() {
+        let mut data = vec![0xFF, 0xFE];
+        for unit in "hi\n".encode_utf16() {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        let blob = Blob::parse(Bytes::from(data), HashVersion::Sha1);
+        assert_eq!(blob.detect_encoding(), Some("UTF-16LE"));
+        // Like git, UTF-16 content counts as binary for diffing.
+        assert!(blob.is_binary());
+        let be = Blob::parse(Bytes::from(&b"\xFE\xFF\0h"[..]), HashVersion::Sha1);
+        assert_eq!(be.detect_encoding(), Some("UTF-16BE"));
+    }
 }
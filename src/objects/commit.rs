@@ -2,7 +2,7 @@ use crate::error::GitInnerError;
 use crate::objects::ObjectTrait;
 use crate::objects::signature::Signature;
 use crate::objects::types::ObjectType;
-use crate::sha::{HashValue, HashVersion};
+use crate::sha::{HashValue, HashVersion, hash_object};
 use bincode::{Decode, Encode};
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
@@ -31,10 +31,7 @@ pub enum ParseError {}
 impl Commit {
     pub fn parse(input: Bytes, version: HashVersion) -> Result<Commit, GitInnerError> {
         // --- 先按原始 bytes 计算 hash（不要因为解析替换 CRLF 而影响哈希） ---
-        let mut hash_prev = Vec::new();
-        hash_prev.extend_from_slice(format!("commit {}\0", input.len()).as_bytes());
-        hash_prev.extend_from_slice(&input);
-        let hash = version.hash(Bytes::from(hash_prev));
+        let hash = hash_object(ObjectType::Commit, &input, version);
         if hash.to_string() == "89830fdb21a8b52d53a8ed1e6d47fa452fbe35af" {
             println!("{:?}", input);
         }
@@ -47,32 +44,26 @@ impl Commit {
             input_str.to_string()
         };
 
-        // --- 定位 header/message 边界（找第一个连续的两个换行） ---
-        let header_end_pos = normalized.find("\n\n").unwrap_or(normalized.len());
-        let header = &normalized[..header_end_pos];
-        let message = if header_end_pos == normalized.len() {
-            ""
-        } else {
-            // 跳过两个换行符
-            &normalized[header_end_pos + 2..]
-        };
-
         // --- 解析 header ---
         let mut tree: Option<HashValue> = None;
         let mut parents: Vec<HashValue> = Vec::new();
         let mut author: Option<Signature> = None;
         let mut committer: Option<Signature> = None;
         let mut gpgsig: Option<String> = None;
+        let mut message = "";
 
         let mut collecting_gpgsig = false;
         let mut gpgsig_lines: Vec<&str> = Vec::new();
 
-        for line in header.split('\n') {
+        let mut offset = 0;
+        for raw_line in normalized.split_inclusive('\n') {
+            offset += raw_line.len();
+            let line = raw_line.strip_suffix('\n').unwrap_or(raw_line);
             if collecting_gpgsig {
-                // 保留原始行（包括可能的前导空格）
+                // 保留原始行（包括可能的前导空格）；签名块内的空行也属于签名，不是 header 结束
                 gpgsig_lines.push(line);
                 // 结束标志可能带前导空格 -> 用 trim_start() 比较
-                if line.trim_start() == "-----END PGP SIGNATURE-----" {
+                if is_signature_end(line) {
                     collecting_gpgsig = false;
                     // 将收集到的行以 '\n' 拼回成一个字符串（不额外添加/去掉前导空格）
                     let sig = gpgsig_lines.join("\n");
@@ -82,6 +73,12 @@ impl Commit {
                 continue;
             }
 
+            // 签名块之外的第一个空行是 header/message 边界
+            if line.is_empty() {
+                message = &normalized[offset..];
+                break;
+            }
+
             if line.starts_with("gpgsig ") {
                 // gpgsig header 行（后续行为 continuation，通常以单空格开头）
                 collecting_gpgsig = true;
@@ -122,8 +119,24 @@ impl Commit {
         })
     }
 }
-impl Display for Commit {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+impl Commit {
+    /// The bytes covered by `gpgsig`: the commit object with the signature header removed.
+    pub fn signed_payload(&self) -> String {
+        let mut payload = String::new();
+        // 写入 String 不会失败
+        let _ = self.write_object(&mut payload, false);
+        payload
+    }
+
+    /// Check `gpgsig` against the signed payload. Unsigned commits verify as `false`.
+    pub fn verify_signature(&self, keyring: &dyn SignatureVerifier) -> Result<bool, GitInnerError> {
+        match &self.gpgsig {
+            Some(gpgsig) => keyring.verify(&gpgsig.armored(), self.signed_payload().as_bytes()),
+            None => Ok(false),
+        }
+    }
+
+    fn write_object(&self, f: &mut dyn fmt::Write, with_gpgsig: bool) -> fmt::Result {
         if let Some(tree) = &self.tree {
             writeln!(f, "tree {}", tree)?;
         }
@@ -133,7 +146,7 @@ impl Display for Commit {
         }
         writeln!(f, "author {}", self.author)?;
         writeln!(f, "committer {}", self.committer)?;
-        if let Some(gpgsig) = &self.gpgsig {
+        if let Some(gpgsig) = self.gpgsig.as_ref().filter(|_| with_gpgsig) {
             let mut parts = gpgsig.signature.split('\n');
             if let Some(first) = parts.next() {
                 writeln!(f, "{}", first)?;
@@ -148,6 +161,39 @@ impl Display for Commit {
     }
 }
 
+impl Gpgsig {
+    /// The ASCII-armored signature without the `gpgsig ` header and continuation indent.
+    pub fn armored(&self) -> String {
+        let mut lines = self.signature.split('\n');
+        let mut armored = Vec::new();
+        if let Some(first) = lines.next() {
+            armored.push(first.strip_prefix("gpgsig ").unwrap_or(first));
+        }
+        for line in lines {
+            armored.push(line.strip_prefix(' ').unwrap_or(line));
+        }
+        let mut armored = armored.join("\n");
+        armored.push('\n');
+        armored
+    }
+}
+
+/// Verifies a detached signature over a commit payload, e.g. backed by a GPG keyring.
+pub trait SignatureVerifier {
+    fn verify(&self, signature: &str, payload: &[u8]) -> Result<bool, GitInnerError>;
+}
+
+fn is_signature_end(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("-----END ") && line.ends_with("-----")
+}
+
+impl Display for Commit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write_object(f, true)
+    }
+}
+
 impl Debug for Commit {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         Display::fmt(self, f)
@@ -173,38 +219,7 @@ mod tests {
     use super::*;
     use crate::sha::HashVersion;
 
-    #[test]
-    fn test_commit_parse_basic() {
-        let commit_data = Bytes::from(
-            "tree 7551d4da2e9c1ae9397c47709253b405fb6b6206\n\
-             parent ee98d64f596ae42fadf9eeae1d0efa22b14b0829\n\
-             author ZhenYi <434836402@qq.com> 1740189120 +0800\n\
-             committer ZhenYi <434836402@qq.com> 1740189120 +0800\n\n\
-             build(deps): Update dependencies and replace poem with actix-web\n",
-        );
-
-        let commit = Commit::parse(commit_data, HashVersion::Sha1).unwrap();
-
-        assert_eq!(
-            commit.tree.as_ref().unwrap().to_string(),
-            "7551d4da2e9c1ae9397c47709253b405fb6b6206"
-        );
-        assert_eq!(commit.parents.len(), 1);
-        assert_eq!(
-            commit.parents[0].to_string(),
-            "ee98d64f596ae42fadf9eeae1d0efa22b14b0829"
-        );
-        assert_eq!(
-            commit.message,
-            "build(deps): Update dependencies and replace poem with actix-web\n"
-        );
-    }
-
-    #[test]
-    fn test_commit_parse_with_gpg_signature() {
-        let commit_data = Bytes::from(
-            br#"
-tree 6dc1b8e401ddab32b91a5ea7979affb3fc92d2f8
+    const GPG_SIGNED_COMMIT: &[u8] = br#"tree 6dc1b8e401ddab32b91a5ea7979affb3fc92d2f8
 parent f1d872891b5a6672183ebd6936dfce09c60d2061
 author taoshengshi <taoshengshi01@gmail.com> 1751768083 +0800
 committer GitHub <noreply@github.com> 1751768083 +0800
@@ -239,10 +254,39 @@ Feat/doc (#189)
 * change picture alignment
 
 * update image
-"#
-            .to_vec(),
+"#;
+
+    #[test]
+    fn test_commit_parse_basic() {
+        let commit_data = Bytes::from(
+            "tree 7551d4da2e9c1ae9397c47709253b405fb6b6206\n\
+             parent ee98d64f596ae42fadf9eeae1d0efa22b14b0829\n\
+             author ZhenYi <434836402@qq.com> 1740189120 +0800\n\
+             committer ZhenYi <434836402@qq.com> 1740189120 +0800\n\n\
+             build(deps): Update dependencies and replace poem with actix-web\n",
         );
 
+        let commit = Commit::parse(commit_data, HashVersion::Sha1).unwrap();
+
+        assert_eq!(
+            commit.tree.as_ref().unwrap().to_string(),
+            "7551d4da2e9c1ae9397c47709253b405fb6b6206"
+        );
+        assert_eq!(commit.parents.len(), 1);
+        assert_eq!(
+            commit.parents[0].to_string(),
+            "ee98d64f596ae42fadf9eeae1d0efa22b14b0829"
+        );
+        assert_eq!(
+            commit.message,
+            "build(deps): Update dependencies and replace poem with actix-web\n"
+        );
+    }
+
+    #[test]
+    fn test_commit_parse_with_gpg_signature() {
+        let commit_data = Bytes::from(GPG_SIGNED_COMMIT.to_vec());
+
         let commit = match Commit::parse(commit_data, HashVersion::Sha1) {
             Ok(c) => c,
             Err(e) => {
@@ -314,6 +358,21 @@ Feat/doc (#189)
         assert_eq!(commit.get_data(), Bytes::from(commit.to_string()));
     }
 
+    #[test]
+    fn test_commit_negative_zero_timezone_round_trip() {
+        let commit_data = Bytes::from(
+            "tree 7551d4da2e9c1ae9397c47709253b405fb6b6206\n\
+             author ZhenYi <434836402@qq.com> 1740189120 -0000\n\
+             committer ZhenYi <434836402@qq.com> 1740189120 -0000\n\n\
+             initial\n",
+        );
+
+        let commit = Commit::parse(commit_data.clone(), HashVersion::Sha1).unwrap();
+
+        assert_eq!(commit.author.tz_offset_minutes, Some(0));
+        assert_eq!(commit.get_data(), commit_data);
+    }
+
     #[test]
     fn test_commit_parse_error_cases() {
         // 测试缺少author的错误情况
@@ -336,4 +395,67 @@ Feat/doc (#189)
         let result2 = Commit::parse(invalid_commit_data2, HashVersion::Sha1);
         assert!(matches!(result2, Err(GitInnerError::MissingCommitter)));
     }
+
+    struct RecordingVerifier {
+        signer_payload: String,
+    }
+
+    impl SignatureVerifier for RecordingVerifier {
+        fn verify(&self, signature: &str, payload: &[u8]) -> Result<bool, GitInnerError> {
+            assert!(signature.starts_with("-----BEGIN PGP SIGNATURE-----\n\nwsFcBAAB"));
+            assert!(signature.ends_with("=b5jG\n-----END PGP SIGNATURE-----\n"));
+            Ok(payload == self.signer_payload.as_bytes())
+        }
+    }
+
+    #[test]
+    fn test_commit_signed_payload() {
+        let commit =
+            Commit::parse(Bytes::from_static(GPG_SIGNED_COMMIT), HashVersion::Sha1).unwrap();
+        assert!(commit.gpgsig.is_some());
+        assert_eq!(commit.author.name, "taoshengshi");
+        assert_eq!(commit.parents.len(), 1);
+
+        // 签名内容 = 原始对象去掉 gpgsig header 及其全部 continuation 行（含空行）
+        let raw = std::str::from_utf8(GPG_SIGNED_COMMIT).unwrap();
+        let mut expected = String::new();
+        let mut in_signature = false;
+        for line in raw.split_inclusive('\n') {
+            if line.starts_with("gpgsig ") {
+                in_signature = true;
+            }
+            if !in_signature {
+                expected.push_str(line);
+            } else if is_signature_end(line.trim_end()) {
+                in_signature = false;
+            }
+        }
+        let payload = commit.signed_payload();
+        assert_eq!(payload, expected);
+        assert!(payload.starts_with("tree 6dc1b8e401ddab32b91a5ea7979affb3fc92d2f8\n"));
+        assert!(!payload.contains("PGP"));
+
+        let verifier = RecordingVerifier {
+            signer_payload: expected,
+        };
+        assert!(commit.verify_signature(&verifier).unwrap());
+    }
+
+    #[test]
+    fn test_verify_unsigned_commit() {
+        let commit = Commit::parse(
+            Bytes::from(
+                "tree 7551d4da2e9c1ae9397c47709253b405fb6b6206\n\
+                 author ZhenYi <434836402@qq.com> 1740189120 +0800\n\
+                 committer ZhenYi <434836402@qq.com> 1740189120 +0800\n\n\
+                 unsigned\n",
+            ),
+            HashVersion::Sha1,
+        )
+        .unwrap();
+        let verifier = RecordingVerifier {
+            signer_payload: String::new(),
+        };
+        assert!(!commit.verify_signature(&verifier).unwrap());
+    }
 }
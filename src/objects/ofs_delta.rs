@@ -176,17 +176,18 @@ impl OfsDelta {
         current_offset: usize,
         hash_version: impl Fn(&Bytes) -> HashValue,
     ) -> Result<Self, GitInnerError> {
-        let mut i = 0;
-        let mut ofs = 0usize;
-        loop {
-            let byte = *input.get(i).ok_or(GitInnerError::UnexpectedEof)?;
+        // 负偏移的 varint 每多一个字节要先加 1，和 git 的 encode_in_pack_object_header 对应
+        let mut byte = *input.first().ok_or(GitInnerError::UnexpectedEof)?;
+        let mut i = 1;
+        let mut ofs = (byte & 0x7F) as usize;
+        while (byte & 0x80) != 0 {
+            byte = *input.get(i).ok_or(GitInnerError::UnexpectedEof)?;
             i += 1;
-            ofs = (ofs << 7) | ((byte & 0x7F) as usize);
-            if (byte & 0x80) == 0 {
-                break;
-            }
+            ofs = ((ofs + 1) << 7) | ((byte & 0x7F) as usize);
         }
-        let absolute_base_offset = current_offset - ofs;
+        let absolute_base_offset = current_offset
+            .checked_sub(ofs)
+            .ok_or(GitInnerError::InvalidDelta)?;
         let delta_data = input.split_off(i);
 
         Ok(OfsDelta::new(
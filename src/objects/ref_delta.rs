@@ -5,7 +5,7 @@ use crate::odb::OdbTransaction;
 use crate::sha::HashValue;
 use bstr::ByteSlice;
 use bytes::{Bytes, BytesMut};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -139,7 +139,101 @@ impl RefDelta {
         Ok(result)
     }
 }
-impl RefDelta {}
+impl RefDelta {
+    /// Encode `target` as a git delta against `base`.
+    ///
+    /// Matches are found by indexing `base` in 16-byte blocks and extending each hit
+    /// forward; unmatched bytes are emitted as insert instructions.
+    pub fn encode_delta(base: &[u8], target: &[u8]) -> Bytes {
+        const BLOCK: usize = 16;
+        const MAX_INSERT: usize = 0x7f;
+        const MAX_COPY: usize = 0xffff;
+
+        let mut out = Vec::new();
+        Self::write_varint(&mut out, base.len());
+        Self::write_varint(&mut out, target.len());
+
+        let mut index: HashMap<&[u8], usize> = HashMap::new();
+        if base.len() >= BLOCK {
+            for offset in (0..=base.len() - BLOCK).step_by(BLOCK) {
+                index.entry(&base[offset..offset + BLOCK]).or_insert(offset);
+            }
+        }
+
+        let mut pending: Vec<u8> = Vec::new();
+        let mut pos = 0usize;
+        while pos < target.len() {
+            let hit = if pos + BLOCK <= target.len() {
+                index.get(&target[pos..pos + BLOCK]).copied()
+            } else {
+                None
+            };
+            let Some(offset) = hit else {
+                pending.push(target[pos]);
+                pos += 1;
+                continue;
+            };
+            let mut len = BLOCK;
+            while offset + len < base.len()
+                && pos + len < target.len()
+                && base[offset + len] == target[pos + len]
+            {
+                len += 1;
+            }
+            Self::flush_insert(&mut out, &mut pending, MAX_INSERT);
+            let mut copied = 0usize;
+            while copied < len {
+                let size = (len - copied).min(MAX_COPY);
+                Self::write_copy(&mut out, offset + copied, size);
+                copied += size;
+            }
+            pos += len;
+        }
+        Self::flush_insert(&mut out, &mut pending, MAX_INSERT);
+        Bytes::from(out)
+    }
+
+    fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                break;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn flush_insert(out: &mut Vec<u8>, pending: &mut Vec<u8>, max_insert: usize) {
+        for chunk in pending.chunks(max_insert) {
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+        pending.clear();
+    }
+
+    fn write_copy(out: &mut Vec<u8>, offset: usize, size: usize) {
+        let mut opcode = 0x80u8;
+        let mut args = Vec::with_capacity(7);
+        for i in 0..4 {
+            let byte = ((offset >> (i * 8)) & 0xff) as u8;
+            if byte != 0 {
+                opcode |= 1 << i;
+                args.push(byte);
+            }
+        }
+        for i in 0..3 {
+            let byte = ((size >> (i * 8)) & 0xff) as u8;
+            if byte != 0 {
+                opcode |= 0x10 << i;
+                args.push(byte);
+            }
+        }
+        out.push(opcode);
+        out.extend_from_slice(&args);
+    }
+}
 
 impl RefDelta {
     pub fn new(
@@ -202,3 +296,25 @@ impl std::fmt::Display for RefDelta {
         writeln!(f, "Size: {}", self.delta_data.len())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_delta_round_trip() {
+        let base = (0..200)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let mut target = base.replace("line 100\n", "changed line\n");
+        target.push_str("appended\n");
+        let delta = RefDelta::encode_delta(base.as_bytes(), target.as_bytes());
+        assert!(delta.len() < target.len() / 4);
+        let result = RefDelta::apply_git_delta(&Bytes::from(base.clone()), &delta).unwrap();
+        assert_eq!(result, Bytes::from(target));
+
+        let unrelated = RefDelta::encode_delta(b"short", b"something else entirely");
+        let result = RefDelta::apply_git_delta(&Bytes::from_static(b"short"), &unrelated).unwrap();
+        assert_eq!(result, Bytes::from_static(b"something else entirely"));
+    }
+}
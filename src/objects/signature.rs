@@ -57,7 +57,10 @@ pub struct Signature {
     pub name: String,
     pub email: String,
     pub timestamp: usize,
+    /// The timezone token exactly as it appeared in the object, written back unchanged.
     pub timezone: String,
+    /// The timezone parsed into a signed minute offset, `None` when the token is not `±HHMM`.
+    pub tz_offset_minutes: Option<i32>,
 }
 
 impl Display for Signature {
@@ -84,6 +87,7 @@ impl Default for Signature {
             email: "".to_string(),
             timestamp: 0,
             timezone: "".to_string(),
+            tz_offset_minutes: None,
         }
     }
 }
@@ -131,6 +135,8 @@ impl Signature {
         };
 
         let timezone = unsafe { sign[timestamp_split + 1..].to_str_unchecked().to_string() };
+        // 无法解析的时区仍然原样保留，只是不提供偏移量
+        let tz_offset_minutes = parse_tz_offset(&timezone).ok();
 
         Ok(Signature {
             signature_type,
@@ -138,6 +144,7 @@ impl Signature {
             email,
             timestamp,
             timezone,
+            tz_offset_minutes,
         })
     }
 
@@ -166,18 +173,105 @@ impl Signature {
 
         let offset = local_time.offset().fix().local_minus_utc();
 
-        let hours = offset / 60 / 60;
-
-        let minutes = offset / 60 % 60;
-
-        let offset_str = format!("{hours:+03}{minutes:02}");
-
         Signature {
             signature_type: sign_type,
             name: author,
             email,
             timestamp: chrono::Utc::now().timestamp() as usize,
-            timezone: offset_str,
+            timezone: format_tz_offset(offset / 60),
+            tz_offset_minutes: Some(offset / 60),
         }
     }
 }
+
+/// Parse a git timezone token such as `+0800` or `-0430` into an offset in minutes.
+pub fn parse_tz_offset(s: &str) -> Result<i32, GitInnerError> {
+    let s = s.trim();
+    let bytes = s.as_bytes();
+    if bytes.len() != 5 || !bytes[1..].iter().all(|b| b.is_ascii_digit()) {
+        return Err(GitInnerError::InvalidTimestamp);
+    }
+    let sign = match bytes[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return Err(GitInnerError::InvalidTimestamp),
+    };
+    let hours = s[1..3]
+        .parse::<i32>()
+        .map_err(|_| GitInnerError::InvalidTimestamp)?;
+    let minutes = s[3..5]
+        .parse::<i32>()
+        .map_err(|_| GitInnerError::InvalidTimestamp)?;
+    Ok(sign * (hours * 60 + minutes))
+}
+
+/// Format an offset in minutes back into the `+HHMM` form used in git objects.
+pub fn format_tz_offset(offset_minutes: i32) -> String {
+    let sign = if offset_minutes < 0 { '-' } else { '+' };
+    let abs = offset_minutes.unsigned_abs();
+    format!("{}{:02}{:02}", sign, abs / 60, abs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(line: &str, offset: Option<i32>) {
+        let sign = Signature::from_data(line.as_bytes().to_vec()).unwrap();
+        assert_eq!(sign.tz_offset_minutes, offset);
+        assert_eq!(
+            format!("{} {}", sign.signature_type, sign),
+            line.to_string()
+        );
+        assert_eq!(sign.to_data().unwrap(), line.as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_signature_positive_offset() {
+        round_trip(
+            "author ZhenYi <434836402@qq.com> 1740189120 +0800",
+            Some(480),
+        );
+    }
+
+    #[test]
+    fn test_signature_negative_offset() {
+        round_trip(
+            "committer Test <test@example.com> 1740189120 -0430",
+            Some(-270),
+        );
+    }
+
+    #[test]
+    fn test_signature_zero_offset() {
+        round_trip("tagger Test <test@example.com> 1740189120 +0000", Some(0));
+    }
+
+    #[test]
+    fn test_signature_negative_zero_offset() {
+        round_trip(
+            "committer Test <test@example.com> 1740189120 -0000",
+            Some(0),
+        );
+    }
+
+    #[test]
+    fn test_signature_unparsed_timezone_kept() {
+        round_trip("author Test <test@example.com> 1740189120 0800", None);
+        round_trip("author Test <test@example.com> 1740189120 +08000", None);
+    }
+
+    #[test]
+    fn test_parse_tz_offset_invalid() {
+        assert!(parse_tz_offset("0800").is_err());
+        assert!(parse_tz_offset("+08:00").is_err());
+        assert!(parse_tz_offset("*0800").is_err());
+    }
+
+    #[test]
+    fn test_format_tz_offset() {
+        assert_eq!(format_tz_offset(480), "+0800");
+        assert_eq!(format_tz_offset(-270), "-0430");
+        assert_eq!(format_tz_offset(0), "+0000");
+    }
+}
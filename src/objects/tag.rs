@@ -2,7 +2,7 @@ use crate::error::GitInnerError;
 use crate::objects::ObjectTrait;
 use crate::objects::signature::Signature;
 use crate::objects::types::ObjectType;
-use crate::sha::{HashValue, HashVersion};
+use crate::sha::{HashValue, HashVersion, hash_object};
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -49,18 +49,14 @@ impl Tag {
             } else if line.starts_with("tag ") {
                 tag_name = Some(line["tag ".len()..].trim().to_string());
             } else if line.starts_with("tagger ") {
-                let tagger_data = line["tagger ".len()..].trim();
-                tagger = Signature::from_data(tagger_data.as_bytes().to_vec()).ok();
+                tagger = Signature::from_data(line.trim().as_bytes().to_vec()).ok();
             }
         }
         let object_hash = object_hash.ok_or(GitInnerError::MissingField("object"))?;
         let object_type = object_type.ok_or(GitInnerError::MissingField("type"))?;
         let tag_name = tag_name.ok_or(GitInnerError::MissingField("tag"))?;
         let tagger = tagger.ok_or(GitInnerError::MissingField("tagger"))?;
-        let mut hash_input = Vec::new();
-        hash_input.extend_from_slice(format!("tag {}\0", input.len()).as_bytes());
-        hash_input.extend_from_slice(&input);
-        let id = hash_version.hash(Bytes::from(hash_input));
+        let id = hash_object(ObjectType::Tag, &input, hash_version);
         Ok(Tag {
             id,
             object_hash,
@@ -1,7 +1,7 @@
 use crate::error::GitInnerError;
 use crate::objects::ObjectTrait;
 use crate::objects::types::ObjectType;
-use crate::sha::{HashValue, HashVersion};
+use crate::sha::{HashValue, HashVersion, hash_object};
 use bincode::{Decode, Encode};
 use bytes::{Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
@@ -59,6 +59,16 @@ impl TreeItemMode {
     pub fn to_str(self) -> &'static str {
         std::str::from_utf8(self.to_bytes()).unwrap()
     }
+
+    /// Symbolic link entry; its blob holds the link target path.
+    pub fn is_symlink(self) -> bool {
+        self == TreeItemMode::Link
+    }
+
+    /// Submodule (gitlink) entry; its id is a commit in another repository.
+    pub fn is_submodule(self) -> bool {
+        self == TreeItemMode::Commit
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize, Hash)]
@@ -85,14 +95,7 @@ impl TreeItem {
         bytes.push(b' ');
         bytes.extend_from_slice(self.name.as_bytes());
         bytes.push(0);
-        let raw = self.id.raw();
-        let raw_bytes = match raw.len() {
-            20 | 32 => raw.clone(),
-            40 | 64 => hex::decode(raw).expect("invalid hex hash string"),
-            len => panic!("unexpected hash length: {}", len),
-        };
-
-        bytes.extend_from_slice(&raw_bytes);
+        bytes.extend_from_slice(self.id.as_bytes());
         bytes
     }
 }
@@ -117,10 +120,12 @@ impl Display for Tree {
                 "{} {} {}\t{}",
                 item.mode.to_str(),
                 match item.mode {
-                    TreeItemMode::Blob | TreeItemMode::BlobExecutable => "blob",
+                    // 符号链接的对象类型仍是 blob，与 `git ls-tree` 一致
+                    TreeItemMode::Blob | TreeItemMode::BlobExecutable | TreeItemMode::Link => {
+                        "blob"
+                    }
                     TreeItemMode::Tree => "tree",
                     TreeItemMode::Commit => "commit",
-                    TreeItemMode::Link => "link",
                 },
                 item.id,
                 item.name
@@ -174,13 +179,15 @@ impl Tree {
                 .map_err(|_| GitInnerError::InvalidTreeItem("Filename not UTF-8".into()))?;
 
             pos += null_pos + 1;
-            if pos + 20 > input_len {
+            let hash_len = hash_version.len();
+            if pos + hash_len > input_len {
                 return Err(GitInnerError::InvalidTreeItem(
                     "Tree item hash truncated".into(),
                 ));
             }
-            let id = HashValue::from_bytes(&BytesMut::from(&input[pos..pos + 20])).unwrap();
-            pos += 20;
+            let id = HashValue::from_bytes(&BytesMut::from(&input[pos..pos + hash_len]))
+                .ok_or_else(|| GitInnerError::InvalidTreeItem("Invalid tree item hash".into()))?;
+            pos += hash_len;
 
             tree_items.push(TreeItem::new(mode, id, name));
         }
@@ -192,11 +199,75 @@ impl Tree {
             )));
         }
 
-        let mut hash_input = Vec::new();
-        hash_input.extend_from_slice(format!("tree {}\0", input_len).as_bytes());
-        hash_input.extend_from_slice(&input);
-        let id = hash_version.hash(Bytes::from(hash_input));
+        let id = hash_object(ObjectType::Tree, &input, hash_version);
 
         Ok(Tree { id, tree_items })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree_data(items: &[TreeItem]) -> Bytes {
+        let mut data = Vec::new();
+        for item in items {
+            data.extend_from_slice(&item.to_data());
+        }
+        Bytes::from(data)
+    }
+
+    #[test]
+    fn test_parse_sha256_tree() {
+        let blob = HashVersion::Sha256.hash(Bytes::from("blob"));
+        let sub = HashVersion::Sha256.hash(Bytes::from("tree"));
+        let items = vec![
+            TreeItem::new(TreeItemMode::Blob, blob.clone(), "README".to_string()),
+            TreeItem::new(TreeItemMode::Tree, sub.clone(), "src".to_string()),
+        ];
+        let tree = Tree::parse(tree_data(&items), HashVersion::Sha256).unwrap();
+        assert_eq!(tree.tree_items, items);
+        assert_eq!(tree.id.get_version(), HashVersion::Sha256);
+        assert_eq!(tree.get_data(), tree_data(&items));
+    }
+
+    #[test]
+    fn test_parse_truncated_hash() {
+        let blob = HashVersion::Sha256.hash(Bytes::from("blob"));
+        let item = TreeItem::new(TreeItemMode::Blob, blob, "README".to_string());
+        let data = tree_data(&[item]);
+        let truncated = data.slice(..data.len() - 4);
+        assert!(matches!(
+            Tree::parse(truncated, HashVersion::Sha256),
+            Err(GitInnerError::InvalidTreeItem(_))
+        ));
+        // A SHA-256 entry read as SHA-1 leaves trailing bytes that do not form an entry.
+        assert!(Tree::parse(data, HashVersion::Sha1).is_err());
+    }
+
+    #[test]
+    fn test_symlink_round_trip() {
+        let target = HashVersion::Sha1.hash(Bytes::from("target"));
+        let item = TreeItem::new(TreeItemMode::Link, target.clone(), "link".to_string());
+        assert!(item.to_data().starts_with(b"120000 link\0"));
+        let tree = Tree::parse(tree_data(&[item.clone()]), HashVersion::Sha1).unwrap();
+        assert_eq!(tree.tree_items, vec![item]);
+        assert!(tree.tree_items[0].mode.is_symlink());
+        assert_eq!(tree.get_data(), tree_data(&tree.tree_items));
+        assert_eq!(tree.to_string(), format!("120000 blob {}\tlink\n", target));
+    }
+
+    #[test]
+    fn test_to_data_matches_git() {
+        // `printf 'hello world\n' > hello.txt && git add hello.txt && git write-tree`
+        let blob = HashValue::from_str("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").unwrap();
+        let item = TreeItem::new(TreeItemMode::Blob, blob.clone(), "hello.txt".to_string());
+        let data = item.to_data();
+        assert!(data.ends_with(&hex::decode(blob.to_string()).unwrap()));
+        let tree = Tree::parse(tree_data(&[item]), HashVersion::Sha1).unwrap();
+        assert_eq!(
+            tree.id.to_string(),
+            "68aba62e560c0ebc3396e8ae9335232cd93a3f60"
+        );
+    }
+}
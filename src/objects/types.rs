@@ -1,6 +1,5 @@
-use crate::sha::{HashValue, HashVersion};
-use bytes::Bytes;
-use bytes::BytesMut;
+use crate::error::GitInnerError;
+use crate::sha::{HashValue, HashVersion, hash_object};
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
@@ -18,9 +17,7 @@ pub enum ObjectType {
 
 impl ObjectType {
     pub fn hash_value(&self, hash_version: HashVersion, data: &[u8]) -> HashValue {
-        let mut start = BytesMut::from(self.to_raw());
-        start.extend_from_slice(data);
-        hash_version.hash(Bytes::from(start))
+        hash_object(*self, data, hash_version)
     }
 }
 
@@ -49,6 +46,20 @@ impl ObjectType {
         }
     }
 
+    /// The type encoded in the 3-bit type field of a pack entry header.
+    pub fn from_pack_type(id: u8) -> Result<Self, GitInnerError> {
+        match ObjectType::from_u8(id) {
+            ObjectType::Unknown => Err(GitInnerError::InvalidData),
+            object_type => Ok(object_type),
+        }
+    }
+
+    /// The 3-bit type field written into a pack entry header. `Unknown` has no pack type
+    /// and maps to the invalid id 0.
+    pub fn to_pack_type(&self) -> u8 {
+        self.to_u8()
+    }
+
     pub fn from_str(value: &str) -> Self {
         match value {
             "commit" => ObjectType::Commit,
@@ -83,3 +94,28 @@ impl Display for ObjectType {
         write!(f, "{}", self.to_str())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_type_round_trip() {
+        for id in [1, 2, 3, 4, 6, 7] {
+            let object_type = ObjectType::from_pack_type(id).unwrap();
+            assert_eq!(object_type.to_pack_type(), id);
+        }
+        assert_eq!(ObjectType::from_pack_type(1).unwrap(), ObjectType::Commit);
+        assert_eq!(ObjectType::from_pack_type(7).unwrap(), ObjectType::RefDelta);
+    }
+
+    #[test]
+    fn test_invalid_pack_type() {
+        for id in [0, 5, 8, 0xFF] {
+            assert!(matches!(
+                ObjectType::from_pack_type(id),
+                Err(GitInnerError::InvalidData)
+            ));
+        }
+    }
+}
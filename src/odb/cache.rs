@@ -0,0 +1,119 @@
+use crate::error::GitInnerError;
+use crate::objects::blob::Blob;
+use crate::objects::commit::Commit;
+use crate::objects::tag::Tag;
+use crate::objects::tree::Tree;
+use crate::odb::{Odb, OdbObjectMeta, OdbStats, OdbTransaction};
+use crate::sha::HashValue;
+use async_trait::async_trait;
+use lru::LruCache;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+/// Odb wrapper keeping the most recently read commits in memory. Commits are immutable, so
+/// cached entries never need invalidating; everything else goes straight to the inner odb.
+pub struct CachedOdb {
+    inner: Arc<Box<dyn Odb>>,
+    commits: Mutex<LruCache<HashValue, Commit>>,
+}
+
+impl CachedOdb {
+    pub fn new(inner: Arc<Box<dyn Odb>>, capacity: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            commits: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+#[async_trait]
+impl Odb for CachedOdb {
+    async fn put_commit(&self, commit: &Commit) -> Result<HashValue, GitInnerError> {
+        self.inner.put_commit(commit).await
+    }
+
+    async fn get_commit(&self, hash: &HashValue) -> Result<Commit, GitInnerError> {
+        if let Some(commit) = self.commits.lock().unwrap().get(hash) {
+            return Ok(commit.clone());
+        }
+        let commit = self.inner.get_commit(hash).await?;
+        self.commits
+            .lock()
+            .unwrap()
+            .put(hash.clone(), commit.clone());
+        Ok(commit)
+    }
+
+    async fn has_commit(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        if self.commits.lock().unwrap().contains(hash) {
+            return Ok(true);
+        }
+        self.inner.has_commit(hash).await
+    }
+
+    async fn put_tag(&self, tag: &Tag) -> Result<HashValue, GitInnerError> {
+        self.inner.put_tag(tag).await
+    }
+
+    async fn get_tag(&self, hash: &HashValue) -> Result<Tag, GitInnerError> {
+        self.inner.get_tag(hash).await
+    }
+
+    async fn has_tag(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.inner.has_tag(hash).await
+    }
+
+    async fn put_tree(&self, tree: &Tree) -> Result<HashValue, GitInnerError> {
+        self.inner.put_tree(tree).await
+    }
+
+    async fn get_tree(&self, hash: &HashValue) -> Result<Tree, GitInnerError> {
+        self.inner.get_tree(hash).await
+    }
+
+    async fn has_tree(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.inner.has_tree(hash).await
+    }
+
+    async fn put_blob(&self, blob: Blob) -> Result<HashValue, GitInnerError> {
+        self.inner.put_blob(blob).await
+    }
+
+    async fn get_blob(&self, hash: &HashValue) -> Result<Blob, GitInnerError> {
+        self.inner.get_blob(hash).await
+    }
+
+    async fn has_blob(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.inner.has_blob(hash).await
+    }
+
+    async fn has_objects(
+        &self,
+        hashes: &[HashValue],
+    ) -> Result<HashMap<HashValue, bool>, GitInnerError> {
+        self.inner.has_objects(hashes).await
+    }
+
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Vec<HashValue>, GitInnerError> {
+        self.inner.find_by_prefix(prefix).await
+    }
+
+    async fn stats(&self) -> Result<OdbStats, GitInnerError> {
+        self.inner.stats().await
+    }
+
+    async fn list_objects(&self) -> Result<Vec<OdbObjectMeta>, GitInnerError> {
+        self.inner.list_objects().await
+    }
+
+    async fn delete_object(&self, object: &OdbObjectMeta) -> Result<(), GitInnerError> {
+        // gc 删除的提交不能再从缓存里读到
+        self.commits.lock().unwrap().pop(&object.hash);
+        self.inner.delete_object(object).await
+    }
+
+    async fn begin_transaction(&self) -> Result<Box<dyn OdbTransaction>, GitInnerError> {
+        self.inner.begin_transaction().await
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::GitInnerError;
+use crate::objects::blob::Blob;
+use crate::objects::commit::Commit;
+use crate::objects::tag::Tag;
+use crate::objects::tree::Tree;
+use crate::odb::{Odb, OdbObjectMeta, OdbStats, OdbTransaction};
+use crate::sha::HashValue;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+
+/// Wraps an [`OdbTransaction`] and aborts it when dropped without a commit or abort, so a
+/// `?` between `begin_transaction` and `commit` cannot leak its session and quarantine.
+///
+/// `Drop` cannot await, so the abort is handed to a task on the current tokio runtime.
+pub struct TransactionGuard {
+    inner: Option<Box<dyn OdbTransaction>>,
+    finished: AtomicBool,
+}
+
+impl TransactionGuard {
+    pub fn new(inner: Box<dyn OdbTransaction>) -> Self {
+        Self {
+            inner: Some(inner),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    fn inner(&self) -> &dyn OdbTransaction {
+        // 只有 Drop 会取走 inner
+        self.inner.as_deref().expect("transaction used after drop")
+    }
+}
+
+impl Drop for TransactionGuard {
+    fn drop(&mut self) {
+        if self.finished.load(Ordering::Acquire) {
+            return;
+        }
+        let Some(inner) = self.inner.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!("odb transaction dropped outside a runtime, leaving it to the quarantine sweep");
+            return;
+        };
+        runtime.spawn(async move {
+            if let Err(err) = inner.abort().await {
+                warn!("abort of dropped odb transaction failed: {:?}", err);
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Odb for TransactionGuard {
+    async fn put_commit(&self, commit: &Commit) -> Result<HashValue, GitInnerError> {
+        self.inner().put_commit(commit).await
+    }
+
+    async fn get_commit(&self, hash: &HashValue) -> Result<Commit, GitInnerError> {
+        self.inner().get_commit(hash).await
+    }
+
+    async fn has_commit(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.inner().has_commit(hash).await
+    }
+
+    async fn put_tag(&self, tag: &Tag) -> Result<HashValue, GitInnerError> {
+        self.inner().put_tag(tag).await
+    }
+
+    async fn get_tag(&self, hash: &HashValue) -> Result<Tag, GitInnerError> {
+        self.inner().get_tag(hash).await
+    }
+
+    async fn has_tag(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.inner().has_tag(hash).await
+    }
+
+    async fn put_tree(&self, tree: &Tree) -> Result<HashValue, GitInnerError> {
+        self.inner().put_tree(tree).await
+    }
+
+    async fn get_tree(&self, hash: &HashValue) -> Result<Tree, GitInnerError> {
+        self.inner().get_tree(hash).await
+    }
+
+    async fn has_tree(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.inner().has_tree(hash).await
+    }
+
+    async fn put_blob(&self, blob: Blob) -> Result<HashValue, GitInnerError> {
+        self.inner().put_blob(blob).await
+    }
+
+    async fn get_blob(&self, hash: &HashValue) -> Result<Blob, GitInnerError> {
+        self.inner().get_blob(hash).await
+    }
+
+    async fn has_blob(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.inner().has_blob(hash).await
+    }
+
+    async fn has_objects(
+        &self,
+        hashes: &[HashValue],
+    ) -> Result<HashMap<HashValue, bool>, GitInnerError> {
+        self.inner().has_objects(hashes).await
+    }
+
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Vec<HashValue>, GitInnerError> {
+        self.inner().find_by_prefix(prefix).await
+    }
+
+    async fn stats(&self) -> Result<OdbStats, GitInnerError> {
+        self.inner().stats().await
+    }
+
+    async fn list_objects(&self) -> Result<Vec<OdbObjectMeta>, GitInnerError> {
+        self.inner().list_objects().await
+    }
+
+    async fn delete_object(&self, object: &OdbObjectMeta) -> Result<(), GitInnerError> {
+        self.inner().delete_object(object).await
+    }
+
+    async fn begin_transaction(&self) -> Result<Box<dyn OdbTransaction>, GitInnerError> {
+        self.inner().begin_transaction().await
+    }
+}
+
+#[async_trait]
+impl OdbTransaction for TransactionGuard {
+    async fn commit(&self) -> Result<(), GitInnerError> {
+        self.inner().commit().await?;
+        self.finished.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    async fn abort(&self) -> Result<(), GitInnerError> {
+        // 失败的回滚也不再重试，遗留的暂存目录交给定期清理
+        self.finished.store(true, Ordering::Release);
+        self.inner().abort().await
+    }
+
+    async fn rollback(&self) -> Result<(), GitInnerError> {
+        self.finished.store(true, Ordering::Release);
+        self.inner().rollback().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::odb::memory::{OdbMemory, OdbMemoryTransaction};
+    use crate::sha::HashVersion;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    fn guarded(odb: &OdbMemory) -> (TransactionGuard, OdbMemoryTransaction) {
+        let txn = OdbMemoryTransaction {
+            parent: odb.clone(),
+            staging: OdbMemory::default(),
+        };
+        (TransactionGuard::new(Box::new(txn.clone())), txn)
+    }
+
+    #[tokio::test]
+    async fn test_dropped_guard_aborts() {
+        let odb = OdbMemory::default();
+        let (guard, txn) = guarded(&odb);
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        guard.put_blob(blob.clone()).await.unwrap();
+        assert!(txn.staging.has_blob(&blob.id).await.unwrap());
+        drop(guard);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while odb.aborts.load(Ordering::Relaxed) == 0 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(!txn.staging.has_blob(&blob.id).await.unwrap());
+        assert!(!odb.has_blob(&blob.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_committed_guard_does_not_abort() {
+        let odb = OdbMemory::default();
+        let (guard, _) = guarded(&odb);
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        guard.put_blob(blob.clone()).await.unwrap();
+        guard.commit().await.unwrap();
+        drop(guard);
+
+        tokio::task::yield_now().await;
+        assert_eq!(odb.aborts.load(Ordering::Relaxed), 0);
+        assert!(odb.has_blob(&blob.id).await.unwrap());
+    }
+}
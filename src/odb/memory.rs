@@ -0,0 +1,415 @@
+use crate::error::GitInnerError;
+use crate::objects::ObjectTrait;
+use crate::objects::blob::Blob;
+use crate::objects::commit::Commit;
+use crate::objects::tag::Tag;
+use crate::objects::tree::Tree;
+use crate::objects::types::ObjectType;
+use crate::odb::{Odb, OdbObjectMeta, OdbStats, OdbTransaction};
+use crate::sha::HashValue;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+pub struct MemoryObjects {
+    pub commits: HashMap<HashValue, Commit>,
+    pub trees: HashMap<HashValue, Tree>,
+    pub tags: HashMap<HashValue, Tag>,
+    pub blobs: HashMap<HashValue, Blob>,
+    /// Unix time each object was first stored; tests rewrite it to age objects.
+    pub created_at: HashMap<HashValue, i64>,
+}
+
+impl MemoryObjects {
+    fn hashes(&self) -> impl Iterator<Item = &HashValue> {
+        self.commits
+            .keys()
+            .chain(self.trees.keys())
+            .chain(self.tags.keys())
+            .chain(self.blobs.keys())
+    }
+
+    fn touch(&mut self, hash: &HashValue) {
+        self.created_at
+            .entry(hash.clone())
+            .or_insert_with(|| chrono::Utc::now().timestamp());
+    }
+
+    fn meta(&self, hash: &HashValue, object_type: ObjectType, size: usize) -> OdbObjectMeta {
+        OdbObjectMeta {
+            hash: hash.clone(),
+            object_type,
+            size: size as u64,
+            created_at: self.created_at.get(hash).copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// In-memory object database used by unit tests.
+#[derive(Clone, Default)]
+pub struct OdbMemory {
+    pub objects: Arc<Mutex<MemoryObjects>>,
+    /// Number of `get_commit` calls, for asserting how much a walk reads.
+    pub commit_reads: Arc<AtomicUsize>,
+    /// Number of `get_tree` calls.
+    pub tree_reads: Arc<AtomicUsize>,
+    /// Number of transactions aborted against this store.
+    pub aborts: Arc<AtomicUsize>,
+    /// Number of existence lookups, a batched `has_objects` counting as one.
+    pub exists_queries: Arc<AtomicUsize>,
+    /// Bytes of blob data returned by `get_blob` that are still referenced somewhere.
+    pub live_blob_bytes: Arc<AtomicUsize>,
+    /// Highest value `live_blob_bytes` has reached.
+    pub peak_blob_bytes: Arc<AtomicUsize>,
+}
+
+/// Blob data handed out by [`OdbMemory::get_blob`], counted in `live_blob_bytes` until the
+/// last reference to it is dropped.
+struct LiveBlobData {
+    data: Bytes,
+    live: Arc<AtomicUsize>,
+}
+
+impl LiveBlobData {
+    fn track(data: Bytes, live: &Arc<AtomicUsize>, peak: &AtomicUsize) -> Bytes {
+        let now = live.fetch_add(data.len(), Ordering::SeqCst) + data.len();
+        peak.fetch_max(now, Ordering::SeqCst);
+        Bytes::from_owner(LiveBlobData {
+            data,
+            live: live.clone(),
+        })
+    }
+}
+
+impl AsRef<[u8]> for LiveBlobData {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for LiveBlobData {
+    fn drop(&mut self) {
+        self.live.fetch_sub(self.data.len(), Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl Odb for OdbMemory {
+    async fn put_commit(&self, commit: &Commit) -> Result<HashValue, GitInnerError> {
+        let mut objects = self.objects.lock().unwrap();
+        objects.commits.insert(commit.hash.clone(), commit.clone());
+        objects.touch(&commit.hash);
+        Ok(commit.hash.clone())
+    }
+
+    async fn get_commit(&self, hash: &HashValue) -> Result<Commit, GitInnerError> {
+        self.commit_reads.fetch_add(1, Ordering::Relaxed);
+        let objects = self.objects.lock().unwrap();
+        objects
+            .commits
+            .get(hash)
+            .cloned()
+            .ok_or(GitInnerError::ObjectNotFound(hash.clone()))
+    }
+
+    async fn has_commit(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.exists_queries.fetch_add(1, Ordering::Relaxed);
+        Ok(self.objects.lock().unwrap().commits.contains_key(hash))
+    }
+
+    async fn put_tag(&self, tag: &Tag) -> Result<HashValue, GitInnerError> {
+        let mut objects = self.objects.lock().unwrap();
+        objects.tags.insert(tag.id.clone(), tag.clone());
+        objects.touch(&tag.id);
+        Ok(tag.id.clone())
+    }
+
+    async fn get_tag(&self, hash: &HashValue) -> Result<Tag, GitInnerError> {
+        let objects = self.objects.lock().unwrap();
+        objects
+            .tags
+            .get(hash)
+            .cloned()
+            .ok_or(GitInnerError::ObjectNotFound(hash.clone()))
+    }
+
+    async fn has_tag(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.exists_queries.fetch_add(1, Ordering::Relaxed);
+        Ok(self.objects.lock().unwrap().tags.contains_key(hash))
+    }
+
+    async fn put_tree(&self, tree: &Tree) -> Result<HashValue, GitInnerError> {
+        let mut objects = self.objects.lock().unwrap();
+        objects.trees.insert(tree.id.clone(), tree.clone());
+        objects.touch(&tree.id);
+        Ok(tree.id.clone())
+    }
+
+    async fn get_tree(&self, hash: &HashValue) -> Result<Tree, GitInnerError> {
+        self.tree_reads.fetch_add(1, Ordering::Relaxed);
+        let objects = self.objects.lock().unwrap();
+        objects
+            .trees
+            .get(hash)
+            .cloned()
+            .ok_or(GitInnerError::ObjectNotFound(hash.clone()))
+    }
+
+    async fn has_tree(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.exists_queries.fetch_add(1, Ordering::Relaxed);
+        Ok(self.objects.lock().unwrap().trees.contains_key(hash))
+    }
+
+    async fn put_blob(&self, blob: Blob) -> Result<HashValue, GitInnerError> {
+        let mut objects = self.objects.lock().unwrap();
+        let id = blob.id.clone();
+        objects.blobs.insert(id.clone(), blob);
+        objects.touch(&id);
+        Ok(id)
+    }
+
+    async fn get_blob(&self, hash: &HashValue) -> Result<Blob, GitInnerError> {
+        let objects = self.objects.lock().unwrap();
+        let blob = objects
+            .blobs
+            .get(hash)
+            .ok_or(GitInnerError::ObjectNotFound(hash.clone()))?;
+        Ok(Blob {
+            id: blob.id.clone(),
+            data: LiveBlobData::track(
+                blob.data.clone(),
+                &self.live_blob_bytes,
+                &self.peak_blob_bytes,
+            ),
+        })
+    }
+
+    async fn has_blob(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        self.exists_queries.fetch_add(1, Ordering::Relaxed);
+        Ok(self.objects.lock().unwrap().blobs.contains_key(hash))
+    }
+
+    async fn has_objects(
+        &self,
+        hashes: &[HashValue],
+    ) -> Result<HashMap<HashValue, bool>, GitInnerError> {
+        self.exists_queries.fetch_add(1, Ordering::Relaxed);
+        let objects = self.objects.lock().unwrap();
+        Ok(hashes
+            .iter()
+            .map(|hash| {
+                let exists = objects.commits.contains_key(hash)
+                    || objects.trees.contains_key(hash)
+                    || objects.tags.contains_key(hash)
+                    || objects.blobs.contains_key(hash);
+                (hash.clone(), exists)
+            })
+            .collect())
+    }
+
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Vec<HashValue>, GitInnerError> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects
+            .hashes()
+            .filter(|x| x.to_string().starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    async fn stats(&self) -> Result<OdbStats, GitInnerError> {
+        let objects = self.objects.lock().unwrap();
+        Ok(OdbStats {
+            commits: objects.commits.len() as u64,
+            trees: objects.trees.len() as u64,
+            tags: objects.tags.len() as u64,
+            blobs: objects.blobs.len() as u64,
+            blob_bytes: objects.blobs.values().map(|x| x.data.len() as u64).sum(),
+        })
+    }
+
+    async fn list_objects(&self) -> Result<Vec<OdbObjectMeta>, GitInnerError> {
+        let objects = self.objects.lock().unwrap();
+        let mut result = Vec::new();
+        for (hash, commit) in &objects.commits {
+            result.push(objects.meta(hash, ObjectType::Commit, commit.get_size()));
+        }
+        for (hash, tree) in &objects.trees {
+            result.push(objects.meta(hash, ObjectType::Tree, tree.get_size()));
+        }
+        for (hash, tag) in &objects.tags {
+            result.push(objects.meta(hash, ObjectType::Tag, tag.get_size()));
+        }
+        for (hash, blob) in &objects.blobs {
+            result.push(objects.meta(hash, ObjectType::Blob, blob.data.len()));
+        }
+        Ok(result)
+    }
+
+    async fn delete_object(&self, object: &OdbObjectMeta) -> Result<(), GitInnerError> {
+        let mut objects = self.objects.lock().unwrap();
+        match object.object_type {
+            ObjectType::Commit => {
+                objects.commits.remove(&object.hash);
+            }
+            ObjectType::Tree => {
+                objects.trees.remove(&object.hash);
+            }
+            ObjectType::Tag => {
+                objects.tags.remove(&object.hash);
+            }
+            ObjectType::Blob => {
+                objects.blobs.remove(&object.hash);
+            }
+            _ => return Err(GitInnerError::InvalidData),
+        }
+        objects.created_at.remove(&object.hash);
+        Ok(())
+    }
+
+    async fn begin_transaction(&self) -> Result<Box<dyn OdbTransaction>, GitInnerError> {
+        Ok(Box::new(OdbMemoryTransaction {
+            parent: self.clone(),
+            staging: OdbMemory::default(),
+        }))
+    }
+}
+
+/// Transaction over [`OdbMemory`]: writes go to a staging area and are copied into the
+/// parent store on commit.
+#[derive(Clone)]
+pub struct OdbMemoryTransaction {
+    pub parent: OdbMemory,
+    pub staging: OdbMemory,
+}
+
+#[async_trait]
+impl Odb for OdbMemoryTransaction {
+    async fn put_commit(&self, commit: &Commit) -> Result<HashValue, GitInnerError> {
+        self.staging.put_commit(commit).await
+    }
+
+    async fn get_commit(&self, hash: &HashValue) -> Result<Commit, GitInnerError> {
+        match self.staging.get_commit(hash).await {
+            Ok(commit) => Ok(commit),
+            Err(_) => self.parent.get_commit(hash).await,
+        }
+    }
+
+    async fn has_commit(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        Ok(self.staging.has_commit(hash).await? || self.parent.has_commit(hash).await?)
+    }
+
+    async fn put_tag(&self, tag: &Tag) -> Result<HashValue, GitInnerError> {
+        self.staging.put_tag(tag).await
+    }
+
+    async fn get_tag(&self, hash: &HashValue) -> Result<Tag, GitInnerError> {
+        match self.staging.get_tag(hash).await {
+            Ok(tag) => Ok(tag),
+            Err(_) => self.parent.get_tag(hash).await,
+        }
+    }
+
+    async fn has_tag(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        Ok(self.staging.has_tag(hash).await? || self.parent.has_tag(hash).await?)
+    }
+
+    async fn put_tree(&self, tree: &Tree) -> Result<HashValue, GitInnerError> {
+        self.staging.put_tree(tree).await
+    }
+
+    async fn get_tree(&self, hash: &HashValue) -> Result<Tree, GitInnerError> {
+        match self.staging.get_tree(hash).await {
+            Ok(tree) => Ok(tree),
+            Err(_) => self.parent.get_tree(hash).await,
+        }
+    }
+
+    async fn has_tree(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        Ok(self.staging.has_tree(hash).await? || self.parent.has_tree(hash).await?)
+    }
+
+    async fn put_blob(&self, blob: Blob) -> Result<HashValue, GitInnerError> {
+        self.staging.put_blob(blob).await
+    }
+
+    async fn get_blob(&self, hash: &HashValue) -> Result<Blob, GitInnerError> {
+        match self.staging.get_blob(hash).await {
+            Ok(blob) => Ok(blob),
+            Err(_) => self.parent.get_blob(hash).await,
+        }
+    }
+
+    async fn has_blob(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
+        Ok(self.staging.has_blob(hash).await? || self.parent.has_blob(hash).await?)
+    }
+
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Vec<HashValue>, GitInnerError> {
+        let mut result = self.parent.find_by_prefix(prefix).await?;
+        for hash in self.staging.find_by_prefix(prefix).await? {
+            if !result.contains(&hash) {
+                result.push(hash);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn stats(&self) -> Result<OdbStats, GitInnerError> {
+        let parent = self.parent.stats().await?;
+        let staging = self.staging.stats().await?;
+        Ok(OdbStats {
+            commits: parent.commits + staging.commits,
+            trees: parent.trees + staging.trees,
+            tags: parent.tags + staging.tags,
+            blobs: parent.blobs + staging.blobs,
+            blob_bytes: parent.blob_bytes + staging.blob_bytes,
+        })
+    }
+
+    async fn list_objects(&self) -> Result<Vec<OdbObjectMeta>, GitInnerError> {
+        Err(GitInnerError::Other(
+            "object listing is not supported inside a transaction".to_string(),
+        ))
+    }
+
+    async fn delete_object(&self, _object: &OdbObjectMeta) -> Result<(), GitInnerError> {
+        Err(GitInnerError::Other(
+            "object deletion is not supported inside a transaction".to_string(),
+        ))
+    }
+
+    async fn begin_transaction(&self) -> Result<Box<dyn OdbTransaction>, GitInnerError> {
+        Err(GitInnerError::Other(
+            "nested transactions are not supported".to_string(),
+        ))
+    }
+}
+
+#[async_trait]
+impl OdbTransaction for OdbMemoryTransaction {
+    async fn commit(&self) -> Result<(), GitInnerError> {
+        let staged = std::mem::take(&mut *self.staging.objects.lock().unwrap());
+        let mut parent = self.parent.objects.lock().unwrap();
+        parent.commits.extend(staged.commits);
+        parent.trees.extend(staged.trees);
+        parent.tags.extend(staged.tags);
+        parent.blobs.extend(staged.blobs);
+        for (hash, created_at) in staged.created_at {
+            parent.created_at.entry(hash).or_insert(created_at);
+        }
+        Ok(())
+    }
+
+    async fn abort(&self) -> Result<(), GitInnerError> {
+        *self.staging.objects.lock().unwrap() = MemoryObjects::default();
+        self.parent.aborts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn rollback(&self) -> Result<(), GitInnerError> {
+        self.abort().await
+    }
+}
@@ -3,8 +3,11 @@ use crate::objects::blob::Blob;
 use crate::objects::commit::Commit;
 use crate::objects::tag::Tag;
 use crate::objects::tree::Tree;
+use crate::objects::types::ObjectType;
 use crate::sha::HashValue;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[async_trait]
 pub trait Odb: Send + Sync {
@@ -20,9 +23,49 @@ pub trait Odb: Send + Sync {
     async fn put_blob(&self, blob: Blob) -> Result<HashValue, GitInnerError>;
     async fn get_blob(&self, hash: &HashValue) -> Result<Blob, GitInnerError>;
     async fn has_blob(&self, hash: &HashValue) -> Result<bool, GitInnerError>;
+    /// Which of `hashes` are stored, as any object type. Backends that can answer in one
+    /// round-trip should override the per-hash default.
+    async fn has_objects(
+        &self,
+        hashes: &[HashValue],
+    ) -> Result<HashMap<HashValue, bool>, GitInnerError> {
+        let mut result = HashMap::with_capacity(hashes.len());
+        for hash in hashes {
+            let exists = self.has_commit(hash).await?
+                || self.has_tree(hash).await?
+                || self.has_blob(hash).await?
+                || self.has_tag(hash).await?;
+            result.insert(hash.clone(), exists);
+        }
+        Ok(result)
+    }
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Vec<HashValue>, GitInnerError>;
+    async fn stats(&self) -> Result<OdbStats, GitInnerError>;
+    /// Every stored object with its type, stored size and creation time, for gc.
+    async fn list_objects(&self) -> Result<Vec<OdbObjectMeta>, GitInnerError>;
+    async fn delete_object(&self, object: &OdbObjectMeta) -> Result<(), GitInnerError>;
     async fn begin_transaction(&self) -> Result<Box<dyn OdbTransaction>, GitInnerError>;
 }
 
+/// Object counts and total blob size of one repository.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OdbStats {
+    pub commits: u64,
+    pub trees: u64,
+    pub tags: u64,
+    pub blobs: u64,
+    pub blob_bytes: u64,
+}
+
+/// An entry of [`Odb::list_objects`]. `created_at` is a unix timestamp in seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OdbObjectMeta {
+    pub hash: HashValue,
+    pub object_type: ObjectType,
+    pub size: u64,
+    pub created_at: i64,
+}
+
 #[async_trait]
 pub trait OdbTransaction: Send + Sync + Odb {
     async fn commit(&self) -> Result<(), GitInnerError>;
@@ -30,4 +73,8 @@ pub trait OdbTransaction: Send + Sync + Odb {
     async fn rollback(&self) -> Result<(), GitInnerError>;
 }
 
+pub mod cache;
+pub mod guard;
+#[cfg(test)]
+pub mod memory;
 pub mod mongo;
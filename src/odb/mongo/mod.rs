@@ -1,2 +1,636 @@
+use crate::error::GitInnerError;
+use crate::sha::HashValue;
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use mongodb::bson::{Document, Uuid, doc};
+use object_store::path::Path;
+use object_store::{ObjectStore, PutMode, PutPayload};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
 pub mod odb;
 pub mod transaction;
+
+/// Directory holding every blob of a repository, staged ones included.
+pub fn repo_dir(repo_uid: &Uuid) -> Path {
+    Path::from(repo_uid.to_string())
+}
+
+/// Where a committed blob lives in the object store. Both [`odb::OdbMongo`] and transaction
+/// commits go through this so they always agree on the layout.
+pub fn blob_path(repo_uid: &Uuid, hash: &HashValue) -> Path {
+    Path::from(format!("{}/{}", repo_uid, hash))
+}
+
+/// Where a blob lives in the pool shared by all repositories.
+pub fn shared_blob_path(hash: &HashValue) -> Path {
+    Path::from(format!("shared/{}", hash))
+}
+
+/// Store the content of blob `hash` for a repository.
+///
+/// With `shared` the content is written once to [`shared_blob_path`] and the repository only
+/// gets an empty marker at its [`blob_path`], which keeps existence checks, listings and gc
+/// per repository. Shared content is never deleted, since other repositories may use it.
+pub async fn write_blob(
+    store: &dyn ObjectStore,
+    repo_uid: &Uuid,
+    hash: &HashValue,
+    data: Bytes,
+    shared: bool,
+) -> Result<(), GitInnerError> {
+    // 空 blob 的标记本身就是它的内容
+    if shared && !data.is_empty() {
+        put_shared(store, &shared_blob_path(hash), PutPayload::from(data)).await?;
+        store
+            .put(&blob_path(repo_uid, hash), PutPayload::new())
+            .await?;
+    } else {
+        store
+            .put(&blob_path(repo_uid, hash), PutPayload::from(data))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Read the content of blob `hash`, following an empty marker (or a missing repository
+/// copy) to the shared pool.
+pub async fn read_blob(
+    store: &dyn ObjectStore,
+    repo_uid: &Uuid,
+    hash: &HashValue,
+) -> Result<Bytes, GitInnerError> {
+    let local = match store.get(&blob_path(repo_uid, hash)).await {
+        Ok(result) => Some(result.bytes().await?),
+        Err(object_store::Error::NotFound { .. }) => None,
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(data) = &local
+        && !data.is_empty()
+    {
+        return Ok(data.clone());
+    }
+    match store.get(&shared_blob_path(hash)).await {
+        Ok(result) => Ok(result.bytes().await?),
+        Err(object_store::Error::NotFound { .. }) if local.is_some() => Ok(Bytes::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn put_shared(
+    store: &dyn ObjectStore,
+    path: &Path,
+    payload: PutPayload,
+) -> Result<(), GitInnerError> {
+    if store.head(path).await.is_ok() {
+        return Ok(());
+    }
+    match store.put_opts(path, payload, PutMode::Create.into()).await {
+        // 内容寻址，并发写入的内容相同
+        Ok(_) | Err(object_store::Error::AlreadyExists { .. }) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Directory a transaction stages its blobs under until commit.
+pub fn staging_dir(repo_uid: &Uuid, txn_id: &str) -> Path {
+    Path::from(format!("{}/txn.{}", repo_uid, txn_id))
+}
+
+/// Where a transaction stages `hash`.
+pub fn staged_blob_path(repo_uid: &Uuid, txn_id: &str, hash: &HashValue) -> Path {
+    staging_dir(repo_uid, txn_id).child(hash.to_string())
+}
+
+/// Delete every blob staged by transaction `txn_id`.
+pub async fn discard_staged_blobs(
+    store: &dyn ObjectStore,
+    repo_uid: &Uuid,
+    txn_id: &str,
+) -> Result<(), GitInnerError> {
+    let staged = store
+        .list(Some(&staging_dir(repo_uid, txn_id)))
+        .try_collect::<Vec<_>>()
+        .await?;
+    for meta in staged {
+        match store.delete(&meta.location).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Staging directories (quarantines) of transactions that started more than `max_age` ago,
+/// left behind by a process that crashed between staging a blob and committing or aborting.
+/// Deletes every such blob and returns how many were removed.
+pub async fn sweep_quarantine(
+    store: &dyn ObjectStore,
+    max_age: Duration,
+) -> Result<usize, GitInnerError> {
+    let cutoff = chrono::Utc::now().timestamp() - max_age.as_secs() as i64;
+    let mut removed = 0;
+    // 只按目录逐层列出，不扫描仓库里已提交的 blob 和共享池
+    let repos = store.list_with_delimiter(None).await?;
+    for repo in repos.common_prefixes {
+        if repo
+            .filename()
+            .and_then(|x| Uuid::parse_str(x).ok())
+            .is_none()
+        {
+            continue;
+        }
+        let dirs = store.list_with_delimiter(Some(&repo)).await?;
+        for dir in dirs.common_prefixes {
+            // <repo_uid>/txn.<开始时间戳>-<uuid>/
+            let Some(started) = dir.filename().and_then(|part| {
+                let (started, _) = part.strip_prefix("txn.")?.split_once('-')?;
+                started.parse::<i64>().ok()
+            }) else {
+                continue;
+            };
+            if started >= cutoff {
+                continue;
+            }
+            let staged = store.list(Some(&dir)).try_collect::<Vec<_>>().await?;
+            for meta in staged {
+                match store.delete(&meta.location).await {
+                    Ok(()) => removed += 1,
+                    Err(object_store::Error::NotFound { .. }) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Run [`sweep_quarantine`] now and then every `interval` in the background.
+pub fn spawn_quarantine_sweeper(
+    store: Arc<Box<dyn ObjectStore>>,
+    interval: Duration,
+    max_age: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            // 第一次 tick 立即返回，即启动时先清理一次
+            interval.tick().await;
+            match sweep_quarantine(store.as_ref().as_ref(), max_age).await {
+                Ok(0) => {}
+                Ok(removed) => info!("removed {} stale quarantined blobs", removed),
+                Err(err) => warn!("quarantine sweep failed: {:?}", err),
+            }
+        }
+    })
+}
+
+/// Move every blob staged by transaction `txn_id` to its committed [`blob_path`], or with
+/// `shared` to the shared pool as [`write_blob`] does.
+pub async fn promote_staged_blobs(
+    store: &dyn ObjectStore,
+    repo_uid: &Uuid,
+    txn_id: &str,
+    shared: bool,
+) -> Result<(), GitInnerError> {
+    let staged = store
+        .list(Some(&staging_dir(repo_uid, txn_id)))
+        .try_collect::<Vec<_>>()
+        .await?;
+    for meta in staged {
+        let Some(hash) = meta.location.filename().and_then(HashValue::from_str) else {
+            continue;
+        };
+        if shared && meta.size > 0 {
+            match store
+                .copy_if_not_exists(&meta.location, &shared_blob_path(&hash))
+                .await
+            {
+                Ok(()) | Err(object_store::Error::AlreadyExists { .. }) => {}
+                Err(e) => return Err(GitInnerError::ObjectStoreError(format!("{}", e))),
+            }
+            store
+                .put(&blob_path(repo_uid, &hash), PutPayload::new())
+                .await?;
+            store.delete(&meta.location).await?;
+            continue;
+        }
+        match store
+            .copy_if_not_exists(&meta.location, &blob_path(repo_uid, &hash))
+            .await
+        {
+            // 内容寻址，已存在的 blob 内容相同
+            Ok(()) | Err(object_store::Error::AlreadyExists { .. }) => {}
+            Err(e) => return Err(GitInnerError::ObjectStoreError(format!("{}", e))),
+        }
+        store.delete(&meta.location).await?;
+    }
+    Ok(())
+}
+
+/// Delete everything stored under [`repo_dir`], returning how many objects were removed.
+pub async fn delete_repo_blobs(
+    store: &dyn ObjectStore,
+    repo_uid: &Uuid,
+) -> Result<usize, GitInnerError> {
+    let objects = store
+        .list(Some(&repo_dir(repo_uid)))
+        .try_collect::<Vec<_>>()
+        .await?;
+    for meta in &objects {
+        match store.delete(&meta.location).await {
+            // 并发删除时对象可能已经不在了
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(objects.len())
+}
+
+/// Check that `prefix` is an abbreviated object id: lowercase hex digits only, so it can be
+/// used as a query bound or path without escaping.
+pub fn check_hash_prefix(prefix: &str) -> Result<(), GitInnerError> {
+    if prefix.is_empty()
+        || !prefix
+            .bytes()
+            .all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return Err(GitInnerError::InvalidHash);
+    }
+    Ok(())
+}
+
+/// Filter for the objects of `repo_uid` whose `hash` starts with `prefix`, as a range the
+/// `hash` index can serve: hex ids that start with `prefix` sort below `prefix` + `g`.
+pub fn hash_prefix_filter(repo_uid: Uuid, prefix: &str) -> Document {
+    doc! {
+        "repo_uid": repo_uid,
+        "hash": { "$gte": prefix, "$lt": format!("{}g", prefix) }
+    }
+}
+
+/// Ids of the blobs directly in `dir` that start with `prefix`, which must have passed
+/// [`check_hash_prefix`]. The listing starts at the prefix, so stores listing in key order
+/// skip the ids before it.
+pub async fn list_blobs_with_prefix(
+    store: &dyn ObjectStore,
+    dir: &Path,
+    prefix: &str,
+) -> Result<Vec<HashValue>, GitInnerError> {
+    // 偏移量本身不会被列出，从少一位的前缀开始，完整 id 也能找到
+    let offset = Path::from(format!("{}/{}", dir, &prefix[..prefix.len() - 1]));
+    let depth = dir.parts().count() + 1;
+    let mut listing = store.list_with_offset(Some(dir), &offset);
+    let mut result = vec![];
+    while let Some(meta) = listing.try_next().await? {
+        // 跳过子目录（如事务暂存目录）里的对象
+        if meta.location.parts().count() != depth {
+            continue;
+        }
+        if let Some(hash) = meta
+            .location
+            .filename()
+            .filter(|name| name.starts_with(prefix))
+            .and_then(HashValue::from_str)
+        {
+            result.push(hash);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::blob::Blob;
+    use crate::sha::HashVersion;
+    use bytes::Bytes;
+    use object_store::PutPayload;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn test_promote_staged_blob_to_committed_path() {
+        let store = InMemory::new();
+        let repo_uid = Uuid::new();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let staged = staged_blob_path(&repo_uid, "1-abc", &blob.id);
+        store
+            .put(&staged, PutPayload::from(blob.data.clone()))
+            .await
+            .unwrap();
+        // 已提交过的 blob 再次提交不应报错
+        let existing = Blob::parse(Bytes::from("world\n"), HashVersion::Sha1);
+        for path in [
+            blob_path(&repo_uid, &existing.id),
+            staged_blob_path(&repo_uid, "1-abc", &existing.id),
+        ] {
+            store
+                .put(&path, PutPayload::from(existing.data.clone()))
+                .await
+                .unwrap();
+        }
+
+        promote_staged_blobs(&store, &repo_uid, "1-abc", false)
+            .await
+            .unwrap();
+
+        let data = store
+            .get(&blob_path(&repo_uid, &blob.id))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(data, blob.data);
+        assert!(
+            store
+                .head(&blob_path(&repo_uid, &existing.id))
+                .await
+                .is_ok()
+        );
+        assert!(store.head(&staged).await.is_err());
+        let left = store
+            .list(Some(&staging_dir(&repo_uid, "1-abc")))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(left.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_removes_stale_quarantine() {
+        let store = InMemory::new();
+        let repo_uid = Uuid::new();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        // 进程在写入暂存 blob 后崩溃，事务既没有提交也没有回滚
+        let now = chrono::Utc::now().timestamp();
+        let dropped = format!("{}-abc", now - 7200);
+        let running = format!("{}-def", now);
+        for path in [
+            staged_blob_path(&repo_uid, &dropped, &blob.id),
+            staged_blob_path(&repo_uid, &running, &blob.id),
+            blob_path(&repo_uid, &blob.id),
+        ] {
+            store
+                .put(&path, PutPayload::from(blob.data.clone()))
+                .await
+                .unwrap();
+        }
+
+        let max_age = Duration::from_secs(3600);
+        assert_eq!(sweep_quarantine(&store, max_age).await.unwrap(), 1);
+        let left = store
+            .list(Some(&staging_dir(&repo_uid, &dropped)))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(left.is_empty());
+        let staged = staged_blob_path(&repo_uid, &running, &blob.id);
+        assert!(store.head(&staged).await.is_ok());
+        assert!(store.head(&blob_path(&repo_uid, &blob.id)).await.is_ok());
+        assert_eq!(sweep_quarantine(&store, max_age).await.unwrap(), 0);
+    }
+
+    /// Store that records the prefix of every full `list`, to check what a sweep scans.
+    #[derive(Debug, Default)]
+    struct ListRecorder {
+        inner: InMemory,
+        listed: std::sync::Mutex<Vec<Option<String>>>,
+    }
+
+    impl std::fmt::Display for ListRecorder {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "ListRecorder({})", self.inner)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ObjectStore for ListRecorder {
+        async fn put_opts(
+            &self,
+            location: &Path,
+            payload: PutPayload,
+            opts: object_store::PutOptions,
+        ) -> object_store::Result<object_store::PutResult> {
+            self.inner.put_opts(location, payload, opts).await
+        }
+
+        async fn put_multipart_opts(
+            &self,
+            location: &Path,
+            opts: object_store::PutMultipartOptions,
+        ) -> object_store::Result<Box<dyn object_store::MultipartUpload>> {
+            self.inner.put_multipart_opts(location, opts).await
+        }
+
+        async fn get_opts(
+            &self,
+            location: &Path,
+            options: object_store::GetOptions,
+        ) -> object_store::Result<object_store::GetResult> {
+            self.inner.get_opts(location, options).await
+        }
+
+        async fn delete(&self, location: &Path) -> object_store::Result<()> {
+            self.inner.delete(location).await
+        }
+
+        fn list(
+            &self,
+            prefix: Option<&Path>,
+        ) -> futures_util::stream::BoxStream<'static, object_store::Result<object_store::ObjectMeta>>
+        {
+            self.listed
+                .lock()
+                .unwrap()
+                .push(prefix.map(|x| x.to_string()));
+            self.inner.list(prefix)
+        }
+
+        async fn list_with_delimiter(
+            &self,
+            prefix: Option<&Path>,
+        ) -> object_store::Result<object_store::ListResult> {
+            self.inner.list_with_delimiter(prefix).await
+        }
+
+        async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+            self.inner.copy(from, to).await
+        }
+
+        async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+            self.inner.copy_if_not_exists(from, to).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sweep_lists_only_stale_quarantines() {
+        let store = ListRecorder::default();
+        let (repo_uid, other) = (Uuid::new(), Uuid::new());
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let now = chrono::Utc::now().timestamp();
+        let dropped = format!("{}-abc", now - 7200);
+        let running = format!("{}-def", now);
+        for path in [
+            staged_blob_path(&repo_uid, &dropped, &blob.id),
+            staged_blob_path(&other, &running, &blob.id),
+            blob_path(&repo_uid, &blob.id),
+            blob_path(&other, &blob.id),
+            shared_blob_path(&blob.id),
+        ] {
+            store
+                .put(&path, PutPayload::from(blob.data.clone()))
+                .await
+                .unwrap();
+        }
+
+        let max_age = Duration::from_secs(3600);
+        assert_eq!(sweep_quarantine(&store, max_age).await.unwrap(), 1);
+        // 只有过期的暂存目录被完整列出
+        assert_eq!(
+            *store.listed.lock().unwrap(),
+            vec![Some(staging_dir(&repo_uid, &dropped).to_string())]
+        );
+        assert!(store.head(&shared_blob_path(&blob.id)).await.is_ok());
+        assert!(store.head(&blob_path(&other, &blob.id)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_delete_repo_blobs() {
+        let store = InMemory::new();
+        let (repo_uid, other) = (Uuid::new(), Uuid::new());
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        for path in [
+            blob_path(&repo_uid, &blob.id),
+            staged_blob_path(&repo_uid, "1-abc", &blob.id),
+            blob_path(&other, &blob.id),
+        ] {
+            store
+                .put(&path, PutPayload::from(blob.data.clone()))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(delete_repo_blobs(&store, &repo_uid).await.unwrap(), 2);
+        let left = store
+            .list(Some(&repo_dir(&repo_uid)))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(left.is_empty());
+        assert!(store.head(&blob_path(&other, &blob.id)).await.is_ok());
+        assert_eq!(delete_repo_blobs(&store, &repo_uid).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_shared_blobs_stored_once() {
+        let store = InMemory::new();
+        let (first, second) = (Uuid::new(), Uuid::new());
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        write_blob(&store, &first, &blob.id, blob.data.clone(), true)
+            .await
+            .unwrap();
+        // 第二个仓库经由事务提交同一个 blob
+        let staged = staged_blob_path(&second, "1-abc", &blob.id);
+        store
+            .put(&staged, PutPayload::from(blob.data.clone()))
+            .await
+            .unwrap();
+        promote_staged_blobs(&store, &second, "1-abc", true)
+            .await
+            .unwrap();
+
+        let all = store.list(None).try_collect::<Vec<_>>().await.unwrap();
+        let with_content = all.iter().filter(|x| x.size > 0).collect::<Vec<_>>();
+        assert_eq!(with_content.len(), 1);
+        assert_eq!(with_content[0].location, shared_blob_path(&blob.id));
+        for repo_uid in [&first, &second] {
+            assert!(store.head(&blob_path(repo_uid, &blob.id)).await.is_ok());
+            assert_eq!(
+                read_blob(&store, repo_uid, &blob.id).await.unwrap(),
+                blob.data
+            );
+        }
+        // 只有本仓库副本的 blob 仍按原路径读取
+        let local = Blob::parse(Bytes::from("local\n"), HashVersion::Sha1);
+        write_blob(&store, &first, &local.id, local.data.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_blob(&store, &first, &local.id).await.unwrap(),
+            local.data
+        );
+        assert!(read_blob(&store, &second, &local.id).await.is_err());
+        let empty = Blob::parse(Bytes::new(), HashVersion::Sha1);
+        write_blob(&store, &first, &empty.id, Bytes::new(), true)
+            .await
+            .unwrap();
+        assert!(
+            read_blob(&store, &first, &empty.id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_check_hash_prefix() {
+        assert!(check_hash_prefix("0a1b").is_ok());
+        for prefix in ["", ".*", "^ab", "ABCD", "ab|c", "abcg"] {
+            assert!(check_hash_prefix(prefix).is_err(), "{}", prefix);
+        }
+        let filter = hash_prefix_filter(Uuid::new(), "ab");
+        let range = filter.get_document("hash").unwrap();
+        assert_eq!(range.get_str("$gte").unwrap(), "ab");
+        assert_eq!(range.get_str("$lt").unwrap(), "abg");
+    }
+
+    #[tokio::test]
+    async fn test_list_blobs_with_prefix() {
+        let store = InMemory::new();
+        let repo_uid = Uuid::new();
+        let blobs = (0..64)
+            .map(|i| Blob::parse(Bytes::from(format!("blob {}", i)), HashVersion::Sha1))
+            .collect::<Vec<_>>();
+        for blob in &blobs {
+            store
+                .put(
+                    &blob_path(&repo_uid, &blob.id),
+                    PutPayload::from(blob.data.clone()),
+                )
+                .await
+                .unwrap();
+        }
+        // 暂存目录里的 blob 不属于仓库目录
+        let staged = Blob::parse(Bytes::from("staged"), HashVersion::Sha1);
+        store
+            .put(
+                &staged_blob_path(&repo_uid, "1-abc", &staged.id),
+                PutPayload::from(staged.data.clone()),
+            )
+            .await
+            .unwrap();
+
+        let dir = repo_dir(&repo_uid);
+        let prefix = &staged.id.to_string()[..1];
+        let mut found = list_blobs_with_prefix(&store, &dir, prefix).await.unwrap();
+        found.sort_by_key(|x| x.to_string());
+        let mut expected = blobs
+            .iter()
+            .map(|x| x.id.clone())
+            .filter(|x| x.to_string().starts_with(prefix))
+            .collect::<Vec<_>>();
+        expected.sort_by_key(|x| x.to_string());
+        assert_eq!(found, expected);
+
+        let full = blobs[0].id.to_string();
+        let found = list_blobs_with_prefix(&store, &dir, &full).await.unwrap();
+        assert_eq!(found, vec![blobs[0].id.clone()]);
+        let staging = staging_dir(&repo_uid, "1-abc");
+        let found = list_blobs_with_prefix(&store, &staging, prefix)
+            .await
+            .unwrap();
+        assert_eq!(found, vec![staged.id.clone()]);
+    }
+}
@@ -6,14 +6,21 @@ use crate::objects::blob::Blob;
 use crate::objects::commit::Commit;
 use crate::objects::tag::Tag;
 use crate::objects::tree::Tree;
+use crate::objects::types::ObjectType;
 use crate::odb::mongo::transaction::OdbMongoTransaction;
-use crate::odb::{Odb, OdbTransaction};
+use crate::odb::mongo::{
+    blob_path, check_hash_prefix, hash_prefix_filter, list_blobs_with_prefix, read_blob, repo_dir,
+    write_blob,
+};
+use crate::odb::{Odb, OdbObjectMeta, OdbStats, OdbTransaction};
 use crate::sha::HashValue;
 use async_trait::async_trait;
-use mongodb::bson::{Uuid, doc};
+use futures_util::TryStreamExt;
+use mongodb::bson::{Document, Uuid, doc};
 use mongodb::{Client, Collection};
+use object_store::ObjectStore;
 use object_store::path::Path;
-use object_store::{ObjectStore, PutPayload};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -25,6 +32,8 @@ pub struct OdbMongoObject {
     pub commit: Collection<OdbMongoCommit>,
     pub tag: Collection<OdbMongoTag>,
     pub tree: Collection<OdbMongoTree>,
+    /// Write blob contents to the pool shared by all repositories.
+    pub shared_blobs: bool,
 }
 
 #[async_trait]
@@ -53,8 +62,7 @@ impl Odb for OdbMongoObject {
                 "repo_uid": self.repo_uid,
                 "hash": mongodb::bson::to_bson(&hash)?
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(obj) => Ok(obj.commit),
             None => Err(GitInnerError::ObjectNotFound(hash.clone())),
@@ -68,8 +76,7 @@ impl Odb for OdbMongoObject {
                 "repo_uid": self.repo_uid,
                 "hash": mongodb::bson::to_bson(&hash)?
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(_) => Ok(true),
             None => Ok(false),
@@ -100,8 +107,7 @@ impl Odb for OdbMongoObject {
                 "repo_uid": self.repo_uid,
                 "hash": mongodb::bson::to_bson(&hash)?
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(obj) => Ok(obj.tag),
             None => Err(GitInnerError::ObjectNotFound(hash.clone())),
@@ -115,8 +121,7 @@ impl Odb for OdbMongoObject {
                 "repo_uid": self.repo_uid,
                 "hash": mongodb::bson::to_bson(&hash)?
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(_) => Ok(true),
             None => Ok(false),
@@ -147,8 +152,7 @@ impl Odb for OdbMongoObject {
                 "repo_uid": self.repo_uid,
                 "hash": mongodb::bson::to_bson(&hash)?
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(obj) => Ok(obj.tree),
             None => Err(GitInnerError::ObjectNotFound(hash.clone())),
@@ -162,8 +166,7 @@ impl Odb for OdbMongoObject {
                 "repo_uid": self.repo_uid,
                 "hash": mongodb::bson::to_bson(&hash)?
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(_) => Ok(true),
             None => Ok(false),
@@ -171,50 +174,220 @@ impl Odb for OdbMongoObject {
     }
 
     async fn put_blob(&self, blob: Blob) -> Result<HashValue, GitInnerError> {
-        let path = format!("{}/{}", self.repo_uid, blob.id.to_string());
-        let result = self
-            .store
-            .put(&Path::from(path), PutPayload::from(blob.data))
-            .await
-            .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)));
-        match result {
-            Ok(_) => Ok(blob.id.clone()),
-            Err(e) => Err(e),
-        }
+        write_blob(
+            self.store.as_ref().as_ref(),
+            &self.repo_uid,
+            &blob.id,
+            blob.data,
+            self.shared_blobs,
+        )
+        .await?;
+        Ok(blob.id)
     }
 
     async fn get_blob(&self, hash: &HashValue) -> Result<Blob, GitInnerError> {
-        let path = format!("{}/{}", self.repo_uid, hash.to_string());
-        let result = self
-            .store
-            .get(&Path::from(path))
-            .await
-            .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)))?;
+        let data = read_blob(self.store.as_ref().as_ref(), &self.repo_uid, hash).await?;
         Ok(Blob {
             id: hash.clone(),
-            data: result
-                .bytes()
-                .await
-                .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)))?,
+            data,
         })
     }
 
     async fn has_blob(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
-        let path = format!("{}/{}", self.repo_uid, hash.to_string());
-        let result = self.store.head(&Path::from(path)).await;
+        let path = blob_path(&self.repo_uid, hash);
+        let result = self.store.head(&path).await;
         Ok(result.is_ok())
     }
 
+    async fn has_objects(
+        &self,
+        hashes: &[HashValue],
+    ) -> Result<HashMap<HashValue, bool>, GitInnerError> {
+        let mut result: HashMap<HashValue, bool> =
+            hashes.iter().map(|hash| (hash.clone(), false)).collect();
+        if hashes.is_empty() {
+            return Ok(result);
+        }
+        let filter = doc! {
+            "repo_uid": self.repo_uid,
+            "hash": { "$in": mongodb::bson::to_bson(hashes)? }
+        };
+        let stage = |collection: &str| {
+            doc! {
+                "$unionWith": {
+                    "coll": collection,
+                    "pipeline": [
+                        { "$match": filter.clone() },
+                        { "$project": { "_id": 0, "hash": 1 } }
+                    ]
+                }
+            }
+        };
+        // commit / tree / tag 三个集合用 $unionWith 合成一次查询
+        let pipeline = vec![
+            doc! { "$match": filter.clone() },
+            doc! { "$project": { "_id": 0, "hash": 1 } },
+            stage(self.tree.name()),
+            stage(self.tag.name()),
+        ];
+        let docs = self
+            .commit
+            .aggregate(pipeline)
+            .await?
+            .try_collect::<Vec<Document>>()
+            .await?;
+        for doc in docs {
+            if let Some(hash) = doc.get_str("hash").ok().and_then(HashValue::from_str) {
+                result.insert(hash, true);
+            }
+        }
+        // 剩下的可能是 blob，并发 head
+        let missing = result
+            .iter()
+            .filter(|(_, exists)| !**exists)
+            .map(|(hash, _)| hash.clone())
+            .collect::<Vec<_>>();
+        let blobs = futures_util::future::join_all(missing.into_iter().map(|hash| async move {
+            let exists = self
+                .store
+                .head(&blob_path(&self.repo_uid, &hash))
+                .await
+                .is_ok();
+            (hash, exists)
+        }))
+        .await;
+        result.extend(blobs);
+        Ok(result)
+    }
+
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Vec<HashValue>, GitInnerError> {
+        check_hash_prefix(prefix)?;
+        let filter = hash_prefix_filter(self.repo_uid, prefix);
+        let mut result = Vec::new();
+        let commits = self
+            .commit
+            .find(filter.clone())
+            .await?
+            .try_collect::<Vec<OdbMongoCommit>>()
+            .await?;
+        result.extend(commits.into_iter().map(|x| x.hash));
+        let trees = self
+            .tree
+            .find(filter.clone())
+            .await?
+            .try_collect::<Vec<OdbMongoTree>>()
+            .await?;
+        result.extend(trees.into_iter().map(|x| x.hash));
+        let tags = self
+            .tag
+            .find(filter)
+            .await?
+            .try_collect::<Vec<OdbMongoTag>>()
+            .await?;
+        result.extend(tags.into_iter().map(|x| x.hash));
+        let dir = repo_dir(&self.repo_uid);
+        result.extend(list_blobs_with_prefix(self.store.as_ref().as_ref(), &dir, prefix).await?);
+        Ok(result)
+    }
+
+    async fn stats(&self) -> Result<OdbStats, GitInnerError> {
+        let filter = doc! { "repo_uid": self.repo_uid };
+        let commits = self.commit.count_documents(filter.clone()).await?;
+        let trees = self.tree.count_documents(filter.clone()).await?;
+        let tags = self.tag.count_documents(filter).await?;
+        // 只统计仓库根目录下的 blob，进行中的事务目录 (txn.*) 不计入
+        let blobs = self
+            .store
+            .list_with_delimiter(Some(&Path::from(self.repo_uid.to_string())))
+            .await?;
+        Ok(OdbStats {
+            commits,
+            trees,
+            tags,
+            blobs: blobs.objects.len() as u64,
+            blob_bytes: blobs.objects.iter().map(|meta| meta.size).sum(),
+        })
+    }
+
+    async fn list_objects(&self) -> Result<Vec<OdbObjectMeta>, GitInnerError> {
+        let filter = doc! { "repo_uid": self.repo_uid };
+        let mut result = Vec::new();
+        for (object_type, docs) in [
+            (
+                ObjectType::Commit,
+                self.commit.clone_with_type::<Document>(),
+            ),
+            (ObjectType::Tree, self.tree.clone_with_type::<Document>()),
+            (ObjectType::Tag, self.tag.clone_with_type::<Document>()),
+        ] {
+            let docs = docs
+                .find(filter.clone())
+                .await?
+                .try_collect::<Vec<Document>>()
+                .await?;
+            for doc in docs {
+                let Some(hash) = doc.get_str("hash").ok().and_then(HashValue::from_str) else {
+                    continue;
+                };
+                // ObjectId 自带创建时间；没有 _id 的文档视为刚写入，不会被回收
+                let created_at = doc
+                    .get_object_id("_id")
+                    .map(|id| id.timestamp().timestamp_millis() / 1000)
+                    .unwrap_or_else(|_| chrono::Utc::now().timestamp());
+                let size = mongodb::bson::to_vec(&doc).map(|x| x.len()).unwrap_or(0);
+                result.push(OdbObjectMeta {
+                    hash,
+                    object_type,
+                    size: size as u64,
+                    created_at,
+                });
+            }
+        }
+        let blobs = self
+            .store
+            .list_with_delimiter(Some(&Path::from(self.repo_uid.to_string())))
+            .await?;
+        for meta in blobs.objects {
+            let Some(hash) = meta.location.filename().and_then(HashValue::from_str) else {
+                continue;
+            };
+            result.push(OdbObjectMeta {
+                hash,
+                object_type: ObjectType::Blob,
+                size: meta.size,
+                created_at: meta.last_modified.timestamp(),
+            });
+        }
+        Ok(result)
+    }
+
+    async fn delete_object(&self, object: &OdbObjectMeta) -> Result<(), GitInnerError> {
+        let filter = doc! {
+            "repo_uid": self.repo_uid,
+            "hash": mongodb::bson::to_bson(&object.hash)?
+        };
+        let result = match object.object_type {
+            ObjectType::Commit => self.commit.delete_one(filter).await,
+            ObjectType::Tree => self.tree.delete_one(filter).await,
+            ObjectType::Tag => self.tag.delete_one(filter).await,
+            ObjectType::Blob => {
+                let path = blob_path(&self.repo_uid, &object.hash);
+                return self
+                    .store
+                    .delete(&path)
+                    .await
+                    .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)));
+            }
+            _ => return Err(GitInnerError::InvalidData),
+        };
+        result
+            .map(|_| ())
+            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))
+    }
+
     async fn begin_transaction(&self) -> Result<Box<dyn OdbTransaction>, GitInnerError> {
-        let mut session = self
-            .db_client
-            .start_session()
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
-        session
-            .start_transaction()
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+        let mut session = self.db_client.start_session().await?;
+        session.start_transaction().await?;
         let transaction = OdbMongoTransaction {
             db_client: self.db_client.clone(),
             session: Arc::new(Mutex::new(session)),
@@ -223,7 +396,12 @@ impl Odb for OdbMongoObject {
             tag: self.tag.clone(),
             tree: self.tree.clone(),
             store: self.store.clone(),
-            id: chrono::Utc::now().timestamp(),
+            shared_blobs: self.shared_blobs,
+            id: format!(
+                "{}-{}",
+                chrono::Utc::now().timestamp(),
+                uuid::Uuid::new_v4().simple()
+            ),
         };
         Ok(Box::new(transaction))
     }
@@ -6,16 +6,20 @@ use crate::objects::blob::Blob;
 use crate::objects::commit::Commit;
 use crate::objects::tag::Tag;
 use crate::objects::tree::Tree;
-use crate::odb::{Odb, OdbTransaction};
+use crate::odb::mongo::{
+    blob_path, check_hash_prefix, discard_staged_blobs, hash_prefix_filter, list_blobs_with_prefix,
+    promote_staged_blobs, read_blob, repo_dir, staged_blob_path, staging_dir,
+};
+use crate::odb::{Odb, OdbObjectMeta, OdbStats, OdbTransaction};
 use crate::sha::HashValue;
 use async_trait::async_trait;
+use futures_util::TryStreamExt;
 use mongodb::bson::{Uuid, doc};
 use mongodb::{Client, ClientSession, Collection};
 use object_store::path::Path;
 use object_store::{ObjectStore, PutPayload};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tokio_stream::StreamExt;
 
 #[derive(Clone)]
 pub struct OdbMongoTransaction {
@@ -26,7 +30,10 @@ pub struct OdbMongoTransaction {
     pub tag: Collection<OdbMongoTag>,
     pub tree: Collection<OdbMongoTree>,
     pub store: Arc<Box<dyn ObjectStore>>,
-    pub id: i64,
+    /// Promote blobs to the pool shared by all repositories on commit.
+    pub shared_blobs: bool,
+    /// 暂存目录名，时间戳加 uuid，避免同一秒内开启的事务冲突
+    pub id: String,
 }
 
 #[async_trait]
@@ -59,8 +66,7 @@ impl Odb for OdbMongoTransaction {
                 "hash": mongodb::bson::to_bson(&hash)?
             })
             .session(&mut *session)
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(obj) => Ok(obj.commit),
             None => Err(GitInnerError::ObjectNotFound(hash.clone())),
@@ -76,8 +82,7 @@ impl Odb for OdbMongoTransaction {
                 "hash": mongodb::bson::to_bson(&hash)?
             })
             .session(&mut *session)
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(_) => Ok(true),
             None => Ok(false),
@@ -113,8 +118,7 @@ impl Odb for OdbMongoTransaction {
                 "hash": mongodb::bson::to_bson(&hash)?
             })
             .session(&mut *session)
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(obj) => Ok(obj.tag),
             None => Err(GitInnerError::ObjectNotFound(hash.clone())),
@@ -130,8 +134,7 @@ impl Odb for OdbMongoTransaction {
                 "hash": mongodb::bson::to_bson(&hash)?
             })
             .session(&mut *session)
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(_) => Ok(true),
             None => Ok(false),
@@ -166,8 +169,7 @@ impl Odb for OdbMongoTransaction {
                 "hash": mongodb::bson::to_bson(&hash)?
             })
             .session(&mut *session)
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(obj) => Ok(obj.tree),
             None => Err(GitInnerError::ObjectNotFound(hash.clone())),
@@ -183,8 +185,7 @@ impl Odb for OdbMongoTransaction {
                 "hash": mongodb::bson::to_bson(&hash)?
             })
             .session(&mut *session)
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+            .await?;
         match result {
             Some(_) => Ok(true),
             None => Ok(false),
@@ -192,10 +193,10 @@ impl Odb for OdbMongoTransaction {
     }
 
     async fn put_blob(&self, blob: Blob) -> Result<HashValue, GitInnerError> {
-        let path = format!("{}/txn.{}/{}", self.repo_uid, self.id, blob.id.to_string());
+        let path = staged_blob_path(&self.repo_uid, &self.id, &blob.id);
         let result = self
             .store
-            .put(&Path::from(path), PutPayload::from(blob.data))
+            .put(&path, PutPayload::from(blob.data))
             .await
             .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)));
         match result {
@@ -205,35 +206,117 @@ impl Odb for OdbMongoTransaction {
     }
 
     async fn get_blob(&self, hash: &HashValue) -> Result<Blob, GitInnerError> {
-        let path = format!("{}/{}", self.repo_uid, hash.to_string());
-        let result = match self.store.get(&Path::from(path)).await {
-            Ok(result) => result,
-            Err(_) => {
-                let txn_path = format!("{}/txn.{}/{}", self.repo_uid, self.id, hash.to_string());
-                let txn_result = self.store.get(&Path::from(txn_path)).await;
-                match txn_result {
-                    Ok(result) => result,
-                    Err(e) => {
-                        return Err(GitInnerError::ObjectStoreError(format!("{}", e)));
-                    }
-                }
-            }
+        // 先读本事务暂存的对象，再回退到已提交的对象
+        let txn_path = staged_blob_path(&self.repo_uid, &self.id, hash);
+        let data = match self.store.get(&txn_path).await {
+            Ok(result) => result.bytes().await?,
+            Err(_) => read_blob(self.store.as_ref().as_ref(), &self.repo_uid, hash).await?,
         };
         Ok(Blob {
             id: hash.clone(),
-            data: result
-                .bytes()
-                .await
-                .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)))?,
+            data,
         })
     }
 
     async fn has_blob(&self, hash: &HashValue) -> Result<bool, GitInnerError> {
-        let path = format!("{}/{}", self.repo_uid, hash.to_string());
-        let result = self.store.head(&Path::from(path)).await;
-        let txn_path = format!("{}/txn.{}/{}", self.repo_uid, self.id, hash.to_string());
-        let txn_result = self.store.head(&Path::from(txn_path)).await;
-        Ok(result.is_ok() || txn_result.is_ok())
+        let txn_path = staged_blob_path(&self.repo_uid, &self.id, hash);
+        if self.store.head(&txn_path).await.is_ok() {
+            return Ok(true);
+        }
+        let path = blob_path(&self.repo_uid, hash);
+        Ok(self.store.head(&path).await.is_ok())
+    }
+
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Vec<HashValue>, GitInnerError> {
+        check_hash_prefix(prefix)?;
+        let filter = hash_prefix_filter(self.repo_uid, prefix);
+        let mut result = Vec::new();
+        let mut session = self.session.lock().await;
+        let commits = self
+            .commit
+            .find(filter.clone())
+            .session(&mut *session)
+            .await?
+            .stream(&mut session)
+            .try_collect::<Vec<OdbMongoCommit>>()
+            .await?;
+        result.extend(commits.into_iter().map(|x| x.hash));
+        let trees = self
+            .tree
+            .find(filter.clone())
+            .session(&mut *session)
+            .await?
+            .stream(&mut session)
+            .try_collect::<Vec<OdbMongoTree>>()
+            .await?;
+        result.extend(trees.into_iter().map(|x| x.hash));
+        let tags = self
+            .tag
+            .find(filter)
+            .session(&mut *session)
+            .await?
+            .stream(&mut session)
+            .try_collect::<Vec<OdbMongoTag>>()
+            .await?;
+        result.extend(tags.into_iter().map(|x| x.hash));
+        for dir in [
+            repo_dir(&self.repo_uid),
+            staging_dir(&self.repo_uid, &self.id),
+        ] {
+            let store = self.store.as_ref().as_ref();
+            result.extend(list_blobs_with_prefix(store, &dir, prefix).await?);
+        }
+        Ok(result)
+    }
+
+    async fn stats(&self) -> Result<OdbStats, GitInnerError> {
+        let filter = doc! { "repo_uid": self.repo_uid };
+        let mut session = self.session.lock().await;
+        let commits = self
+            .commit
+            .count_documents(filter.clone())
+            .session(&mut *session)
+            .await?;
+        let trees = self
+            .tree
+            .count_documents(filter.clone())
+            .session(&mut *session)
+            .await?;
+        let tags = self
+            .tag
+            .count_documents(filter)
+            .session(&mut *session)
+            .await?;
+        let mut stats = OdbStats {
+            commits,
+            trees,
+            tags,
+            ..Default::default()
+        };
+        for dir in [
+            format!("{}", self.repo_uid),
+            format!("{}/txn.{}", self.repo_uid, self.id),
+        ] {
+            let blobs = self
+                .store
+                .list_with_delimiter(Some(&Path::from(dir)))
+                .await?;
+            stats.blobs += blobs.objects.len() as u64;
+            stats.blob_bytes += blobs.objects.iter().map(|meta| meta.size).sum::<u64>();
+        }
+        Ok(stats)
+    }
+
+    async fn list_objects(&self) -> Result<Vec<OdbObjectMeta>, GitInnerError> {
+        Err(GitInnerError::Other(
+            "object listing is not supported inside a transaction".to_string(),
+        ))
+    }
+
+    async fn delete_object(&self, _object: &OdbObjectMeta) -> Result<(), GitInnerError> {
+        Err(GitInnerError::Other(
+            "object deletion is not supported inside a transaction".to_string(),
+        ))
     }
 
     async fn begin_transaction(&self) -> Result<Box<dyn OdbTransaction>, GitInnerError> {
@@ -245,69 +328,26 @@ impl Odb for OdbMongoTransaction {
 impl OdbTransaction for OdbMongoTransaction {
     async fn commit(&self) -> Result<(), GitInnerError> {
         let mut session = self.session.lock().await;
-        let mut list = self.store.list(Option::from(&Path::from(format!(
-            "{}/txn.{}",
-            self.repo_uid, self.id
-        ))));
-        while let Some(Ok(next)) = list.next().await {
-            self.store
-                .copy_if_not_exists(
-                    &next.location,
-                    &Path::from(format!(
-                        "{}/{}",
-                        self.repo_uid,
-                        next.location.filename().unwrap_or("")
-                    )),
-                )
-                .await
-                .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)))?;
-            self.store
-                .delete(&next.location)
-                .await
-                .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)))?;
-        }
-        session
-            .commit_transaction()
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
+        promote_staged_blobs(
+            self.store.as_ref().as_ref(),
+            &self.repo_uid,
+            &self.id,
+            self.shared_blobs,
+        )
+        .await?;
+        session.commit_transaction().await?;
         Ok(())
     }
 
     async fn abort(&self) -> Result<(), GitInnerError> {
-        let mut session = self.session.lock().await;
-        session
-            .abort_transaction()
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
-        let mut list = self.store.list(Option::from(&Path::from(format!(
-            "{}/txn.{}",
-            self.repo_uid, self.id
-        ))));
-        while let Some(Ok(next)) = list.next().await {
-            self.store
-                .delete(&next.location)
-                .await
-                .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)))?;
-        }
+        let aborted = self.session.lock().await.abort_transaction().await;
+        // 会话回滚失败也要清掉暂存的 blob
+        discard_staged_blobs(self.store.as_ref().as_ref(), &self.repo_uid, &self.id).await?;
+        aborted?;
         Ok(())
     }
 
     async fn rollback(&self) -> Result<(), GitInnerError> {
-        let mut session = self.session.lock().await;
-        session
-            .abort_transaction()
-            .await
-            .map_err(|e| GitInnerError::MongodbError(format!("{}", e)))?;
-        let mut list = self.store.list(Option::from(&Path::from(format!(
-            "{}/txn.{}",
-            self.repo_uid, self.id
-        ))));
-        while let Some(Ok(next)) = list.next().await {
-            self.store
-                .delete(&next.location)
-                .await
-                .map_err(|e| GitInnerError::ObjectStoreError(format!("{}", e)))?;
-        }
-        Ok(())
+        self.abort().await
     }
 }
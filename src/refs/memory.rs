@@ -0,0 +1,247 @@
+use crate::error::GitInnerError;
+use crate::refs::{RefItem, ReflogEntry, RefsManager, unborn_head};
+use crate::sha::{HashValue, HashVersion};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// In-memory refs manager used by unit tests.
+#[derive(Clone)]
+pub struct MemoryRefsManager {
+    pub default_branch: Arc<Mutex<String>>,
+    pub hash_version: HashVersion,
+    pub refs: Arc<Mutex<BTreeMap<String, RefItem>>>,
+    pub reflog: Arc<Mutex<Vec<ReflogEntry>>>,
+    /// Report an unborn HEAD as a symref to the default branch.
+    pub symbolic_head: bool,
+    /// Fail every create and update with a storage error.
+    pub fail_writes: Arc<AtomicBool>,
+}
+
+impl MemoryRefsManager {
+    fn check_write(&self) -> Result<(), GitInnerError> {
+        if self.fail_writes.load(Ordering::SeqCst) {
+            return Err(GitInnerError::Other("refs storage unavailable".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn new(default_branch: &str, hash_version: HashVersion) -> Self {
+        Self {
+            default_branch: Arc::new(Mutex::new(default_branch.to_string())),
+            hash_version,
+            refs: Arc::new(Mutex::new(BTreeMap::new())),
+            reflog: Arc::new(Mutex::new(Vec::new())),
+            symbolic_head: false,
+            fail_writes: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+#[async_trait]
+impl RefsManager for MemoryRefsManager {
+    async fn head(&self) -> Result<RefItem, GitInnerError> {
+        let refs = self.refs.lock().unwrap();
+        // 优先取 HEAD 指向的分支，其次才是分离的 HEAD
+        let head = refs
+            .values()
+            .find(|x| x.is_head && x.is_branch)
+            .or_else(|| refs.values().find(|x| x.is_head));
+        match head {
+            Some(item) => Ok(item.clone()),
+            None => Ok(unborn_head(
+                &self.default_branch.lock().unwrap(),
+                self.hash_version,
+                self.symbolic_head,
+            )),
+        }
+    }
+
+    async fn refs(&self) -> Result<Vec<RefItem>, GitInnerError> {
+        Ok(self.refs.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn refs_page(
+        &self,
+        prefix: Option<String>,
+        after: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<RefItem>, GitInnerError> {
+        let prefix = prefix.unwrap_or_default();
+        Ok(self
+            .refs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|x| x.name.starts_with(&prefix))
+            .filter(|x| after.as_ref().is_none_or(|after| &x.name > after))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn tags(&self) -> Result<Vec<RefItem>, GitInnerError> {
+        Ok(self
+            .refs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|x| x.is_tag)
+            .cloned()
+            .collect())
+    }
+
+    async fn branches(&self) -> Result<Vec<RefItem>, GitInnerError> {
+        Ok(self
+            .refs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|x| x.is_branch)
+            .cloned()
+            .collect())
+    }
+
+    async fn del_refs(&self, ref_name: String) -> Result<(), GitInnerError> {
+        let default_branch = self.default_branch.lock().unwrap().clone();
+        if ref_name.strip_prefix("refs/heads/") == Some(default_branch.as_str()) {
+            return Err(GitInnerError::DefaultBranchCannotBeDeleted);
+        }
+        self.refs.lock().unwrap().remove(&ref_name);
+        Ok(())
+    }
+
+    async fn create_refs(
+        &self,
+        ref_name: String,
+        ref_value: HashValue,
+    ) -> Result<(), GitInnerError> {
+        self.check_write()?;
+        let default_branch = self.default_branch.lock().unwrap().clone();
+        let item = RefItem {
+            name: ref_name.clone(),
+            value: ref_value,
+            is_branch: ref_name.starts_with("refs/heads/"),
+            is_tag: ref_name.starts_with("refs/tags/"),
+            is_head: ref_name == "HEAD"
+                || ref_name.strip_prefix("refs/heads/") == Some(default_branch.as_str()),
+        };
+        match self.refs.lock().unwrap().entry(ref_name) {
+            Entry::Occupied(entry) => Err(GitInnerError::RefAlreadyExists(entry.key().clone())),
+            Entry::Vacant(entry) => {
+                entry.insert(item);
+                Ok(())
+            }
+        }
+    }
+
+    async fn update_refs(
+        &self,
+        ref_name: String,
+        ref_value: HashValue,
+    ) -> Result<(), GitInnerError> {
+        self.check_write()?;
+        if let Some(item) = self.refs.lock().unwrap().get_mut(&ref_name) {
+            item.value = ref_value;
+        }
+        Ok(())
+    }
+
+    async fn cas_update(
+        &self,
+        ref_name: String,
+        expected: HashValue,
+        new: HashValue,
+    ) -> Result<bool, GitInnerError> {
+        self.check_write()?;
+        match self.refs.lock().unwrap().get_mut(&ref_name) {
+            Some(item) if item.value == expected => {
+                item.value = new;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn get_refs(&self, ref_name: String) -> Result<RefItem, GitInnerError> {
+        self.refs
+            .lock()
+            .unwrap()
+            .get(&ref_name)
+            .cloned()
+            .ok_or(GitInnerError::ObjectNotFound(self.hash_version.default()))
+    }
+
+    async fn exists_refs(&self, ref_name: String) -> Result<bool, GitInnerError> {
+        Ok(self.refs.lock().unwrap().contains_key(&ref_name))
+    }
+
+    async fn get_value_refs(&self, ref_name: String) -> Result<HashValue, GitInnerError> {
+        Ok(self.get_refs(ref_name).await?.value)
+    }
+
+    async fn exchange_default_branch(&self, branch_name: String) -> Result<(), GitInnerError> {
+        self.set_head(branch_name).await
+    }
+
+    async fn set_head(&self, target_ref: String) -> Result<(), GitInnerError> {
+        let Some(short) = target_ref.strip_prefix("refs/heads/") else {
+            return Err(GitInnerError::InvalidArgument(format!(
+                "HEAD must point at a branch: {}",
+                target_ref
+            )));
+        };
+        let mut refs = self.refs.lock().unwrap();
+        if !refs.contains_key(&target_ref) {
+            return Err(GitInnerError::ObjectNotFound(self.hash_version.default()));
+        }
+        for item in refs.values_mut() {
+            item.is_head = item.name == target_ref;
+        }
+        *self.default_branch.lock().unwrap() = short.to_string();
+        Ok(())
+    }
+
+    async fn append_reflog(&self, entry: ReflogEntry) -> Result<(), GitInnerError> {
+        self.reflog.lock().unwrap().push(entry);
+        Ok(())
+    }
+
+    async fn reflog(&self, ref_name: String) -> Result<Vec<ReflogEntry>, GitInnerError> {
+        Ok(self
+            .reflog
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.ref_name == ref_name)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_head_resolves_to_default_branch() {
+        let refs = MemoryRefsManager::new("main", HashVersion::Sha1);
+        let head = refs.head().await.unwrap();
+        assert_eq!(head.name, "HEAD");
+        assert!(head.value.is_zero());
+
+        let commit = HashValue::from_str("cdfdb42577e2506715f8cfeacdbabc092bf63e8d").unwrap();
+        let detached = HashValue::from_str("15027957951b64cf874c3557a0f3547bd83b3ff6").unwrap();
+        refs.create_refs("HEAD".to_string(), detached)
+            .await
+            .unwrap();
+        refs.create_refs("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+        let head = refs.head().await.unwrap();
+        assert_eq!(head.name, "refs/heads/main");
+        assert_eq!(head.value, commit);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::GitInnerError;
-use crate::sha::HashValue;
+use crate::sha::{HashValue, HashVersion};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
@@ -7,9 +7,18 @@ use serde::{Deserialize, Serialize};
 pub trait RefsManager: Send + Sync {
     async fn head(&self) -> Result<RefItem, GitInnerError>;
     async fn refs(&self) -> Result<Vec<RefItem>, GitInnerError>;
+    /// List up to `limit` refs sorted by name, restricted to names starting with `prefix`
+    /// and strictly greater than `after`.
+    async fn refs_page(
+        &self,
+        prefix: Option<String>,
+        after: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<RefItem>, GitInnerError>;
     async fn tags(&self) -> Result<Vec<RefItem>, GitInnerError>;
     async fn branches(&self) -> Result<Vec<RefItem>, GitInnerError>;
     async fn del_refs(&self, ref_name: String) -> Result<(), GitInnerError>;
+    /// Create `ref_name`, failing with `RefAlreadyExists` if it is already present.
     async fn create_refs(
         &self,
         ref_name: String,
@@ -20,10 +29,25 @@ pub trait RefsManager: Send + Sync {
         ref_name: String,
         ref_value: HashValue,
     ) -> Result<(), GitInnerError>;
+    /// Set `ref_name` to `new` only if it currently points at `expected`. Returns whether
+    /// the swap happened; a missing ref counts as a mismatch.
+    async fn cas_update(
+        &self,
+        ref_name: String,
+        expected: HashValue,
+        new: HashValue,
+    ) -> Result<bool, GitInnerError>;
     async fn get_refs(&self, ref_name: String) -> Result<RefItem, GitInnerError>;
     async fn exists_refs(&self, ref_name: String) -> Result<bool, GitInnerError>;
     async fn get_value_refs(&self, ref_name: String) -> Result<HashValue, GitInnerError>;
     async fn exchange_default_branch(&self, branch_name: String) -> Result<(), GitInnerError>;
+    /// Point HEAD at the branch `target_ref` (a full `refs/heads/...` name), leaving it the
+    /// only ref flagged `is_head`.
+    async fn set_head(&self, target_ref: String) -> Result<(), GitInnerError>;
+    /// Record a ref change in the audit log.
+    async fn append_reflog(&self, entry: ReflogEntry) -> Result<(), GitInnerError>;
+    /// Changes recorded for `ref_name`, oldest first.
+    async fn reflog(&self, ref_name: String) -> Result<Vec<ReflogEntry>, GitInnerError>;
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -35,4 +59,96 @@ pub struct RefItem {
     pub is_head: bool,
 }
 
+/// One ref change, as written to the reflog after a successful push.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReflogEntry {
+    pub ref_name: String,
+    pub old: HashValue,
+    pub new: HashValue,
+    /// Authenticated user who made the change, if known.
+    pub actor: Option<String>,
+    /// Unix timestamp in seconds.
+    pub timestamp: i64,
+}
+
+/// HEAD of a repository that has no HEAD branch yet. With `symbolic` it already names
+/// `refs/heads/<default_branch>`, like the HEAD `git init` writes; otherwise it is a bare
+/// `HEAD` without a target. Either way it points at the zero id.
+pub fn unborn_head(default_branch: &str, hash_version: HashVersion, symbolic: bool) -> RefItem {
+    let (name, is_branch) = match symbolic {
+        true => (format!("refs/heads/{}", default_branch), true),
+        false => ("HEAD".to_string(), false),
+    };
+    RefItem {
+        name,
+        value: hash_version.default(),
+        is_branch,
+        is_tag: false,
+        is_head: true,
+    }
+}
+
+/// Check a ref name component such as a branch or tag name against the rules of
+/// `git check-ref-format`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    if name.is_empty() || name == "@" {
+        return false;
+    }
+    if name.starts_with('-') || name.starts_with('/') || name.ends_with('/') {
+        return false;
+    }
+    if name.ends_with('.') || name.ends_with(".lock") {
+        return false;
+    }
+    if name.contains("..") || name.contains("//") || name.contains("@{") {
+        return false;
+    }
+    if name
+        .split('/')
+        .any(|x| x.starts_with('.') || x.ends_with(".lock"))
+    {
+        return false;
+    }
+    !name
+        .chars()
+        .any(|c| c.is_control() || c == ' ' || "~^:?*[\\".contains(c))
+}
+
+#[cfg(test)]
+pub mod memory;
 pub mod mongo;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_ref_name() {
+        for name in ["main", "feature/login", "release-1.0", "v1.0.0"] {
+            assert!(is_valid_ref_name(name), "{}", name);
+        }
+        for name in [
+            "",
+            "a..b",
+            "has space",
+            "ctrl\x07",
+            "-lead",
+            "trail/",
+            "x.lock",
+            ".hidden",
+            "a//b",
+            "a@{1}",
+            "a~1",
+            "a^",
+            "a:b",
+            "a?",
+            "a*",
+            "a[",
+            "a\\b",
+            "@",
+            "end.",
+        ] {
+            assert!(!is_valid_ref_name(name), "{}", name);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::GitInnerError;
-use crate::refs::{RefItem, RefsManager};
+use crate::refs::{RefItem, ReflogEntry, RefsManager, unborn_head};
 use crate::sha::{HashValue, HashVersion};
 use async_trait::async_trait;
 use futures_util::stream::TryStreamExt;
@@ -13,12 +13,21 @@ pub struct MongoRefItem {
     pub ref_item: RefItem,
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MongoReflogEntry {
+    pub repo_uid: Uuid,
+    pub entry: ReflogEntry,
+}
+
 pub struct MongoRefsManager {
     pub repo_uid: Uuid,
     pub default_branch: String,
     pub db_client: Client,
     pub refs: Collection<MongoRefItem>,
+    pub reflog: Collection<MongoReflogEntry>,
     pub hash_version: HashVersion,
+    /// Report an unborn HEAD as a symref to the default branch.
+    pub symbolic_head: bool,
 }
 
 #[async_trait]
@@ -30,18 +39,17 @@ impl RefsManager for MongoRefsManager {
                 "repo_uid": self.repo_uid,
                 "ref_item.is_head": true
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            // 优先取 HEAD 指向的分支，其次才是分离的 HEAD
+            .sort(doc! { "ref_item.is_branch": -1 })
+            .await?;
 
         match result {
             Some(mongo_ref_item) => Ok(mongo_ref_item.ref_item),
-            None => Ok(RefItem {
-                name: "HEAD".to_string(),
-                value: self.hash_version.default(),
-                is_branch: false,
-                is_tag: false,
-                is_head: true,
-            }),
+            None => Ok(unborn_head(
+                &self.default_branch,
+                self.hash_version,
+                self.symbolic_head,
+            )),
         }
     }
 
@@ -51,12 +59,44 @@ impl RefsManager for MongoRefsManager {
             .find(doc! {
                 "repo_uid": self.repo_uid
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            .await?;
         let ref_items: Vec<RefItem> = cursor
             .try_collect::<Vec<MongoRefItem>>()
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?
+            .await?
+            .into_iter()
+            .map(|mongo_ref_item| mongo_ref_item.ref_item)
+            .collect();
+        Ok(ref_items)
+    }
+
+    async fn refs_page(
+        &self,
+        prefix: Option<String>,
+        after: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<RefItem>, GitInnerError> {
+        let mut filter = doc! {
+            "repo_uid": self.repo_uid
+        };
+        let mut name = doc! {};
+        if let Some(prefix) = prefix {
+            name.insert("$regex", format!("^{}", escape_regex(&prefix)));
+        }
+        if let Some(after) = after {
+            name.insert("$gt", after);
+        }
+        if !name.is_empty() {
+            filter.insert("ref_item.name", name);
+        }
+        let cursor = self
+            .refs
+            .find(filter)
+            .sort(doc! { "ref_item.name": 1 })
+            .limit(limit as i64)
+            .await?;
+        let ref_items: Vec<RefItem> = cursor
+            .try_collect::<Vec<MongoRefItem>>()
+            .await?
             .into_iter()
             .map(|mongo_ref_item| mongo_ref_item.ref_item)
             .collect();
@@ -70,12 +110,10 @@ impl RefsManager for MongoRefsManager {
                 "repo_uid": self.repo_uid,
                 "ref_item.is_tag": true
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            .await?;
         let ref_items: Vec<RefItem> = cursor
             .try_collect::<Vec<MongoRefItem>>()
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?
+            .await?
             .into_iter()
             .map(|mongo_ref_item| mongo_ref_item.ref_item)
             .collect();
@@ -90,12 +128,10 @@ impl RefsManager for MongoRefsManager {
                 "repo_uid": self.repo_uid,
                 "ref_item.is_branch": true
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            .await?;
         let ref_items: Vec<RefItem> = cursor
             .try_collect::<Vec<MongoRefItem>>()
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?
+            .await?
             .into_iter()
             .map(|mongo_ref_item| mongo_ref_item.ref_item)
             .collect();
@@ -104,18 +140,15 @@ impl RefsManager for MongoRefsManager {
     }
 
     async fn del_refs(&self, ref_name: String) -> Result<(), GitInnerError> {
-        if let Some(refs_name_prefix) = Some(ref_name.strip_prefix("refs/heads/").unwrap()) {
-            if refs_name_prefix == self.default_branch {
-                return Err(GitInnerError::DefaultBranchCannotBeDeleted);
-            }
+        if is_default_branch(&ref_name, &self.default_branch) {
+            return Err(GitInnerError::DefaultBranchCannotBeDeleted);
         }
         self.refs
             .delete_one(doc! {
                 "repo_uid": self.repo_uid,
                 "ref_item.name": ref_name
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            .await?;
 
         Ok(())
     }
@@ -127,29 +160,31 @@ impl RefsManager for MongoRefsManager {
     ) -> Result<(), GitInnerError> {
         let is_branch = ref_name.starts_with("refs/heads/");
         let is_tag = ref_name.starts_with("refs/tags/");
-        let mut is_head = ref_name == "HEAD";
-        if let Some(refs_name_prefix) = Some(ref_name.strip_prefix("refs/heads/").unwrap()) {
-            if refs_name_prefix == self.default_branch {
-                is_head = true;
-            }
-        }
-        let ref_item = RefItem {
-            name: ref_name.clone(),
-            value: ref_value,
-            is_branch,
-            is_tag,
-            is_head,
-        };
-
-        let mongo_ref_item = MongoRefItem {
-            repo_uid: self.repo_uid,
-            ref_item,
-        };
+        let is_head = ref_name == "HEAD" || is_default_branch(&ref_name, &self.default_branch);
 
-        self.refs
-            .insert_one(mongo_ref_item)
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+        // 只在不存在时插入，单条 upsert 保证同名 ref 不会出现两份；
+        // 过滤条件中的 repo_uid 与名称会写入新文档
+        let result = self
+            .refs
+            .update_one(
+                doc! {
+                    "repo_uid": self.repo_uid,
+                    "ref_item.name": ref_name.clone()
+                },
+                doc! {
+                    "$setOnInsert": {
+                        "ref_item.value": mongodb::bson::to_bson(&ref_value)?,
+                        "ref_item.is_branch": is_branch,
+                        "ref_item.is_tag": is_tag,
+                        "ref_item.is_head": is_head,
+                    }
+                },
+            )
+            .upsert(true)
+            .await?;
+        if result.upserted_id.is_none() {
+            return Err(GitInnerError::RefAlreadyExists(ref_name));
+        }
 
         Ok(())
     }
@@ -172,12 +207,36 @@ impl RefsManager for MongoRefsManager {
                 },
                 update,
             )
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            .await?;
 
         Ok(())
     }
 
+    async fn cas_update(
+        &self,
+        ref_name: String,
+        expected: HashValue,
+        new: HashValue,
+    ) -> Result<bool, GitInnerError> {
+        // 名称与当前值同时作为过滤条件，单条 update_one 即是原子的比较并交换
+        let result = self
+            .refs
+            .update_one(
+                doc! {
+                    "repo_uid": self.repo_uid,
+                    "ref_item.name": ref_name,
+                    "ref_item.value": mongodb::bson::to_bson(&expected)?
+                },
+                doc! {
+                    "$set": {
+                        "ref_item.value": mongodb::bson::to_bson(&new)?
+                    }
+                },
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
     async fn get_refs(&self, ref_name: String) -> Result<RefItem, GitInnerError> {
         let result = self
             .refs
@@ -185,8 +244,7 @@ impl RefsManager for MongoRefsManager {
                 "repo_uid": self.repo_uid,
                 "ref_item.name": ref_name
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            .await?;
 
         match result {
             Some(mongo_ref_item) => Ok(mongo_ref_item.ref_item),
@@ -201,8 +259,7 @@ impl RefsManager for MongoRefsManager {
                 "repo_uid": self.repo_uid,
                 "ref_item.name": ref_name
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            .await?;
 
         Ok(result.is_some())
     }
@@ -214,8 +271,7 @@ impl RefsManager for MongoRefsManager {
                 "repo_uid": self.repo_uid,
                 "ref_item.name": ref_name
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+            .await?;
 
         match result {
             Some(mongo_ref_item) => Ok(mongo_ref_item.ref_item.value),
@@ -226,36 +282,167 @@ impl RefsManager for MongoRefsManager {
         if branch_name == self.default_branch {
             return Ok(());
         }
-        if !self.exists_refs(branch_name.clone()).await? {
-            return Err(GitInnerError::ObjectNotFound(self.hash_version.default()));
+        self.set_head(branch_name).await
+    }
+
+    async fn set_head(&self, target_ref: String) -> Result<(), GitInnerError> {
+        if !target_ref.starts_with("refs/heads/") {
+            return Err(GitInnerError::InvalidArgument(format!(
+                "HEAD must point at a branch: {}",
+                target_ref
+            )));
         }
-        self.refs
-            .update_many(
-                doc! {
-                    "repo_uid": self.repo_uid,
-                },
-                doc! {
-                    "$set": {
-                        "ref_item.is_head": false
-                    }
-                },
-            )
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
-        self.refs
-            .update_one(
-                doc! {
-                    "repo_uid": self.repo_uid,
-                    "ref_item.name": branch_name
-                },
-                doc! {
-                    "$set": {
-                        "ref_item.is_head": true
-                    }
-                },
-            )
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?;
+        let mut session = self.db_client.start_session().await?;
+        session.start_transaction().await?;
+        // 清空其它 ref 的 is_head 与设置目标放在同一个事务里，保证恰好一个 HEAD
+        let result = async {
+            self.refs
+                .update_many(
+                    doc! {
+                        "repo_uid": self.repo_uid,
+                        "ref_item.name": { "$ne": &target_ref }
+                    },
+                    doc! { "$set": { "ref_item.is_head": false } },
+                )
+                .session(&mut session)
+                .await?;
+            self.refs
+                .update_one(
+                    doc! {
+                        "repo_uid": self.repo_uid,
+                        "ref_item.name": &target_ref
+                    },
+                    doc! { "$set": { "ref_item.is_head": true } },
+                )
+                .session(&mut session)
+                .await
+        }
+        .await;
+        match result {
+            Ok(update) if update.matched_count == 1 => session
+                .commit_transaction()
+                .await
+                .map_err(|e| GitInnerError::MongodbError(e.to_string())),
+            Ok(_) => {
+                session.abort_transaction().await.ok();
+                Err(GitInnerError::ObjectNotFound(self.hash_version.default()))
+            }
+            Err(e) => {
+                session.abort_transaction().await.ok();
+                Err(GitInnerError::MongodbError(e.to_string()))
+            }
+        }
+    }
+
+    async fn append_reflog(&self, entry: ReflogEntry) -> Result<(), GitInnerError> {
+        self.reflog
+            .insert_one(MongoReflogEntry {
+                repo_uid: self.repo_uid,
+                entry,
+            })
+            .await?;
         Ok(())
     }
+
+    async fn reflog(&self, ref_name: String) -> Result<Vec<ReflogEntry>, GitInnerError> {
+        // _id 为 ObjectId，按插入顺序递增
+        let cursor = self
+            .reflog
+            .find(doc! {
+                "repo_uid": self.repo_uid,
+                "entry.ref_name": ref_name
+            })
+            .sort(doc! { "_id": 1 })
+            .await?;
+        Ok(cursor
+            .try_collect::<Vec<MongoReflogEntry>>()
+            .await?
+            .into_iter()
+            .map(|x| x.entry)
+            .collect())
+    }
+}
+
+/// Whether `ref_name` is `refs/heads/<default_branch>`. Other refs such as tags never match.
+fn is_default_branch(ref_name: &str, default_branch: &str) -> bool {
+    ref_name.strip_prefix("refs/heads/") == Some(default_branch)
+}
+
+/// Escape regex metacharacters so a ref name prefix can be used in a `$regex` query.
+fn escape_regex(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_default_branch() {
+        assert!(is_default_branch("refs/heads/main", "main"));
+        assert!(!is_default_branch("refs/heads/dev", "main"));
+        assert!(!is_default_branch("refs/tags/main", "main"));
+        assert!(!is_default_branch("refs/notes/commits", "main"));
+        assert!(!is_default_branch("HEAD", "main"));
+    }
+
+    #[tokio::test]
+    async fn test_del_non_branch_refs() {
+        // 客户端惰性连接且没有可用的服务器：需要访问数据库的调用很快失败，但不会 panic
+        let client = Client::with_uri_str("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100")
+            .await
+            .unwrap();
+        let database = client.database("git");
+        let refs = MongoRefsManager {
+            repo_uid: Uuid::new(),
+            default_branch: "main".to_string(),
+            refs: database.collection("refs"),
+            reflog: database.collection("reflog"),
+            db_client: client,
+            hash_version: HashVersion::Sha1,
+            symbolic_head: false,
+        };
+        assert!(matches!(
+            refs.del_refs("refs/heads/main".to_string()).await,
+            Err(GitInnerError::DefaultBranchCannotBeDeleted)
+        ));
+        // 其他引用都要走到数据库
+        for name in [
+            "refs/tags/v1",
+            "refs/notes/commits",
+            "refs/heads/dev",
+            "HEAD",
+        ] {
+            assert!(
+                matches!(
+                    refs.del_refs(name.to_string()).await,
+                    Err(GitInnerError::MongodbError(_))
+                ),
+                "{}",
+                name
+            );
+            assert!(
+                matches!(
+                    refs.create_refs(name.to_string(), HashVersion::Sha1.default())
+                        .await,
+                    Err(GitInnerError::MongodbError(_))
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_escape_regex() {
+        assert_eq!(escape_regex("refs/tags/v1.0"), "refs/tags/v1\\.0");
+        assert_eq!(escape_regex("refs/heads/a+b"), "refs/heads/a\\+b");
+    }
 }
@@ -0,0 +1,493 @@
+use crate::error::GitInnerError;
+use crate::objects::tree::TreeItemMode;
+use crate::repository::Repository;
+use async_stream::try_stream;
+use bytes::Bytes;
+use chrono::{DateTime, Datelike, Timelike};
+use flate2::Compression;
+use flate2::Crc;
+use flate2::write::DeflateEncoder;
+use std::io::Write;
+use std::pin::Pin;
+use tokio_stream::Stream;
+
+const TAR_BLOCK: usize = 512;
+const TAR_RECORD: usize = 20 * TAR_BLOCK;
+const ZIP_VERSION: u16 = 20;
+const ZIP_UTF8: u16 = 0x0800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+pub type ArchiveStream = Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>> + Send>>;
+
+impl Repository {
+    /// Stream a snapshot of the tree of `rev` as a tar or zip archive, with every path
+    /// prefixed by `prefix` (e.g. `"project/"`). Entries are emitted one at a time while the
+    /// tree is walked, so only a single blob is held in memory at once. Executable blobs keep
+    /// their bit, symlinks become link entries and submodules empty directories; every entry
+    /// carries the committer time.
+    pub async fn archive(
+        &self,
+        rev: &str,
+        format: ArchiveFormat,
+        prefix: &str,
+    ) -> Result<ArchiveStream, GitInnerError> {
+        let hash = self.resolve_rev(rev).await?;
+        let hash = self.peel_to_commit(&hash).await?;
+        let commit = self.odb.get_commit(&hash).await?;
+        let tree = commit.tree.ok_or(GitInnerError::MissingField("tree"))?;
+        let mut writer = ArchiveWriter::new(format, commit.committer.timestamp as u64);
+        let odb = self.odb.clone();
+        let prefix = prefix.to_string();
+        Ok(Box::pin(try_stream! {
+            if prefix.ends_with('/') {
+                yield writer.directory(&prefix)?;
+            }
+            let items = odb.get_tree(&tree).await?.tree_items.into_iter();
+            let mut stack = vec![(prefix, items)];
+            while let Some((dir, items)) = stack.last_mut() {
+                let Some(item) = items.next() else {
+                    stack.pop();
+                    continue;
+                };
+                let path = format!("{}{}", dir, item.name);
+                match item.mode {
+                    TreeItemMode::Tree => {
+                        let dir = format!("{}/", path);
+                        yield writer.directory(&dir)?;
+                        let items = odb.get_tree(&item.id).await?.tree_items.into_iter();
+                        stack.push((dir, items));
+                    }
+                    TreeItemMode::Commit => {
+                        yield writer.directory(&format!("{}/", path))?;
+                    }
+                    TreeItemMode::Blob | TreeItemMode::BlobExecutable | TreeItemMode::Link => {
+                        let blob = odb.get_blob(&item.id).await?;
+                        yield writer.file(&path, item.mode, &blob.data)?;
+                    }
+                }
+            }
+            yield writer.finish()?;
+        }))
+    }
+}
+
+struct ArchiveWriter {
+    format: ArchiveFormat,
+    mtime: u64,
+    // 已输出的字节数，zip 用作本地文件头的偏移
+    offset: u64,
+    central: Vec<u8>,
+    entries: u64,
+}
+
+impl ArchiveWriter {
+    fn new(format: ArchiveFormat, mtime: u64) -> Self {
+        ArchiveWriter {
+            format,
+            mtime,
+            offset: 0,
+            central: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    fn directory(&mut self, path: &str) -> Result<Bytes, GitInnerError> {
+        let out = match self.format {
+            ArchiveFormat::Tar => tar_entry(path, b'5', 0o755, &[], "", self.mtime)?,
+            ArchiveFormat::Zip => self.zip_entry(path, 0o040755, &[], false)?,
+        };
+        Ok(self.emit(out))
+    }
+
+    fn file(
+        &mut self,
+        path: &str,
+        mode: TreeItemMode,
+        data: &[u8],
+    ) -> Result<Bytes, GitInnerError> {
+        let out = match (self.format, mode) {
+            (ArchiveFormat::Tar, TreeItemMode::Link) => {
+                let target = String::from_utf8_lossy(data);
+                tar_entry(path, b'2', 0o777, &[], &target, self.mtime)?
+            }
+            (ArchiveFormat::Tar, TreeItemMode::BlobExecutable) => {
+                tar_entry(path, b'0', 0o755, data, "", self.mtime)?
+            }
+            (ArchiveFormat::Tar, _) => tar_entry(path, b'0', 0o644, data, "", self.mtime)?,
+            (ArchiveFormat::Zip, TreeItemMode::Link) => {
+                self.zip_entry(path, 0o120777, data, false)?
+            }
+            (ArchiveFormat::Zip, TreeItemMode::BlobExecutable) => {
+                self.zip_entry(path, 0o100755, data, true)?
+            }
+            (ArchiveFormat::Zip, _) => self.zip_entry(path, 0o100644, data, true)?,
+        };
+        Ok(self.emit(out))
+    }
+
+    fn finish(&mut self) -> Result<Bytes, GitInnerError> {
+        let out = match self.format {
+            ArchiveFormat::Tar => {
+                // 两个全零块结束归档，再补齐到整条记录
+                let end = self.offset as usize + 2 * TAR_BLOCK;
+                vec![0; end.next_multiple_of(TAR_RECORD) - self.offset as usize]
+            }
+            ArchiveFormat::Zip => {
+                let entries = u16::try_from(self.entries).map_err(|_| zip_too_large())?;
+                let size = u32::try_from(self.central.len()).map_err(|_| zip_too_large())?;
+                let offset = u32::try_from(self.offset).map_err(|_| zip_too_large())?;
+                let mut out = std::mem::take(&mut self.central);
+                out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+                out.extend_from_slice(&[0; 4]);
+                out.extend_from_slice(&entries.to_le_bytes());
+                out.extend_from_slice(&entries.to_le_bytes());
+                out.extend_from_slice(&size.to_le_bytes());
+                out.extend_from_slice(&offset.to_le_bytes());
+                out.extend_from_slice(&[0; 2]);
+                out
+            }
+        };
+        Ok(self.emit(out))
+    }
+
+    fn emit(&mut self, out: Vec<u8>) -> Bytes {
+        self.offset += out.len() as u64;
+        Bytes::from(out)
+    }
+
+    /// Local file header plus data for one zip entry; the matching central directory record
+    /// is kept until `finish`.
+    fn zip_entry(
+        &mut self,
+        path: &str,
+        mode: u32,
+        data: &[u8],
+        deflate: bool,
+    ) -> Result<Vec<u8>, GitInnerError> {
+        let mut crc = Crc::new();
+        crc.update(data);
+        let (method, body) = if deflate {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(data)
+                .map_err(|err| GitInnerError::Io(err.to_string()))?;
+            let body = encoder
+                .finish()
+                .map_err(|err| GitInnerError::Io(err.to_string()))?;
+            (8u16, body)
+        } else {
+            (0u16, data.to_vec())
+        };
+        let offset = u32::try_from(self.offset).map_err(|_| zip_too_large())?;
+        let compressed = u32::try_from(body.len()).map_err(|_| zip_too_large())?;
+        let size = u32::try_from(data.len()).map_err(|_| zip_too_large())?;
+        let name_len = u16::try_from(path.len()).map_err(|_| zip_too_large())?;
+        let (time, date) = dos_time(self.mtime);
+
+        // 本地文件头与中央目录记录共用的字段
+        let mut common = Vec::with_capacity(26);
+        common.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        common.extend_from_slice(&ZIP_UTF8.to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&compressed.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&name_len.to_le_bytes());
+        common.extend_from_slice(&[0; 2]);
+
+        let mut out = Vec::with_capacity(30 + path.len() + body.len());
+        out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(path.as_bytes());
+        out.extend_from_slice(&body);
+
+        let dos_dir = if mode & 0o170000 == 0o040000 { 0x10 } else { 0 };
+        self.central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        // 版本高字节 3 表示 Unix，外部属性高 16 位存放文件模式
+        self.central
+            .extend_from_slice(&((3u16 << 8) | ZIP_VERSION).to_le_bytes());
+        self.central.extend_from_slice(&common);
+        self.central.extend_from_slice(&[0; 6]);
+        self.central
+            .extend_from_slice(&((mode << 16) | dos_dir).to_le_bytes());
+        self.central.extend_from_slice(&offset.to_le_bytes());
+        self.central.extend_from_slice(path.as_bytes());
+        self.entries += 1;
+        Ok(out)
+    }
+}
+
+/// One ustar entry: header, data and padding. Paths or link targets too long for the header
+/// are carried in a preceding pax extended header.
+fn tar_entry(
+    path: &str,
+    kind: u8,
+    mode: u32,
+    data: &[u8],
+    link: &str,
+    mtime: u64,
+) -> Result<Vec<u8>, GitInnerError> {
+    let mut out = Vec::new();
+    let mut pax = Vec::new();
+    if path.len() > 100 {
+        pax.extend_from_slice(&pax_record("path", path));
+    }
+    if link.len() > 100 {
+        pax.extend_from_slice(&pax_record("linkpath", link));
+    }
+    if !pax.is_empty() {
+        out.extend_from_slice(&tar_header(path, b'x', 0o644, pax.len() as u64, "", mtime)?);
+        out.extend_from_slice(&pax);
+        out.resize(out.len().next_multiple_of(TAR_BLOCK), 0);
+    }
+    out.extend_from_slice(&tar_header(
+        path,
+        kind,
+        mode,
+        data.len() as u64,
+        link,
+        mtime,
+    )?);
+    out.extend_from_slice(data);
+    out.resize(out.len().next_multiple_of(TAR_BLOCK), 0);
+    Ok(out)
+}
+
+fn tar_header(
+    path: &str,
+    kind: u8,
+    mode: u32,
+    size: u64,
+    link: &str,
+    mtime: u64,
+) -> Result<[u8; TAR_BLOCK], GitInnerError> {
+    let mut header = [0u8; TAR_BLOCK];
+    put_str(&mut header[0..100], path);
+    put_octal(&mut header[100..108], mode as u64)?;
+    put_octal(&mut header[108..116], 0)?;
+    put_octal(&mut header[116..124], 0)?;
+    put_octal(&mut header[124..136], size)?;
+    put_octal(&mut header[136..148], mtime)?;
+    header[156] = kind;
+    put_str(&mut header[157..257], link);
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    put_str(&mut header[265..297], "root");
+    put_str(&mut header[297..329], "root");
+    header[148..156].fill(b' ');
+    let sum = header.iter().map(|x| *x as u64).sum::<u64>();
+    put_octal(&mut header[148..155], sum)?;
+    Ok(header)
+}
+
+/// Copy as much of `value` as fits; the full value goes into a pax record when it is longer.
+fn put_str(field: &mut [u8], value: &str) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value.as_bytes()[..len]);
+}
+
+fn put_octal(field: &mut [u8], value: u64) -> Result<(), GitInnerError> {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    if digits.len() >= field.len() {
+        return Err(GitInnerError::InvalidArgument(
+            "archive entry too large for tar".to_string(),
+        ));
+    }
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+    Ok(())
+}
+
+/// `"<len> <key>=<value>\n"`, where the length counts the whole record including itself.
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let body = key.len() + value.len() + 3;
+    let mut len = body + body.to_string().len();
+    if len.to_string().len() + body != len {
+        len += 1;
+    }
+    format!("{} {}={}\n", len, key, value).into_bytes()
+}
+
+/// MS-DOS `(time, date)` pair for a unix timestamp, clamped to the 1980 epoch.
+fn dos_time(timestamp: u64) -> (u16, u16) {
+    let Some(time) = DateTime::from_timestamp(timestamp as i64, 0).filter(|x| x.year() >= 1980)
+    else {
+        return (0, (1 << 5) | 1);
+    };
+    let date =
+        (((time.year() - 1980) as u16) << 9) | ((time.month() as u16) << 5) | time.day() as u16;
+    let clock =
+        ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | (time.second() as u16 / 2);
+    (clock, date)
+}
+
+fn zip_too_large() -> GitInnerError {
+    GitInnerError::InvalidArgument("archive too large for zip".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+    use tokio_stream::StreamExt;
+
+    async fn snapshot(repo: &Repository) -> String {
+        let run = put_blob(repo, "#!/bin/sh\necho hi\n").await;
+        let readme = put_blob(repo, "hello\n").await;
+        let target = put_blob(repo, "README").await;
+        let bin = put_tree(repo, vec![(TreeItemMode::BlobExecutable, "run.sh", run)]).await;
+        let tree = put_tree(
+            repo,
+            vec![
+                (TreeItemMode::Blob, "README", readme),
+                (TreeItemMode::Tree, "bin", bin),
+                (TreeItemMode::Link, "link", target),
+            ],
+        )
+        .await;
+        put_commit(repo, &tree, &[], "init", 1).await.to_string()
+    }
+
+    async fn collect(mut stream: ArchiveStream) -> Vec<u8> {
+        let mut out = vec![];
+        while let Some(chunk) = stream.next().await {
+            out.extend_from_slice(&chunk.unwrap());
+        }
+        out
+    }
+
+    fn field(bytes: &[u8]) -> String {
+        let end = bytes.iter().position(|x| *x == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).to_string()
+    }
+
+    fn octal(bytes: &[u8]) -> u64 {
+        u64::from_str_radix(field(bytes).trim(), 8).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tar_archive() {
+        let repo = memory_repository();
+        let rev = snapshot(&repo).await;
+        let tar = collect(
+            repo.archive(&rev, ArchiveFormat::Tar, "proj/")
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(tar.len() % TAR_RECORD, 0);
+
+        // (name, type, mode, content or link target)
+        let mut entries = vec![];
+        let mut pos = 0;
+        while tar[pos] != 0 {
+            let header = &tar[pos..pos + TAR_BLOCK];
+            let size = octal(&header[124..136]) as usize;
+            let body = &tar[pos + TAR_BLOCK..pos + TAR_BLOCK + size];
+            let content = if header[156] == b'2' {
+                field(&header[157..257])
+            } else {
+                String::from_utf8_lossy(body).to_string()
+            };
+            let sum = header
+                .iter()
+                .enumerate()
+                .map(|(i, x)| if (148..156).contains(&i) { b' ' } else { *x } as u64)
+                .sum::<u64>();
+            assert_eq!(octal(&header[148..156]), sum);
+            entries.push((
+                field(&header[0..100]),
+                header[156],
+                octal(&header[100..108]),
+                content,
+            ));
+            pos += TAR_BLOCK + size.next_multiple_of(TAR_BLOCK);
+        }
+        let entry = |name: &str, kind: u8, mode: u64, content: &str| {
+            (name.to_string(), kind, mode, content.to_string())
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry("proj/", b'5', 0o755, ""),
+                entry("proj/README", b'0', 0o644, "hello\n"),
+                entry("proj/bin/", b'5', 0o755, ""),
+                entry("proj/bin/run.sh", b'0', 0o755, "#!/bin/sh\necho hi\n"),
+                entry("proj/link", b'2', 0o777, "README"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_zip_archive() {
+        let repo = memory_repository();
+        let rev = snapshot(&repo).await;
+        let zip = collect(repo.archive(&rev, ArchiveFormat::Zip, "").await.unwrap()).await;
+
+        let u16_at = |pos: usize| u16::from_le_bytes([zip[pos], zip[pos + 1]]) as usize;
+        let u32_at =
+            |pos: usize| u32::from_le_bytes(zip[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(end), 0x06054b50);
+        assert_eq!(u16_at(end + 10), 4);
+
+        let mut files = vec![];
+        let mut pos = u32_at(end + 16);
+        for _ in 0..4 {
+            assert_eq!(u32_at(pos), 0x02014b50);
+            let name_len = u16_at(pos + 28);
+            let mode = u32_at(pos + 38) >> 16;
+            let name = String::from_utf8_lossy(&zip[pos + 46..pos + 46 + name_len]).to_string();
+            let local = u32_at(pos + 42);
+            let method = u16_at(local + 8);
+            let data_start = local + 30 + u16_at(local + 26);
+            let data = &zip[data_start..data_start + u32_at(local + 18)];
+            let content = if method == 8 {
+                let mut out = String::new();
+                DeflateDecoder::new(data).read_to_string(&mut out).unwrap();
+                out
+            } else {
+                String::from_utf8_lossy(data).to_string()
+            };
+            let mut crc = Crc::new();
+            crc.update(content.as_bytes());
+            assert_eq!(crc.sum() as usize, u32_at(local + 14));
+            files.push((name, mode, content));
+            pos += 46 + name_len;
+        }
+        let file =
+            |name: &str, mode: usize, content: &str| (name.to_string(), mode, content.to_string());
+        assert_eq!(
+            files,
+            vec![
+                file("README", 0o100644, "hello\n"),
+                file("bin/", 0o040755, ""),
+                file("bin/run.sh", 0o100755, "#!/bin/sh\necho hi\n"),
+                file("link", 0o120777, "README"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pax_record_length() {
+        for value in ["a", &"x".repeat(95), &"y".repeat(994)] {
+            let record = pax_record("path", value);
+            let len = String::from_utf8_lossy(&record)
+                .split(' ')
+                .next()
+                .unwrap()
+                .parse::<usize>()
+                .unwrap();
+            assert_eq!(len, record.len());
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::error::GitInnerError;
+use crate::objects::tree::TreeItemMode;
+use crate::repository::Repository;
+use crate::repository::diff::{DiffLine, diff_lines};
+use crate::repository::walk::visit;
+use crate::sha::HashValue;
+use std::collections::HashSet;
+
+/// Consecutive lines of a file last changed by the same commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameSpan {
+    /// First line of the span, 1-based.
+    pub start_line: usize,
+    pub line_count: usize,
+    pub commit: HashValue,
+}
+
+impl Repository {
+    /// Attribute every line of the file at `path` in `rev` to the commit that introduced it.
+    ///
+    /// History is followed through the parent holding the same blob, or else the first
+    /// parent that has the file; lines that a commit adds compared to that parent belong to
+    /// the commit. Renames are not followed, and a version too large to diff is attributed
+    /// as a whole. Binary files fail with `InvalidArgument`.
+    pub async fn blame(&self, rev: &str, path: &str) -> Result<Vec<BlameSpan>, GitInnerError> {
+        let head = self.resolve_rev(rev).await?;
+        let mut commit = self.peel_to_commit(&head).await?;
+        let mut blob = self
+            .blame_blob(&commit, path)
+            .await?
+            .ok_or_else(|| GitInnerError::PathNotFound(path.to_string()))?;
+        let data = self.odb.get_blob(&blob).await?;
+        if data.is_binary() {
+            return Err(GitInnerError::InvalidArgument(format!(
+                "cannot blame binary file: {}",
+                path
+            )));
+        }
+        let mut text = String::from_utf8_lossy(&data.data).to_string();
+        let line_count = text.lines().count();
+        let mut owners: Vec<Option<HashValue>> = vec![None; line_count];
+        // 每一行在当前查看版本中的行号，已归属的行为 None
+        let mut positions: Vec<Option<usize>> = (0..line_count).map(Some).collect();
+        let mut visited = HashSet::new();
+
+        while positions.iter().any(Option::is_some) {
+            visit(&mut visited, &commit, self.max_walk_nodes)?;
+            let parents = self.odb.get_commit(&commit).await?.parents;
+            let mut next = None;
+            for parent in &parents {
+                if let Some(parent_blob) = self.blame_blob(parent, path).await? {
+                    if parent_blob == blob {
+                        next = Some((parent.clone(), parent_blob));
+                        break;
+                    }
+                    if next.is_none() {
+                        next = Some((parent.clone(), parent_blob));
+                    }
+                }
+            }
+            let Some((parent, parent_blob)) = next else {
+                assign(&mut owners, &mut positions, &commit, |_| None);
+                break;
+            };
+            if parent_blob != blob {
+                let parent_text =
+                    String::from_utf8_lossy(&self.odb.get_blob(&parent_blob).await?.data)
+                        .to_string();
+                let Some(ops) = diff_lines(&parent_text, &text) else {
+                    assign(&mut owners, &mut positions, &commit, |_| None);
+                    break;
+                };
+                // 新版本每一行在父版本中对应的行号
+                let mut to_parent = Vec::with_capacity(ops.len());
+                let mut old_line = 0;
+                for op in ops {
+                    match op {
+                        DiffLine::Context(_) => {
+                            to_parent.push(Some(old_line));
+                            old_line += 1;
+                        }
+                        DiffLine::Added(_) => to_parent.push(None),
+                        DiffLine::Removed(_) => old_line += 1,
+                    }
+                }
+                assign(&mut owners, &mut positions, &commit, |x| to_parent[x]);
+                text = parent_text;
+            }
+            commit = parent;
+            blob = parent_blob;
+        }
+
+        let mut spans: Vec<BlameSpan> = vec![];
+        for (line, owner) in owners.into_iter().enumerate() {
+            let Some(owner) = owner else {
+                continue;
+            };
+            match spans.last_mut() {
+                Some(last)
+                    if last.commit == owner && last.start_line + last.line_count == line + 1 =>
+                {
+                    last.line_count += 1;
+                }
+                _ => spans.push(BlameSpan {
+                    start_line: line + 1,
+                    line_count: 1,
+                    commit: owner,
+                }),
+            }
+        }
+        Ok(spans)
+    }
+
+    /// Blob id of the file at `path` in `commit`, or `None` if there is no such file.
+    async fn blame_blob(
+        &self,
+        commit: &HashValue,
+        path: &str,
+    ) -> Result<Option<HashValue>, GitInnerError> {
+        match self.tree_item_at_path(&commit.to_string(), path).await {
+            Ok(item)
+                if matches!(
+                    item.mode,
+                    TreeItemMode::Blob | TreeItemMode::BlobExecutable | TreeItemMode::Link
+                ) =>
+            {
+                Ok(Some(item.id))
+            }
+            Ok(_)
+            | Err(GitInnerError::PathNotFound(_))
+            | Err(GitInnerError::SubmoduleBoundary { .. }) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Move every unattributed line to its position in the parent version given by `to_parent`;
+/// lines without one were introduced by `commit`.
+fn assign(
+    owners: &mut [Option<HashValue>],
+    positions: &mut [Option<usize>],
+    commit: &HashValue,
+    to_parent: impl Fn(usize) -> Option<usize>,
+) {
+    for (owner, position) in owners.iter_mut().zip(positions.iter_mut()) {
+        let Some(current) = *position else {
+            continue;
+        };
+        *position = to_parent(current);
+        if position.is_none() {
+            *owner = Some(commit.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    #[tokio::test]
+    async fn test_blame_three_commits() {
+        let repo = memory_repository();
+        let mut parents: Vec<HashValue> = vec![];
+        let mut commits = vec![];
+        for (i, content) in ["a\nb\nc\n", "a\nB\nc\n", "a\nB\nc\nd\n"]
+            .into_iter()
+            .enumerate()
+        {
+            let blob = put_blob(&repo, content).await;
+            let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "file.txt", blob)]).await;
+            let commit = put_commit(&repo, &tree, &parents, &format!("c{}", i), i + 1).await;
+            parents = vec![commit.clone()];
+            commits.push(commit);
+        }
+        let spans = repo
+            .blame(&commits[2].to_string(), "file.txt")
+            .await
+            .unwrap();
+        let span = |start_line, commit: &HashValue| BlameSpan {
+            start_line,
+            line_count: 1,
+            commit: commit.clone(),
+        };
+        assert_eq!(
+            spans,
+            vec![
+                span(1, &commits[0]),
+                span(2, &commits[1]),
+                span(3, &commits[0]),
+                span(4, &commits[2]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blame_binary() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "\0\x01\x02").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "bin", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "bin", 1).await;
+        assert!(matches!(
+            repo.blame(&commit.to_string(), "bin").await,
+            Err(GitInnerError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use crate::error::GitInnerError;
+use crate::objects::tree::{TreeItem, TreeItemMode};
+use crate::repository::Repository;
+use crate::sha::HashValue;
+use bytes::Bytes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobContent {
+    pub id: HashValue,
+    pub mode: TreeItemMode,
+    pub size: usize,
+    pub data: Bytes,
+    pub is_binary: bool,
+    pub truncated: bool,
+}
+
+impl Repository {
+    /// Read the blob at `path` in the tree of `rev`.
+    ///
+    /// When `max_bytes` is set the returned data is cut to that length and `truncated` is
+    /// set; `size` always reports the full blob size.
+    pub async fn get_blob_at_path(
+        &self,
+        rev: &str,
+        path: &str,
+        max_bytes: Option<usize>,
+    ) -> Result<BlobContent, GitInnerError> {
+        let item = self.tree_item_at_path(rev, path).await?;
+        if item.mode.is_submodule() {
+            return Err(GitInnerError::SubmoduleBoundary {
+                path: path.trim_matches('/').to_string(),
+                commit: item.id,
+            });
+        }
+        if !matches!(
+            item.mode,
+            TreeItemMode::Blob | TreeItemMode::BlobExecutable | TreeItemMode::Link
+        ) {
+            return Err(GitInnerError::PathNotFound(path.to_string()));
+        }
+        let blob = self.odb.get_blob(&item.id).await?;
+        let size = blob.data.len();
+        let is_binary = blob.is_binary();
+        let (data, truncated) = match max_bytes {
+            Some(max) if max < size => (blob.data.slice(..max), true),
+            _ => (blob.data, false),
+        };
+        Ok(BlobContent {
+            id: item.id,
+            mode: item.mode,
+            size,
+            data,
+            is_binary,
+            truncated,
+        })
+    }
+
+    /// Read the target path of the symbolic link at `path` in the tree of `rev`.
+    pub async fn read_symlink(&self, rev: &str, path: &str) -> Result<String, GitInnerError> {
+        let item = self.tree_item_at_path(rev, path).await?;
+        if !item.mode.is_symlink() {
+            return Err(GitInnerError::InvalidArgument(format!(
+                "not a symbolic link: {}",
+                path
+            )));
+        }
+        let blob = self.odb.get_blob(&item.id).await?;
+        String::from_utf8(blob.data.to_vec()).map_err(|_| GitInnerError::InvalidUtf8)
+    }
+
+    /// Walk the tree of `rev` down to the entry named by the slash separated `path`.
+    ///
+    /// A path that continues past a submodule entry fails with `SubmoduleBoundary`, since the
+    /// rest of it lives in another repository.
+    pub async fn tree_item_at_path(
+        &self,
+        rev: &str,
+        path: &str,
+    ) -> Result<TreeItem, GitInnerError> {
+        let hash = self.resolve_rev(rev).await?;
+        let hash = self.peel_to_commit(&hash).await?;
+        let mut tree = self
+            .odb
+            .get_commit(&hash)
+            .await?
+            .tree
+            .ok_or_else(|| GitInnerError::PathNotFound(path.to_string()))?;
+        let mut parts = path.split('/').filter(|x| !x.is_empty()).peekable();
+        let mut walked = Vec::new();
+        while let Some(part) = parts.next() {
+            let item = self
+                .odb
+                .get_tree(&tree)
+                .await?
+                .tree_items
+                .into_iter()
+                .find(|x| x.name == part)
+                .ok_or_else(|| GitInnerError::PathNotFound(path.to_string()))?;
+            if parts.peek().is_none() {
+                return Ok(item);
+            }
+            walked.push(part);
+            if item.mode.is_submodule() {
+                return Err(GitInnerError::SubmoduleBoundary {
+                    path: walked.join("/"),
+                    commit: item.id,
+                });
+            }
+            if item.mode != TreeItemMode::Tree {
+                return Err(GitInnerError::PathNotFound(path.to_string()));
+            }
+            tree = item.id;
+        }
+        Err(GitInnerError::PathNotFound(path.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+    use crate::repository::tree::PathFilter;
+
+    async fn sample(repo: &Repository) {
+        let readme = put_blob(repo, "hello world\n").await;
+        let bin = put_blob(repo, "\0\x01\x02").await;
+        let target = put_blob(repo, "src/nested/lib.rs").await;
+        let sub = put_tree(repo, vec![(TreeItemMode::Blob, "lib.rs", readme.clone())]).await;
+        let vendor = put_commit(repo, &sub, &[], "vendor", 1).await;
+        let src = put_tree(repo, vec![(TreeItemMode::Tree, "nested", sub)]).await;
+        let tree = put_tree(
+            repo,
+            vec![
+                (TreeItemMode::Blob, "README", readme),
+                (TreeItemMode::Blob, "data.bin", bin),
+                (TreeItemMode::Link, "lib", target),
+                (TreeItemMode::Tree, "src", src),
+                (TreeItemMode::Commit, "vendor", vendor),
+            ],
+        )
+        .await;
+        let commit = put_commit(repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_text_blob() {
+        let repo = memory_repository();
+        sample(&repo).await;
+        let blob = repo.get_blob_at_path("main", "README", None).await.unwrap();
+        assert_eq!(blob.data, Bytes::from("hello world\n"));
+        assert_eq!(blob.size, 12);
+        assert!(!blob.is_binary);
+        assert!(!blob.truncated);
+
+        let blob = repo
+            .get_blob_at_path("main", "README", Some(5))
+            .await
+            .unwrap();
+        assert_eq!(blob.data, Bytes::from("hello"));
+        assert_eq!(blob.size, 12);
+        assert!(blob.truncated);
+
+        let blob = repo
+            .get_blob_at_path("main", "data.bin", None)
+            .await
+            .unwrap();
+        assert!(blob.is_binary);
+    }
+
+    #[tokio::test]
+    async fn test_get_nested_blob() {
+        let repo = memory_repository();
+        sample(&repo).await;
+        let blob = repo
+            .get_blob_at_path("HEAD", "src/nested/lib.rs", None)
+            .await
+            .unwrap();
+        assert_eq!(blob.data, Bytes::from("hello world\n"));
+    }
+
+    #[tokio::test]
+    async fn test_read_symlink() {
+        let repo = memory_repository();
+        sample(&repo).await;
+        let item = repo.tree_item_at_path("main", "lib").await.unwrap();
+        assert!(item.mode.is_symlink());
+        assert_eq!(
+            repo.read_symlink("main", "lib").await.unwrap(),
+            "src/nested/lib.rs"
+        );
+        assert!(matches!(
+            repo.read_symlink("main", "README").await,
+            Err(GitInnerError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_submodule_boundary() {
+        let repo = memory_repository();
+        sample(&repo).await;
+        let item = repo.tree_item_at_path("main", "vendor").await.unwrap();
+        assert!(item.mode.is_submodule());
+        match repo.tree_item_at_path("main", "vendor/lib.rs").await {
+            Err(GitInnerError::SubmoduleBoundary { path, commit }) => {
+                assert_eq!(path, "vendor");
+                assert_eq!(commit, item.id);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(matches!(
+            repo.get_blob_at_path("main", "vendor", None).await,
+            Err(GitInnerError::SubmoduleBoundary { .. })
+        ));
+        let entries = repo
+            .list_tree("main", "", &PathFilter::default())
+            .await
+            .unwrap();
+        assert!(entries.contains(&item));
+        assert!(matches!(
+            repo.list_tree("main", "vendor", &PathFilter::default())
+                .await,
+            Err(GitInnerError::SubmoduleBoundary { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_missing_path() {
+        let repo = memory_repository();
+        sample(&repo).await;
+        for path in ["missing", "src/nested", "README/x", "src/nested/none.rs"] {
+            assert!(matches!(
+                repo.get_blob_at_path("main", path, None).await,
+                Err(GitInnerError::PathNotFound(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::auth::AccessLevel;
+use crate::error::GitInnerError;
+use crate::refs::is_valid_ref_name;
+use crate::repository::Repository;
+use crate::sha::HashValue;
+
+impl Repository {
+    /// Create `refs/heads/<name>` pointing at the commit `start_point` resolves to.
+    ///
+    /// Requires write access. Fails with `RefAlreadyExists` if the branch is present.
+    pub async fn create_branch(
+        &self,
+        access: &AccessLevel,
+        name: &str,
+        start_point: &str,
+    ) -> Result<HashValue, GitInnerError> {
+        if matches!(access, AccessLevel::Read) {
+            return Err(GitInnerError::PermissionDenied);
+        }
+        if !is_valid_ref_name(name) {
+            return Err(GitInnerError::InvalidArgument(format!(
+                "invalid branch name: {}",
+                name
+            )));
+        }
+        let ref_name = format!("refs/heads/{}", name);
+        if self.refs.exists_refs(ref_name.clone()).await? {
+            return Err(GitInnerError::RefAlreadyExists(ref_name));
+        }
+        let hash = self.resolve_rev(start_point).await?;
+        let hash = self.peel_to_commit(&hash).await?;
+        if !self.odb.has_commit(&hash).await? {
+            return Err(GitInnerError::InvalidArgument(format!(
+                "start point is not a commit: {}",
+                start_point
+            )));
+        }
+        self.refs.create_refs(ref_name, hash.clone()).await?;
+        Ok(hash)
+    }
+
+    /// Make branch `name` (short or `refs/heads/...` form) the repository's HEAD.
+    ///
+    /// Requires admin access; the branch must already exist.
+    pub async fn set_default_branch(
+        &self,
+        access: &AccessLevel,
+        name: &str,
+    ) -> Result<(), GitInnerError> {
+        if !matches!(access, AccessLevel::Admin) {
+            return Err(GitInnerError::PermissionDenied);
+        }
+        let ref_name = if name.starts_with("refs/heads/") {
+            name.to_string()
+        } else {
+            format!("refs/heads/{}", name)
+        };
+        self.refs.set_head(ref_name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    async fn init(repo: &Repository) -> HashValue {
+        let blob = put_blob(repo, "hello").await;
+        let tree = put_tree(repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+        commit
+    }
+
+    #[tokio::test]
+    async fn test_create_branch() {
+        let repo = memory_repository();
+        let commit = init(&repo).await;
+        let hash = repo
+            .create_branch(&AccessLevel::Write, "feature/x", "main")
+            .await
+            .unwrap();
+        assert_eq!(hash, commit);
+        assert_eq!(
+            repo.refs_get_value("refs/heads/feature/x".to_string())
+                .await
+                .unwrap(),
+            commit
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_branch_duplicate() {
+        let repo = memory_repository();
+        init(&repo).await;
+        assert!(matches!(
+            repo.create_branch(&AccessLevel::Admin, "main", "main")
+                .await,
+            Err(GitInnerError::RefAlreadyExists(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_create_branch_rejected() {
+        let repo = memory_repository();
+        init(&repo).await;
+        for name in ["a..b", "has space", "bell\x07"] {
+            assert!(matches!(
+                repo.create_branch(&AccessLevel::Write, name, "main").await,
+                Err(GitInnerError::InvalidArgument(_))
+            ));
+        }
+        assert!(matches!(
+            repo.create_branch(&AccessLevel::Read, "ok", "main").await,
+            Err(GitInnerError::PermissionDenied)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_set_default_branch() {
+        let repo = memory_repository();
+        init(&repo).await;
+        repo.create_branch(&AccessLevel::Write, "dev", "main")
+            .await
+            .unwrap();
+        assert_eq!(repo.refs.head().await.unwrap().name, "refs/heads/main");
+
+        repo.set_default_branch(&AccessLevel::Admin, "dev")
+            .await
+            .unwrap();
+        assert_eq!(repo.refs.head().await.unwrap().name, "refs/heads/dev");
+        let heads = repo.refs.refs().await.unwrap();
+        assert_eq!(heads.iter().filter(|x| x.is_head).count(), 1);
+
+        repo.set_default_branch(&AccessLevel::Admin, "refs/heads/main")
+            .await
+            .unwrap();
+        assert_eq!(repo.refs.head().await.unwrap().name, "refs/heads/main");
+    }
+
+    #[tokio::test]
+    async fn test_set_default_branch_rejected() {
+        let repo = memory_repository();
+        init(&repo).await;
+        assert!(matches!(
+            repo.set_default_branch(&AccessLevel::Write, "main").await,
+            Err(GitInnerError::PermissionDenied)
+        ));
+        assert!(matches!(
+            repo.set_default_branch(&AccessLevel::Admin, "missing")
+                .await,
+            Err(GitInnerError::ObjectNotFound(_))
+        ));
+        assert!(matches!(
+            repo.refs.set_head("refs/tags/v1".to_string()).await,
+            Err(GitInnerError::InvalidArgument(_))
+        ));
+        assert_eq!(repo.refs.head().await.unwrap().name, "refs/heads/main");
+    }
+}
@@ -0,0 +1,411 @@
+use crate::error::GitInnerError;
+use crate::objects::tree::{TreeItem, TreeItemMode};
+use crate::repository::Repository;
+use crate::sha::HashValue;
+use std::collections::BTreeMap;
+
+/// Number of unchanged lines kept around each change in a patch hunk.
+const DIFF_CONTEXT_LINES: usize = 3;
+/// Line-diff work limit (old lines * new lines); larger blobs are reported without a patch.
+const DIFF_MAX_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStatus {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Context(String),
+    Added(String),
+    Removed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffEntry {
+    pub path: String,
+    pub status: DiffStatus,
+    pub old_id: Option<HashValue>,
+    pub new_id: Option<HashValue>,
+    pub old_mode: Option<TreeItemMode>,
+    pub new_mode: Option<TreeItemMode>,
+    pub hunks: Vec<DiffHunk>,
+}
+
+impl Repository {
+    /// Compare the trees of two revisions and return one entry per changed file.
+    ///
+    /// Renames are reported as a removal plus an addition. When `path` is set only entries
+    /// at or below that path are returned. With `include_patch`, modified text blobs carry
+    /// unified-diff hunks.
+    pub async fn get_diff(
+        &self,
+        base: &str,
+        head: &str,
+        path: Option<&str>,
+        include_patch: bool,
+    ) -> Result<Vec<DiffEntry>, GitInnerError> {
+        let base_tree = self.rev_tree(base).await?;
+        let head_tree = self.rev_tree(head).await?;
+        let filter = path.map(|x| x.trim_matches('/')).filter(|x| !x.is_empty());
+        let mut result = Vec::new();
+        let mut stack = vec![(String::new(), base_tree, head_tree)];
+        while let Some((prefix, old_tree, new_tree)) = stack.pop() {
+            let old_items = self.diff_tree_items(old_tree.as_ref()).await?;
+            let new_items = self.diff_tree_items(new_tree.as_ref()).await?;
+            let mut names = old_items.keys().chain(new_items.keys()).collect::<Vec<_>>();
+            names.sort();
+            names.dedup();
+            for name in names {
+                let path = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}/{}", prefix, name)
+                };
+                let old = old_items.get(name);
+                let new = new_items.get(name);
+                if let (Some(old), Some(new)) = (old, new)
+                    && old.id == new.id
+                    && old.mode == new.mode
+                {
+                    continue;
+                }
+                let old_is_tree = old.is_some_and(|x| x.mode == TreeItemMode::Tree);
+                let new_is_tree = new.is_some_and(|x| x.mode == TreeItemMode::Tree);
+                if (old_is_tree || new_is_tree) && diff_path_may_match(&path, filter) {
+                    stack.push((
+                        path.clone(),
+                        old.filter(|_| old_is_tree).map(|x| x.id.clone()),
+                        new.filter(|_| new_is_tree).map(|x| x.id.clone()),
+                    ));
+                }
+                if !diff_path_matches(&path, filter) {
+                    continue;
+                }
+                let old = old.filter(|_| !old_is_tree);
+                let new = new.filter(|_| !new_is_tree);
+                let status = match (old, new) {
+                    (None, None) => continue,
+                    (None, Some(_)) => DiffStatus::Added,
+                    (Some(_), None) => DiffStatus::Removed,
+                    (Some(_), Some(_)) => DiffStatus::Modified,
+                };
+                let mut entry = DiffEntry {
+                    path,
+                    status,
+                    old_id: old.map(|x| x.id.clone()),
+                    new_id: new.map(|x| x.id.clone()),
+                    old_mode: old.map(|x| x.mode),
+                    new_mode: new.map(|x| x.mode),
+                    hunks: vec![],
+                };
+                if include_patch && status == DiffStatus::Modified && entry.old_id != entry.new_id {
+                    entry.hunks = self.diff_blob_hunks(old.unwrap(), new.unwrap()).await?;
+                }
+                result.push(entry);
+            }
+        }
+        result.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(result)
+    }
+
+    async fn rev_tree(&self, rev: &str) -> Result<Option<HashValue>, GitInnerError> {
+        let hash = self.resolve_rev(rev).await?;
+        let hash = self.peel_to_commit(&hash).await?;
+        Ok(self.odb.get_commit(&hash).await?.tree)
+    }
+
+    async fn diff_tree_items(
+        &self,
+        tree: Option<&HashValue>,
+    ) -> Result<BTreeMap<String, TreeItem>, GitInnerError> {
+        let Some(tree) = tree else {
+            return Ok(BTreeMap::new());
+        };
+        Ok(self
+            .odb
+            .get_tree(tree)
+            .await?
+            .tree_items
+            .into_iter()
+            .map(|x| (x.name.clone(), x))
+            .collect())
+    }
+
+    async fn diff_blob_hunks(
+        &self,
+        old: &TreeItem,
+        new: &TreeItem,
+    ) -> Result<Vec<DiffHunk>, GitInnerError> {
+        let is_blob =
+            |x: &TreeItem| matches!(x.mode, TreeItemMode::Blob | TreeItemMode::BlobExecutable);
+        if !is_blob(old) || !is_blob(new) {
+            return Ok(vec![]);
+        }
+        let old = self.odb.get_blob(&old.id).await?;
+        let new = self.odb.get_blob(&new.id).await?;
+        if old.is_binary() || new.is_binary() {
+            return Ok(vec![]);
+        }
+        let (Ok(old), Ok(new)) = (
+            std::str::from_utf8(&old.data),
+            std::str::from_utf8(&new.data),
+        ) else {
+            return Ok(vec![]);
+        };
+        Ok(diff_hunks(old, new))
+    }
+}
+
+fn diff_path_matches(path: &str, filter: Option<&str>) -> bool {
+    match filter {
+        None => true,
+        Some(filter) => {
+            path == filter
+                || path
+                    .strip_prefix(filter)
+                    .is_some_and(|x| x.starts_with('/'))
+        }
+    }
+}
+
+fn diff_path_may_match(dir: &str, filter: Option<&str>) -> bool {
+    match filter {
+        None => true,
+        Some(filter) => {
+            diff_path_matches(dir, Some(filter))
+                || filter.strip_prefix(dir).is_some_and(|x| x.starts_with('/'))
+        }
+    }
+}
+
+/// Line-by-line edit script between two texts from a longest-common-subsequence match, or
+/// `None` when the texts are too large to compare.
+pub(crate) fn diff_lines(old: &str, new: &str) -> Option<Vec<DiffLine>> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    if old.len().saturating_mul(new.len()) > DIFF_MAX_CELLS {
+        return None;
+    }
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            ops.push(DiffLine::Context(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(DiffLine::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            ops.push(DiffLine::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    Some(ops)
+}
+
+/// Build unified-diff hunks between two texts using a longest-common-subsequence line match.
+pub fn diff_hunks(old: &str, new: &str) -> Vec<DiffHunk> {
+    let Some(ops) = diff_lines(old, new) else {
+        return vec![];
+    };
+    let changes = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| !matches!(x, DiffLine::Context(_)))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for idx in changes {
+        let start = idx.saturating_sub(DIFF_CONTEXT_LINES);
+        let end = (idx + DIFF_CONTEXT_LINES + 1).min(ops.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let (mut old_line, mut new_line, mut pos) = (1, 1, 0);
+    for (start, end) in ranges {
+        for op in &ops[pos..start] {
+            match op {
+                DiffLine::Context(_) => {
+                    old_line += 1;
+                    new_line += 1;
+                }
+                DiffLine::Added(_) => new_line += 1,
+                DiffLine::Removed(_) => old_line += 1,
+            }
+        }
+        let lines = ops[start..end].to_vec();
+        let old_lines = lines
+            .iter()
+            .filter(|x| !matches!(x, DiffLine::Added(_)))
+            .count();
+        let new_lines = lines
+            .iter()
+            .filter(|x| !matches!(x, DiffLine::Removed(_)))
+            .count();
+        hunks.push(DiffHunk {
+            old_start: if old_lines == 0 {
+                old_line - 1
+            } else {
+                old_line
+            },
+            old_lines,
+            new_start: if new_lines == 0 {
+                new_line - 1
+            } else {
+                new_line
+            },
+            new_lines,
+            lines,
+        });
+        old_line += old_lines;
+        new_line += new_lines;
+        pos = end;
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    async fn commit_tree(
+        repo: &Repository,
+        items: Vec<(TreeItemMode, &str, HashValue)>,
+        parents: &[HashValue],
+    ) -> HashValue {
+        let tree = put_tree(repo, items).await;
+        put_commit(repo, &tree, parents, "commit", parents.len() + 1).await
+    }
+
+    #[tokio::test]
+    async fn test_diff_add_and_delete() {
+        let repo = memory_repository();
+        let a = put_blob(&repo, "a\n").await;
+        let b = put_blob(&repo, "b\n").await;
+        let base = commit_tree(&repo, vec![(TreeItemMode::Blob, "a.txt", a.clone())], &[]).await;
+        let head = commit_tree(&repo, vec![(TreeItemMode::Blob, "b.txt", b.clone())], &[]).await;
+        let diff = repo
+            .get_diff(&base.to_string(), &head.to_string(), None, false)
+            .await
+            .unwrap();
+        assert_eq!(diff.len(), 2);
+        assert_eq!(diff[0].path, "a.txt");
+        assert_eq!(diff[0].status, DiffStatus::Removed);
+        assert_eq!(diff[0].old_id, Some(a));
+        assert_eq!(diff[1].path, "b.txt");
+        assert_eq!(diff[1].status, DiffStatus::Added);
+        assert_eq!(diff[1].new_id, Some(b));
+    }
+
+    #[tokio::test]
+    async fn test_diff_rename_is_add_and_delete() {
+        let repo = memory_repository();
+        let a = put_blob(&repo, "same\n").await;
+        let base = commit_tree(&repo, vec![(TreeItemMode::Blob, "old.txt", a.clone())], &[]).await;
+        let sub = put_tree(&repo, vec![(TreeItemMode::Blob, "new.txt", a.clone())]).await;
+        let head = commit_tree(&repo, vec![(TreeItemMode::Tree, "dir", sub)], &[]).await;
+        let diff = repo
+            .get_diff(&base.to_string(), &head.to_string(), None, true)
+            .await
+            .unwrap();
+        let summary = diff
+            .iter()
+            .map(|x| (x.path.as_str(), x.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("dir/new.txt", DiffStatus::Added),
+                ("old.txt", DiffStatus::Removed)
+            ]
+        );
+        let only_dir = repo
+            .get_diff(&base.to_string(), &head.to_string(), Some("dir"), false)
+            .await
+            .unwrap();
+        assert_eq!(only_dir.len(), 1);
+        assert_eq!(only_dir[0].path, "dir/new.txt");
+    }
+
+    #[tokio::test]
+    async fn test_diff_mode_change() {
+        let repo = memory_repository();
+        let a = put_blob(&repo, "#!/bin/sh\n").await;
+        let base = commit_tree(&repo, vec![(TreeItemMode::Blob, "run.sh", a.clone())], &[]).await;
+        let head = commit_tree(
+            &repo,
+            vec![(TreeItemMode::BlobExecutable, "run.sh", a.clone())],
+            &[],
+        )
+        .await;
+        let diff = repo
+            .get_diff(&base.to_string(), &head.to_string(), None, true)
+            .await
+            .unwrap();
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].status, DiffStatus::Modified);
+        assert_eq!(diff[0].old_mode, Some(TreeItemMode::Blob));
+        assert_eq!(diff[0].new_mode, Some(TreeItemMode::BlobExecutable));
+        assert!(diff[0].hunks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_diff_modified_patch() {
+        let repo = memory_repository();
+        let old = put_blob(&repo, "1\n2\n3\n4\n5\n6\n7\n8\n9\n").await;
+        let new = put_blob(&repo, "1\n2\n3\n4\nfive\n6\n7\n8\n9\n").await;
+        let base = commit_tree(&repo, vec![(TreeItemMode::Blob, "n.txt", old)], &[]).await;
+        let head = commit_tree(&repo, vec![(TreeItemMode::Blob, "n.txt", new)], &[]).await;
+        let diff = repo
+            .get_diff(&base.to_string(), &head.to_string(), None, true)
+            .await
+            .unwrap();
+        assert_eq!(diff.len(), 1);
+        let hunks = &diff[0].hunks;
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(
+            (
+                hunks[0].old_start,
+                hunks[0].old_lines,
+                hunks[0].new_start,
+                hunks[0].new_lines
+            ),
+            (2, 7, 2, 7)
+        );
+        assert!(hunks[0].lines.contains(&DiffLine::Removed("5".to_string())));
+        assert!(
+            hunks[0]
+                .lines
+                .contains(&DiffLine::Added("five".to_string()))
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::GitInnerError;
+use crate::objects::ObjectTrait;
+use crate::repository::Repository;
+use crate::sha::HashValue;
+use bytes::Bytes;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+impl Repository {
+    /// The object `hash` as a loose object file: `"<type> <size>\0"` followed by its
+    /// content, zlib-compressed at `level`, as served under `objects/<xx>/<rest>` by the
+    /// dumb protocol.
+    pub async fn loose_object(
+        &self,
+        hash: &HashValue,
+        level: Compression,
+    ) -> Result<Bytes, GitInnerError> {
+        let object: Box<dyn ObjectTrait + Send> =
+            if let Ok(commit) = self.odb.get_commit(hash).await {
+                Box::new(commit)
+            } else if let Ok(tree) = self.odb.get_tree(hash).await {
+                Box::new(tree)
+            } else if let Ok(tag) = self.odb.get_tag(hash).await {
+                Box::new(tag)
+            } else if let Ok(blob) = self.odb.get_blob(hash).await {
+                Box::new(blob)
+            } else {
+                return Err(GitInnerError::ObjectNotFound(hash.clone()));
+            };
+        let data = object.get_data();
+        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+        encoder
+            .write_all(format!("{} {}\0", object.get_type().to_str(), data.len()).as_bytes())
+            .map_err(|_| GitInnerError::ZlibError)?;
+        encoder
+            .write_all(&data)
+            .map_err(|_| GitInnerError::ZlibError)?;
+        Ok(Bytes::from(
+            encoder.finish().map_err(|_| GitInnerError::ZlibError)?,
+        ))
+    }
+
+    /// The dumb protocol `info/refs` file: one `<oid>\t<refname>` line per ref, sorted by
+    /// name, each annotated tag followed by its peeled `<refname>^{}` line.
+    pub async fn dumb_info_refs(&self) -> Result<String, GitInnerError> {
+        let mut refs = self
+            .refs
+            .refs()
+            .await?
+            .into_iter()
+            .filter(|x| x.name != "HEAD")
+            .collect::<Vec<_>>();
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut result = String::new();
+        for item in refs {
+            result.push_str(&format!("{}\t{}\n", item.value, item.name));
+            if item.is_tag && self.odb.has_tag(&item.value).await? {
+                let peeled = self.peel_to_commit(&item.value).await?;
+                result.push_str(&format!("{}\t{}^{{}}\n", peeled, item.name));
+            }
+        }
+        Ok(result)
+    }
+
+    /// The dumb protocol `HEAD` file, a symbolic ref to the default branch.
+    pub async fn dumb_head(&self) -> Result<String, GitInnerError> {
+        let head = self.refs.head().await?;
+        if head.is_branch {
+            Ok(format!("ref: {}\n", head.name))
+        } else {
+            Ok(format!("{}\n", head.value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+    use crate::sha::HashVersion;
+    use bytes::Bytes;
+    use flate2::Compression;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn test_loose_object_round_trip() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let loose = repo
+            .loose_object(&blob, Compression::default())
+            .await
+            .unwrap();
+        let mut data = Vec::new();
+        flate2::read::ZlibDecoder::new(&loose[..])
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"blob 5\0hello");
+        // 松散对象文件内容的哈希就是对象 id
+        let hex = blob.to_string();
+        assert_eq!(HashVersion::Sha1.hash(Bytes::from(data)).to_string(), hex);
+
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        let loose = repo
+            .loose_object(&commit, Compression::default())
+            .await
+            .unwrap();
+        let mut data = Vec::new();
+        flate2::read::ZlibDecoder::new(&loose[..])
+            .read_to_end(&mut data)
+            .unwrap();
+        assert!(data.starts_with(b"commit "));
+        assert!(
+            repo.loose_object(&repo.hash_version.default(), Compression::default())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dumb_info_refs() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+        repo.refs_insert("refs/heads/dev".to_string(), commit.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.dumb_info_refs().await.unwrap(),
+            format!("{}\trefs/heads/dev\n{}\trefs/heads/main\n", commit, commit)
+        );
+        assert_eq!(repo.dumb_head().await.unwrap(), "ref: refs/heads/main\n");
+    }
+}
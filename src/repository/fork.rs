@@ -0,0 +1,94 @@
+use crate::error::GitInnerError;
+use crate::objects::types::ObjectType;
+use crate::repository::Repository;
+
+impl Repository {
+    /// Copy every object and ref of this repository into `target`, e.g. to fill a fork, and
+    /// return how many objects were copied. HEAD is pointed at the same branch.
+    ///
+    /// The copy shares nothing with the source afterwards: pushes, ref updates, gc or
+    /// deletion on either side never show up in the other. Objects written to the source
+    /// while the copy runs may or may not be included, and refs are copied last so they only
+    /// name objects that were copied.
+    pub async fn copy_into(&self, target: &Repository) -> Result<usize, GitInnerError> {
+        if self.hash_version != target.hash_version {
+            return Err(GitInnerError::ObjectFormatMismatch(
+                "cannot copy objects between repositories of different hash versions".to_string(),
+            ));
+        }
+        let mut copied = 0;
+        for object in self.odb.list_objects().await? {
+            match object.object_type {
+                ObjectType::Commit => {
+                    let commit = self.odb.get_commit(&object.hash).await?;
+                    target.odb.put_commit(&commit).await?;
+                }
+                ObjectType::Tree => {
+                    let tree = self.odb.get_tree(&object.hash).await?;
+                    target.odb.put_tree(&tree).await?;
+                }
+                ObjectType::Tag => {
+                    let tag = self.odb.get_tag(&object.hash).await?;
+                    target.odb.put_tag(&tag).await?;
+                }
+                ObjectType::Blob => {
+                    let blob = self.odb.get_blob(&object.hash).await?;
+                    target.odb.put_blob(blob).await?;
+                }
+                _ => continue,
+            }
+            copied += 1;
+        }
+        for item in self.refs.refs().await? {
+            if target.refs.exists_refs(item.name.clone()).await? {
+                target.refs.update_refs(item.name, item.value).await?;
+            } else {
+                target.refs.create_refs(item.name, item.value).await?;
+            }
+        }
+        let head = self.refs.head().await?;
+        if head.is_branch && target.refs.exists_refs(head.name.clone()).await? {
+            target.refs.set_head(head.name).await?;
+        }
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    #[tokio::test]
+    async fn test_fork_is_independent() {
+        let source = memory_repository();
+        let blob = put_blob(&source, "hello").await;
+        let tree = put_tree(&source, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let first = put_commit(&source, &tree, &[], "first", 1).await;
+        source
+            .refs_insert("refs/heads/main".to_string(), first.clone())
+            .await
+            .unwrap();
+
+        let fork = memory_repository();
+        assert_eq!(source.copy_into(&fork).await.unwrap(), 3);
+        assert_eq!(fork.resolve_rev("HEAD").await.unwrap(), first);
+        assert_eq!(
+            fork.get_blob_at_path("HEAD", "README", None)
+                .await
+                .unwrap()
+                .data,
+            "hello"
+        );
+
+        // 源仓库后续的提交不会出现在 fork 中
+        let second = put_commit(&source, &tree, &[first.clone()], "second", 2).await;
+        source
+            .refs_update("refs/heads/main".to_string(), second.clone())
+            .await
+            .unwrap();
+        assert_eq!(source.resolve_rev("HEAD").await.unwrap(), second);
+        assert_eq!(fork.resolve_rev("HEAD").await.unwrap(), first);
+        assert!(!fork.odb.has_commit(&second).await.unwrap());
+    }
+}
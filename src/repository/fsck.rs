@@ -0,0 +1,264 @@
+use crate::error::GitInnerError;
+use crate::objects::ObjectTrait;
+use crate::objects::commit::Commit;
+use crate::objects::tag::Tag;
+use crate::objects::tree::{Tree, TreeItemMode};
+use crate::objects::types::ObjectType;
+use crate::repository::Repository;
+use crate::sha::{HashValue, hash_object};
+use std::collections::HashSet;
+
+/// A problem found by [`Repository::fsck`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// A ref points at an object that is not in the odb.
+    DanglingRef { name: String, target: HashValue },
+    /// A commit parent or tree, or a tag target, is missing.
+    MissingObject {
+        hash: HashValue,
+        referenced_by: HashValue,
+    },
+    /// A tree entry points at a missing object.
+    MissingTreeEntry {
+        tree: HashValue,
+        name: String,
+        id: HashValue,
+    },
+    /// The stored object hashes to something other than its id.
+    HashMismatch {
+        object_type: ObjectType,
+        expected: HashValue,
+        actual: HashValue,
+    },
+    /// The object could not be loaded or re-parsed.
+    Unparseable {
+        hash: HashValue,
+        object_type: ObjectType,
+        error: String,
+    },
+}
+
+impl Repository {
+    /// Walk everything reachable from the refs, re-hash and re-parse each object and
+    /// collect every problem instead of stopping at the first one.
+    pub async fn fsck(&self) -> Result<Vec<FsckIssue>, GitInnerError> {
+        let mut issues = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = Vec::new();
+        for item in self.refs.refs().await? {
+            if item.value.is_zero() {
+                continue;
+            }
+            if self.object_type(&item.value).await.is_err() {
+                issues.push(FsckIssue::DanglingRef {
+                    name: item.name,
+                    target: item.value,
+                });
+                continue;
+            }
+            stack.push(item.value);
+        }
+        while let Some(hash) = stack.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            let object_type = match self.object_type(&hash).await {
+                Ok(object_type) => object_type,
+                // 引用方已经报告过缺失
+                Err(GitInnerError::ObjectNotFound(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let referenced = match self.fsck_object(&hash, object_type, &mut issues).await {
+                Some(referenced) => referenced,
+                None => continue,
+            };
+            for (child, tree_entry) in referenced {
+                if self.object_type(&child).await.is_err() {
+                    issues.push(match tree_entry {
+                        Some(name) => FsckIssue::MissingTreeEntry {
+                            tree: hash.clone(),
+                            name,
+                            id: child,
+                        },
+                        None => FsckIssue::MissingObject {
+                            hash: child,
+                            referenced_by: hash.clone(),
+                        },
+                    });
+                    continue;
+                }
+                stack.push(child);
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Check one object and return what it references, with the entry name for trees.
+    async fn fsck_object(
+        &self,
+        hash: &HashValue,
+        object_type: ObjectType,
+        issues: &mut Vec<FsckIssue>,
+    ) -> Option<Vec<(HashValue, Option<String>)>> {
+        let version = self.hash_version;
+        let unparseable = |error: GitInnerError| FsckIssue::Unparseable {
+            hash: hash.clone(),
+            object_type,
+            error: format!("{:?}", error),
+        };
+        let (data, referenced) = match object_type {
+            ObjectType::Commit => {
+                let commit = match self.odb.get_commit(hash).await {
+                    Ok(commit) => commit,
+                    Err(e) => {
+                        issues.push(unparseable(e));
+                        return None;
+                    }
+                };
+                let data = commit.get_data();
+                if let Err(e) = Commit::parse(data.clone(), version) {
+                    issues.push(unparseable(e));
+                }
+                let referenced = commit
+                    .tree
+                    .into_iter()
+                    .chain(commit.parents)
+                    .map(|x| (x, None))
+                    .collect();
+                (data, referenced)
+            }
+            ObjectType::Tree => {
+                let tree = match self.odb.get_tree(hash).await {
+                    Ok(tree) => tree,
+                    Err(e) => {
+                        issues.push(unparseable(e));
+                        return None;
+                    }
+                };
+                let data = tree.get_data();
+                if let Err(e) = Tree::parse(data.clone(), version) {
+                    issues.push(unparseable(e));
+                }
+                let referenced = tree
+                    .tree_items
+                    .into_iter()
+                    .filter(|x| x.mode != TreeItemMode::Commit)
+                    .map(|x| (x.id, Some(x.name)))
+                    .collect();
+                (data, referenced)
+            }
+            ObjectType::Tag => {
+                let tag = match self.odb.get_tag(hash).await {
+                    Ok(tag) => tag,
+                    Err(e) => {
+                        issues.push(unparseable(e));
+                        return None;
+                    }
+                };
+                let data = tag.get_data();
+                if let Err(e) = Tag::parse(data.clone(), version) {
+                    issues.push(unparseable(e));
+                }
+                (data, vec![(tag.object_hash, None)])
+            }
+            _ => match self.odb.get_blob(hash).await {
+                Ok(blob) => (blob.data, vec![]),
+                Err(e) => {
+                    issues.push(unparseable(e));
+                    return None;
+                }
+            },
+        };
+        let actual = hash_object(object_type, &data, version);
+        if &actual != hash {
+            issues.push(FsckIssue::HashMismatch {
+                object_type,
+                expected: hash.clone(),
+                actual,
+            });
+        }
+        Some(referenced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::tree::TreeItem;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    #[tokio::test]
+    async fn test_fsck_clean_repository() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit)
+            .await
+            .unwrap();
+        assert!(repo.fsck().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fsck_reports_corrupted_tree() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let missing = put_blob(&repo, "missing").await;
+        repo.odb
+            .delete_object(&crate::odb::OdbObjectMeta {
+                hash: missing.clone(),
+                object_type: ObjectType::Blob,
+                size: 0,
+                created_at: 0,
+            })
+            .await
+            .unwrap();
+        // 存储的 id 与内容不符，且有一项指向不存在的 blob
+        let items = vec![
+            TreeItem::new(TreeItemMode::Blob, blob.clone(), "README".to_string()),
+            TreeItem::new(TreeItemMode::Blob, missing.clone(), "gone".to_string()),
+        ];
+        let corrupt_id = repo.hash_version.hash(bytes::Bytes::from("corrupt"));
+        repo.odb
+            .put_tree(&Tree {
+                id: corrupt_id.clone(),
+                tree_items: items.clone(),
+            })
+            .await
+            .unwrap();
+        let commit = put_commit(&repo, &corrupt_id, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit)
+            .await
+            .unwrap();
+        let dangling = repo.hash_version.hash(bytes::Bytes::from("nowhere"));
+        repo.refs_insert("refs/heads/dangling".to_string(), dangling.clone())
+            .await
+            .unwrap();
+
+        let issues = repo.fsck().await.unwrap();
+        let actual = hash_object(
+            ObjectType::Tree,
+            &Tree {
+                id: corrupt_id.clone(),
+                tree_items: items,
+            }
+            .get_data(),
+            repo.hash_version,
+        );
+        assert!(issues.contains(&FsckIssue::HashMismatch {
+            object_type: ObjectType::Tree,
+            expected: corrupt_id.clone(),
+            actual,
+        }));
+        assert!(issues.contains(&FsckIssue::MissingTreeEntry {
+            tree: corrupt_id,
+            name: "gone".to_string(),
+            id: missing,
+        }));
+        assert!(issues.contains(&FsckIssue::DanglingRef {
+            name: "refs/heads/dangling".to_string(),
+            target: dangling,
+        }));
+        assert_eq!(issues.len(), 3);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::error::GitInnerError;
+use crate::objects::tree::TreeItemMode;
+use crate::repository::Repository;
+use crate::sha::HashValue;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Unreachable objects younger than this are kept, so objects written by a push that
+/// has not updated its refs yet survive a concurrent gc.
+pub const GC_GRACE_PERIOD: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub removed_objects: u64,
+    pub reclaimed_bytes: u64,
+}
+
+impl Repository {
+    /// Delete objects that no ref can reach and that are older than [`GC_GRACE_PERIOD`].
+    pub async fn gc(&self) -> Result<GcReport, GitInnerError> {
+        self.gc_with_grace_period(GC_GRACE_PERIOD).await
+    }
+
+    pub async fn gc_with_grace_period(&self, grace: Duration) -> Result<GcReport, GitInnerError> {
+        // 先固定 refs 快照和截止时间，之后写入的对象都比截止时间新，不会被删除
+        let refs = self.refs.refs().await?;
+        let cutoff = chrono::Utc::now().timestamp() - grace.as_secs() as i64;
+        let reachable = self
+            .reachable_objects(refs.into_iter().map(|x| x.value))
+            .await?;
+        let mut report = GcReport::default();
+        for object in self.odb.list_objects().await? {
+            if reachable.contains(&object.hash) || object.created_at > cutoff {
+                continue;
+            }
+            self.odb.delete_object(&object).await?;
+            report.removed_objects += 1;
+            report.reclaimed_bytes += object.size;
+        }
+        Ok(report)
+    }
+
+    /// Every object reachable from `roots` through tags, commits and trees.
+    pub async fn reachable_objects(
+        &self,
+        roots: impl IntoIterator<Item = HashValue>,
+    ) -> Result<HashSet<HashValue>, GitInnerError> {
+        let mut seen = HashSet::new();
+        let mut stack = roots
+            .into_iter()
+            .filter(|x| !x.is_zero())
+            .collect::<Vec<_>>();
+        while let Some(hash) = stack.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            if self.odb.has_tag(&hash).await? {
+                stack.push(self.odb.get_tag(&hash).await?.object_hash);
+            } else if self.odb.has_commit(&hash).await? {
+                let commit = self.odb.get_commit(&hash).await?;
+                stack.extend(commit.tree);
+                stack.extend(commit.parents);
+            } else if self.odb.has_tree(&hash).await? {
+                let tree = self.odb.get_tree(&hash).await?;
+                // 子模块 (gitlink) 指向其他仓库的提交，不属于本仓库
+                stack.extend(
+                    tree.tree_items
+                        .into_iter()
+                        .filter(|x| x.mode != TreeItemMode::Commit)
+                        .map(|x| x.id),
+                );
+            }
+        }
+        Ok(seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{memory_repository_with_odb, put_blob, put_commit, put_tree};
+
+    #[tokio::test]
+    async fn test_gc_removes_only_unreachable() {
+        let (repo, odb) = memory_repository_with_odb();
+        let readme = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", readme.clone())]).await;
+        let first = put_commit(&repo, &tree, &[], "first", 1).await;
+        let head = put_commit(&repo, &tree, &[first.clone()], "second", 2).await;
+        repo.refs_insert("refs/heads/main".to_string(), head.clone())
+            .await
+            .unwrap();
+
+        // 被拒绝的推送留下的对象
+        let orphan_blob = put_blob(&repo, "rejected").await;
+        let orphan_tree =
+            put_tree(&repo, vec![(TreeItemMode::Blob, "x", orphan_blob.clone())]).await;
+        let orphan_commit = put_commit(&repo, &orphan_tree, &[head.clone()], "rejected", 3).await;
+        for created_at in odb.objects.lock().unwrap().created_at.values_mut() {
+            *created_at = 0;
+        }
+        // 正在进行的推送写入的新对象，还在宽限期内
+        let fresh = put_blob(&repo, "in flight").await;
+
+        let report = repo.gc().await.unwrap();
+        assert_eq!(report.removed_objects, 3);
+        assert!(report.reclaimed_bytes > "rejected".len() as u64);
+
+        for hash in [&readme, &tree, &first, &head, &fresh] {
+            assert!(
+                repo.odb.has_blob(hash).await.unwrap()
+                    || repo.odb.has_tree(hash).await.unwrap()
+                    || repo.odb.has_commit(hash).await.unwrap()
+            );
+        }
+        assert!(!repo.odb.has_blob(&orphan_blob).await.unwrap());
+        assert!(!repo.odb.has_tree(&orphan_tree).await.unwrap());
+        assert!(!repo.odb.has_commit(&orphan_commit).await.unwrap());
+
+        assert_eq!(repo.gc().await.unwrap(), GcReport::default());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::error::GitInnerError;
+use crate::objects::tree::{TreeItem, TreeItemMode};
+use crate::repository::Repository;
+use crate::repository::walk::visit;
+use crate::sha::HashValue;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct LastCommitEntry {
+    pub item: TreeItem,
+    /// Most recent commit that changed this entry.
+    pub commit: HashValue,
+}
+
+struct CommitInfo {
+    tree: Option<HashValue>,
+    parents: Vec<HashValue>,
+    timestamp: usize,
+}
+
+/// Per-request memo of commits, trees and `(commit, path)` lookups, so the history walk
+/// reads each object at most once.
+pub(super) struct TreeWalkCache<'a> {
+    repo: &'a Repository,
+    commits: HashMap<HashValue, Arc<CommitInfo>>,
+    trees: HashMap<HashValue, Arc<Vec<TreeItem>>>,
+    dirs: HashMap<(HashValue, String), Option<HashValue>>,
+}
+
+impl<'a> TreeWalkCache<'a> {
+    pub(super) fn new(repo: &'a Repository) -> Self {
+        TreeWalkCache {
+            repo,
+            commits: HashMap::new(),
+            trees: HashMap::new(),
+            dirs: HashMap::new(),
+        }
+    }
+
+    async fn commit(&mut self, hash: &HashValue) -> Result<Arc<CommitInfo>, GitInnerError> {
+        if let Some(info) = self.commits.get(hash) {
+            return Ok(info.clone());
+        }
+        let commit = self.repo.odb.get_commit(hash).await?;
+        let info = Arc::new(CommitInfo {
+            tree: commit.tree,
+            parents: commit.parents,
+            timestamp: commit.committer.timestamp,
+        });
+        self.commits.insert(hash.clone(), info.clone());
+        Ok(info)
+    }
+
+    async fn tree(&mut self, hash: &HashValue) -> Result<Arc<Vec<TreeItem>>, GitInnerError> {
+        if let Some(items) = self.trees.get(hash) {
+            return Ok(items.clone());
+        }
+        let items = Arc::new(self.repo.odb.get_tree(hash).await?.tree_items);
+        self.trees.insert(hash.clone(), items.clone());
+        Ok(items)
+    }
+
+    /// Tree id of directory `path` in `commit`, or `None` if it does not exist there.
+    async fn dir_at(
+        &mut self,
+        commit: &HashValue,
+        path: &str,
+    ) -> Result<Option<HashValue>, GitInnerError> {
+        let key = (commit.clone(), path.to_string());
+        if let Some(dir) = self.dirs.get(&key) {
+            return Ok(dir.clone());
+        }
+        let mut dir = self.commit(commit).await?.tree.clone();
+        for part in path.split('/').filter(|x| !x.is_empty()) {
+            let Some(tree) = dir else {
+                break;
+            };
+            dir = self
+                .tree(&tree)
+                .await?
+                .iter()
+                .find(|x| x.name == part && x.mode == TreeItemMode::Tree)
+                .map(|x| x.id.clone());
+        }
+        self.dirs.insert(key, dir.clone());
+        Ok(dir)
+    }
+
+    /// Id of the blob or tree at `path` in `commit`, or `None` if it does not exist there.
+    pub(super) async fn entry_at(
+        &mut self,
+        commit: &HashValue,
+        path: &str,
+    ) -> Result<Option<HashValue>, GitInnerError> {
+        let path = path.trim_matches('/');
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() {
+            return Ok(self.commit(commit).await?.tree.clone());
+        }
+        let Some(dir) = self.dir_at(commit, dir).await? else {
+            return Ok(None);
+        };
+        Ok(self
+            .tree(&dir)
+            .await?
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| x.id.clone()))
+    }
+
+    async fn entries(
+        &mut self,
+        dir: Option<&HashValue>,
+    ) -> Result<HashMap<String, HashValue>, GitInnerError> {
+        let Some(dir) = dir else {
+            return Ok(HashMap::new());
+        };
+        Ok(self
+            .tree(dir)
+            .await?
+            .iter()
+            .map(|x| (x.name.clone(), x.id.clone()))
+            .collect())
+    }
+}
+
+impl Repository {
+    /// For every entry of directory `path` at `rev`, find the most recent commit that
+    /// changed it.
+    ///
+    /// History is walked newest first by committer time. Commits whose directory is
+    /// identical to one of their parents are skipped through that parent, and the walk
+    /// stops as soon as every entry has been assigned. With `first_parent` only the first
+    /// parent of a merge is followed, so changes merged in are attributed to the merge.
+    pub async fn last_commits(
+        &self,
+        rev: &str,
+        path: &str,
+        first_parent: bool,
+    ) -> Result<Vec<LastCommitEntry>, GitInnerError> {
+        let path = path.trim_matches('/');
+        let head = self.resolve_rev(rev).await?;
+        let head = self.peel_to_commit(&head).await?;
+        let mut cache = TreeWalkCache::new(self);
+        let head_dir = cache
+            .dir_at(&head, path)
+            .await?
+            .ok_or_else(|| GitInnerError::PathNotFound(path.to_string()))?;
+        let head_items = cache.tree(&head_dir).await?;
+        let mut pending = head_items
+            .iter()
+            .map(|x| (x.name.clone(), x.id.clone()))
+            .collect::<HashMap<_, _>>();
+        let mut found: HashMap<String, HashValue> = HashMap::new();
+
+        let mut queue = BinaryHeap::new();
+        let mut seen = HashSet::new();
+        let info = cache.commit(&head).await?;
+        queue.push((info.timestamp, head.to_string()));
+        visit(&mut seen, &head, self.max_walk_nodes)?;
+        while !pending.is_empty() {
+            let Some((_, hex)) = queue.pop() else {
+                break;
+            };
+            let hash = HashValue::from_str(&hex).ok_or(GitInnerError::InvalidHash)?;
+            let info = cache.commit(&hash).await?;
+            let parents = match first_parent {
+                true => &info.parents[..info.parents.len().min(1)],
+                false => &info.parents[..],
+            };
+            let dir = cache.dir_at(&hash, path).await?;
+            let mut parent_dirs = Vec::with_capacity(parents.len());
+            for parent in parents {
+                parent_dirs.push(cache.dir_at(parent, path).await?);
+            }
+            let next = match parent_dirs.iter().position(|x| *x == dir) {
+                Some(same) => vec![parents[same].clone()],
+                None => {
+                    let entries = cache.entries(dir.as_ref()).await?;
+                    let mut parent_entries = Vec::with_capacity(parent_dirs.len());
+                    for parent_dir in &parent_dirs {
+                        parent_entries.push(cache.entries(parent_dir.as_ref()).await?);
+                    }
+                    pending.retain(|name, id| {
+                        let changed = entries.get(name) == Some(id)
+                            && parent_entries.iter().all(|x| x.get(name) != Some(id));
+                        if changed {
+                            found.insert(name.clone(), hash.clone());
+                        }
+                        !changed
+                    });
+                    parents.to_vec()
+                }
+            };
+            for parent in next {
+                if visit(&mut seen, &parent, self.max_walk_nodes)? {
+                    let timestamp = cache.commit(&parent).await?.timestamp;
+                    queue.push((timestamp, parent.to_string()));
+                }
+            }
+        }
+
+        Ok(head_items
+            .iter()
+            .filter_map(|item| {
+                found.get(&item.name).map(|commit| LastCommitEntry {
+                    item: item.clone(),
+                    commit: commit.clone(),
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{
+        memory_repository_with_odb, merge_history, put_blob, put_commit, put_tree,
+    };
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn test_last_commits_long_history() {
+        let (repo, odb) = memory_repository_with_odb();
+        let mut commits: Vec<HashValue> = Vec::new();
+        let mut trees = HashSet::new();
+        for i in 0..200usize {
+            let a = put_blob(&repo, &format!("a{}", i - i % 7)).await;
+            let b = put_blob(&repo, "b").await;
+            let c = put_blob(&repo, &format!("c{}", i / 50)).await;
+            let dir = put_tree(&repo, vec![(TreeItemMode::Blob, "c.txt", c)]).await;
+            let root = put_tree(
+                &repo,
+                vec![
+                    (TreeItemMode::Blob, "a.txt", a),
+                    (TreeItemMode::Blob, "b.txt", b),
+                    (TreeItemMode::Tree, "dir", dir.clone()),
+                ],
+            )
+            .await;
+            trees.insert(dir);
+            trees.insert(root.clone());
+            let parents = commits.last().cloned().into_iter().collect::<Vec<_>>();
+            let commit = put_commit(&repo, &root, &parents, &format!("c{}", i), i + 1).await;
+            commits.push(commit);
+        }
+        let head = commits.last().unwrap().to_string();
+
+        odb.commit_reads.store(0, Ordering::Relaxed);
+        odb.tree_reads.store(0, Ordering::Relaxed);
+        let entries = repo.last_commits(&head, "", false).await.unwrap();
+        let result = entries
+            .iter()
+            .map(|x| (x.item.name.as_str(), x.commit.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            result,
+            vec![
+                ("a.txt", commits[196].clone()),
+                ("b.txt", commits[0].clone()),
+                ("dir", commits[150].clone()),
+            ]
+        );
+        assert!(odb.commit_reads.load(Ordering::Relaxed) <= commits.len());
+        assert!(odb.tree_reads.load(Ordering::Relaxed) <= trees.len());
+
+        let entries = repo.last_commits(&head, "dir", false).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].commit, commits[150]);
+    }
+
+    #[tokio::test]
+    async fn test_first_parent_attributes_merge() {
+        let (repo, _) = memory_repository_with_odb();
+        let (_, main, side, merge) = merge_history(&repo).await;
+        let head = merge.to_string();
+
+        let entries = repo.last_commits(&head, "", false).await.unwrap();
+        let result = entries
+            .iter()
+            .map(|x| (x.item.name.as_str(), x.commit.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(result, vec![("a.txt", main.clone()), ("b.txt", side)]);
+
+        let entries = repo.last_commits(&head, "", true).await.unwrap();
+        let result = entries
+            .iter()
+            .map(|x| (x.item.name.as_str(), x.commit.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(result, vec![("a.txt", main), ("b.txt", merge)]);
+    }
+}
@@ -0,0 +1,447 @@
+use crate::error::GitInnerError;
+use crate::objects::commit::Commit;
+use crate::repository::Repository;
+use crate::repository::last_commit::TreeWalkCache;
+use crate::repository::walk::visit;
+use crate::sha::HashValue;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BinaryHeap, HashSet};
+
+/// Which part of the history [`Repository::log`] returns.
+#[derive(Clone, Debug, Default)]
+pub struct LogOptions {
+    /// Commits to skip from the tip. Ignored when `page_token` is set.
+    pub offset: usize,
+    /// Most commits returned.
+    pub limit: usize,
+    /// `next_page_token` of the previous page, to continue where it stopped.
+    pub page_token: Option<String>,
+    /// Only commits whose author name or email contains this text.
+    pub author_contains: Option<String>,
+    /// Only commits whose message matches this regular expression.
+    pub message_grep: Option<String>,
+    /// Only commits with a committer time at or after this Unix timestamp.
+    pub since: Option<u64>,
+    /// Only commits with a committer time at or before this Unix timestamp.
+    pub until: Option<u64>,
+    /// Follow only the first parent of merges, like `git log --first-parent`.
+    pub first_parent: bool,
+    /// Only commits that changed the file or directory at this path, like `git log -- <path>`.
+    pub path: Option<String>,
+}
+
+/// One page of [`Repository::log`].
+#[derive(Clone, Debug)]
+pub struct LogPage {
+    pub commits: Vec<Commit>,
+    /// Resumes the walk after the last returned commit; `None` once the history is exhausted.
+    pub next_page_token: Option<String>,
+}
+
+/// Walk state carried by a page token: commits waiting in the queue and how many commits
+/// earlier pages have returned.
+#[derive(Serialize, Deserialize)]
+struct PageToken {
+    returned: usize,
+    frontier: Vec<String>,
+}
+
+impl PageToken {
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(token: &str) -> Result<Self, GitInnerError> {
+        let invalid = || GitInnerError::InvalidArgument("invalid page token".to_string());
+        let bytes = hex::decode(token).map_err(|_| invalid())?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid())
+    }
+}
+
+impl Repository {
+    /// History of `rev`, newest commit first by committer time like `git log`.
+    ///
+    /// A page token resumes from the queue of the previous walk instead of re-walking from
+    /// the tip, so pages stay stable while new commits land on `rev`. Commits already
+    /// returned are not tracked across pages: with committer clocks going backwards a merged
+    /// commit may show up again.
+    ///
+    /// Filters only decide which commits are returned, and `offset` counts returned commits;
+    /// the walk still goes through the parents of commits that are filtered out.
+    ///
+    /// With `path` a commit is returned only when the id at that path differs from every
+    /// parent. As in git's default history simplification, a merge that kept the path of one
+    /// parent is followed through that parent alone. Renames are not followed.
+    pub async fn log(&self, rev: &str, options: &LogOptions) -> Result<LogPage, GitInnerError> {
+        let message_grep = options
+            .message_grep
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|err| GitInnerError::InvalidArgument(err.to_string()))?;
+        let matches = |commit: &Commit| {
+            let time = commit.committer.timestamp as u64;
+            options.since.is_none_or(|x| time >= x)
+                && options.until.is_none_or(|x| time <= x)
+                && options.author_contains.as_ref().is_none_or(|x| {
+                    commit.author.name.contains(x.as_str())
+                        || commit.author.email.contains(x.as_str())
+                })
+                && message_grep
+                    .as_ref()
+                    .is_none_or(|x| x.is_match(&commit.message))
+        };
+        let path = options.path.as_deref();
+        let mut cache = TreeWalkCache::new(self);
+        let mut visited = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        let (frontier, mut returned, offset) = match &options.page_token {
+            Some(token) => {
+                let token = PageToken::decode(token)?;
+                let frontier = token
+                    .frontier
+                    .iter()
+                    .map(|x| HashValue::from_str(x).ok_or(GitInnerError::InvalidHash))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (frontier, token.returned, 0)
+            }
+            None => (vec![self.resolve_rev(rev).await?], 0, options.offset),
+        };
+        for hash in frontier {
+            if visit(&mut visited, &hash, self.max_walk_nodes)? {
+                let timestamp = self.odb.get_commit(&hash).await?.committer.timestamp;
+                queue.push((timestamp, hash.to_string()));
+            }
+        }
+        let mut skipped = 0;
+        let mut commits = vec![];
+        while commits.len() < options.limit
+            && let Some((_, hex)) = queue.pop()
+        {
+            let hash = HashValue::from_str(&hex).ok_or(GitInnerError::InvalidHash)?;
+            let commit = self.odb.get_commit(&hash).await?;
+            let parents = match options.first_parent {
+                true => &commit.parents[..commit.parents.len().min(1)],
+                false => &commit.parents[..],
+            };
+            let (touched, parents) = match path {
+                None => (true, parents.to_vec()),
+                Some(path) => {
+                    let entry = cache.entry_at(&hash, path).await?;
+                    let mut same = None;
+                    for parent in parents {
+                        if cache.entry_at(parent, path).await? == entry {
+                            same = Some(parent.clone());
+                            break;
+                        }
+                    }
+                    match same {
+                        Some(parent) => (false, vec![parent]),
+                        None => (entry.is_some() || !parents.is_empty(), parents.to_vec()),
+                    }
+                }
+            };
+            for parent in &parents {
+                if visit(&mut visited, parent, self.max_walk_nodes)? {
+                    let timestamp = self.odb.get_commit(parent).await?.committer.timestamp;
+                    queue.push((timestamp, parent.to_string()));
+                }
+            }
+            if !touched || !matches(&commit) {
+                continue;
+            }
+            if skipped < offset {
+                skipped += 1;
+            } else {
+                commits.push(commit);
+            }
+        }
+        returned += skipped + commits.len();
+        let next_page_token = (!queue.is_empty()).then(|| {
+            PageToken {
+                returned,
+                frontier: queue.into_iter().map(|(_, hex)| hex).collect(),
+            }
+            .encode()
+        });
+        Ok(LogPage {
+            commits,
+            next_page_token,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::tree::TreeItemMode;
+    use crate::odb::Odb;
+    use crate::odb::cache::CachedOdb;
+    use crate::repository::testing::{
+        memory_repository, memory_repository_with_odb, merge_history, put_blob, put_commit,
+        put_tree,
+    };
+    use bytes::Bytes;
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+
+    async fn linear(repo: &Repository, count: usize) -> Vec<HashValue> {
+        let blob = put_blob(repo, "hello").await;
+        let tree = put_tree(repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let mut commits: Vec<HashValue> = vec![];
+        for i in 0..count {
+            let parents = commits.last().cloned().into_iter().collect::<Vec<_>>();
+            commits.push(put_commit(repo, &tree, &parents, &format!("c{}", i), i + 1).await);
+        }
+        repo.refs_insert("refs/heads/main".to_string(), commits[count - 1].clone())
+            .await
+            .unwrap();
+        commits
+    }
+
+    #[tokio::test]
+    async fn test_log_newest_first() {
+        let repo = memory_repository();
+        let commits = linear(&repo, 10).await;
+        let options = LogOptions {
+            offset: 2,
+            limit: 3,
+            ..Default::default()
+        };
+        let page = repo.log("main", &options).await.unwrap().commits;
+        assert_eq!(
+            page.iter().map(|x| x.hash.clone()).collect::<Vec<_>>(),
+            vec![commits[7].clone(), commits[6].clone(), commits[5].clone()]
+        );
+        let options = LogOptions {
+            offset: 8,
+            limit: 10,
+            ..Default::default()
+        };
+        let page = repo.log("main", &options).await.unwrap();
+        assert_eq!(page.commits.len(), 2);
+        assert!(page.next_page_token.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_second_log_hits_commit_cache() {
+        let (mut repo, odb) = memory_repository_with_odb();
+        linear(&repo, 20).await;
+        let inner: Arc<Box<dyn Odb>> = repo.odb.clone();
+        repo.odb = Arc::new(Box::new(CachedOdb::new(
+            inner,
+            NonZeroUsize::new(100).unwrap(),
+        )));
+
+        let options = LogOptions {
+            limit: 20,
+            ..Default::default()
+        };
+        let first = repo.log("main", &options).await.unwrap().commits;
+        let reads = odb.commit_reads.load(Ordering::Relaxed);
+        assert!(reads >= 20);
+        let second = repo.log("main", &options).await.unwrap().commits;
+        assert_eq!(first, second);
+        assert_eq!(odb.commit_reads.load(Ordering::Relaxed), reads);
+    }
+
+    #[tokio::test]
+    async fn test_search_author_and_message() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let mut parent: Vec<HashValue> = vec![];
+        let mut expected_fix = vec![];
+        for (i, (email, message)) in [
+            ("alice@example.com", "fix: crash on empty repo"),
+            ("bob@corp.test", "add readme"),
+            ("alice@example.com", "docs: typo"),
+            ("bob@corp.test", "Fix: off by one"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut data = format!("tree {}\n", tree);
+            for p in &parent {
+                data.push_str(&format!("parent {}\n", p));
+            }
+            data.push_str(&format!(
+                "author A <{}> {} +0000\ncommitter A <{}> {} +0000\n\n{}\n",
+                email, i, email, i, message
+            ));
+            let commit = Commit::parse(Bytes::from(data), repo.hash_version).unwrap();
+            let hash = repo.odb.put_commit(&commit).await.unwrap();
+            if message.to_lowercase().starts_with("fix") {
+                expected_fix.insert(0, hash.clone());
+            }
+            parent = vec![hash];
+        }
+        repo.refs_insert("refs/heads/main".to_string(), parent[0].clone())
+            .await
+            .unwrap();
+
+        let options = LogOptions {
+            limit: 10,
+            author_contains: Some("@corp.test".to_string()),
+            ..Default::default()
+        };
+        let page = repo.log("main", &options).await.unwrap().commits;
+        assert_eq!(page.len(), 2);
+        assert!(page.iter().all(|x| x.author.email == "bob@corp.test"));
+
+        let options = LogOptions {
+            limit: 10,
+            message_grep: Some("^(?i)fix:".to_string()),
+            ..Default::default()
+        };
+        let page = repo.log("main", &options).await.unwrap().commits;
+        assert_eq!(
+            page.into_iter().map(|x| x.hash).collect::<Vec<_>>(),
+            expected_fix
+        );
+
+        let options = LogOptions {
+            limit: 10,
+            message_grep: Some("fix(".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            repo.log("main", &options).await,
+            Err(GitInnerError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_since_until() {
+        let repo = memory_repository();
+        // 提交时间依次为 1..=20
+        let commits = linear(&repo, 20).await;
+        let options = LogOptions {
+            limit: 10,
+            since: Some(5),
+            until: Some(9),
+            ..Default::default()
+        };
+        let page = repo.log("main", &options).await.unwrap().commits;
+        assert_eq!(
+            page.into_iter().map(|x| x.hash).collect::<Vec<_>>(),
+            commits[4..9].iter().rev().cloned().collect::<Vec<_>>()
+        );
+
+        let options = LogOptions {
+            offset: 1,
+            limit: 2,
+            since: Some(15),
+            ..Default::default()
+        };
+        let page = repo.log("main", &options).await.unwrap().commits;
+        assert_eq!(
+            page.into_iter().map(|x| x.hash).collect::<Vec<_>>(),
+            vec![commits[18].clone(), commits[17].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_first_parent() {
+        let repo = memory_repository();
+        let (base, main, side, merge) = merge_history(&repo).await;
+        repo.refs_insert("refs/heads/main".to_string(), merge.clone())
+            .await
+            .unwrap();
+        let mut options = LogOptions {
+            limit: 10,
+            ..Default::default()
+        };
+        let walk = |page: LogPage| page.commits.into_iter().map(|x| x.hash).collect::<Vec<_>>();
+        assert_eq!(
+            walk(repo.log("main", &options).await.unwrap()),
+            vec![merge.clone(), side, main.clone(), base.clone()]
+        );
+        options.first_parent = true;
+        assert_eq!(
+            walk(repo.log("main", &options).await.unwrap()),
+            vec![merge, main, base]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_path_history() {
+        let repo = memory_repository();
+        let mut other = vec![];
+        for i in 0..5 {
+            other.push(put_blob(&repo, &format!("other {}", i)).await);
+        }
+        let (a1, a2) = (put_blob(&repo, "a1").await, put_blob(&repo, "a2").await);
+        let mut commits: Vec<HashValue> = vec![];
+        for i in 0..5 {
+            let mut items = vec![(TreeItemMode::Blob, "other.txt", other[i].clone())];
+            match i {
+                1 | 2 => items.insert(0, (TreeItemMode::Blob, "a.txt", a1.clone())),
+                3 | 4 => items.insert(0, (TreeItemMode::Blob, "a.txt", a2.clone())),
+                _ => {}
+            }
+            let tree = put_tree(&repo, items).await;
+            let parents = commits.last().cloned().into_iter().collect::<Vec<_>>();
+            commits.push(put_commit(&repo, &tree, &parents, &format!("c{}", i), i + 1).await);
+        }
+        let options = LogOptions {
+            limit: 10,
+            path: Some("a.txt".to_string()),
+            ..Default::default()
+        };
+        let page = repo.log(&commits[4].to_string(), &options).await.unwrap();
+        assert_eq!(
+            page.commits.into_iter().map(|x| x.hash).collect::<Vec<_>>(),
+            vec![commits[3].clone(), commits[1].clone()]
+        );
+
+        let (base, _, side, merge) = merge_history(&repo).await;
+        let options = LogOptions {
+            limit: 10,
+            path: Some("b.txt".to_string()),
+            ..Default::default()
+        };
+        let page = repo.log(&merge.to_string(), &options).await.unwrap();
+        assert_eq!(
+            page.commits.into_iter().map(|x| x.hash).collect::<Vec<_>>(),
+            vec![side, base]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_page_tokens() {
+        let repo = memory_repository();
+        let commits = linear(&repo, 100).await;
+        let mut options = LogOptions {
+            limit: 40,
+            ..Default::default()
+        };
+        let mut pages = vec![];
+        loop {
+            let page = repo.log("main", &options).await.unwrap();
+            pages.push(page.commits);
+            match page.next_page_token {
+                Some(token) => options.page_token = Some(token),
+                None => break,
+            }
+        }
+        assert_eq!(
+            pages.iter().map(|x| x.len()).collect::<Vec<_>>(),
+            vec![40, 40, 20]
+        );
+        let walked = pages
+            .concat()
+            .into_iter()
+            .map(|x| x.hash)
+            .collect::<Vec<_>>();
+        assert_eq!(walked, commits.into_iter().rev().collect::<Vec<_>>());
+
+        options.page_token = Some("zz".to_string());
+        assert!(matches!(
+            repo.log("main", &options).await,
+            Err(GitInnerError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::error::GitInnerError;
+use crate::repository::Repository;
+use crate::repository::walk::visit;
+use crate::sha::HashValue;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 1 << 1;
+const STALE: u8 = 1 << 2;
+const RESULT: u8 = 1 << 3;
+
+impl Repository {
+    /// Best common ancestor of commits `a` and `b`, or `None` when their histories are
+    /// unrelated. If one commit is an ancestor of the other, that commit is returned.
+    pub async fn merge_base(
+        &self,
+        a: &HashValue,
+        b: &HashValue,
+    ) -> Result<Option<HashValue>, GitInnerError> {
+        if a == b {
+            return Ok(Some(a.clone()));
+        }
+        let candidates = self.paint_down_to_common(a, b).await?;
+        // 去掉是其它候选祖先的候选，剩下的按提交时间取最新
+        for candidate in &candidates {
+            let mut redundant = false;
+            for other in candidates.iter().filter(|x| *x != candidate) {
+                if self.is_ancestor(candidate, other).await? {
+                    redundant = true;
+                    break;
+                }
+            }
+            if !redundant {
+                return Ok(Some(candidate.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Walk both histories newest-first, colouring commits reachable from `a` and from `b`.
+    /// Commits reached from both sides are common ancestors; their own ancestors are marked
+    /// stale and stop the walk once nothing else is left. Returned newest first.
+    async fn paint_down_to_common(
+        &self,
+        a: &HashValue,
+        b: &HashValue,
+    ) -> Result<Vec<HashValue>, GitInnerError> {
+        let mut paint = PaintQueue::default();
+        for (hash, flag) in [(a, PARENT1), (b, PARENT2)] {
+            let timestamp = self.odb.get_commit(hash).await?.committer.timestamp;
+            paint.push(hash.clone(), timestamp, flag);
+        }
+        let mut result = vec![];
+        while paint.non_stale > 0 {
+            let Some(Queued { timestamp, hash }) = paint.pop() else {
+                break;
+            };
+            let flag = paint.flag(&hash);
+            let mut colour = flag & (PARENT1 | PARENT2 | STALE);
+            if colour == PARENT1 | PARENT2 {
+                if flag & RESULT == 0 {
+                    paint.flags.insert(hash.clone(), flag | RESULT);
+                    result.push((timestamp, hash.clone()));
+                }
+                colour |= STALE;
+            }
+            let commit = self.odb.get_commit(&hash).await?;
+            for parent in commit.parents {
+                let parent_flag = paint.flag(&parent);
+                if parent_flag & colour == colour {
+                    continue;
+                }
+                if parent_flag == 0 && paint.flags.len() >= self.max_walk_nodes {
+                    return Err(GitInnerError::GraphTooLarge);
+                }
+                let timestamp = self.odb.get_commit(&parent).await?.committer.timestamp;
+                paint.push(parent, timestamp, colour);
+            }
+        }
+        result.sort_by_key(|x| Reverse(x.0));
+        Ok(result.into_iter().map(|(_, hash)| hash).collect())
+    }
+
+    /// Whether `ancestor` is reachable from `descendant` by following parents. A commit
+    /// counts as its own ancestor.
+    pub async fn is_ancestor(
+        &self,
+        ancestor: &HashValue,
+        descendant: &HashValue,
+    ) -> Result<bool, GitInnerError> {
+        let mut stack = vec![descendant.clone()];
+        let mut seen = HashSet::new();
+        while let Some(hash) = stack.pop() {
+            if &hash == ancestor {
+                return Ok(true);
+            }
+            if visit(&mut seen, &hash, self.max_walk_nodes)? {
+                stack.extend(self.odb.get_commit(&hash).await?.parents);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// A commit waiting in the walk queue, newest first; ties go to the larger id.
+#[derive(Clone)]
+struct Queued {
+    timestamp: usize,
+    hash: HashValue,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.timestamp, self.hash.as_bytes()).cmp(&(other.timestamp, other.hash.as_bytes()))
+    }
+}
+
+/// Flags of the commits seen by [`Repository::paint_down_to_common`] and the queue of commits
+/// still to walk, counting the queued entries that are not stale so the walk knows when to
+/// stop without scanning the queue.
+#[derive(Default)]
+struct PaintQueue {
+    flags: HashMap<HashValue, u8>,
+    queue: BinaryHeap<Queued>,
+    /// 每个提交在队列里的条目数；同一提交被涂上新颜色时会再次入队
+    queued: HashMap<HashValue, usize>,
+    non_stale: usize,
+}
+
+impl PaintQueue {
+    fn flag(&self, hash: &HashValue) -> u8 {
+        self.flags.get(hash).copied().unwrap_or_default()
+    }
+
+    /// Add `paint` to the flags of `hash` and queue it.
+    fn push(&mut self, hash: HashValue, timestamp: usize, paint: u8) {
+        let flag = self.flag(&hash);
+        let queued = self.queued.entry(hash.clone()).or_default();
+        if flag & STALE == 0 && paint & STALE != 0 {
+            // 已在队列里的条目随之变为 stale
+            self.non_stale -= *queued;
+        }
+        *queued += 1;
+        if (flag | paint) & STALE == 0 {
+            self.non_stale += 1;
+        }
+        self.flags.insert(hash.clone(), flag | paint);
+        self.queue.push(Queued { timestamp, hash });
+    }
+
+    fn pop(&mut self) -> Option<Queued> {
+        let next = self.queue.pop()?;
+        if let Some(queued) = self.queued.get_mut(&next.hash) {
+            *queued -= 1;
+            if *queued == 0 {
+                self.queued.remove(&next.hash);
+            }
+        }
+        if self.flag(&next.hash) & STALE == 0 {
+            self.non_stale -= 1;
+        }
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+    use crate::sha::HashVersion;
+    use bytes::Bytes;
+
+    #[tokio::test]
+    async fn test_merge_base_diamond() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        //   root - base - left  - merge
+        //              \- right -/
+        let root = put_commit(&repo, &tree, &[], "root", 1).await;
+        let base = put_commit(&repo, &tree, &[root.clone()], "base", 2).await;
+        let left = put_commit(&repo, &tree, &[base.clone()], "left", 3).await;
+        let right = put_commit(&repo, &tree, &[base.clone()], "right", 4).await;
+        let merge = put_commit(&repo, &tree, &[left.clone(), right.clone()], "merge", 5).await;
+        let tip = put_commit(&repo, &tree, &[right.clone()], "tip", 6).await;
+
+        assert_eq!(
+            repo.merge_base(&left, &right).await.unwrap(),
+            Some(base.clone())
+        );
+        assert_eq!(
+            repo.merge_base(&right, &left).await.unwrap(),
+            Some(base.clone())
+        );
+        // 一方是另一方的祖先
+        assert_eq!(
+            repo.merge_base(&merge, &left).await.unwrap(),
+            Some(left.clone())
+        );
+        assert_eq!(
+            repo.merge_base(&root, &merge).await.unwrap(),
+            Some(root.clone())
+        );
+        assert_eq!(
+            repo.merge_base(&merge, &tip).await.unwrap(),
+            Some(right.clone())
+        );
+        assert_eq!(repo.merge_base(&left, &left).await.unwrap(), Some(left));
+    }
+
+    #[test]
+    fn test_paint_queue_counts_non_stale() {
+        let [a, b] = ["a", "b"].map(|x| HashVersion::Sha1.hash(Bytes::from(x)));
+        let mut paint = PaintQueue::default();
+        paint.push(a.clone(), 2, PARENT1);
+        paint.push(b.clone(), 1, PARENT2);
+        paint.push(a.clone(), 2, PARENT2);
+        assert_eq!(paint.non_stale, 3);
+        // a 的两个排队条目一起变为 stale
+        paint.push(a.clone(), 2, STALE);
+        assert_eq!(paint.non_stale, 1);
+        for _ in 0..3 {
+            assert!(paint.pop().unwrap().hash == a);
+        }
+        assert_eq!(paint.non_stale, 1);
+        assert!(paint.pop().unwrap().hash == b);
+        assert_eq!(paint.non_stale, 0);
+        assert!(paint.queued.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_merge_base_unrelated() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let a1 = put_commit(&repo, &tree, &[], "a1", 1).await;
+        let a2 = put_commit(&repo, &tree, &[a1.clone()], "a2", 2).await;
+        let b1 = put_commit(&repo, &tree, &[], "b1", 3).await;
+        let b2 = put_commit(&repo, &tree, &[b1], "b2", 4).await;
+        assert_eq!(repo.merge_base(&a2, &b2).await.unwrap(), None);
+        assert!(!repo.is_ancestor(&a1, &b2).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_is_ancestor() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let c1 = put_commit(&repo, &tree, &[], "c1", 1).await;
+        let c2 = put_commit(&repo, &tree, &[c1.clone()], "c2", 2).await;
+        let side = put_commit(&repo, &tree, &[c1.clone()], "side", 3).await;
+        let merge = put_commit(&repo, &tree, &[c2.clone(), side.clone()], "merge", 4).await;
+        assert!(repo.is_ancestor(&c1, &c2).await.unwrap());
+        assert!(repo.is_ancestor(&side, &merge).await.unwrap());
+        assert!(repo.is_ancestor(&c2, &c2).await.unwrap());
+        assert!(!repo.is_ancestor(&c2, &c1).await.unwrap());
+        assert!(!repo.is_ancestor(&side, &c2).await.unwrap());
+    }
+}
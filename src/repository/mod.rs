@@ -13,6 +13,27 @@ pub struct Repository {
     pub refs: Arc<Box<dyn RefsManager>>,
     pub hash_version: HashVersion,
     pub is_public: bool,
+    /// Most nodes a single history or tree walk over this repository may visit.
+    pub max_walk_nodes: usize,
 }
 
+pub mod archive;
+pub mod blame;
+pub mod blob;
+pub mod branch;
+pub mod diff;
+pub mod dumb;
+pub mod fork;
+pub mod fsck;
+pub mod gc;
+pub mod last_commit;
+pub mod log;
+pub mod merge_base;
 pub mod refs;
+pub mod rev;
+pub mod stats;
+pub mod tag;
+#[cfg(test)]
+pub mod testing;
+pub mod tree;
+pub mod walk;
@@ -1,7 +1,18 @@
 use crate::error::GitInnerError;
-use crate::refs::RefItem;
+use crate::refs::{RefItem, ReflogEntry};
 use crate::repository::Repository;
 use crate::sha::HashValue;
+use bytes::Bytes;
+
+/// Most refs [`Repository::refs_list_page`] returns at a time; larger limits are clamped.
+pub const MAX_REFS_PAGE: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct RefsPage {
+    pub refs: Vec<RefItem>,
+    /// Pass back as `page_token` to fetch the next page; `None` on the last page.
+    pub next_page_token: Option<String>,
+}
 
 impl Repository {
     pub async fn refs_insert(&self, name: String, value: HashValue) -> Result<(), GitInnerError> {
@@ -13,16 +24,210 @@ impl Repository {
     pub async fn refs_update(&self, name: String, value: HashValue) -> Result<(), GitInnerError> {
         self.refs.update_refs(name, value).await
     }
+    /// Move `name` to `new` only if it still points at `expected`; returns whether it moved.
+    pub async fn refs_cas_update(
+        &self,
+        name: String,
+        expected: HashValue,
+        new: HashValue,
+    ) -> Result<bool, GitInnerError> {
+        self.refs.cas_update(name, expected, new).await
+    }
     pub async fn refs_delete(&self, name: String) -> Result<(), GitInnerError> {
         self.refs.del_refs(name).await
     }
+    /// Audit history of `name`, oldest change first.
+    pub async fn reflog(&self, name: String) -> Result<Vec<ReflogEntry>, GitInnerError> {
+        self.refs.reflog(name).await
+    }
     pub async fn refs_list(&self) -> Result<Vec<RefItem>, GitInnerError> {
         self.refs.refs().await
     }
+    /// List refs sorted by name, `limit` at a time but at most [`MAX_REFS_PAGE`]. The page
+    /// token is the name of the last ref of the previous page, so pages stay stable while refs
+    /// are added or removed.
+    pub async fn refs_list_page(
+        &self,
+        prefix: Option<String>,
+        limit: usize,
+        page_token: Option<String>,
+    ) -> Result<RefsPage, GitInnerError> {
+        let limit = limit.clamp(1, MAX_REFS_PAGE);
+        let mut refs = self.refs.refs_page(prefix, page_token, limit + 1).await?;
+        let next_page_token = if refs.len() > limit {
+            refs.truncate(limit);
+            refs.last().map(|x| x.name.clone())
+        } else {
+            None
+        };
+        Ok(RefsPage {
+            refs,
+            next_page_token,
+        })
+    }
     pub async fn refs_exists(&self, name: String) -> Result<bool, GitInnerError> {
         self.refs.exists_refs(name).await
     }
     pub async fn refs_get_value(&self, name: String) -> Result<HashValue, GitInnerError> {
         self.refs.get_value_refs(name).await
     }
+    /// Weak ETag of the current ref set as rendered for `variant`, such as the service and
+    /// protocol version of an advertisement: a hash over `variant` and the name and oid of
+    /// every ref, sorted by name, with the ref `HEAD` points at marked. Identical ref states
+    /// give identical tags for the same variant.
+    pub async fn refs_etag(&self, variant: &str) -> Result<String, GitInnerError> {
+        let mut refs = self.refs.refs().await?;
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut data = format!("{}\n", variant);
+        for item in refs {
+            let head = if item.is_head { " HEAD" } else { "" };
+            data.push_str(&format!("{} {}{}\n", item.value, item.name, head));
+        }
+        Ok(format!(
+            "W/\"{}\"",
+            self.hash_version.hash(Bytes::from(data))
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repository::refs::MAX_REFS_PAGE;
+    use crate::repository::testing::{memory_repository, put_blob};
+
+    #[tokio::test]
+    async fn test_refs_list_page_prefix() {
+        let repo = memory_repository();
+        let hash = put_blob(&repo, "x").await;
+        for name in ["refs/heads/main", "refs/tags/v1", "refs/tags/v2"] {
+            repo.refs_insert(name.to_string(), hash.clone())
+                .await
+                .unwrap();
+        }
+        let page = repo
+            .refs_list_page(Some("refs/tags/".to_string()), 10, None)
+            .await
+            .unwrap();
+        let names = page
+            .refs
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["refs/tags/v1", "refs/tags/v2"]);
+        assert!(page.next_page_token.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refs_list_page_two_pages() {
+        let repo = memory_repository();
+        let hash = put_blob(&repo, "x").await;
+        for name in [
+            "refs/tags/c",
+            "refs/tags/a",
+            "refs/heads/main",
+            "refs/tags/b",
+        ] {
+            repo.refs_insert(name.to_string(), hash.clone())
+                .await
+                .unwrap();
+        }
+        let prefix = Some("refs/tags/".to_string());
+        let first = repo.refs_list_page(prefix.clone(), 2, None).await.unwrap();
+        let names = first
+            .refs
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["refs/tags/a", "refs/tags/b"]);
+        assert_eq!(first.next_page_token.as_deref(), Some("refs/tags/b"));
+
+        let second = repo
+            .refs_list_page(prefix, 2, first.next_page_token)
+            .await
+            .unwrap();
+        let names = second
+            .refs
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["refs/tags/c"]);
+        assert!(second.next_page_token.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refs_list_page_limit_clamped() {
+        let repo = memory_repository();
+        let hash = put_blob(&repo, "x").await;
+        for i in 0..MAX_REFS_PAGE + 1 {
+            repo.refs_insert(format!("refs/tags/t{:04}", i), hash.clone())
+                .await
+                .unwrap();
+        }
+        let page = repo.refs_list_page(None, usize::MAX, None).await.unwrap();
+        assert_eq!(page.refs.len(), MAX_REFS_PAGE);
+        assert_eq!(page.next_page_token.as_deref(), Some("refs/tags/t0999"));
+    }
+
+    #[tokio::test]
+    async fn test_refs_cas_update() {
+        let repo = memory_repository();
+        let one = put_blob(&repo, "one").await;
+        let two = put_blob(&repo, "two").await;
+        let three = put_blob(&repo, "three").await;
+        let name = "refs/heads/main".to_string();
+        repo.refs_insert(name.clone(), one.clone()).await.unwrap();
+
+        assert!(
+            repo.refs_cas_update(name.clone(), one.clone(), two.clone())
+                .await
+                .unwrap()
+        );
+        assert_eq!(repo.refs_get_value(name.clone()).await.unwrap(), two);
+
+        // 期望值已过期，不应改动
+        assert!(
+            !repo
+                .refs_cas_update(name.clone(), one.clone(), three.clone())
+                .await
+                .unwrap()
+        );
+        assert_eq!(repo.refs_get_value(name).await.unwrap(), two);
+        assert!(
+            !repo
+                .refs_cas_update("refs/heads/missing".to_string(), one, three)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refs_etag() {
+        let repo = memory_repository();
+        let one = put_blob(&repo, "one").await;
+        let two = put_blob(&repo, "two").await;
+        repo.refs_insert("refs/heads/main".to_string(), one.clone())
+            .await
+            .unwrap();
+        let etag = repo.refs_etag("v1").await.unwrap();
+        assert!(etag.starts_with("W/\""));
+        assert_eq!(repo.refs_etag("v1").await.unwrap(), etag);
+
+        // 相同的引用状态在另一个仓库里得到相同的 ETag
+        let other = memory_repository();
+        other
+            .refs_insert("refs/heads/main".to_string(), one.clone())
+            .await
+            .unwrap();
+        assert_eq!(other.refs_etag("v1").await.unwrap(), etag);
+
+        repo.refs_update("refs/heads/main".to_string(), two)
+            .await
+            .unwrap();
+        assert_ne!(repo.refs_etag("v1").await.unwrap(), etag);
+        // 同一引用状态的不同渲染方式不共用 ETag
+        assert_ne!(
+            repo.refs_etag("v1").await.unwrap(),
+            repo.refs_etag("v2").await.unwrap()
+        );
+    }
 }
@@ -0,0 +1,219 @@
+use crate::error::GitInnerError;
+use crate::repository::Repository;
+use crate::sha::HashValue;
+
+impl Repository {
+    /// Resolve a revision expression to an object id.
+    ///
+    /// Accepts a full or abbreviated hex oid, a full ref name (`refs/heads/main`), a short
+    /// branch or tag name (`main`), `HEAD`, followed by any number of `^N` / `~N` parent
+    /// suffixes such as `HEAD~2` or `main^2`.
+    pub async fn resolve_rev(&self, rev: &str) -> Result<HashValue, GitInnerError> {
+        let rev = rev.trim();
+        let split = rev.find(['^', '~']).unwrap_or(rev.len());
+        let (base, mut suffix) = rev.split_at(split);
+        if base.is_empty() {
+            return Err(GitInnerError::InvalidRevision(rev.to_string()));
+        }
+        let mut hash = self.resolve_rev_base(base).await?;
+        while let Some(op) = suffix.chars().next() {
+            suffix = &suffix[1..];
+            let digits_end = suffix
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(suffix.len());
+            let (digits, rest) = suffix.split_at(digits_end);
+            suffix = rest;
+            let n = if digits.is_empty() {
+                1
+            } else {
+                digits
+                    .parse::<usize>()
+                    .map_err(|_| GitInnerError::InvalidRevision(rev.to_string()))?
+            };
+            match op {
+                '^' => {
+                    hash = self.peel_to_commit(&hash).await?;
+                    if n == 0 {
+                        continue;
+                    }
+                    let commit = self.odb.get_commit(&hash).await?;
+                    hash = commit
+                        .parents
+                        .get(n - 1)
+                        .cloned()
+                        .ok_or_else(|| GitInnerError::RevisionNotFound(rev.to_string()))?;
+                }
+                '~' => {
+                    hash = self.peel_to_commit(&hash).await?;
+                    for _ in 0..n {
+                        let commit = self.odb.get_commit(&hash).await?;
+                        hash = commit
+                            .parents
+                            .first()
+                            .cloned()
+                            .ok_or_else(|| GitInnerError::RevisionNotFound(rev.to_string()))?;
+                    }
+                }
+                _ => return Err(GitInnerError::InvalidRevision(rev.to_string())),
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Follow annotated tags until a non-tag object is reached.
+    pub async fn peel_to_commit(&self, hash: &HashValue) -> Result<HashValue, GitInnerError> {
+        let mut hash = hash.clone();
+        while self.odb.has_tag(&hash).await? {
+            hash = self.odb.get_tag(&hash).await?.object_hash;
+        }
+        Ok(hash)
+    }
+
+    async fn resolve_rev_base(&self, base: &str) -> Result<HashValue, GitInnerError> {
+        if let Some(hash) = HashValue::from_str(base) {
+            return Ok(hash);
+        }
+        if base == "HEAD" {
+            let head = self.refs.head().await?;
+            if head.value.is_zero() {
+                return Err(GitInnerError::RevisionNotFound(base.to_string()));
+            }
+            return Ok(head.value);
+        }
+        for name in [
+            base.to_string(),
+            format!("refs/{}", base),
+            format!("refs/tags/{}", base),
+            format!("refs/heads/{}", base),
+        ] {
+            if self.refs.exists_refs(name.clone()).await? {
+                return self.refs.get_value_refs(name).await;
+            }
+        }
+        if base.len() >= 4 && base.chars().all(|c| c.is_ascii_hexdigit()) {
+            let prefix = base.to_ascii_lowercase();
+            let mut matches = self.odb.find_by_prefix(&prefix).await?;
+            matches.sort_by_key(|x| x.to_string());
+            matches.dedup();
+            return match matches.len() {
+                0 => Err(GitInnerError::RevisionNotFound(base.to_string())),
+                1 => Ok(matches.remove(0)),
+                _ => Err(GitInnerError::AmbiguousRevision(base.to_string())),
+            };
+        }
+        Err(GitInnerError::RevisionNotFound(base.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::GitInnerError;
+    use crate::objects::blob::Blob;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::Repository;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+    use crate::sha::{HashValue, HashVersion};
+    use bytes::Bytes;
+    use std::collections::HashMap;
+
+    async fn linear_history(repo: &Repository) -> Vec<HashValue> {
+        let blob = put_blob(repo, "hello").await;
+        let tree = put_tree(repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let c1 = put_commit(repo, &tree, &[], "first", 1).await;
+        let c2 = put_commit(repo, &tree, &[c1.clone()], "second", 2).await;
+        let c3 = put_commit(repo, &tree, &[c2.clone()], "third", 3).await;
+        repo.refs_insert("refs/heads/main".to_string(), c3.clone())
+            .await
+            .unwrap();
+        vec![c1, c2, c3]
+    }
+
+    #[tokio::test]
+    async fn test_resolve_full_and_short_oid() {
+        let repo = memory_repository();
+        let commits = linear_history(&repo).await;
+        let full = commits[1].to_string();
+        assert_eq!(repo.resolve_rev(&full).await.unwrap(), commits[1]);
+        assert_eq!(repo.resolve_rev(&full[..10]).await.unwrap(), commits[1]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ref_names() {
+        let repo = memory_repository();
+        let commits = linear_history(&repo).await;
+        assert_eq!(
+            repo.resolve_rev("refs/heads/main").await.unwrap(),
+            commits[2]
+        );
+        assert_eq!(repo.resolve_rev("main").await.unwrap(), commits[2]);
+        assert_eq!(repo.resolve_rev("HEAD").await.unwrap(), commits[2]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_parent_suffixes() {
+        let repo = memory_repository();
+        let commits = linear_history(&repo).await;
+        assert_eq!(repo.resolve_rev("HEAD^").await.unwrap(), commits[1]);
+        assert_eq!(repo.resolve_rev("HEAD~2").await.unwrap(), commits[0]);
+        assert_eq!(repo.resolve_rev("main~1^").await.unwrap(), commits[0]);
+        assert_eq!(repo.resolve_rev("HEAD^0").await.unwrap(), commits[2]);
+        assert!(matches!(
+            repo.resolve_rev("HEAD~3").await,
+            Err(GitInnerError::RevisionNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_second_parent() {
+        let repo = memory_repository();
+        let commits = linear_history(&repo).await;
+        let tree = repo.odb.get_commit(&commits[0]).await.unwrap().tree.unwrap();
+        let merge = put_commit(
+            &repo,
+            &tree,
+            &[commits[2].clone(), commits[0].clone()],
+            "merge",
+            4,
+        )
+        .await;
+        let rev = format!("{}^2", merge);
+        assert_eq!(repo.resolve_rev(&rev).await.unwrap(), commits[0]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ambiguous_short_oid() {
+        let repo = memory_repository();
+        let mut seen: HashMap<String, String> = HashMap::new();
+        let mut prefix = None;
+        for i in 0.. {
+            let content = format!("blob {}", i);
+            let blob = Blob::parse(Bytes::from(content.clone()), HashVersion::Sha1);
+            let key = blob.id.to_string()[..4].to_string();
+            if let Some(other) = seen.insert(key.clone(), content.clone()) {
+                put_blob(&repo, &other).await;
+                put_blob(&repo, &content).await;
+                prefix = Some(key);
+                break;
+            }
+        }
+        let prefix = prefix.unwrap();
+        assert!(matches!(
+            repo.resolve_rev(&prefix).await,
+            Err(GitInnerError::AmbiguousRevision(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_unknown() {
+        let repo = memory_repository();
+        linear_history(&repo).await;
+        assert!(matches!(
+            repo.resolve_rev("does-not-exist").await,
+            Err(GitInnerError::RevisionNotFound(_))
+        ));
+        assert!(matches!(
+            repo.resolve_rev("~1").await,
+            Err(GitInnerError::InvalidRevision(_))
+        ));
+    }
+}
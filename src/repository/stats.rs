@@ -0,0 +1,66 @@
+use crate::error::GitInnerError;
+use crate::odb::OdbStats;
+use crate::repository::Repository;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a computed [`OdbStats`] is served before the store is queried again.
+pub const STATS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    static ref STATS_CACHE: Mutex<HashMap<Uuid, (Instant, OdbStats)>> = Mutex::new(HashMap::new());
+}
+
+impl Repository {
+    /// Object counts and total blob bytes, cached per repository for [`STATS_CACHE_TTL`].
+    pub async fn stats(&self) -> Result<OdbStats, GitInnerError> {
+        if let Some((at, stats)) = STATS_CACHE.lock().unwrap().get(&self.id)
+            && at.elapsed() < STATS_CACHE_TTL
+        {
+            return Ok(*stats);
+        }
+        let stats = self.odb.stats().await?;
+        STATS_CACHE
+            .lock()
+            .unwrap()
+            .insert(self.id, (Instant::now(), stats));
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    #[tokio::test]
+    async fn test_stats_counts_objects() {
+        let repo = memory_repository();
+        let readme = put_blob(&repo, "hello").await;
+        let main = put_blob(&repo, "fn main() {}\n").await;
+        let tree = put_tree(
+            &repo,
+            vec![
+                (TreeItemMode::Blob, "README", readme),
+                (TreeItemMode::Blob, "main.rs", main),
+            ],
+        )
+        .await;
+        let first = put_commit(&repo, &tree, &[], "first", 1).await;
+        put_commit(&repo, &tree, &[first], "second", 2).await;
+
+        let stats = repo.stats().await.unwrap();
+        assert_eq!(stats.commits, 2);
+        assert_eq!(stats.trees, 1);
+        assert_eq!(stats.tags, 0);
+        assert_eq!(stats.blobs, 2);
+        assert_eq!(stats.blob_bytes, 5 + 13);
+
+        // Served from the cache until the TTL expires.
+        put_blob(&repo, "more").await;
+        assert_eq!(repo.stats().await.unwrap(), stats);
+        assert_eq!(repo.odb.stats().await.unwrap().blobs, 3);
+    }
+}
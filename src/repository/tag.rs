@@ -0,0 +1,155 @@
+use crate::auth::AccessLevel;
+use crate::error::GitInnerError;
+use crate::objects::signature::Signature;
+use crate::objects::tag::Tag;
+use crate::objects::types::ObjectType;
+use crate::refs::is_valid_ref_name;
+use crate::repository::Repository;
+use crate::sha::HashValue;
+use bytes::Bytes;
+
+impl Repository {
+    /// Create `refs/tags/<name>` pointing at `target`.
+    ///
+    /// Without a message this is a lightweight tag. With a message an annotated tag object
+    /// is written first and the ref points at it; `tagger` is then required.
+    pub async fn create_tag(
+        &self,
+        access: &AccessLevel,
+        name: &str,
+        target: &str,
+        message: Option<String>,
+        tagger: Option<Signature>,
+    ) -> Result<HashValue, GitInnerError> {
+        if matches!(access, AccessLevel::Read) {
+            return Err(GitInnerError::PermissionDenied);
+        }
+        if !is_valid_ref_name(name) {
+            return Err(GitInnerError::InvalidArgument(format!(
+                "invalid tag name: {}",
+                name
+            )));
+        }
+        let ref_name = format!("refs/tags/{}", name);
+        if self.refs.exists_refs(ref_name.clone()).await? {
+            return Err(GitInnerError::RefAlreadyExists(ref_name));
+        }
+        let target = self.resolve_rev(target).await?;
+        let object_type = self.object_type(&target).await?;
+        let value = match message {
+            None => target,
+            Some(message) => {
+                let tagger = tagger.ok_or_else(|| {
+                    GitInnerError::InvalidArgument("annotated tag requires a tagger".to_string())
+                })?;
+                let mut message = message;
+                if !message.ends_with('\n') {
+                    message.push('\n');
+                }
+                let data = format!(
+                    "object {}\ntype {}\ntag {}\ntagger {}\n\n{}",
+                    target, object_type, name, tagger, message
+                );
+                let tag = Tag::parse(Bytes::from(data), self.hash_version)?;
+                self.odb.put_tag(&tag).await?
+            }
+        };
+        self.refs.create_refs(ref_name, value.clone()).await?;
+        Ok(value)
+    }
+
+    /// Look up which kind of object `hash` is.
+    pub async fn object_type(&self, hash: &HashValue) -> Result<ObjectType, GitInnerError> {
+        if self.odb.has_commit(hash).await? {
+            Ok(ObjectType::Commit)
+        } else if self.odb.has_tree(hash).await? {
+            Ok(ObjectType::Tree)
+        } else if self.odb.has_blob(hash).await? {
+            Ok(ObjectType::Blob)
+        } else if self.odb.has_tag(hash).await? {
+            Ok(ObjectType::Tag)
+        } else {
+            Err(GitInnerError::ObjectNotFound(hash.clone()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::ObjectTrait;
+    use crate::objects::signature::SignatureType;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    async fn init(repo: &Repository) -> HashValue {
+        let blob = put_blob(repo, "hello").await;
+        let tree = put_tree(repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+        commit
+    }
+
+    fn tagger() -> Signature {
+        Signature {
+            signature_type: SignatureType::Tagger,
+            name: "Tagger".to_string(),
+            email: "tagger@example.com".to_string(),
+            timestamp: 1700000000,
+            timezone: "+0800".to_string(),
+            tz_offset_minutes: Some(480),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_lightweight_tag() {
+        let repo = memory_repository();
+        let commit = init(&repo).await;
+        let value = repo
+            .create_tag(&AccessLevel::Write, "v1.0", "main", None, None)
+            .await
+            .unwrap();
+        assert_eq!(value, commit);
+        assert_eq!(
+            repo.refs_get_value("refs/tags/v1.0".to_string())
+                .await
+                .unwrap(),
+            commit
+        );
+        assert!(matches!(
+            repo.create_tag(&AccessLevel::Write, "v1.0", "main", None, None)
+                .await,
+            Err(GitInnerError::RefAlreadyExists(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_create_annotated_tag() {
+        let repo = memory_repository();
+        let commit = init(&repo).await;
+        let value = repo
+            .create_tag(
+                &AccessLevel::Write,
+                "v2.0",
+                "main",
+                Some("release".to_string()),
+                Some(tagger()),
+            )
+            .await
+            .unwrap();
+        assert_ne!(value, commit);
+        let tag = repo.odb.get_tag(&value).await.unwrap();
+        assert_eq!(tag.object_hash, commit);
+        assert_eq!(tag.object_type, ObjectType::Commit);
+        assert_eq!(tag.tag_name, "v2.0");
+        assert_eq!(tag.message, "release\n");
+        assert_eq!(tag.tagger.email, "tagger@example.com");
+        assert_eq!(tag.tagger.tz_offset_minutes, Some(480));
+
+        let parsed = Tag::parse(tag.get_data(), repo.hash_version).unwrap();
+        assert_eq!(parsed.id, value);
+        assert_eq!(repo.resolve_rev("v2.0^0").await.unwrap(), commit);
+    }
+}
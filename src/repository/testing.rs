@@ -0,0 +1,159 @@
+use crate::callback::CallBack;
+use crate::objects::blob::Blob;
+use crate::objects::commit::Commit;
+use crate::objects::tree::{Tree, TreeItem, TreeItemMode};
+use crate::odb::memory::OdbMemory;
+use crate::refs::memory::MemoryRefsManager;
+use crate::repository::Repository;
+use crate::repository::walk::MAX_WALK_NODES;
+use crate::sha::{HashValue, HashVersion};
+use crate::transaction::upload::recursion::Object;
+use crate::transaction::{GitProtoVersion, ProtocolType, Transaction, TransactionService};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::sync::Arc;
+
+/// Build a SHA-1 repository backed by the in-memory odb and refs manager.
+pub fn memory_repository() -> Repository {
+    memory_repository_with_odb().0
+}
+
+/// Like [`memory_repository`], also returning a handle to the odb for inspecting counters.
+pub fn memory_repository_with_odb() -> (Repository, OdbMemory) {
+    let odb = OdbMemory::default();
+    let repo = Repository {
+        id: uuid::Uuid::new_v4(),
+        default_branch: "main".to_string(),
+        owner: Default::default(),
+        odb: Arc::new(Box::new(odb.clone())),
+        refs: Arc::new(Box::new(MemoryRefsManager::new("main", HashVersion::Sha1))),
+        hash_version: HashVersion::Sha1,
+        is_public: true,
+        max_walk_nodes: MAX_WALK_NODES,
+    };
+    (repo, odb)
+}
+
+/// Wrap `repo` in a protocol v2 upload-pack transaction whose output can be read back
+/// from `call_back.receive`.
+pub fn transaction(repo: &Repository) -> Transaction {
+    Transaction {
+        service: TransactionService::UploadPack,
+        repository: repo.clone(),
+        version: GitProtoVersion::V2,
+        call_back: CallBack::new(1024),
+        protocol: ProtocolType::Http,
+        timeouts: Default::default(),
+        pack_limits: Default::default(),
+        actor: None,
+        maintenance: Default::default(),
+        metrics: Default::default(),
+        fetch_resume: Default::default(),
+        hooks: None,
+        push_limiter: Default::default(),
+        deny_non_fast_forwards: false,
+    }
+}
+
+/// A protocol v1 receive-pack transaction over `repo`.
+pub fn receive_transaction(repo: &Repository) -> Transaction {
+    Transaction {
+        service: TransactionService::ReceivePack,
+        version: GitProtoVersion::V1,
+        ..transaction(repo)
+    }
+}
+
+/// Everything `txn` has sent to its client so far, lossily decoded.
+pub async fn output(txn: &Transaction) -> String {
+    let mut receiver = txn.call_back.receive.lock().await;
+    let mut data = Vec::new();
+    while let Ok(next) = receiver.try_recv() {
+        data.extend_from_slice(&next);
+    }
+    String::from_utf8_lossy(&data).to_string()
+}
+
+pub async fn put_blob(repo: &Repository, content: &str) -> HashValue {
+    let blob = Blob::parse(Bytes::from(content.to_string()), repo.hash_version);
+    repo.odb.put_blob(blob).await.unwrap()
+}
+
+pub async fn put_tree(repo: &Repository, items: Vec<(TreeItemMode, &str, HashValue)>) -> HashValue {
+    let mut items = items
+        .into_iter()
+        .map(|(mode, name, id)| TreeItem::new(mode, id, name.to_string()))
+        .collect::<Vec<_>>();
+    items.sort_by_key(|x| {
+        if x.mode == TreeItemMode::Tree {
+            format!("{}/", x.name)
+        } else {
+            x.name.clone()
+        }
+    });
+    let mut data = Vec::new();
+    for item in &items {
+        data.extend_from_slice(&item.to_data());
+    }
+    let tree = Tree::parse(Bytes::from(data), repo.hash_version).unwrap();
+    repo.odb.put_tree(&tree).await.unwrap()
+}
+
+pub async fn put_commit(
+    repo: &Repository,
+    tree: &HashValue,
+    parents: &[HashValue],
+    message: &str,
+    timestamp: usize,
+) -> HashValue {
+    let mut data = format!("tree {}\n", tree);
+    for parent in parents {
+        data.push_str(&format!("parent {}\n", parent));
+    }
+    data.push_str(&format!(
+        "author Test <test@example.com> {} +0000\n",
+        timestamp
+    ));
+    data.push_str(&format!(
+        "committer Test <test@example.com> {} +0000\n\n{}\n",
+        timestamp, message
+    ));
+    let commit = Commit::parse(Bytes::from(data), repo.hash_version).unwrap();
+    repo.odb.put_commit(&commit).await.unwrap()
+}
+
+/// `base - main - merge` with `side` branching off `base` and merged as the second parent.
+/// `main` changes `a.txt` and `side` changes `b.txt`. Returns `(base, main, side, merge)`.
+pub async fn merge_history(repo: &Repository) -> (HashValue, HashValue, HashValue, HashValue) {
+    let a1 = put_blob(repo, "a1").await;
+    let a2 = put_blob(repo, "a2").await;
+    let b1 = put_blob(repo, "b1").await;
+    let b2 = put_blob(repo, "b2").await;
+    let tree = |a: &HashValue, b: &HashValue| {
+        vec![
+            (TreeItemMode::Blob, "a.txt", a.clone()),
+            (TreeItemMode::Blob, "b.txt", b.clone()),
+        ]
+    };
+    let base_tree = put_tree(repo, tree(&a1, &b1)).await;
+    let main_tree = put_tree(repo, tree(&a2, &b1)).await;
+    let side_tree = put_tree(repo, tree(&a1, &b2)).await;
+    let merge_tree = put_tree(repo, tree(&a2, &b2)).await;
+    let base = put_commit(repo, &base_tree, &[], "base", 1).await;
+    let main = put_commit(repo, &main_tree, &[base.clone()], "main", 2).await;
+    let side = put_commit(repo, &side_tree, &[base.clone()], "side", 3).await;
+    let merge = put_commit(repo, &merge_tree, &[main.clone(), side.clone()], "merge", 4).await;
+    (base, main, side, merge)
+}
+
+/// Encode `objects` as a version 2 packfile, trailer checksum included.
+pub fn pack(objects: &[Object]) -> Bytes {
+    let mut data = BytesMut::from(&b"PACK"[..]);
+    data.put_u32(2);
+    data.put_u32(objects.len() as u32);
+    for object in objects {
+        data.extend_from_slice(&object.zlib(flate2::Compression::default()).unwrap());
+    }
+    let checksum = HashVersion::Sha1.hash(Bytes::from(data.to_vec()));
+    data.extend_from_slice(checksum.as_bytes());
+    data.freeze()
+}
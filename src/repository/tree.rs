@@ -0,0 +1,372 @@
+use crate::error::GitInnerError;
+use crate::objects::tree::{TreeItem, TreeItemMode};
+use crate::repository::Repository;
+use crate::sha::HashValue;
+
+/// Gitignore-style exclude patterns matched against repo-relative paths.
+///
+/// `*` and `?` stay within one path component, `**` spans components and a trailing `/`
+/// restricts a pattern to directories. A pattern without any other `/` matches the entry
+/// name at any depth; otherwise it is anchored at the repository root. Entries below an
+/// excluded directory are excluded as well.
+#[derive(Clone, Debug, Default)]
+pub struct PathFilter {
+    patterns: Vec<PathPattern>,
+}
+
+/// Entry of a recursive tree listing, with its path relative to the repository root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub path: String,
+    pub mode: TreeItemMode,
+    pub id: HashValue,
+}
+
+#[derive(Clone, Debug)]
+struct PathPattern {
+    glob: String,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl PathFilter {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let patterns = patterns
+            .iter()
+            .map(|x| x.as_ref().trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                let dir_only = x.ends_with('/');
+                let glob = x.trim_end_matches('/');
+                let anchored = glob.contains('/');
+                PathPattern {
+                    glob: glob.trim_start_matches('/').to_string(),
+                    dir_only,
+                    anchored,
+                }
+            })
+            .collect();
+        PathFilter { patterns }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether `path` (or one of its parent directories) matches an exclude pattern.
+    pub fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        let path = path.trim_matches('/');
+        for (idx, _) in path.match_indices('/') {
+            if self.matches(&path[..idx], true) {
+                return true;
+            }
+        }
+        self.matches(path, is_dir)
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.patterns.iter().any(|x| {
+            if x.dir_only && !is_dir {
+                return false;
+            }
+            let target = if x.anchored { path } else { name };
+            glob_match(x.glob.as_bytes(), target.as_bytes())
+        })
+    }
+}
+
+/// Whether `path` matches `pattern`. Every pair of pattern and path positions is decided at
+/// most once, so patterns with many `*` stay polynomial instead of backtracking
+/// exponentially.
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    let mut memo = vec![None; (pattern.len() + 1) * (path.len() + 1)];
+    glob_match_at(pattern, path, 0, 0, &mut memo)
+}
+
+/// Whether `path[t..]` matches `pattern[p..]`, memoised in `memo`.
+fn glob_match_at(
+    pattern: &[u8],
+    path: &[u8],
+    p: usize,
+    t: usize,
+    memo: &mut [Option<bool>],
+) -> bool {
+    let key = p * (path.len() + 1) + t;
+    if let Some(matched) = memo[key] {
+        return matched;
+    }
+    let matched = if p == pattern.len() {
+        t == path.len()
+    } else if pattern[p..].starts_with(b"**") {
+        // `**/` 也可以不匹配任何目录
+        (pattern.get(p + 2) == Some(&b'/') && glob_match_at(pattern, path, p + 3, t, memo))
+            || (t..=path.len()).any(|i| glob_match_at(pattern, path, p + 2, i, memo))
+    } else {
+        match pattern[p] {
+            b'*' => {
+                // `*` 不跨越目录
+                let end = path[t..]
+                    .iter()
+                    .position(|x| *x == b'/')
+                    .map_or(path.len(), |x| t + x);
+                (t..=end).any(|i| glob_match_at(pattern, path, p + 1, i, memo))
+            }
+            b'?' => {
+                t < path.len()
+                    && path[t] != b'/'
+                    && glob_match_at(pattern, path, p + 1, t + 1, memo)
+            }
+            c => t < path.len() && path[t] == c && glob_match_at(pattern, path, p + 1, t + 1, memo),
+        }
+    };
+    memo[key] = Some(matched);
+    matched
+}
+
+impl Repository {
+    /// List the entries of the directory `path` in the tree of `rev`, dropping entries whose
+    /// repo-relative path is matched by `exclude`. Submodules are listed as `Commit` entries
+    /// carrying the commit they point at.
+    pub async fn list_tree(
+        &self,
+        rev: &str,
+        path: &str,
+        exclude: &PathFilter,
+    ) -> Result<Vec<TreeItem>, GitInnerError> {
+        let path = path.trim_matches('/');
+        let tree = self.tree_at_path(rev, path).await?;
+        let items = self.odb.get_tree(&tree).await?.tree_items;
+        if exclude.is_empty() {
+            return Ok(items);
+        }
+        Ok(items
+            .into_iter()
+            .filter(|x| {
+                !exclude.is_excluded(&join_path(path, &x.name), x.mode == TreeItemMode::Tree)
+            })
+            .collect())
+    }
+
+    /// List everything below the directory `path` in the tree of `rev`, parents before their
+    /// children. `max_depth` bounds how many directory levels below `path` are entered:
+    /// `Some(0)` lists only `path` itself and `None` walks the whole subtree. Excluded
+    /// directories are not entered, and listings longer than `max_walk_nodes` entries fail
+    /// with `GraphTooLarge`.
+    pub async fn list_tree_recursive(
+        &self,
+        rev: &str,
+        path: &str,
+        max_depth: Option<usize>,
+        exclude: &PathFilter,
+    ) -> Result<Vec<TreeEntry>, GitInnerError> {
+        let path = path.trim_matches('/');
+        let tree = self.tree_at_path(rev, path).await?;
+        let mut entries = vec![];
+        // (目录路径, 深度, 待输出的条目，逆序存放以便 pop)
+        let mut stack = vec![(path.to_string(), 0, self.tree_items_rev(&tree).await?)];
+        while let Some((dir, depth, items)) = stack.last_mut() {
+            let Some(item) = items.pop() else {
+                stack.pop();
+                continue;
+            };
+            let full = join_path(dir, &item.name);
+            let is_dir = item.mode == TreeItemMode::Tree;
+            if exclude.is_excluded(&full, is_dir) {
+                continue;
+            }
+            if entries.len() >= self.max_walk_nodes {
+                return Err(GitInnerError::GraphTooLarge);
+            }
+            let depth = *depth;
+            entries.push(TreeEntry {
+                path: full.clone(),
+                mode: item.mode,
+                id: item.id.clone(),
+            });
+            if is_dir && max_depth.is_none_or(|max| depth < max) {
+                let items = self.tree_items_rev(&item.id).await?;
+                stack.push((full, depth + 1, items));
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn tree_items_rev(&self, tree: &HashValue) -> Result<Vec<TreeItem>, GitInnerError> {
+        let mut items = self.odb.get_tree(tree).await?.tree_items;
+        items.reverse();
+        Ok(items)
+    }
+
+    /// Id of the tree at the directory `path` (already trimmed of slashes) in `rev`.
+    async fn tree_at_path(&self, rev: &str, path: &str) -> Result<HashValue, GitInnerError> {
+        Ok(if path.is_empty() {
+            let hash = self.resolve_rev(rev).await?;
+            let hash = self.peel_to_commit(&hash).await?;
+            self.odb
+                .get_commit(&hash)
+                .await?
+                .tree
+                .ok_or_else(|| GitInnerError::PathNotFound(path.to_string()))?
+        } else {
+            let item = self.tree_item_at_path(rev, path).await?;
+            if item.mode.is_submodule() {
+                return Err(GitInnerError::SubmoduleBoundary {
+                    path: path.to_string(),
+                    commit: item.id,
+                });
+            }
+            if item.mode != TreeItemMode::Tree {
+                return Err(GitInnerError::PathNotFound(path.to_string()));
+            }
+            item.id
+        })
+    }
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    #[test]
+    fn test_path_filter() {
+        let filter = PathFilter::new(&["*.lock", "target/**", "build/", "/docs/*.md"]);
+        assert!(filter.is_excluded("Cargo.lock", false));
+        assert!(filter.is_excluded("crates/a/Cargo.lock", false));
+        assert!(!filter.is_excluded("Cargo.toml", false));
+        assert!(filter.is_excluded("target/debug", true));
+        assert!(filter.is_excluded("target/debug/app", false));
+        assert!(!filter.is_excluded("target", true));
+        assert!(filter.is_excluded("build", true));
+        assert!(!filter.is_excluded("build", false));
+        assert!(filter.is_excluded("build/out.o", false));
+        assert!(filter.is_excluded("docs/index.md", false));
+        assert!(!filter.is_excluded("docs/api/index.md", false));
+        assert!(PathFilter::new(&["a/**/b"]).is_excluded("a/b", false));
+        assert!(PathFilter::new(&["a/**/b"]).is_excluded("a/x/y/b", false));
+    }
+
+    #[test]
+    fn test_glob_many_stars_is_fast() {
+        let pattern = "*a".repeat(30) + "b";
+        let path = "a".repeat(200);
+        assert!(!glob_match(pattern.as_bytes(), path.as_bytes()));
+        assert!(glob_match(pattern.as_bytes(), (path + "b").as_bytes()));
+        let pattern = "**/a".repeat(20) + "/b";
+        let path = "a/".repeat(100) + "c";
+        assert!(!glob_match(pattern.as_bytes(), path.as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn test_list_tree_excludes() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "x").await;
+        let debug = put_tree(&repo, vec![(TreeItemMode::Blob, "app", blob.clone())]).await;
+        let target = put_tree(
+            &repo,
+            vec![
+                (TreeItemMode::Tree, "debug", debug),
+                (TreeItemMode::Blob, "Cargo.lock", blob.clone()),
+            ],
+        )
+        .await;
+        let tree = put_tree(
+            &repo,
+            vec![
+                (TreeItemMode::Blob, "Cargo.lock", blob.clone()),
+                (TreeItemMode::Blob, "Cargo.toml", blob.clone()),
+                (TreeItemMode::Tree, "target", target),
+            ],
+        )
+        .await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        let rev = commit.to_string();
+
+        let filter = PathFilter::new(&["*.lock"]);
+        let names = repo
+            .list_tree(&rev, "", &filter)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Cargo.toml", "target"]);
+
+        let filter = PathFilter::new(&["target/**"]);
+        assert!(
+            repo.list_tree(&rev, "target", &filter)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let all = repo
+            .list_tree(&rev, "target", &PathFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_list_tree_recursive_depth() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "x").await;
+        let c = put_tree(&repo, vec![(TreeItemMode::Blob, "c.txt", blob.clone())]).await;
+        let b = put_tree(
+            &repo,
+            vec![
+                (TreeItemMode::Blob, "b.txt", blob.clone()),
+                (TreeItemMode::Tree, "c", c),
+            ],
+        )
+        .await;
+        let tree = put_tree(
+            &repo,
+            vec![
+                (TreeItemMode::Blob, "a.txt", blob.clone()),
+                (TreeItemMode::Tree, "b", b),
+                (TreeItemMode::Blob, "z.txt", blob),
+            ],
+        )
+        .await;
+        let rev = put_commit(&repo, &tree, &[], "init", 1).await.to_string();
+        let paths = |depth: Option<usize>, path: &'static str| {
+            let repo = repo.clone();
+            let rev = rev.clone();
+            async move {
+                repo.list_tree_recursive(&rev, path, depth, &PathFilter::default())
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|x| x.path)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(paths(Some(0), "").await, vec!["a.txt", "b", "z.txt"]);
+        assert_eq!(
+            paths(Some(1), "").await,
+            vec!["a.txt", "b", "b/b.txt", "b/c", "z.txt"]
+        );
+        let all = vec!["a.txt", "b", "b/b.txt", "b/c", "b/c/c.txt", "z.txt"];
+        assert_eq!(paths(Some(2), "").await, all);
+        assert_eq!(paths(None, "").await, all);
+        assert_eq!(paths(None, "b/").await, vec!["b/b.txt", "b/c", "b/c/c.txt"]);
+
+        let filter = PathFilter::new(&["c/"]);
+        let entries = repo
+            .list_tree_recursive(&rev, "", None, &filter)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[2].mode, TreeItemMode::Blob);
+    }
+}
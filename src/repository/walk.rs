@@ -0,0 +1,92 @@
+use crate::error::GitInnerError;
+use crate::sha::HashValue;
+use std::collections::HashSet;
+
+/// Most nodes a single history walk inside a repository may visit, unless the repository
+/// store is configured with another limit.
+pub const MAX_WALK_NODES: usize = 1_000_000;
+
+/// Mark `hash` as visited by a graph walk. Returns `false` when it was already visited, which
+/// is also what ends a walk around a parent cycle, and fails with `GraphTooLarge` once
+/// `visited` would grow past `limit`.
+pub fn visit(
+    visited: &mut HashSet<HashValue>,
+    hash: &HashValue,
+    limit: usize,
+) -> Result<bool, GitInnerError> {
+    if visited.contains(hash) {
+        return Ok(false);
+    }
+    if visited.len() >= limit {
+        return Err(GitInnerError::GraphTooLarge);
+    }
+    visited.insert(hash.clone());
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::Repository;
+    use crate::repository::log::LogOptions;
+    use crate::repository::testing::{memory_repository, put_blob, put_commit, put_tree};
+
+    /// Two commits naming each other as parent, as a crafted push could store.
+    async fn cycle(repo: &Repository) -> (HashValue, HashValue) {
+        let blob = put_blob(repo, "hello").await;
+        let tree = put_tree(repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let a = put_commit(repo, &tree, &[], "a", 1).await;
+        let b = put_commit(repo, &tree, &[a.clone()], "b", 2).await;
+        let mut commit = repo.odb.get_commit(&a).await.unwrap();
+        commit.parents = vec![b.clone()];
+        repo.odb.put_commit(&commit).await.unwrap();
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn test_walk_terminates_on_cycle() {
+        let repo = memory_repository();
+        let (a, b) = cycle(&repo).await;
+        let options = LogOptions {
+            limit: 10,
+            ..Default::default()
+        };
+        assert!(repo.is_ancestor(&a, &b).await.unwrap());
+        let page = repo.log(&b.to_string(), &options).await.unwrap();
+        assert_eq!(
+            page.commits
+                .iter()
+                .map(|x| x.hash.clone())
+                .collect::<Vec<_>>(),
+            vec![b.clone(), a.clone()]
+        );
+
+        let other = put_blob(&repo, "not a commit").await;
+        assert!(!repo.is_ancestor(&other, &b).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_walk_limit() {
+        let mut repo = memory_repository();
+        let (_, b) = cycle(&repo).await;
+        repo.max_walk_nodes = 1;
+        let options = LogOptions {
+            limit: 10,
+            ..Default::default()
+        };
+        let other = put_blob(&repo, "not a commit").await;
+        assert!(matches!(
+            repo.is_ancestor(&other, &b).await,
+            Err(GitInnerError::GraphTooLarge)
+        ));
+        assert!(matches!(
+            repo.log(&b.to_string(), &options).await,
+            Err(GitInnerError::GraphTooLarge)
+        ));
+        assert!(matches!(
+            repo.last_commits(&b.to_string(), "", false).await,
+            Err(GitInnerError::GraphTooLarge)
+        ));
+    }
+}
@@ -0,0 +1,29 @@
+use serde::Serialize;
+
+/// Result of probing one backing service for readiness.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DependencyCheck {
+    /// Name of the dependency, e.g. `mongodb` or `object_store`.
+    pub name: String,
+    /// Why the dependency is unusable; `None` when it answered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DependencyCheck {
+    pub fn ok(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            error: None,
+        }
+    }
+    pub fn failed(name: &str, error: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            error: Some(error.to_string()),
+        }
+    }
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
@@ -0,0 +1,299 @@
+use crate::config::transaction::TransactionConfig;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+
+/// Per-repository cap on concurrent receive-pack transactions.
+pub struct TransactionLimiter {
+    max_concurrent: usize,
+    semaphores: Mutex<HashMap<Uuid, Arc<Semaphore>>>,
+}
+
+impl TransactionLimiter {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            max_concurrent: max_concurrent.max(1),
+            semaphores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for a transaction slot on `repo`. The slot is released when the permit is dropped.
+    pub async fn acquire(&self, repo: Uuid) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut semaphores = self.semaphores.lock().unwrap();
+            // 只剩表里这一份引用的信号量既没有持有者也没有等待者，可以丢掉
+            semaphores.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+            semaphores
+                .entry(repo)
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max_concurrent)))
+                .clone()
+        };
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("transaction semaphore is never closed")
+    }
+}
+
+impl Default for TransactionLimiter {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+/// Token bucket of one client.
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-IP cap on new transactions: each client gets a token bucket of `capacity` tokens
+/// that refills evenly over `interval`, and every transaction takes one token.
+pub struct RateLimiter {
+    capacity: u32,
+    interval: Duration,
+    buckets: DashMap<IpAddr, Bucket>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// A `capacity` of 0 disables the limiter.
+    pub fn new(capacity: u32, interval: Duration) -> Self {
+        Self {
+            capacity,
+            interval: interval.max(Duration::from_millis(1)),
+            buckets: DashMap::new(),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// How long an empty bucket takes to earn the next token.
+    pub fn retry_after(&self) -> Duration {
+        self.interval / self.capacity.max(1)
+    }
+
+    /// Take a token for a new transaction from `ip`, returning false when its bucket is empty.
+    pub fn check(&self, ip: IpAddr) -> bool {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        self.sweep(now);
+        let capacity = self.capacity as f64;
+        let mut bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens
+            + capacity * elapsed.as_secs_f64() / self.interval.as_secs_f64())
+        .min(capacity);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Once per interval, forget clients whose bucket has refilled; they would start full anyway.
+    fn sweep(&self, now: Instant) {
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if now.saturating_duration_since(*last_sweep) < self.interval {
+                return;
+            }
+            *last_sweep = now;
+        }
+        self.buckets
+            .retain(|_, bucket| now.saturating_duration_since(bucket.updated) < self.interval);
+    }
+}
+
+impl From<&TransactionConfig> for RateLimiter {
+    fn from(config: &TransactionConfig) -> Self {
+        RateLimiter::new(
+            config.rate_limit_transactions,
+            Duration::from_secs(config.rate_limit_interval_secs),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::GitInnerError;
+    use crate::objects::blob::Blob;
+    use crate::repository::Repository;
+    use crate::repository::testing::{memory_repository, pack, receive_transaction};
+    use crate::sha::{HashValue, HashVersion};
+    use crate::transaction::upload::recursion::Object;
+    use crate::write_pkt_line;
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    async fn push(
+        limiter: &Arc<TransactionLimiter>,
+        repo: &Repository,
+        ref_name: &str,
+        content: &str,
+        active: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    ) -> HashValue {
+        let blob = Blob::parse(Bytes::from(content.to_string()), HashVersion::Sha1);
+        let mut commands = write_pkt_line(format!(
+            "{} {} {}\0report-status",
+            HashVersion::Sha1.default(),
+            blob.id,
+            ref_name
+        ));
+        commands.extend_from_slice(b"0000");
+        let body = pack(&[Object::Blob(blob.clone())]);
+        // 读到请求才算进入事务；pack 数据延迟到达，让另一个推送有机会并发进入
+        let counter = active.clone();
+        let stream = futures_util::stream::once(async move {
+            let now = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            Ok::<_, GitInnerError>(commands.freeze())
+        })
+        .chain(futures_util::stream::once(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(body)
+        }));
+        let mut txn = receive_transaction(repo);
+        txn.push_limiter = limiter.clone();
+        txn.receive_pack(Box::pin(stream)).await.unwrap();
+        active.fetch_sub(1, Ordering::SeqCst);
+        blob.id
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_pushes_serialize() {
+        let repo = memory_repository();
+        let limiter = Arc::new(TransactionLimiter::new(1));
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (a, b) = tokio::join!(
+            push(
+                &limiter,
+                &repo,
+                "refs/heads/a",
+                "a\n",
+                active.clone(),
+                peak.clone()
+            ),
+            push(
+                &limiter,
+                &repo,
+                "refs/heads/b",
+                "b\n",
+                active.clone(),
+                peak.clone()
+            ),
+        );
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+        for (name, id) in [("refs/heads/a", a), ("refs/heads/b", b)] {
+            assert!(repo.odb.has_blob(&id).await.unwrap());
+            let value = repo.refs.get_value_refs(name.to_string()).await.unwrap();
+            assert_eq!(value, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_semaphores_pruned() {
+        let limiter = TransactionLimiter::new(1);
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        drop(limiter.acquire(a).await);
+        let _held = limiter.acquire(b).await;
+        // a 的许可已释放，取 b 的许可时被清理；b 仍被持有，保留
+        assert_eq!(
+            limiter
+                .semaphores
+                .lock()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec![&b]
+        );
+        let _other = limiter.acquire(a).await;
+        assert_eq!(limiter.semaphores.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_limiter_is_per_repository() {
+        let limiter = TransactionLimiter::new(1);
+        let _a = limiter.acquire(Uuid::new_v4()).await;
+        let other =
+            tokio::time::timeout(Duration::from_millis(50), limiter.acquire(Uuid::new_v4()));
+        assert!(other.await.is_ok());
+    }
+
+    #[test]
+    fn test_rate_limit_rejects_burst() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let other: IpAddr = "203.0.113.8".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at(ip, now));
+        }
+        assert!(!limiter.check_at(ip, now));
+        // 其他客户端有各自的令牌桶
+        assert!(limiter.check_at(other, now));
+    }
+
+    #[test]
+    fn test_rate_limit_refills_over_time() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let now = Instant::now();
+        assert!(limiter.check_at(ip, now));
+        assert!(limiter.check_at(ip, now));
+        assert!(!limiter.check_at(ip, now));
+
+        // 30 秒补回一个令牌
+        let later = now + Duration::from_secs(30);
+        assert!(limiter.check_at(ip, later));
+        assert!(!limiter.check_at(ip, later));
+
+        // 空闲再久也不会超过容量
+        let much_later = later + Duration::from_secs(3600);
+        assert!(limiter.check_at(ip, much_later));
+        assert!(limiter.check_at(ip, much_later));
+        assert!(!limiter.check_at(ip, much_later));
+    }
+
+    #[test]
+    fn test_rate_limit_sweeps_idle_clients() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(limiter.check_at("203.0.113.7".parse().unwrap(), now));
+        assert_eq!(limiter.buckets.len(), 1);
+        assert!(limiter.check_at(
+            "203.0.113.8".parse().unwrap(),
+            now + Duration::from_secs(61)
+        ));
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn test_rate_limit_disabled() {
+        let limiter = RateLimiter::new(0, Duration::from_secs(60));
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!((0..100).all(|_| limiter.check(ip)));
+    }
+}
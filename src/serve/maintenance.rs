@@ -0,0 +1,20 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Message sent to clients whose push is refused while maintenance mode is on.
+pub const MAINTENANCE_MESSAGE: &str = "repository is in read-only maintenance mode";
+
+/// Server-wide read-only switch. Clones share the flag, so toggling it through
+/// [`crate::serve::AppCore::set_maintenance`] affects transactions already built.
+#[derive(Clone, Debug, Default)]
+pub struct MaintenanceMode(Arc<AtomicBool>);
+
+impl MaintenanceMode {
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+    }
+}
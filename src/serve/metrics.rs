@@ -0,0 +1,157 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio_metrics::TaskMonitor;
+
+#[derive(Default)]
+struct Counters {
+    pushes: AtomicU64,
+    fetches: AtomicU64,
+    bytes_served: AtomicU64,
+    auth_failures: AtomicU64,
+}
+
+/// Server-wide git counters, rendered in the Prometheus text format by `/metrics`.
+/// Clones share the same counters, so transactions can record into the registry held by
+/// [`crate::serve::AppCore`].
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Counters>,
+    task_monitor: Arc<OnceLock<TaskMonitor>>,
+}
+
+impl Metrics {
+    /// A receive-pack transaction ran to completion.
+    pub fn record_push(&self) {
+        self.counters.pushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An upload-pack transaction started sending a packfile.
+    pub fn record_fetch(&self) {
+        self.counters.fetches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `bytes` of packfile data were sent to a client.
+    pub fn record_bytes_served(&self, bytes: usize) {
+        self.counters
+            .bytes_served
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// A client presented credentials that were rejected.
+    pub fn record_auth_failure(&self) {
+        self.counters.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn pushes(&self) -> u64 {
+        self.counters.pushes.load(Ordering::Relaxed)
+    }
+
+    pub fn fetches(&self) -> u64 {
+        self.counters.fetches.load(Ordering::Relaxed)
+    }
+
+    /// Include the task metrics of `monitor` (see [`crate::control::Control`]) in the
+    /// rendered output. Only the first monitor attached is kept.
+    pub fn attach_task_monitor(&self, monitor: TaskMonitor) {
+        let _ = self.task_monitor.set(monitor);
+    }
+
+    /// All metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "git_inner_pushes_total",
+                "Completed receive-pack transactions.",
+                &self.counters.pushes,
+            ),
+            (
+                "git_inner_fetches_total",
+                "Packfiles generated by upload-pack.",
+                &self.counters.fetches,
+            ),
+            (
+                "git_inner_bytes_served_total",
+                "Packfile bytes sent to clients.",
+                &self.counters.bytes_served,
+            ),
+            (
+                "git_inner_auth_failures_total",
+                "Rejected authentication attempts.",
+                &self.counters.auth_failures,
+            ),
+        ];
+        for (name, help, value) in counters {
+            metric(
+                &mut out,
+                name,
+                "counter",
+                help,
+                value.load(Ordering::Relaxed),
+            );
+        }
+        if let Some(monitor) = self.task_monitor.get() {
+            let tasks = monitor.cumulative();
+            metric(
+                &mut out,
+                "tokio_task_instrumented_total",
+                "counter",
+                "Tasks instrumented by the task monitor.",
+                tasks.instrumented_count,
+            );
+            metric(
+                &mut out,
+                "tokio_task_dropped_total",
+                "counter",
+                "Instrumented tasks that completed.",
+                tasks.dropped_count,
+            );
+            metric(
+                &mut out,
+                "tokio_task_polls_total",
+                "counter",
+                "Polls of instrumented tasks.",
+                tasks.total_poll_count,
+            );
+            metric(
+                &mut out,
+                "tokio_task_poll_duration_microseconds_total",
+                "counter",
+                "Time spent polling instrumented tasks.",
+                tasks.total_poll_duration.as_micros() as u64,
+            );
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let runtime = handle.metrics();
+            metric(
+                &mut out,
+                "tokio_runtime_workers",
+                "gauge",
+                "Worker threads of the runtime serving HTTP.",
+                runtime.num_workers() as u64,
+            );
+            metric(
+                &mut out,
+                "tokio_runtime_alive_tasks",
+                "gauge",
+                "Tasks alive on the runtime serving HTTP.",
+                runtime.num_alive_tasks() as u64,
+            );
+            metric(
+                &mut out,
+                "tokio_runtime_global_queue_depth",
+                "gauge",
+                "Tasks waiting in the runtime's global queue.",
+                runtime.global_queue_depth() as u64,
+            );
+        }
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
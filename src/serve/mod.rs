@@ -1,6 +1,13 @@
 use crate::auth::Auth;
+use crate::config::AppConfig;
 use crate::error::GitInnerError;
+use crate::hooks::ReceiveHook;
 use crate::repository::Repository;
+use crate::serve::health::DependencyCheck;
+use crate::serve::limit::{RateLimiter, TransactionLimiter};
+use crate::serve::maintenance::MaintenanceMode;
+use crate::serve::metrics::Metrics;
+use crate::transaction::upload::resume::FetchResumeStore;
 use async_trait::async_trait;
 use std::sync::Arc;
 use tokio::sync::OnceCell;
@@ -11,11 +18,54 @@ pub static APP: OnceCell<AppCore> = OnceCell::const_new();
 pub struct AppCore {
     pub repo_store: Arc<Box<dyn RepoStore>>,
     pub auth: Option<Arc<Box<dyn Auth>>>,
+    pub transactions: Arc<TransactionLimiter>,
+    /// Per-IP cap on new transactions, shared by every transport.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// When enabled, pushes are refused while fetches keep working.
+    pub maintenance: MaintenanceMode,
+    /// Git counters exported by `/metrics`.
+    pub metrics: Metrics,
+    /// Negotiated fetches kept for clients resuming an interrupted fetch.
+    pub fetch_resume: FetchResumeStore,
+    /// Hooks run around every push, if any.
+    pub hooks: Option<Arc<Box<dyn ReceiveHook>>>,
 }
 
 #[async_trait]
 pub trait RepoStore: Send + Sync + 'static {
     async fn repo(&self, namespace: String, name: String) -> Result<Repository, GitInnerError>;
+    /// Remove a repository together with its objects and refs, returning how many objects were
+    /// deleted. Deleting a repository that does not exist succeeds and removes nothing.
+    async fn delete_repo(&self, _namespace: String, _name: String) -> Result<usize, GitInnerError> {
+        Err(GitInnerError::NotSupportCommand)
+    }
+    /// Move a repository to `new_namespace/new_name`. Its id, and so its stored objects, stay
+    /// the same. Fails with `RepoAlreadyExists` when the destination is taken.
+    async fn rename_repo(
+        &self,
+        _old_namespace: String,
+        _old_name: String,
+        _new_namespace: String,
+        _new_name: String,
+    ) -> Result<(), GitInnerError> {
+        Err(GitInnerError::NotSupportCommand)
+    }
+    /// Create `target_namespace/target_name` owned by `owner` as a fork of the source
+    /// repository, with its own copy of the source's objects and refs.
+    async fn fork_repo(
+        &self,
+        _source_namespace: String,
+        _source_name: String,
+        _target_namespace: String,
+        _target_name: String,
+        _owner: uuid::Uuid,
+    ) -> Result<Repository, GitInnerError> {
+        Err(GitInnerError::NotSupportCommand)
+    }
+    /// Probe the services this store depends on; an empty list means there is nothing to check.
+    async fn readiness(&self) -> Vec<DependencyCheck> {
+        vec![]
+    }
 }
 
 impl AppCore {
@@ -44,7 +94,21 @@ impl AppCore {
     /// let app = crate::AppCore::new(store, None);
     /// ```
     pub fn new(repo_store: Arc<Box<dyn RepoStore>>, auth: Option<Arc<Box<dyn Auth>>>) -> Self {
-        Self { repo_store, auth }
+        let transactions = TransactionLimiter::new(AppConfig::transaction().max_concurrent_pushes);
+        Self {
+            repo_store,
+            auth,
+            transactions: Arc::new(transactions),
+            rate_limiter: Arc::new(AppConfig::transaction().into()),
+            maintenance: MaintenanceMode::default(),
+            metrics: Metrics::default(),
+            fetch_resume: FetchResumeStore::default(),
+            hooks: None,
+        }
+    }
+    /// Switch read-only maintenance mode on or off for every transport.
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.maintenance.set(enabled);
     }
     /// Initialize the global application singleton with this `AppCore`.
     ///
@@ -80,4 +144,11 @@ impl AppCore {
         APP.get().cloned().ok_or(GitInnerError::AppNotInit)
     }
 }
+pub mod health;
+pub mod limit;
+pub mod maintenance;
+pub mod metrics;
 pub mod mongo;
+pub mod storage;
+#[cfg(test)]
+pub mod testing;
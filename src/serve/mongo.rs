@@ -1,22 +1,56 @@
+use crate::config::AppConfig;
 use crate::error::GitInnerError;
 use crate::model::repository::MongoRepository;
+use crate::odb::Odb;
+use crate::odb::cache::CachedOdb;
 use crate::odb::mongo::odb::OdbMongoObject;
+use crate::odb::mongo::{delete_repo_blobs, spawn_quarantine_sweeper};
 use crate::refs::mongo::MongoRefsManager;
+use crate::refs::{RefsManager, is_valid_ref_name};
 use crate::repository::Repository;
+use crate::repository::walk::MAX_WALK_NODES;
+use crate::serve::health::DependencyCheck;
+use crate::serve::storage::build_object_store;
 use crate::serve::{AppCore, RepoStore};
 use crate::sha::HashVersion;
 use async_trait::async_trait;
-use mongodb::bson::doc;
+use lru::LruCache;
+use mongodb::bson::{Uuid, doc};
 use mongodb::{Client, Collection};
-use object_store::local::LocalFileSystem;
 use object_store::ObjectStore;
-use std::sync::Arc;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-#[derive(Debug, Clone)]
+/// Repositories whose handles [`MongoRepoManager`] keeps unless configured otherwise.
+const DEFAULT_REPO_CAPACITY: NonZeroUsize = NonZeroUsize::new(1000).unwrap();
+
+#[derive(Clone)]
 pub struct MongoRepoManager {
     pub db_client: Client,
     pub repo: Collection<MongoRepository>,
     pub store: Arc<Box<dyn ObjectStore>>,
+    /// Odb and refs handles already built for the most recently used repositories, keyed by
+    /// their uid. Evicting a repository also drops its commit cache.
+    handles: Arc<Mutex<LruCache<Uuid, RepoHandles>>>,
+    /// Commits cached in memory per repository; 0 disables the cache.
+    commit_cache_capacity: usize,
+    /// Store blob contents once in the shared pool instead of per repository.
+    shared_blobs: bool,
+    /// Most nodes a history or tree walk over one repository may visit.
+    max_walk_nodes: usize,
+}
+
+/// Per-repository handles cached by [`MongoRepoManager`], together with the repository
+/// settings they were built from.
+#[derive(Clone)]
+struct RepoHandles {
+    default_branch: String,
+    is_public: bool,
+    symbolic_head: bool,
+    hash_version: HashVersion,
+    odb: Arc<Box<dyn Odb>>,
+    refs: Arc<Box<dyn RefsManager>>,
 }
 
 impl MongoRepoManager {
@@ -41,13 +75,192 @@ impl MongoRepoManager {
             db_client,
             repo,
             store,
+            handles: Arc::new(Mutex::new(LruCache::new(DEFAULT_REPO_CAPACITY))),
+            commit_cache_capacity: 0,
+            shared_blobs: false,
+            max_walk_nodes: MAX_WALK_NODES,
+        }
+    }
+
+    /// Keep the handles of up to `capacity` recently used repositories.
+    pub fn with_repo_capacity(self, capacity: NonZeroUsize) -> Self {
+        self.handles.lock().unwrap().resize(capacity);
+        self
+    }
+
+    /// Keep up to `capacity` recently read commits of each repository in memory.
+    pub fn with_commit_cache(mut self, capacity: usize) -> Self {
+        self.commit_cache_capacity = capacity;
+        self
+    }
+
+    /// Write new blobs to the object store pool shared by all repositories, so forks and
+    /// mirrors do not store the same content again. Blobs already stored per repository
+    /// stay where they are.
+    pub fn with_shared_blobs(mut self, shared: bool) -> Self {
+        self.shared_blobs = shared;
+        self
+    }
+
+    /// Fail history and tree walks that would visit more than `limit` nodes.
+    pub fn with_max_walk_nodes(mut self, limit: usize) -> Self {
+        self.max_walk_nodes = limit;
+        self
+    }
+
+    /// Drop the cached handles of repository `uid`, e.g. after its settings changed.
+    pub fn invalidate(&self, uid: &Uuid) {
+        self.handles.lock().unwrap().pop(uid);
+    }
+
+    /// Create a repository and return it. The default branch must be a valid branch name and
+    /// `namespace/name` must not be taken yet.
+    pub async fn create_repo(&self, options: CreateRepo) -> Result<Repository, GitInnerError> {
+        options.validate()?;
+        let query = doc! {
+            "namespace": &options.namespace,
+            "name": &options.name
+        };
+        if self.repo.find_one(query).await?.is_some() {
+            return Err(GitInnerError::RepoAlreadyExists(format!(
+                "{}/{}",
+                options.namespace, options.name
+            )));
         }
+        let last = self.repo.find_one(doc! {}).sort(doc! { "id": -1 }).await?;
+        let mongo_repo = MongoRepository {
+            id: last.map_or(1, |x| x.id + 1),
+            name: options.name.clone(),
+            namespace: options.namespace.clone(),
+            uid: Uuid::from_bytes(*uuid::Uuid::new_v4().as_bytes()),
+            owner: Uuid::from_bytes(*options.owner.as_bytes()),
+            hash_version: match options.hash_version {
+                HashVersion::Sha1 => 1,
+                HashVersion::Sha256 => 256,
+            },
+            default_branch: options.default_branch,
+            is_public: options.is_public,
+            symbolic_head: options.symbolic_head,
+        };
+        self.repo.insert_one(&mongo_repo).await?;
+        self.repo(options.namespace, options.name).await
     }
+
+    /// Repository backed by the cached handles of `mongo_repo`.
+    fn build_repo(&self, mongo_repo: MongoRepository) -> Result<Repository, GitInnerError> {
+        let hash_version = match mongo_repo.hash_version {
+            1 => HashVersion::Sha1,
+            256 => HashVersion::Sha256,
+            _ => return Err(GitInnerError::HashVersionError),
+        };
+        let handles = self.handles(&mongo_repo, hash_version);
+        Ok(Repository {
+            id: uuid::Uuid::from_slice(mongo_repo.uid.bytes().as_slice())
+                .map_err(|_| GitInnerError::UuidError)?,
+            default_branch: mongo_repo.default_branch,
+            owner: Default::default(),
+            odb: handles.odb,
+            refs: handles.refs,
+            hash_version,
+            is_public: mongo_repo.is_public,
+            max_walk_nodes: self.max_walk_nodes,
+        })
+    }
+
+    /// Handles for `mongo_repo`, reused across requests. They are rebuilt when the stored
+    /// default branch, visibility or hash version no longer match the cached ones.
+    fn handles(&self, mongo_repo: &MongoRepository, hash_version: HashVersion) -> RepoHandles {
+        if let Some(cached) = self.handles.lock().unwrap().get(&mongo_repo.uid)
+            && cached.default_branch == mongo_repo.default_branch
+            && cached.is_public == mongo_repo.is_public
+            && cached.symbolic_head == mongo_repo.symbolic_head
+            && cached.hash_version == hash_version
+        {
+            return cached.clone();
+        }
+        let db = self.db_client.database("git_inner");
+        let odb = OdbMongoObject {
+            repo_uid: mongo_repo.uid,
+            store: self.store.clone(),
+            db_client: self.db_client.clone(),
+            commit: db.collection("commits"),
+            tag: db.collection("tags"),
+            tree: db.collection("trees"),
+            shared_blobs: self.shared_blobs,
+        };
+        let odb: Arc<Box<dyn Odb>> = Arc::new(Box::new(odb));
+        let odb: Arc<Box<dyn Odb>> = match NonZeroUsize::new(self.commit_cache_capacity) {
+            Some(capacity) => Arc::new(Box::new(CachedOdb::new(odb, capacity))),
+            None => odb,
+        };
+        let refs = MongoRefsManager {
+            repo_uid: mongo_repo.uid,
+            default_branch: mongo_repo.default_branch.clone(),
+            db_client: self.db_client.clone(),
+            refs: db.collection("refs"),
+            reflog: db.collection("reflog"),
+            hash_version,
+            symbolic_head: mongo_repo.symbolic_head,
+        };
+        let handles = RepoHandles {
+            default_branch: mongo_repo.default_branch.clone(),
+            is_public: mongo_repo.is_public,
+            symbolic_head: mongo_repo.symbolic_head,
+            hash_version,
+            odb,
+            refs: Arc::new(Box::new(refs)),
+        };
+        self.handles
+            .lock()
+            .unwrap()
+            .put(mongo_repo.uid, handles.clone());
+        handles
+    }
+}
+
+/// Settings of a repository created by [`MongoRepoManager::create_repo`].
+#[derive(Clone, Debug)]
+pub struct CreateRepo {
+    pub namespace: String,
+    pub name: String,
+    pub owner: uuid::Uuid,
+    pub hash_version: HashVersion,
+    /// Short branch name, e.g. `main`.
+    pub default_branch: String,
+    pub is_public: bool,
+    /// Advertise HEAD as a symref to the default branch while the repository is still empty,
+    /// so clients know which branch to check out after the first push.
+    pub symbolic_head: bool,
+}
+
+impl CreateRepo {
+    fn validate(&self) -> Result<(), GitInnerError> {
+        check_repo_path(&self.namespace, &self.name)?;
+        if !is_valid_ref_name(&self.default_branch) {
+            return Err(GitInnerError::InvalidArgument(format!(
+                "invalid default branch: {}",
+                self.default_branch
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reject an empty namespace or a name that is empty or contains a slash.
+fn check_repo_path(namespace: &str, name: &str) -> Result<(), GitInnerError> {
+    if namespace.is_empty() || name.is_empty() || name.contains('/') {
+        return Err(GitInnerError::InvalidArgument(format!(
+            "invalid repository path: {}/{}",
+            namespace, name
+        )));
+    }
+    Ok(())
 }
 
-/// Initializes application components using MongoDB for metadata and a local filesystem for object storage.
+/// Initializes application components using MongoDB for metadata and the configured object storage.
 ///
-/// This sets up environment loading, constructs a local file-backed object store at "./data",
+/// This sets up environment loading, constructs the object store selected by the `storage` config
+/// (a local directory at "./data" by default),
 /// parses `MONGODB_URL` for a MongoDB client, creates a `MongoRepoManager` backed by that client
 /// and the object store, builds an `AppCore` with the manager, and runs its initialization routine.
 ///
@@ -64,14 +277,27 @@ impl MongoRepoManager {
 pub async fn init_app_by_mongodb() {
     dotenv::dotenv().ok();
     let mongodb_url = dotenv::var("MONGODB_URL").expect("MONGODB_URL must be set");
-    let store = LocalFileSystem::new_with_prefix("./data")
-        .expect("Failed to initialize local storage")
-        .with_automatic_cleanup(true);
+    let store =
+        build_object_store(AppConfig::storage()).expect("Failed to initialize object storage");
     let optional = mongodb::options::ClientOptions::parse(mongodb_url)
         .await
         .expect("Failed to parse MongoDB client options");
     let mongodb = mongodb::Client::with_options(optional).expect("Failed to create MongoDB client");
-    let manager = MongoRepoManager::new(mongodb, Arc::new(Box::new(store)));
+    let transaction = AppConfig::transaction();
+    if transaction.quarantine_sweep_secs > 0 {
+        spawn_quarantine_sweeper(
+            store.clone(),
+            Duration::from_secs(transaction.quarantine_sweep_secs),
+            Duration::from_secs(transaction.quarantine_max_age_secs),
+        );
+    }
+    let manager = MongoRepoManager::new(mongodb, store)
+        .with_repo_capacity(
+            NonZeroUsize::new(AppConfig::cache().repo_capacity).unwrap_or(NonZeroUsize::MIN),
+        )
+        .with_commit_cache(AppConfig::cache().commit_capacity)
+        .with_shared_blobs(AppConfig::shared_blobs())
+        .with_max_walk_nodes(transaction.max_walk_nodes);
     let core = AppCore::new(Arc::new(Box::new(manager)), None);
     let _ = core.init();
 }
@@ -105,40 +331,253 @@ impl RepoStore for MongoRepoManager {
                 "namespace": &namespace,
                 "name": &name
             })
-            .await
-            .map_err(|e| GitInnerError::MongodbError(e.to_string()))?
+            .await?
             .ok_or_else(|| GitInnerError::ObjectNotFound(HashVersion::Sha1.default()))?;
-        let hash_version = match mongo_repo.hash_version {
-            1 => HashVersion::Sha1,
-            256 => HashVersion::Sha256,
-            _ => return Err(GitInnerError::HashVersionError),
+        self.build_repo(mongo_repo)
+    }
+
+    /// Ping MongoDB and look up a key in the object store; a missing key still proves the
+    /// store is reachable. Each probe gives up after five seconds.
+    async fn readiness(&self) -> Vec<DependencyCheck> {
+        let timeout = Duration::from_secs(5);
+        let db = self.db_client.database("git_inner");
+        let ping = db.run_command(doc! { "ping": 1 });
+        let mongodb = match tokio::time::timeout(timeout, ping).await {
+            Ok(Ok(_)) => DependencyCheck::ok("mongodb"),
+            Ok(Err(err)) => DependencyCheck::failed("mongodb", err),
+            Err(_) => DependencyCheck::failed("mongodb", "ping timed out"),
         };
-        let db_name = "git_inner";
-        let db = self.db_client.database(db_name);
-        let odb = OdbMongoObject {
-            repo_uid: mongo_repo.uid.clone(),
-            store: self.store.clone(),
-            db_client: self.db_client.clone(),
-            commit: db.collection("commits"),
-            tag: db.collection("tags"),
-            tree: db.collection("trees"),
+        let probe = object_store::path::Path::from("healthz");
+        let head = self.store.head(&probe);
+        let store = match tokio::time::timeout(timeout, head).await {
+            Ok(Ok(_)) | Ok(Err(object_store::Error::NotFound { .. })) => {
+                DependencyCheck::ok("object_store")
+            }
+            Ok(Err(err)) => DependencyCheck::failed("object_store", err),
+            Err(_) => DependencyCheck::failed("object_store", "request timed out"),
         };
-        let refs = MongoRefsManager {
-            repo_uid: mongo_repo.uid.clone(),
-            default_branch: mongo_repo.default_branch.clone(),
-            db_client: self.db_client.clone(),
-            refs: db.collection("refs"),
-            hash_version: hash_version.clone(),
+        vec![mongodb, store]
+    }
+
+    /// Objects and refs go before the repository document, so a deletion that fails halfway
+    /// can simply be retried.
+    async fn delete_repo(&self, namespace: String, name: String) -> Result<usize, GitInnerError> {
+        let query = doc! {
+            "namespace": &namespace,
+            "name": &name
         };
-        Ok(Repository {
-            id: uuid::Uuid::from_slice(mongo_repo.uid.bytes().as_slice())
-                .map_err(|_| GitInnerError::UuidError)?,
-            default_branch: mongo_repo.default_branch,
-            owner: Default::default(),
-            odb: Arc::new(Box::new(odb)),
-            refs: Arc::new(Box::new(refs)),
-            hash_version,
-            is_public: mongo_repo.is_public,
-        })
+        let Some(mongo_repo) = self.repo.find_one(query.clone()).await? else {
+            return Ok(0);
+        };
+        let db = self.db_client.database("git_inner");
+        let scoped = doc! { "repo_uid": mongo_repo.uid };
+        let mut removed = 0;
+        for collection in ["commits", "trees", "tags"] {
+            let result = db
+                .collection::<mongodb::bson::Document>(collection)
+                .delete_many(scoped.clone())
+                .await?;
+            removed += result.deleted_count as usize;
+        }
+        removed += delete_repo_blobs(self.store.as_ref().as_ref(), &mongo_repo.uid).await?;
+        for collection in ["refs", "reflog"] {
+            db.collection::<mongodb::bson::Document>(collection)
+                .delete_many(scoped.clone())
+                .await?;
+        }
+        self.repo.delete_one(query).await?;
+        self.invalidate(&mongo_repo.uid);
+        Ok(removed)
+    }
+
+    /// Only the repository document changes: objects and refs are keyed by its uid.
+    async fn rename_repo(
+        &self,
+        old_namespace: String,
+        old_name: String,
+        new_namespace: String,
+        new_name: String,
+    ) -> Result<(), GitInnerError> {
+        check_repo_path(&new_namespace, &new_name)?;
+        let destination = doc! {
+            "namespace": &new_namespace,
+            "name": &new_name
+        };
+        if (&old_namespace, &old_name) != (&new_namespace, &new_name)
+            && self.repo.find_one(destination.clone()).await?.is_some()
+        {
+            return Err(GitInnerError::RepoAlreadyExists(format!(
+                "{}/{}",
+                new_namespace, new_name
+            )));
+        }
+        let result = self
+            .repo
+            .update_one(
+                doc! {
+                    "namespace": &old_namespace,
+                    "name": &old_name
+                },
+                doc! { "$set": destination },
+            )
+            .await?;
+        if result.matched_count == 0 {
+            return Err(GitInnerError::ObjectNotFound(HashVersion::Sha1.default()));
+        }
+        Ok(())
+    }
+
+    /// Fork `source_namespace/source_name` into `target_namespace/target_name` owned by
+    /// `owner`. The fork gets its own uid and a full copy of the source's objects and refs
+    /// (see [`Repository::copy_into`] for the isolation this gives); it only becomes visible
+    /// once the copy is complete.
+    async fn fork_repo(
+        &self,
+        source_namespace: String,
+        source_name: String,
+        target_namespace: String,
+        target_name: String,
+        owner: uuid::Uuid,
+    ) -> Result<Repository, GitInnerError> {
+        check_repo_path(&target_namespace, &target_name)?;
+        let source = self
+            .repo
+            .find_one(doc! {
+                "namespace": &source_namespace,
+                "name": &source_name
+            })
+            .await?
+            .ok_or_else(|| GitInnerError::ObjectNotFound(HashVersion::Sha1.default()))?;
+        let target_query = doc! {
+            "namespace": &target_namespace,
+            "name": &target_name
+        };
+        if self.repo.find_one(target_query).await?.is_some() {
+            return Err(GitInnerError::RepoAlreadyExists(format!(
+                "{}/{}",
+                target_namespace, target_name
+            )));
+        }
+        let last = self.repo.find_one(doc! {}).sort(doc! { "id": -1 }).await?;
+        let fork = MongoRepository {
+            id: last.map_or(1, |x| x.id + 1),
+            name: target_name,
+            namespace: target_namespace,
+            uid: Uuid::from_bytes(*uuid::Uuid::new_v4().as_bytes()),
+            owner: Uuid::from_bytes(*owner.as_bytes()),
+            ..source.clone()
+        };
+        let target = self.build_repo(fork.clone())?;
+        self.build_repo(source)?.copy_into(&target).await?;
+        self.repo.insert_one(&fork).await?;
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    fn mongo_repo() -> MongoRepository {
+        MongoRepository {
+            id: 1,
+            name: "repo".to_string(),
+            namespace: "ns".to_string(),
+            uid: Uuid::new(),
+            owner: Uuid::new(),
+            hash_version: 1,
+            default_branch: "main".to_string(),
+            is_public: true,
+            symbolic_head: false,
+        }
+    }
+
+    #[test]
+    fn test_create_repo_validates_default_branch() {
+        let options = CreateRepo {
+            namespace: "ns".to_string(),
+            name: "repo".to_string(),
+            owner: uuid::Uuid::new_v4(),
+            hash_version: HashVersion::Sha1,
+            default_branch: "main".to_string(),
+            is_public: true,
+            symbolic_head: true,
+        };
+        assert!(options.validate().is_ok());
+        for branch in ["", "has space", "a..b", "refs/heads/", "-x"] {
+            let options = CreateRepo {
+                default_branch: branch.to_string(),
+                ..options.clone()
+            };
+            assert!(matches!(
+                options.validate(),
+                Err(GitInnerError::InvalidArgument(_))
+            ));
+        }
+        let options = CreateRepo {
+            name: String::new(),
+            ..options
+        };
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_repo_path() {
+        assert!(check_repo_path("ns", "repo").is_ok());
+        for (namespace, name) in [("", "repo"), ("ns", ""), ("ns", "a/b")] {
+            assert!(matches!(
+                check_repo_path(namespace, name),
+                Err(GitInnerError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handles_are_cached_per_repo() {
+        // 客户端是惰性连接的，这里不会访问数据库
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .unwrap();
+        let manager = MongoRepoManager::new(client, Arc::new(Box::new(InMemory::new())));
+        let mut repo = mongo_repo();
+
+        let first = manager.handles(&repo, HashVersion::Sha1);
+        let second = manager.handles(&repo, HashVersion::Sha1);
+        assert!(Arc::ptr_eq(&first.odb, &second.odb));
+        assert!(Arc::ptr_eq(&first.refs, &second.refs));
+
+        // 可见性变化后重建
+        repo.is_public = false;
+        let third = manager.handles(&repo, HashVersion::Sha1);
+        assert!(!Arc::ptr_eq(&first.odb, &third.odb));
+        assert!(Arc::ptr_eq(
+            &third.odb,
+            &manager.handles(&repo, HashVersion::Sha1).odb
+        ));
+
+        manager.invalidate(&repo.uid);
+        let fourth = manager.handles(&repo, HashVersion::Sha1);
+        assert!(!Arc::ptr_eq(&third.refs, &fourth.refs));
+    }
+
+    #[tokio::test]
+    async fn test_handles_evict_least_recently_used() {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .unwrap();
+        let manager = MongoRepoManager::new(client, Arc::new(Box::new(InMemory::new())))
+            .with_repo_capacity(NonZeroUsize::new(2).unwrap());
+        let repos = (0..3).map(|_| mongo_repo()).collect::<Vec<_>>();
+
+        let first = manager.handles(&repos[0], HashVersion::Sha1);
+        manager.handles(&repos[1], HashVersion::Sha1);
+        // 访问第一个仓库后，第二个成为最久未用
+        manager.handles(&repos[0], HashVersion::Sha1);
+        manager.handles(&repos[2], HashVersion::Sha1);
+        assert_eq!(manager.handles.lock().unwrap().len(), 2);
+        assert!(!manager.handles.lock().unwrap().contains(&repos[1].uid));
+        let again = manager.handles(&repos[0], HashVersion::Sha1);
+        assert!(Arc::ptr_eq(&first.odb, &again.odb));
     }
 }
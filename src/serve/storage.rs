@@ -0,0 +1,167 @@
+use crate::config::storage::StorageBackend;
+use crate::error::GitInnerError;
+use object_store::ObjectStore;
+use object_store::local::LocalFileSystem;
+use std::sync::Arc;
+
+/// Build the blob store described by `cfg`.
+///
+/// Cloud backends go through [`object_store::parse_url_opts`] with the `aws` / `gcp`
+/// features. Settings not given in the config are taken from `AWS_*` / `GOOGLE_*`
+/// environment variables, the same way the provider builders' `from_env` does.
+///
+/// Promoting pushed blobs relies on `copy_if_not_exists`, which S3 only provides when told
+/// how; unless configured otherwise it uses conditional multipart uploads (`multipart`).
+pub fn build_object_store(
+    cfg: &StorageBackend,
+) -> Result<Arc<Box<dyn ObjectStore>>, GitInnerError> {
+    let store: Box<dyn ObjectStore> = match cfg {
+        StorageBackend::Local { path } => {
+            std::fs::create_dir_all(path)
+                .map_err(|e| GitInnerError::ObjectStoreError(e.to_string()))?;
+            Box::new(
+                LocalFileSystem::new_with_prefix(path)
+                    .map_err(|e| GitInnerError::ObjectStoreError(e.to_string()))?
+                    .with_automatic_cleanup(true),
+            )
+        }
+        StorageBackend::S3 {
+            bucket,
+            region,
+            endpoint,
+            access_key_id,
+            secret_access_key,
+            copy_if_not_exists,
+        } => {
+            let mut options = env_options("AWS_");
+            options.extend(
+                [
+                    ("aws_region", region),
+                    ("aws_endpoint", endpoint),
+                    ("aws_access_key_id", access_key_id),
+                    ("aws_secret_access_key", secret_access_key),
+                    ("aws_copy_if_not_exists", copy_if_not_exists),
+                ]
+                .into_iter()
+                .filter_map(|(key, value)| Some((key.to_string(), value.clone()?))),
+            );
+            if !options
+                .iter()
+                .any(|(key, _)| key == "aws_copy_if_not_exists")
+            {
+                options.push((
+                    "aws_copy_if_not_exists".to_string(),
+                    "multipart".to_string(),
+                ));
+            }
+            if let Some(endpoint) = endpoint
+                && endpoint.starts_with("http://")
+            {
+                options.push(("aws_allow_http".to_string(), "true".to_string()));
+            }
+            parse_store(&format!("s3://{}", bucket), options)?
+        }
+        StorageBackend::Gcs {
+            bucket,
+            service_account_path,
+        } => {
+            let mut options = env_options("GOOGLE_");
+            if let Some(path) = service_account_path {
+                options.push(("google_service_account".to_string(), path.clone()));
+            }
+            parse_store(&format!("gs://{}", bucket), options)?
+        }
+    };
+    Ok(Arc::new(store))
+}
+
+/// Environment variables starting with `prefix`, keyed the way `object_store` config keys are.
+fn env_options(prefix: &str) -> Vec<(String, String)> {
+    std::env::vars()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .map(|(key, value)| (key.to_ascii_lowercase(), value))
+        .collect()
+}
+
+fn parse_store(
+    url: &str,
+    options: Vec<(String, String)>,
+) -> Result<Box<dyn ObjectStore>, GitInnerError> {
+    let url = url::Url::parse(url).map_err(|e| GitInnerError::ObjectStoreError(e.to_string()))?;
+    let (store, _) = object_store::parse_url_opts(&url, options)
+        .map_err(|e| GitInnerError::ObjectStoreError(e.to_string()))?;
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use object_store::path::Path;
+
+    #[tokio::test]
+    async fn test_build_local_store() {
+        let dir = std::env::temp_dir().join(format!("git-inner-{}", uuid::Uuid::new_v4()));
+        let cfg = StorageBackend::Local {
+            path: dir.to_string_lossy().to_string(),
+        };
+        let store = build_object_store(&cfg).unwrap();
+        store
+            .put(&Path::from("a/b"), Bytes::from("hello").into())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dir.join("a/b")).unwrap(), b"hello");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_s3_store() {
+        let cfg = StorageBackend::S3 {
+            bucket: "git-objects".to_string(),
+            region: Some("us-east-1".to_string()),
+            endpoint: Some("http://localhost:9000".to_string()),
+            access_key_id: Some("key".to_string()),
+            secret_access_key: Some("secret".to_string()),
+            copy_if_not_exists: None,
+        };
+        let store = build_object_store(&cfg).unwrap();
+        assert!(store.to_string().contains("git-objects"));
+
+        let cfg = StorageBackend::S3 {
+            bucket: "git-objects".to_string(),
+            region: Some("us-east-1".to_string()),
+            endpoint: None,
+            access_key_id: None,
+            secret_access_key: None,
+            copy_if_not_exists: Some("header:x-copy-if-absent:true".to_string()),
+        };
+        assert!(build_object_store(&cfg).is_ok());
+
+        let cfg = StorageBackend::S3 {
+            bucket: "git-objects".to_string(),
+            region: Some("us-east-1".to_string()),
+            endpoint: None,
+            access_key_id: None,
+            secret_access_key: None,
+            copy_if_not_exists: Some("sometimes".to_string()),
+        };
+        assert!(build_object_store(&cfg).is_err());
+    }
+
+    #[test]
+    fn test_build_gcs_store() {
+        // 没有服务账号时凭据在第一次请求时才获取，构建本身不访问网络
+        let cfg = StorageBackend::Gcs {
+            bucket: "git-objects".to_string(),
+            service_account_path: None,
+        };
+        let store = build_object_store(&cfg).unwrap();
+        assert!(store.to_string().contains("git-objects"));
+
+        let cfg = StorageBackend::Gcs {
+            bucket: "git-objects".to_string(),
+            service_account_path: Some("/nonexistent/key.json".to_string()),
+        };
+        assert!(build_object_store(&cfg).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use crate::error::GitInnerError;
+use crate::repository::Repository;
+use crate::serve::limit::{RateLimiter, TransactionLimiter};
+use crate::serve::{AppCore, RepoStore};
+use crate::sha::HashVersion;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A store without any repository.
+pub struct EmptyStore;
+
+#[async_trait]
+impl RepoStore for EmptyStore {
+    async fn repo(&self, _: String, _: String) -> Result<Repository, GitInnerError> {
+        Err(GitInnerError::ObjectNotFound(HashVersion::Sha1.default()))
+    }
+}
+
+/// A store serving one repository under every name.
+pub struct SingleStore(pub Repository);
+
+#[async_trait]
+impl RepoStore for SingleStore {
+    async fn repo(&self, _: String, _: String) -> Result<Repository, GitInnerError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// An `AppCore` over `store` without auth, built without reading the global configuration.
+pub fn app_core(store: impl RepoStore) -> AppCore {
+    AppCore {
+        repo_store: Arc::new(Box::new(store)),
+        auth: None,
+        transactions: Arc::new(TransactionLimiter::new(1)),
+        rate_limiter: Arc::new(RateLimiter::new(0, Duration::from_secs(60))),
+        maintenance: Default::default(),
+        metrics: Default::default(),
+        fetch_resume: Default::default(),
+        hooks: None,
+    }
+}
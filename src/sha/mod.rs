@@ -1,3 +1,4 @@
+use crate::objects::types::ObjectType;
 use bytes::{Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display};
@@ -12,7 +13,7 @@ pub trait Sha {
     fn reset(&mut self);
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq, Copy)]
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq, Copy, Hash)]
 pub enum HashVersion {
     Sha1,
     Sha256,
@@ -28,6 +29,20 @@ impl HashVersion {
 }
 
 impl HashVersion {
+    /// Name used by the `object-format` capability.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashVersion::Sha1 => "sha1",
+            HashVersion::Sha256 => "sha256",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<HashVersion> {
+        match name {
+            "sha1" => Some(HashVersion::Sha1),
+            "sha256" => Some(HashVersion::Sha256),
+            _ => None,
+        }
+    }
     pub fn default(&self) -> HashValue {
         match self {
             HashVersion::Sha1 => HashValue::Sha1(sha1::Sha1::new()),
@@ -42,6 +57,16 @@ impl HashVersion {
     }
 }
 
+/// Hash `body` as a git object, i.e. `"<type> <len>\0"` followed by the body, without
+/// copying the body into a separate buffer.
+pub fn hash_object(object_type: ObjectType, body: &[u8], version: HashVersion) -> HashValue {
+    let mut hash = version.default();
+    hash.update(format!("{} {}\0", object_type, body.len()).as_bytes());
+    hash.update(body);
+    hash.finalize();
+    hash
+}
+
 #[derive(Clone)]
 pub enum HashValue {
     Sha1(sha1::Sha1),
@@ -66,12 +91,17 @@ impl HashValue {
             HashValue::Sha256(sha256) => sha256.is_zero(),
         }
     }
-    pub fn raw(&self) -> Vec<u8> {
+    /// The binary object id, i.e. the bytes whose hex encoding is the `Display` form.
+    pub fn as_bytes(&self) -> &[u8] {
         match self {
-            HashValue::Sha1(sha1) => sha1.clone().state.to_vec(),
-            HashValue::Sha256(sha256) => sha256.clone().state.to_vec(),
+            HashValue::Sha1(sha1) => &sha1.state,
+            HashValue::Sha256(sha256) => &sha256.state,
         }
     }
+    /// Owned copy of [`Self::as_bytes`], as written into tree entries and pack headers.
+    pub fn raw(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
     pub fn new(version: HashVersion) -> HashValue {
         match version {
             HashVersion::Sha1 => HashValue::Sha1(sha1::Sha1::new()),
@@ -298,4 +328,50 @@ mod tests {
         assert!(sha1.is_zero());
         assert!(sha256.is_zero());
     }
+
+    #[test]
+    fn test_hash_object_blob() {
+        // `echo 'hello world' | git hash-object --stdin`
+        let hash = hash_object(ObjectType::Blob, b"hello world\n", HashVersion::Sha1);
+        assert_eq!(hash.to_string(), "3b18e512dba79e4c8300dd08aeb37f8e728b8dad");
+        // `git hash-object -t tree /dev/null`
+        let empty_tree = hash_object(ObjectType::Tree, b"", HashVersion::Sha1);
+        assert_eq!(
+            empty_tree.to_string(),
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+        );
+        let sha256 = hash_object(ObjectType::Blob, b"", HashVersion::Sha256);
+        assert_eq!(
+            sha256.to_string(),
+            "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813"
+        );
+    }
+
+    #[test]
+    fn test_hash_object_matches_parsers() {
+        let data = Bytes::from("hello world");
+        let blob = crate::objects::blob::Blob::parse(data.clone(), HashVersion::Sha1);
+        assert_eq!(
+            blob.id,
+            hash_object(ObjectType::Blob, &data, HashVersion::Sha1)
+        );
+        assert_eq!(
+            blob.id.to_string(),
+            "95d09f2b10159347eece71399a7e2e907ea3df4f"
+        );
+    }
+
+    #[test]
+    fn test_raw_is_object_id() {
+        let sha1 = hash_object(ObjectType::Blob, b"hello world\n", HashVersion::Sha1);
+        assert_eq!(sha1.raw(), hex::decode(sha1.to_string()).unwrap());
+        let sha256 = hash_object(ObjectType::Blob, b"hello world\n", HashVersion::Sha256);
+        assert_eq!(sha256.raw(), hex::decode(sha256.to_string()).unwrap());
+        let parsed = HashValue::from_str("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").unwrap();
+        assert_eq!(parsed.raw(), sha1.raw());
+        assert_eq!(
+            HashValue::from_bytes(&BytesMut::from(&sha1.raw()[..])),
+            Some(sha1)
+        );
+    }
 }
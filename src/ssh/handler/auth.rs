@@ -0,0 +1,189 @@
+use crate::auth::AccessLevel;
+use crate::error::GitInnerError;
+use crate::ssh::handler::SshHandler;
+use crate::transaction::TransactionService;
+
+/// Credentials a client authenticated with.
+///
+/// An SSH login happens before the client names a repository, while [`crate::auth::Auth`]
+/// decides per repository. So authentication only records the credentials and every git
+/// command is authorized against its repository once the exec request names it.
+#[derive(Clone, Debug)]
+pub enum SshCredential {
+    Password {
+        user: String,
+        password: String,
+    },
+    /// Base64 encoding of the public key the client proved it owns.
+    PublicKey {
+        key: String,
+    },
+}
+
+impl SshHandler {
+    /// Accept a login for deferred authorization; without an authenticator configured there
+    /// is nothing to check credentials against, so the login is refused.
+    pub fn accept_credential(&mut self, credential: SshCredential) -> russh::server::Auth {
+        if self.core.auth.is_none() {
+            return russh::server::Auth::reject();
+        }
+        self.credential = Some(credential);
+        russh::server::Auth::Accept
+    }
+
+    /// Check the login credentials against `namespace/repo` for `service`: fetching needs
+    /// read access, pushing write access.
+    pub async fn authorize(
+        &self,
+        service: &TransactionService,
+        namespace: &str,
+        repo: &str,
+    ) -> Result<AccessLevel, GitInnerError> {
+        let (Some(auth), Some(credential)) = (&self.core.auth, &self.credential) else {
+            return Err(GitInnerError::PermissionDenied);
+        };
+        let access = match credential {
+            SshCredential::Password { user, password } => {
+                auth.authenticate(user, password, namespace, repo).await
+            }
+            SshCredential::PublicKey { key } => auth.auth_public_key(key, namespace, repo).await,
+        }
+        .map_err(|_| GitInnerError::PermissionDenied)?;
+        let allowed = match service {
+            TransactionService::ReceivePack | TransactionService::ReceivePackLs => {
+                matches!(access, AccessLevel::Write | AccessLevel::Admin)
+            }
+            TransactionService::UploadPack | TransactionService::UploadPackLs => true,
+        };
+        if !allowed {
+            return Err(GitInnerError::PermissionDenied);
+        }
+        Ok(access)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Auth;
+    use crate::serve::testing::{EmptyStore, app_core};
+    use russh::server::Handler;
+    use std::sync::Arc;
+
+    /// Accepts `alice:secret` with write access and `bob:secret` read-only.
+    struct MockAuth;
+
+    #[async_trait::async_trait]
+    impl Auth for MockAuth {
+        async fn authenticate(
+            &self,
+            username: &str,
+            password: &str,
+            namespace: &str,
+            repo: &str,
+        ) -> Result<AccessLevel, GitInnerError> {
+            match (username, password, namespace, repo) {
+                ("alice", "secret", "team", "app") => Ok(AccessLevel::Write),
+                ("bob", "secret", "team", "app") => Ok(AccessLevel::Read),
+                _ => Err(GitInnerError::PermissionDenied),
+            }
+        }
+        async fn auth_public_key(
+            &self,
+            _public_key: &str,
+            _namespace: &str,
+            _repo: &str,
+        ) -> Result<AccessLevel, GitInnerError> {
+            Err(GitInnerError::PermissionDenied)
+        }
+    }
+
+    fn handler(auth: bool) -> SshHandler {
+        let mut core = app_core(EmptyStore);
+        if auth {
+            core.auth = Some(Arc::new(Box::new(MockAuth)));
+        }
+        SshHandler {
+            core,
+            addr: None,
+            service: None,
+            transaction: None,
+            credential: None,
+            exec: None,
+            inputs: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_password_accepted_for_repository() {
+        let mut handler = handler(true);
+        let result = handler.auth_password("alice", "secret").await.unwrap();
+        assert_eq!(result, russh::server::Auth::Accept);
+        for service in [
+            TransactionService::UploadPack,
+            TransactionService::ReceivePack,
+        ] {
+            assert!(handler.authorize(&service, "team", "app").await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_password_rejected_per_command() {
+        let mut handler = handler(true);
+        handler.auth_password("alice", "wrong").await.unwrap();
+        assert!(matches!(
+            handler
+                .authorize(&TransactionService::UploadPack, "team", "app")
+                .await,
+            Err(GitInnerError::PermissionDenied)
+        ));
+        // 密码正确但仓库不同
+        handler.auth_password("alice", "secret").await.unwrap();
+        assert!(
+            handler
+                .authorize(&TransactionService::UploadPack, "team", "other")
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_access_cannot_push() {
+        let mut handler = handler(true);
+        handler.auth_password("bob", "secret").await.unwrap();
+        assert!(
+            handler
+                .authorize(&TransactionService::UploadPack, "team", "app")
+                .await
+                .is_ok()
+        );
+        assert!(
+            handler
+                .authorize(&TransactionService::ReceivePack, "team", "app")
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_password_rejected_without_auth() {
+        let mut handler = handler(false);
+        let result = handler.auth_password("alice", "secret").await.unwrap();
+        assert_eq!(result, russh::server::Auth::reject());
+        assert!(handler.credential.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_keyboard_interactive_prompts_for_password() {
+        let mut handler = handler(true);
+        let result = handler
+            .auth_keyboard_interactive("alice", "", None)
+            .await
+            .unwrap();
+        let russh::server::Auth::Partial { prompts, .. } = result else {
+            panic!("expected a prompt, got {:?}", result);
+        };
+        assert_eq!(prompts.len(), 1);
+        assert!(!prompts[0].1, "password must not be echoed");
+    }
+}
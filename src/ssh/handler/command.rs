@@ -0,0 +1,214 @@
+use crate::error::GitInnerError;
+use crate::transaction::TransactionService;
+
+/// The git commands an SSH client may run; anything else is refused, so the server never
+/// hands out a shell.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SshCommand {
+    UploadPack,
+    ReceivePack,
+    UploadArchive,
+}
+
+impl SshCommand {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "upload-pack" => Some(SshCommand::UploadPack),
+            "receive-pack" => Some(SshCommand::ReceivePack),
+            "upload-archive" => Some(SshCommand::UploadArchive),
+            _ => None,
+        }
+    }
+
+    /// The service the command is authorized as; an archive only needs read access.
+    pub fn service(&self) -> TransactionService {
+        match self {
+            SshCommand::UploadPack | SshCommand::UploadArchive => TransactionService::UploadPack,
+            SshCommand::ReceivePack => TransactionService::ReceivePack,
+        }
+    }
+}
+
+/// An allowed exec request: the command and the repository it targets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SshExec {
+    pub command: SshCommand,
+    pub namespace: String,
+    pub repo: String,
+}
+
+impl SshExec {
+    /// Validate an exec request such as `git-upload-pack '/team/app.git'`. The command
+    /// must be on the allow-list and take exactly one `<ns>/<repo>[.git]` argument, quoted
+    /// the way a POSIX shell would accept it.
+    pub fn parse(line: &[u8]) -> Result<Self, GitInnerError> {
+        let line = std::str::from_utf8(line).map_err(|_| GitInnerError::InvalidUtf8)?;
+        let line = line.trim();
+        // 与 git-shell 一样同时接受 `git-upload-pack` 和 `git upload-pack`
+        let (command, rest) = ["git-", "git "]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(prefix))
+            .and_then(|rest| {
+                let (name, rest) = rest.split_once(char::is_whitespace)?;
+                Some((SshCommand::from_name(name)?, rest))
+            })
+            .ok_or_else(|| {
+                GitInnerError::InvalidArgument(format!("command not allowed: {}", line))
+            })?;
+        let mut args = split_words(rest)?;
+        if args.len() != 1 {
+            return Err(GitInnerError::InvalidArgument(
+                "expected exactly one repository argument".to_string(),
+            ));
+        }
+        let (namespace, repo) = parse_repo_path(&args.remove(0))?;
+        Ok(SshExec {
+            command,
+            namespace,
+            repo,
+        })
+    }
+}
+
+/// Split `input` into words with POSIX shell quoting: `'...'` is literal, `"..."` allows
+/// `\"`, `\\`, `` \` `` and `\$` escapes, and a backslash outside quotes escapes any character.
+fn split_words(input: &str) -> Result<Vec<String>, GitInnerError> {
+    let unterminated = || GitInnerError::InvalidArgument("unterminated quote".to_string());
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or_else(unterminated)? {
+                            c @ ('"' | '\\' | '`' | '$') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let c = chars.next().ok_or_else(|| {
+                    GitInnerError::InvalidArgument("trailing backslash".to_string())
+                })?;
+                word.get_or_insert_with(String::new).push(c);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// `<ns>/<repo>` from a repository argument, with an optional leading `/`, trailing `/`
+/// and `.git` suffix.
+fn parse_repo_path(path: &str) -> Result<(String, String), GitInnerError> {
+    let invalid = || GitInnerError::InvalidArgument(format!("invalid repository path: {}", path));
+    let trimmed = path.trim_start_matches('/').trim_end_matches('/');
+    let trimmed = trimmed.strip_suffix(".git").unwrap_or(trimmed);
+    let (namespace, repo) = trimmed.split_once('/').ok_or_else(invalid)?;
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part != "."
+            && part != ".."
+            && !part.starts_with('-')
+            && !part.contains(['/', '\\', '\0'])
+    };
+    if !valid(namespace) || !valid(repo) {
+        return Err(invalid());
+    }
+    Ok((namespace.to_string(), repo.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exec(command: SshCommand) -> SshExec {
+        SshExec {
+            command,
+            namespace: "team".to_string(),
+            repo: "app".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_allowed_commands() {
+        for (line, command) in [
+            ("git-upload-pack '/team/app.git'", SshCommand::UploadPack),
+            ("git-receive-pack 'team/app.git'", SshCommand::ReceivePack),
+            ("git-upload-archive 'team/app'", SshCommand::UploadArchive),
+            ("git upload-pack '/team/app.git/'", SshCommand::UploadPack),
+        ] {
+            assert_eq!(SshExec::parse(line.as_bytes()).unwrap(), exec(command));
+        }
+    }
+
+    #[test]
+    fn test_rejects_other_commands() {
+        for line in [
+            "sh -c 'cat /etc/passwd'",
+            "git-upload-packx 'team/app.git'",
+            "git-upload-pack",
+            "git-upload-pack 'team/app.git' extra",
+            "git-upload-pack 'team/app.git'; rm -rf /",
+            "git-upload-pack '../etc/passwd'",
+            "git-upload-pack 'team/../app'",
+            "git-upload-pack 'team/--upload-pack=sh'",
+            "git-upload-pack 'app.git'",
+        ] {
+            assert!(
+                matches!(
+                    SshExec::parse(line.as_bytes()),
+                    Err(GitInnerError::InvalidArgument(_))
+                ),
+                "accepted {}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_quoting() {
+        let parse = |line: &str| SshExec::parse(line.as_bytes()).map(|x| x.repo);
+        assert_eq!(parse("git-upload-pack \"team/app.git\"").unwrap(), "app");
+        assert_eq!(parse("git-upload-pack team/app.git").unwrap(), "app");
+        // git 用 '\'' 在单引号中嵌入单引号
+        assert_eq!(
+            parse("git-upload-pack 'team/it'\\''s.git'").unwrap(),
+            "it's"
+        );
+        assert_eq!(
+            parse("git-upload-pack \"team/say \\\"hi\\\"\"").unwrap(),
+            "say \"hi\""
+        );
+        assert_eq!(parse("git-upload-pack team/my\\ app").unwrap(), "my app");
+        // 单引号内反斜杠没有转义含义
+        assert!(parse("git-upload-pack 'team/a\\'b'").is_err());
+        assert!(parse("git-upload-pack 'team/app.git").is_err());
+        assert!(parse("git-upload-pack \"team/app.git").is_err());
+        assert!(parse("git-upload-pack team/app\\").is_err());
+    }
+}
@@ -1,7 +1,23 @@
+use crate::callback::CallBack;
+use crate::config::AppConfig;
 use crate::error::GitInnerError;
 use crate::serve::AppCore;
-use crate::transaction::{Transaction, TransactionService};
+use crate::ssh::handler::auth::SshCredential;
+use crate::ssh::handler::command::{SshCommand, SshExec};
+use crate::transaction::{GitProtoVersion, ProtocolType, Transaction, TransactionService};
+use bytes::Bytes;
+use russh::keys::PublicKeyBase64;
+use russh::server::{Auth, Msg, Response, Session};
+use russh::{Channel, ChannelId, CryptoVec};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{Instrument, error};
+
+pub mod auth;
+pub mod command;
 
 #[derive(Clone)]
 pub struct SshHandler {
@@ -9,8 +25,466 @@ pub struct SshHandler {
     pub addr: Option<SocketAddr>,
     pub service: Option<TransactionService>,
     pub transaction: Option<Transaction>,
+    /// Login credentials, checked against each repository a command targets.
+    pub credential: Option<SshCredential>,
+    /// The validated and authorized command of the session channel.
+    pub exec: Option<SshExec>,
+    /// Feeds each channel's data to the command running on it until the client sends EOF.
+    pub inputs: HashMap<ChannelId, Sender<Result<Bytes, GitInnerError>>>,
+}
+
+impl SshHandler {
+    /// Refuse an exec request: the reason goes to the client's stderr as git-shell would
+    /// print it, then the channel exits with status 128 and closes.
+    fn refuse(
+        &self,
+        channel: ChannelId,
+        session: &mut Session,
+        reason: &str,
+    ) -> Result<(), GitInnerError> {
+        session.channel_failure(channel)?;
+        let message = format!("fatal: {}\n", reason);
+        session.extended_data(channel, 1, CryptoVec::from_slice(message.as_bytes()))?;
+        session.exit_status_request(channel, 128)?;
+        session.close(channel)?;
+        Ok(())
+    }
+
+    /// A transaction of `service` over `namespace/repo` for this connection.
+    async fn transaction(
+        &self,
+        service: TransactionService,
+        namespace: &str,
+        repo: &str,
+    ) -> Result<Transaction, GitInnerError> {
+        let repository = self
+            .core
+            .repo_store
+            .repo(namespace.to_string(), repo.to_string())
+            .await?;
+        let actor = match &self.credential {
+            Some(SshCredential::Password { user, .. }) => Some(user.clone()),
+            _ => None,
+        };
+        Ok(Transaction {
+            service,
+            repository,
+            version: GitProtoVersion::V0,
+            call_back: CallBack::new(1024),
+            protocol: ProtocolType::SSH,
+            timeouts: AppConfig::transaction().into(),
+            pack_limits: AppConfig::cfg().into(),
+            actor,
+            maintenance: self.core.maintenance.clone(),
+            metrics: self.core.metrics.clone(),
+            fetch_resume: self.core.fetch_resume.clone(),
+            hooks: self.core.hooks.clone(),
+            push_limiter: self.core.transactions.clone(),
+            deny_non_fast_forwards: AppConfig::transaction().deny_non_fast_forwards,
+        })
+    }
+
+    /// Run the command of `exec` on `channel`: channel data is its input and everything it
+    /// writes goes back as channel data, followed by the exit status.
+    async fn run_command(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+        exec: &SshExec,
+    ) -> Result<(), GitInnerError> {
+        let transaction = match self
+            .transaction(exec.command.service(), &exec.namespace, &exec.repo)
+            .await
+        {
+            Ok(transaction) => transaction,
+            Err(err) => return self.refuse(channel, session, &err.to_string()),
+        };
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        self.inputs.insert(channel, tx);
+        let handle = session.handle();
+        let span = transaction.span(&exec.namespace, &exec.repo);
+        let command = exec.command.clone();
+        tokio::spawn(
+            async move {
+                let output = |data: Bytes| {
+                    let handle = handle.clone();
+                    async move {
+                        handle
+                            .data(channel, CryptoVec::from_slice(&data))
+                            .await
+                            .is_ok()
+                    }
+                };
+                let status = match execute(transaction, command.clone(), rx, output).await {
+                    Ok(()) => 0,
+                    Err(err) => {
+                        error!("SSH {:?} error: {:?}", command, err);
+                        1
+                    }
+                };
+                handle.exit_status_request(channel, status).await.ok();
+                handle.eof(channel).await.ok();
+                handle.close(channel).await.ok();
+            }
+            .instrument(span),
+        );
+        session.channel_success(channel)?;
+        Ok(())
+    }
+}
+
+impl SshHandler {
+    /// Pass `data` to the command running on `channel`, if it still takes input.
+    async fn feed(&self, channel: ChannelId, data: &[u8]) {
+        if let Some(input) = self.inputs.get(&channel) {
+            // 命令已经结束时不再需要输入
+            input.send(Ok(Bytes::copy_from_slice(data))).await.ok();
+        }
+    }
+}
+
+/// Run `command` over `transaction` with `input` as the client's data, handing every chunk
+/// it writes to `output` until that returns false. Upload-pack and receive-pack advertise
+/// the refs first, as git expects on a stateful connection.
+async fn execute<F, Fut>(
+    mut transaction: Transaction,
+    command: SshCommand,
+    input: Receiver<Result<Bytes, GitInnerError>>,
+    mut output: F,
+) -> Result<(), GitInnerError>
+where
+    F: FnMut(Bytes) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let mut receiver = transaction.call_back.subscribe().await;
+    // transaction 在结束后释放，发送端全部关闭后转发才会结束
+    let run = async move {
+        let stream = ReceiverStream::new(input);
+        match command {
+            SshCommand::UploadArchive => transaction.upload_archive(&mut Box::pin(stream)).await,
+            SshCommand::UploadPack => {
+                transaction.advertise_refs().await?;
+                transaction.upload_pack(&mut Box::pin(stream)).await
+            }
+            SshCommand::ReceivePack => {
+                transaction.advertise_refs().await?;
+                transaction.receive_pack(Box::pin(stream)).await
+            }
+        }
+    };
+    let forward = async move {
+        while let Some(next) = receiver.recv().await {
+            // 空块只是 HTTP 响应的结束标记
+            if !next.is_empty() && !output(next).await {
+                break;
+            }
+        }
+    };
+    let (result, _) = futures_util::future::join(run, forward).await;
+    result
 }
 
 impl russh::server::Handler for SshHandler {
     type Error = GitInnerError;
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        Ok(self.accept_credential(SshCredential::Password {
+            user: user.to_string(),
+            password: password.to_string(),
+        }))
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        _user: &str,
+        public_key: &russh::keys::PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        Ok(self.accept_credential(SshCredential::PublicKey {
+            key: public_key.public_key_base64(),
+        }))
+    }
+
+    /// Keyboard-interactive login for clients that only offer it: a single password prompt
+    /// whose answer is handled like password authentication.
+    async fn auth_keyboard_interactive<'a>(
+        &'a mut self,
+        user: &str,
+        _submethods: &str,
+        response: Option<Response<'a>>,
+    ) -> Result<Auth, Self::Error> {
+        let Some(mut response) = response else {
+            return Ok(Auth::Partial {
+                name: Cow::Borrowed(""),
+                instructions: Cow::Borrowed(""),
+                prompts: Cow::Owned(vec![(Cow::Borrowed("Password: "), false)]),
+            });
+        };
+        let Some(password) = response.next() else {
+            return Ok(Auth::reject());
+        };
+        Ok(self.accept_credential(SshCredential::Password {
+            user: user.to_string(),
+            password: String::from_utf8_lossy(&password).to_string(),
+        }))
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        _channel: Channel<Msg>,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    /// Only the allow-listed git commands run, each authorized against the repository it
+    /// names; anything else, shells included, is refused, as is every command once the peer
+    /// exceeds its transaction rate.
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if let Some(addr) = self.addr
+            && !self.core.rate_limiter.check(addr.ip())
+        {
+            return self.refuse(channel, session, &GitInnerError::RateLimited.to_string());
+        }
+        let exec = match SshExec::parse(data) {
+            Ok(exec) => exec,
+            Err(err) => return self.refuse(channel, session, &err.to_string()),
+        };
+        let service = exec.command.service();
+        if let Err(err) = self.authorize(&service, &exec.namespace, &exec.repo).await {
+            return self.refuse(channel, session, &err.to_string());
+        }
+        self.service = Some(service);
+        self.run_command(channel, session, &exec).await?;
+        self.exec = Some(exec);
+        Ok(())
+    }
+
+    async fn data(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.feed(channel, data).await;
+        Ok(())
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.inputs.remove(&channel);
+        Ok(())
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.inputs.remove(&channel);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callback::sidebend::demux;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::Repository;
+    use crate::repository::testing::{
+        memory_repository, pack, put_blob, put_commit, put_tree, transaction,
+    };
+    use crate::serve::maintenance::MAINTENANCE_MESSAGE;
+    use crate::serve::testing::{EmptyStore, app_core};
+    use crate::sha::{HashValue, HashVersion};
+    use crate::write_pkt_line;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// A repository whose `main` holds one commit.
+    async fn repository() -> (Repository, HashValue) {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello\n").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+        (repo, commit)
+    }
+
+    /// The transaction an SSH channel running `command` on `repo` gets.
+    fn channel_transaction(repo: &Repository, command: &SshCommand) -> Transaction {
+        Transaction {
+            service: command.service(),
+            version: GitProtoVersion::V0,
+            protocol: ProtocolType::SSH,
+            ..transaction(repo)
+        }
+    }
+
+    /// Run `command` the way an SSH channel does, returning its result and everything it
+    /// wrote. `input` stays open unless `eof` is set, so a command waiting for EOF hangs.
+    async fn run(
+        txn: Transaction,
+        command: SshCommand,
+        body: Bytes,
+        eof: bool,
+    ) -> (Result<(), GitInnerError>, Vec<u8>) {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        tx.send(Ok(body)).await.unwrap();
+        let tx = (!eof).then_some(tx);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let sink = out.clone();
+        let output = move |data: Bytes| {
+            sink.lock().unwrap().extend_from_slice(&data);
+            async { true }
+        };
+        let result =
+            tokio::time::timeout(Duration::from_secs(10), execute(txn, command, rx, output))
+                .await
+                .expect("command did not finish");
+        drop(tx);
+        let out = out.lock().unwrap().clone();
+        (result, out)
+    }
+
+    /// Split off the ref advertisement, up to and including its flush.
+    fn advertisement(out: &[u8]) -> (String, &[u8]) {
+        let end = out.windows(4).position(|x| x == b"0000").unwrap() + 4;
+        (
+            String::from_utf8_lossy(&out[..end]).to_string(),
+            &out[end..],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_upload_pack_over_channel() {
+        let (repo, commit) = repository().await;
+        let mut body = write_pkt_line(format!("want {} side-band-64k\n", commit));
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&write_pkt_line("done\n".to_string()));
+        // 客户端发完 done 后不关闭输入，命令也要结束
+        let (result, out) = run(
+            channel_transaction(&repo, &SshCommand::UploadPack),
+            SshCommand::UploadPack,
+            body.freeze(),
+            false,
+        )
+        .await;
+        result.unwrap();
+        let (refs, rest) = advertisement(&out);
+        assert!(refs.contains(&format!("{} refs/heads/main\n", commit)));
+        assert!(rest.starts_with(b"0008NAK\n"));
+        let pack = demux(&rest[8..]).unwrap().pack;
+        assert_eq!(&pack[..4], b"PACK");
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_over_channel() {
+        let (repo, commit) = repository().await;
+        let zero = HashVersion::Sha1.default();
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/feature\0report-status",
+            zero, commit
+        ));
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&pack(&[]));
+        let (result, out) = run(
+            channel_transaction(&repo, &SshCommand::ReceivePack),
+            SshCommand::ReceivePack,
+            body.freeze(),
+            true,
+        )
+        .await;
+        result.unwrap();
+        let (refs, report) = advertisement(&out);
+        assert!(refs.contains(&format!("{} refs/heads/main", commit)));
+        let report = String::from_utf8_lossy(report);
+        assert!(report.contains("unpack ok"));
+        assert!(report.contains("ok refs/heads/feature"));
+        assert_eq!(
+            repo.refs
+                .get_value_refs("refs/heads/feature".to_string())
+                .await
+                .unwrap(),
+            commit
+        );
+    }
+
+    #[tokio::test]
+    async fn test_input_routed_by_channel() {
+        use russh::keys::ssh_encoding::Decode;
+        let id = |n: u32| ChannelId::decode(&mut &n.to_be_bytes()[..]).unwrap();
+        let mut handler = SshHandler {
+            core: app_core(EmptyStore),
+            addr: None,
+            service: None,
+            transaction: None,
+            credential: None,
+            exec: None,
+            inputs: Default::default(),
+        };
+        let (first_tx, mut first) = tokio::sync::mpsc::channel(8);
+        let (second_tx, mut second) = tokio::sync::mpsc::channel(8);
+        handler.inputs.insert(id(1), first_tx);
+        handler.inputs.insert(id(2), second_tx);
+
+        // 第二个命令开始后，第一个通道的数据仍然交给第一个命令
+        handler.feed(id(1), b"one").await;
+        handler.feed(id(2), b"two").await;
+        handler.feed(id(3), b"nobody").await;
+        assert_eq!(first.recv().await.unwrap().unwrap(), "one");
+        assert_eq!(second.recv().await.unwrap().unwrap(), "two");
+        assert!(first.try_recv().is_err());
+        assert!(second.try_recv().is_err());
+
+        handler.inputs.remove(&id(1));
+        assert!(first.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_receive_pack_refused_in_maintenance_mode() {
+        let (repo, commit) = repository().await;
+        let txn = channel_transaction(&repo, &SshCommand::ReceivePack);
+        txn.maintenance.set(true);
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/feature\0report-status",
+            HashVersion::Sha1.default(),
+            commit
+        ));
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&pack(&[]));
+        let (result, out) = run(txn, SshCommand::ReceivePack, body.freeze(), true).await;
+        assert!(matches!(result, Err(GitInnerError::ReadOnly)));
+        let (_, rest) = advertisement(&out);
+        assert_eq!(
+            rest,
+            write_pkt_line(format!("ERR {}\n", MAINTENANCE_MESSAGE)).as_ref()
+        );
+        assert!(
+            !repo
+                .refs
+                .exists_refs("refs/heads/feature".to_string())
+                .await
+                .unwrap()
+        );
+
+        // 维护模式下克隆照常进行
+        let txn = channel_transaction(&repo, &SshCommand::UploadPack);
+        txn.maintenance.set(true);
+        let mut body = write_pkt_line(format!("want {} side-band-64k\n", commit));
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&write_pkt_line("done\n".to_string()));
+        let (result, _) = run(txn, SshCommand::UploadPack, body.freeze(), false).await;
+        result.unwrap();
+    }
 }
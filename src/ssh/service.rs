@@ -4,11 +4,12 @@ use crate::error::GitInnerError;
 use crate::serve::AppCore;
 use crate::ssh::handler::SshHandler;
 use log::{info, warn};
-use russh::keys::PublicKeyBase64;
 use russh::keys::ssh_encoding::base64::Encoding;
-use russh::keys::ssh_encoding::{DecodePem, EncodePem, LineEnding, base64};
+use russh::keys::ssh_encoding::{LineEnding, base64};
+use russh::keys::{Algorithm, PrivateKey, PublicKeyBase64};
 use russh::server::Server;
 use sha2::Digest;
+use std::borrow::Cow;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
@@ -20,9 +21,9 @@ pub struct SshServer {
 impl SshServer {
     /// Starts the SSH server using the configured host, port, and server key.
     ///
-    /// If a server public key is configured the function uses it; otherwise it generates a new Ed25519 key,
-    /// persists the new public key to the global configuration, and uses that key. The server is configured
-    /// with large channel and event buffers and a short authentication rejection timeout before it begins
+    /// Every configured host key (`server_public_key` and `host_keys`) is loaded; if there is none the
+    /// function generates a new Ed25519 key, persists it to the global configuration, and uses that key. The server is configured
+    /// with the configured channel and event buffer sizes and a short authentication rejection timeout before it begins
     /// listening on the configured address. The function returns an error if key decoding/encoding, configuration
     /// persistence, or server startup fails.
     ///
@@ -50,31 +51,23 @@ impl SshServer {
             return Ok(());
         }
         info!("Starting SSH server");
-        let mut cfg = russh::server::Config::default();
-        if let Some(public_key) = &self.config.server_public_key {
-            let mut figure = sha2::Sha256::default();
-            figure.update(public_key);
-            let fingerprint = figure.finalize();
+        let mut keys = host_keys(&self.config)?;
+        for key in &keys {
             info!(
-                "SSH server public key fingerprint: sha256:{}",
-                hex::encode(fingerprint)
+                "SSH server host key {} fingerprint: {}",
+                key.algorithm(),
+                key.fingerprint(Default::default())
             );
-            let private_key = russh::keys::PrivateKey::decode_pem(
-                &base64::Base64::decode_vec(public_key)
-                    .map_err(|e| GitInnerError::SshServerStartError(e.to_string()))?,
-            )
-            .map_err(|e| GitInnerError::SshServerStartError(e.to_string()))?;
-            cfg.keys = vec![private_key]
-        } else {
+        }
+        if keys.is_empty() {
             info!("SSH server public is empty, using new key");
             let private_key = russh::keys::PrivateKey::random(
                 &mut russh::keys::key::safe_rng(),
                 russh::keys::Algorithm::Ed25519,
             )
             .map_err(|e| GitInnerError::SshServerStartError(e.to_string()))?;
-            let mut pem = vec![];
             let private_key_pem = private_key
-                .encode_pem(LineEnding::LF, &mut pem)
+                .to_openssh(LineEnding::LF)
                 .map_err(|e| GitInnerError::SshServerStartError(e.to_string()))?;
             let private_key_pem = base64::Base64::encode_string(private_key_pem.as_bytes());
             let mut config = CFG.clone();
@@ -90,11 +83,9 @@ impl SshServer {
                 "SSH server new public key fingerprint: sha256:{}",
                 hex::encode(fingerprint)
             );
-            cfg.keys = vec![private_key];
+            keys.push(private_key);
         }
-        cfg.channel_buffer_size = usize::MAX;
-        cfg.event_buffer_size = usize::MAX;
-        cfg.auth_rejection_time = std::time::Duration::from_secs(3);
+        let cfg = server_config(&self.config, keys)?;
         self.run_on_address(
             Arc::new(cfg),
             format!("{}:{}", self.config.host, self.config.port),
@@ -144,13 +135,69 @@ impl SshServer {
     }
 }
 
+/// Decode every host key of `config`: `server_public_key` first, then `host_keys` in order.
+pub fn host_keys(config: &SshConfig) -> Result<Vec<PrivateKey>, GitInnerError> {
+    config
+        .server_public_key
+        .iter()
+        .chain(config.host_keys.iter())
+        .map(|encoded| {
+            let pem = base64::Base64::decode_vec(encoded)
+                .map_err(|e| GitInnerError::SshServerStartError(e.to_string()))?;
+            let pem = String::from_utf8(pem)
+                .map_err(|e| GitInnerError::SshServerStartError(e.to_string()))?;
+            // 兼容 OpenSSH、PKCS#8 与 PKCS#1 格式的私钥
+            russh::keys::decode_secret_key(&pem, None)
+                .map_err(|e| GitInnerError::SshServerStartError(e.to_string()))
+        })
+        .collect()
+}
+
+/// The russh server configuration offering `keys`, negotiating `config.key_algorithms` when
+/// set and bounding its queues by the configured buffer sizes. Every configured algorithm
+/// must be backed by one of the keys.
+pub fn server_config(
+    config: &SshConfig,
+    keys: Vec<PrivateKey>,
+) -> Result<russh::server::Config, GitInnerError> {
+    let mut cfg = russh::server::Config::default();
+    if !config.key_algorithms.is_empty() {
+        let algorithms = config
+            .key_algorithms
+            .iter()
+            .map(|name| {
+                let algorithm = Algorithm::new(name)
+                    .map_err(|e| GitInnerError::SshServerStartError(format!("{}: {}", name, e)))?;
+                // rsa-sha2-* 都由同一把 RSA 密钥签名
+                let backed = keys.iter().any(|key| match (key.algorithm(), &algorithm) {
+                    (Algorithm::Rsa { .. }, Algorithm::Rsa { .. }) => true,
+                    (key, algorithm) => key == *algorithm,
+                });
+                if !backed {
+                    return Err(GitInnerError::SshServerStartError(format!(
+                        "no host key for algorithm {}",
+                        name
+                    )));
+                }
+                Ok(algorithm)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        cfg.preferred.key = Cow::Owned(algorithms);
+    }
+    cfg.keys = keys;
+    cfg.channel_buffer_size = config.channel_buffer_size;
+    cfg.event_buffer_size = config.event_buffer_size;
+    cfg.auth_rejection_time = std::time::Duration::from_secs(3);
+    Ok(cfg)
+}
+
 impl Server for SshServer {
     type Handler = SshHandler;
 
     /// Creates a new SSH handler for an incoming connection.
     ///
     /// The returned handler is initialized with a clone of the server's core state and the
-    /// optional peer socket address; `service` and `transaction` are unset.
+    /// optional peer socket address; `service`, `transaction`, `credential` and `exec` are unset.
     ///
     /// # Examples
     ///
@@ -165,6 +212,86 @@ impl Server for SshServer {
             addr: peer_addr,
             service: None,
             transaction: None,
+            credential: None,
+            exec: None,
+            inputs: Default::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::EcdsaCurve;
+
+    fn encoded(algorithm: Algorithm) -> (PrivateKey, String) {
+        let key = PrivateKey::random(&mut russh::keys::key::safe_rng(), algorithm).unwrap();
+        let pem = key.to_openssh(LineEnding::LF).unwrap();
+        let encoded = base64::Base64::encode_string(pem.as_bytes());
+        (key, encoded)
+    }
+
+    #[test]
+    fn test_multiple_host_keys() {
+        let ecdsa = Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP256,
+        };
+        let (ed25519_key, ed25519_pem) = encoded(Algorithm::Ed25519);
+        let (ecdsa_key, ecdsa_pem) = encoded(ecdsa.clone());
+        let config = SshConfig {
+            host_keys: vec![ed25519_pem, ecdsa_pem],
+            key_algorithms: vec!["ecdsa-sha2-nistp256".to_string(), "ssh-ed25519".to_string()],
+            ..Default::default()
+        };
+
+        let cfg = server_config(&config, host_keys(&config).unwrap()).unwrap();
+        let loaded = cfg
+            .keys
+            .iter()
+            .map(|key| key.public_key().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            loaded,
+            vec![
+                ed25519_key.public_key().clone(),
+                ecdsa_key.public_key().clone()
+            ]
+        );
+        assert_eq!(cfg.preferred.key.to_vec(), vec![ecdsa, Algorithm::Ed25519]);
+    }
+
+    #[test]
+    fn test_algorithm_without_host_key() {
+        let (_, ed25519_pem) = encoded(Algorithm::Ed25519);
+        let config = SshConfig {
+            host_keys: vec![ed25519_pem],
+            key_algorithms: vec!["rsa-sha2-512".to_string()],
+            ..Default::default()
+        };
+        let keys = host_keys(&config).unwrap();
+        assert!(server_config(&config, keys).is_err());
+    }
+
+    #[test]
+    fn test_buffer_sizes_from_config() {
+        let (_, ed25519_pem) = encoded(Algorithm::Ed25519);
+        let config = SshConfig {
+            host_keys: vec![ed25519_pem],
+            channel_buffer_size: 64,
+            event_buffer_size: 128,
+            ..Default::default()
+        };
+        let cfg = server_config(&config, host_keys(&config).unwrap()).unwrap();
+        assert_eq!(cfg.channel_buffer_size, 64);
+        assert_eq!(cfg.event_buffer_size, 128);
+
+        let cfg = server_config(&SshConfig::default(), vec![]).unwrap();
+        assert_eq!(cfg.channel_buffer_size, 2048);
+        assert_eq!(cfg.event_buffer_size, 2048);
+    }
+
+    #[test]
+    fn test_no_host_keys_configured() {
+        assert!(host_keys(&SshConfig::default()).unwrap().is_empty());
+    }
+}
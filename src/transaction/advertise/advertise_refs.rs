@@ -7,7 +7,7 @@ impl Transaction {
             ProtocolType::Git => {}
             ProtocolType::SSH => {}
             ProtocolType::Http => {
-                self.http_advertise_header().await;
+                self.http_advertise_header().await?;
             }
         }
         match (&self.service, &self.version) {
@@ -15,20 +15,25 @@ impl Transaction {
                 TransactionService::UploadPack | TransactionService::UploadPackLs,
                 GitProtoVersion::V2,
             ) => {
-                self.call_back.send(Bytes::from("0000")).await;
-                self.write_version().await;
+                self.write_version().await?;
                 self.write_advertise_v2().await?;
             }
-            (TransactionService::UploadPack | TransactionService::UploadPackLs, _)
-            | (TransactionService::ReceivePack | TransactionService::ReceivePackLs, _) => {
-                self.write_version().await;
-                self.call_back.send(Bytes::from("0000")).await;
+            (TransactionService::UploadPack | TransactionService::UploadPackLs, _) => {
+                if self.version == GitProtoVersion::V1 {
+                    self.write_version().await?;
+                }
                 self.write_refs_head_info().await?;
                 self.write_all_refs().await?;
-                self.call_back.send(Bytes::from("0000")).await;
+                self.call_back.send(Bytes::from("0000")).await?;
+            }
+            (TransactionService::ReceivePack | TransactionService::ReceivePackLs, _) => {
+                if self.version == GitProtoVersion::V1 {
+                    self.write_version().await?;
+                }
+                self.advertise_receive_refs().await?;
             }
         }
-        self.call_back.send(Bytes::new()).await;
+        self.call_back.send(Bytes::new()).await?;
         Ok(())
     }
 }
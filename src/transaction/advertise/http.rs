@@ -1,9 +1,10 @@
+use crate::error::GitInnerError;
 use crate::transaction::Transaction;
 use crate::transaction::service::TransactionService;
 use bytes::Bytes;
 
 impl Transaction {
-    pub async fn http_advertise_header(&self) {
+    pub async fn http_advertise_header(&self) -> Result<(), GitInnerError> {
         let head = Bytes::from(format!(
             "# service={}\n",
             match self.service {
@@ -13,6 +14,7 @@ impl Transaction {
                 TransactionService::ReceivePackLs => "git-receive-pack",
             }
         ));
-        self.call_back.send_pkt_line(head).await;
+        self.call_back.send_pkt_line(head).await?;
+        self.call_back.send(Bytes::from("0000")).await
     }
 }
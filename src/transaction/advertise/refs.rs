@@ -1,11 +1,11 @@
-use crate::capability::enums::GitCapability;
+use crate::capability::enums::{GitCapability, render_capability_list};
 use crate::error::GitInnerError;
-use crate::sha::HashVersion;
+use crate::refs::RefItem;
+use crate::sha::HashValue;
 use crate::transaction::Transaction;
 use crate::transaction::service::TransactionService;
 use crate::write_pkt_line;
-use bstr::ByteSlice;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 
 impl Transaction {
     pub async fn write_refs_head_info(&self) -> Result<(), GitInnerError> {
@@ -18,56 +18,312 @@ impl Transaction {
                 capabilities.extend_from_slice(&GitCapability::receive())
             }
         }
-        let sha_version = GitCapability::ObjectFormat(match self.repository.hash_version {
-            HashVersion::Sha1 => "sha1".to_string(),
-            HashVersion::Sha256 => "sha256".to_string(),
-        });
-        capabilities.push(sha_version);
+        capabilities.push(GitCapability::ObjectFormat(self.repository.hash_version));
         let head = self.repository.refs.head().await?;
+        if let Some(target) = symref_target(&head) {
+            capabilities.push(GitCapability::Symref(
+                "HEAD".to_string(),
+                target.to_string(),
+            ));
+        }
         let mut result = BytesMut::new();
         result.extend_from_slice(
             format!(
                 "{} HEAD\0{}\n",
                 head.value.to_string(),
-                capabilities
-                    .iter()
-                    .map(|x| x.to_string())
-                    .collect::<Vec<_>>()
-                    .join(" ")
+                render_capability_list(&capabilities)
             )
             .as_bytes(),
         );
-        self.call_back.send_pkt_line(result.freeze()).await;
+        self.call_back.send_pkt_line(result.freeze()).await?;
         Ok(())
     }
-    pub async fn write_refs_head_info_v2(&self, symref: bool) -> Result<(), GitInnerError> {
+    /// Protocol v0/v1 receive-pack advertisement: every ref as `<oid> <refname>`, the
+    /// capability list after the first one, then a flush. An empty repository advertises
+    /// `<zero-id> capabilities^{}` so the client still learns the capabilities.
+    pub async fn advertise_receive_refs(&self) -> Result<(), GitInnerError> {
+        let mut capabilities = GitCapability::receive();
+        capabilities.push(GitCapability::ObjectFormat(self.repository.hash_version));
+        let capabilities = render_capability_list(&capabilities);
+        let refs = self.repository.refs.refs().await?;
+        if refs.is_empty() {
+            let line = format!(
+                "{} capabilities^{{}}\0{}\n",
+                self.repository.hash_version.default(),
+                capabilities
+            );
+            self.call_back.send(write_pkt_line(line).freeze()).await?;
+        }
+        for (idx, ref_item) in refs.iter().enumerate() {
+            let line = if idx == 0 {
+                format!("{} {}\0{}\n", ref_item.value, ref_item.name, capabilities)
+            } else {
+                format!("{} {}\n", ref_item.value, ref_item.name)
+            };
+            self.call_back.send(write_pkt_line(line).freeze()).await?;
+        }
+        self.call_back.send(Bytes::from("0000")).await?;
+        Ok(())
+    }
+    /// Protocol v2 `ls-refs` line for HEAD; with `symref`, a HEAD pointing at a branch
+    /// carries a `symref-target:<ref>` attribute. An unborn HEAD is only listed, as
+    /// `unborn HEAD`, when the client sent `unborn` and HEAD names a branch.
+    pub async fn write_refs_head_info_v2(
+        &self,
+        symref: bool,
+        unborn: bool,
+    ) -> Result<(), GitInnerError> {
         let head = self.repository.refs.head().await?;
-        let mut result = BytesMut::new();
-        let symref_str = if symref {
-            format!("symref=HEAD:{}", head.name.to_string())
+        let mut line = if head.value.is_zero() {
+            if !unborn || symref_target(&head).is_none() {
+                return Ok(());
+            }
+            "unborn HEAD".to_string()
         } else {
-            String::new()
+            format!("{} HEAD", head.value)
         };
-        result.extend_from_slice(
-            format!("{} HEAD\0{}\n", head.value.to_string(), symref_str).as_bytes(),
-        );
-        self.call_back.send_pkt_line(result.freeze()).await;
+        if symref && let Some(target) = symref_target(&head) {
+            line.push_str(&format!(" symref-target:{}", target));
+        }
+        line.push('\n');
+        self.call_back.send(write_pkt_line(line).freeze()).await?;
         Ok(())
     }
+    /// Advertise every ref, followed by a `<refname>^{}` line with the peeled commit for
+    /// annotated tags.
     pub async fn write_all_refs(&self) -> Result<(), GitInnerError> {
         let refs = self.repository.refs.refs().await?;
         for ref_item in refs {
-            let mut result = BytesMut::new();
-            result.extend_from_slice(
-                write_pkt_line(format!(
-                    "{} {}",
-                    ref_item.value.to_string(),
-                    ref_item.name.to_string()
-                ))
-                .as_bytes(),
-            );
-            self.call_back.send(result.freeze()).await;
+            self.call_back
+                .send(write_pkt_line(format!("{} {}\n", ref_item.value, ref_item.name)).freeze())
+                .await?;
+            if let Some(peeled) = self.peeled_tag(&ref_item).await? {
+                self.call_back
+                    .send(write_pkt_line(format!("{} {}^{{}}\n", peeled, ref_item.name)).freeze())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Protocol v2 `ls-refs` output; with `peel`, annotated tags carry a `peeled:<oid>`
+    /// attribute instead of a separate line.
+    pub async fn write_all_refs_v2(&self, peel: bool) -> Result<(), GitInnerError> {
+        let refs = self.repository.refs.refs().await?;
+        for ref_item in refs {
+            let mut line = format!("{} {}", ref_item.value, ref_item.name);
+            if peel && let Some(peeled) = self.peeled_tag(&ref_item).await? {
+                line.push_str(&format!(" peeled:{}", peeled));
+            }
+            line.push('\n');
+            self.call_back.send(write_pkt_line(line).freeze()).await?;
         }
         Ok(())
     }
+
+    /// The object an annotated tag ref ultimately points to; `None` for lightweight tags
+    /// and non-tag refs.
+    async fn peeled_tag(&self, ref_item: &RefItem) -> Result<Option<HashValue>, GitInnerError> {
+        if !ref_item.is_tag || !self.repository.odb.has_tag(&ref_item.value).await? {
+            return Ok(None);
+        }
+        Ok(Some(self.repository.peel_to_commit(&ref_item.value).await?))
+    }
+}
+
+/// The ref HEAD points at, or `None` for a detached or unborn HEAD.
+fn symref_target(head: &RefItem) -> Option<&str> {
+    Some(head.name.as_str()).filter(|name| name.starts_with("refs/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::AccessLevel;
+    use crate::error::GitInnerError;
+    use crate::objects::signature::{Signature, SignatureType};
+    use crate::objects::tree::TreeItemMode;
+    use crate::refs::memory::MemoryRefsManager;
+    use crate::repository::testing::{
+        memory_repository, put_blob, put_commit, put_tree, receive_transaction, transaction,
+    };
+    use crate::sha::HashVersion;
+    use crate::transaction::Transaction;
+    use std::sync::Arc;
+
+    async fn sent_lines(txn: &Transaction) -> Vec<String> {
+        let mut receive = txn.call_back.receive.lock().await;
+        let mut lines = Vec::new();
+        while let Ok(bytes) = receive.try_recv() {
+            lines.push(String::from_utf8_lossy(&bytes[4..]).trim_end().to_string());
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_peel_annotated_tags_only() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+        let annotated = repo
+            .create_tag(
+                &AccessLevel::Write,
+                "v1.0",
+                "main",
+                Some("release".to_string()),
+                Some(Signature {
+                    signature_type: SignatureType::Tagger,
+                    name: "Tagger".to_string(),
+                    email: "tagger@example.com".to_string(),
+                    timestamp: 1700000000,
+                    timezone: "+0000".to_string(),
+                    tz_offset_minutes: Some(0),
+                }),
+            )
+            .await
+            .unwrap();
+        repo.create_tag(&AccessLevel::Write, "light", "main", None, None)
+            .await
+            .unwrap();
+
+        let txn = transaction(&repo);
+        txn.write_all_refs().await.unwrap();
+        let lines = sent_lines(&txn).await;
+        assert!(lines.contains(&format!("{} refs/tags/v1.0", annotated)));
+        assert!(lines.contains(&format!("{} refs/tags/v1.0^{{}}", commit)));
+        assert!(lines.contains(&format!("{} refs/tags/light", commit)));
+        let peeled = lines.iter().filter(|x| x.ends_with("^{}")).count();
+        assert_eq!(peeled, 1);
+
+        txn.write_all_refs_v2(true).await.unwrap();
+        let lines = sent_lines(&txn).await;
+        assert!(lines.contains(&format!("{} refs/tags/v1.0 peeled:{}", annotated, commit)));
+        assert!(lines.contains(&format!("{} refs/tags/light", commit)));
+    }
+
+    #[tokio::test]
+    async fn test_advertise_head_symref() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+
+        let txn = transaction(&repo);
+        txn.write_refs_head_info().await.unwrap();
+        let lines = sent_lines(&txn).await;
+        assert!(lines[0].starts_with(&format!("{} HEAD\0", commit)));
+        assert!(lines[0].contains("symref=HEAD:refs/heads/main"));
+
+        txn.write_refs_head_info_v2(true, false).await.unwrap();
+        let lines = sent_lines(&txn).await;
+        assert_eq!(
+            lines,
+            vec![format!("{} HEAD symref-target:refs/heads/main", commit)]
+        );
+        txn.write_refs_head_info_v2(false, false).await.unwrap();
+        assert_eq!(sent_lines(&txn).await, vec![format!("{} HEAD", commit)]);
+    }
+
+    #[tokio::test]
+    async fn test_unborn_head_has_no_symref() {
+        let repo = memory_repository();
+        let txn = transaction(&repo);
+        txn.write_refs_head_info().await.unwrap();
+        let lines = sent_lines(&txn).await;
+        assert!(!lines[0].contains("symref="));
+    }
+
+    #[tokio::test]
+    async fn test_symbolic_head_on_empty_repo() {
+        let mut repo = memory_repository();
+        repo.refs = Arc::new(Box::new(MemoryRefsManager {
+            symbolic_head: true,
+            ..MemoryRefsManager::new("trunk", HashVersion::Sha1)
+        }));
+        let txn = transaction(&repo);
+        txn.write_refs_head_info().await.unwrap();
+        let lines = sent_lines(&txn).await;
+        assert!(lines[0].starts_with(&format!("{} HEAD\0", HashVersion::Sha1.default())));
+        assert!(lines[0].contains("symref=HEAD:refs/heads/trunk"));
+
+        txn.write_refs_head_info_v2(true, true).await.unwrap();
+        assert_eq!(
+            sent_lines(&txn).await,
+            vec!["unborn HEAD symref-target:refs/heads/trunk"]
+        );
+        txn.write_refs_head_info_v2(true, false).await.unwrap();
+        assert!(sent_lines(&txn).await.is_empty());
+        assert_eq!(repo.dumb_head().await.unwrap(), "ref: refs/heads/trunk\n");
+        assert!(matches!(
+            repo.resolve_rev("HEAD").await,
+            Err(GitInnerError::RevisionNotFound(_))
+        ));
+
+        // 未开启时空仓库在 v2 中不列出 HEAD
+        let repo = memory_repository();
+        let txn = transaction(&repo);
+        txn.write_refs_head_info_v2(true, true).await.unwrap();
+        assert!(sent_lines(&txn).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_advertise_receive_refs_empty_repo() {
+        let repo = memory_repository();
+        let txn = receive_transaction(&repo);
+        txn.advertise_receive_refs().await.unwrap();
+        let lines = sent_lines(&txn).await;
+        assert_eq!(lines.len(), 2);
+        let (id, caps) = lines[0].split_once('\0').unwrap();
+        assert_eq!(
+            id,
+            format!("{} capabilities^{{}}", HashVersion::Sha1.default())
+        );
+        for cap in ["report-status", "side-band-64k", "atomic"] {
+            assert!(caps.split(' ').any(|x| x == cap), "missing {}", cap);
+        }
+        for cap in ["report-status-v2", "delete-refs"] {
+            assert!(!caps.split(' ').any(|x| x == cap), "unexpected {}", cap);
+        }
+        assert!(caps.contains("object-format=sha1"));
+        assert_eq!(lines[1], "");
+    }
+
+    #[tokio::test]
+    async fn test_advertise_receive_refs() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        for name in ["refs/heads/dev", "refs/heads/main"] {
+            repo.refs_insert(name.to_string(), commit.clone())
+                .await
+                .unwrap();
+        }
+
+        let txn = receive_transaction(&repo);
+        txn.advertise_receive_refs().await.unwrap();
+        let lines = sent_lines(&txn).await;
+        assert_eq!(lines.len(), 3);
+        let (first, caps) = lines[0].split_once('\0').unwrap();
+        assert!(first.starts_with(&commit.to_string()));
+        assert!(caps.contains("report-status"));
+        let second = &lines[1];
+        assert!(!second.contains('\0'));
+        let mut names = vec![first.to_string(), second.clone()];
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                format!("{} refs/heads/dev", commit),
+                format!("{} refs/heads/main", commit)
+            ]
+        );
+        assert_eq!(lines[2], "");
+    }
 }
@@ -0,0 +1,61 @@
+use crate::config::AppConfig;
+use crate::config::transaction::TransactionConfig;
+use flate2::Compression;
+
+/// Upper bounds on the packs a transaction accepts or builds and on the requests it reads.
+#[derive(Clone, Copy, Debug)]
+pub struct PackLimits {
+    /// Largest object count a pack header may declare.
+    pub max_objects: usize,
+    /// Largest total of decompressed object and delta data.
+    pub max_bytes: u64,
+    /// Largest decompressed size of any single pushed blob.
+    pub max_blob_bytes: u64,
+    /// Most objects upload-pack may visit while enumerating a pack.
+    pub max_walk_nodes: usize,
+    /// Most `ls-refs`/`fetch` commands one protocol v2 session may issue.
+    pub max_session_commands: usize,
+    /// Most request bytes one protocol v2 session may send.
+    pub max_session_bytes: u64,
+    /// Most bytes of commands and push options receive-pack reads before the pack.
+    pub max_command_bytes: usize,
+    /// zlib level upload-pack compresses pack entries with, 0–9.
+    pub compression_level: u32,
+}
+
+impl PackLimits {
+    /// The configured compression level as a zlib setting.
+    pub fn compression(&self) -> Compression {
+        Compression::new(self.compression_level.min(9))
+    }
+}
+
+impl Default for PackLimits {
+    fn default() -> Self {
+        (&TransactionConfig::default()).into()
+    }
+}
+
+impl From<&TransactionConfig> for PackLimits {
+    fn from(config: &TransactionConfig) -> Self {
+        Self {
+            max_objects: config.max_pack_objects,
+            max_bytes: config.max_pack_bytes,
+            max_blob_bytes: config.max_blob_bytes,
+            max_walk_nodes: config.max_walk_nodes,
+            max_session_commands: config.max_session_commands,
+            max_session_bytes: config.max_session_bytes,
+            max_command_bytes: config.max_command_bytes,
+            compression_level: Compression::default().level(),
+        }
+    }
+}
+
+impl From<&AppConfig> for PackLimits {
+    fn from(config: &AppConfig) -> Self {
+        Self {
+            compression_level: config.compression_level,
+            ..(&config.transaction).into()
+        }
+    }
+}
@@ -1,13 +1,26 @@
 pub mod advertise;
+pub mod limits;
+pub mod object_format;
+pub mod pkt_line;
 pub mod receive;
 pub mod service;
+pub mod timeout;
 pub mod upload;
 pub mod version;
 
 use crate::callback::CallBack;
+use crate::hooks::ReceiveHook;
 use crate::repository::Repository;
+use crate::serve::limit::TransactionLimiter;
+use crate::serve::maintenance::MaintenanceMode;
+use crate::serve::metrics::Metrics;
+use crate::transaction::limits::PackLimits;
 pub(crate) use crate::transaction::service::TransactionService;
+use crate::transaction::timeout::Timeouts;
+use crate::transaction::upload::resume::FetchResumeStore;
 pub(crate) use crate::transaction::version::GitProtoVersion;
+use std::sync::Arc;
+use tracing::{Span, info_span};
 
 #[derive(Clone)]
 pub struct Transaction {
@@ -16,11 +29,53 @@ pub struct Transaction {
     pub version: GitProtoVersion,
     pub call_back: CallBack,
     pub protocol: ProtocolType,
+    pub timeouts: Timeouts,
+    pub pack_limits: PackLimits,
+    /// Authenticated user driving this transaction, recorded in the reflog.
+    pub actor: Option<String>,
+    /// Server maintenance switch; receive-pack is refused while it is on.
+    pub maintenance: MaintenanceMode,
+    /// Server-wide counters this transaction records pushes, fetches and bytes into.
+    pub metrics: Metrics,
+    /// Server-wide negotiated fetches a follow-up fetch can resume.
+    pub fetch_resume: FetchResumeStore,
+    /// Server-wide receive hooks, run by receive-pack.
+    pub hooks: Option<Arc<Box<dyn ReceiveHook>>>,
+    /// Server-wide per-repository cap on concurrent pushes, taken by receive-pack.
+    pub push_limiter: Arc<TransactionLimiter>,
+    /// Refuse branch updates that are not fast-forwards, like git's
+    /// `receive.denyNonFastForwards`.
+    pub deny_non_fast_forwards: bool,
 }
 
-#[derive(Clone)]
+impl Transaction {
+    /// Span attributing every log of this transaction to its repository, transport and a
+    /// fresh request id. Entry points instrument the transaction future with it.
+    pub fn span(&self, namespace: &str, repo: &str) -> Span {
+        info_span!(
+            "git",
+            request_id = %uuid::Uuid::new_v4(),
+            namespace,
+            repo,
+            protocol = self.protocol.as_str(),
+            service = ?self.service,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum ProtocolType {
     Git,
     SSH,
     Http,
 }
+
+impl ProtocolType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProtocolType::Git => "git",
+            ProtocolType::SSH => "ssh",
+            ProtocolType::Http => "http",
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::capability::enums::GitCapability;
+use crate::error::GitInnerError;
+use crate::transaction::Transaction;
+use crate::write_pkt_line;
+use bytes::Bytes;
+
+impl Transaction {
+    /// Reject an `object-format` request that doesn't match the repository hash.
+    ///
+    /// The client is told why with an `ERR` packet before the error is returned, so it
+    /// never gets as far as sending hashes in the wrong format.
+    pub async fn check_object_format(&self, format: &str) -> Result<(), GitInnerError> {
+        let expected = self.repository.hash_version;
+        if format == expected.name() {
+            return Ok(());
+        }
+        let message = format!(
+            "object-format mismatch: client requested {}, repository uses {}",
+            format,
+            expected.name()
+        );
+        self.call_back
+            .send(Bytes::from(write_pkt_line(format!("ERR {}\n", message))))
+            .await?;
+        Err(GitInnerError::ObjectFormatMismatch(message))
+    }
+
+    /// Run [`Self::check_object_format`] over any `object-format` capability in a list.
+    /// Clients that don't send one are assumed to speak the repository format.
+    pub async fn check_capability_object_format(
+        &self,
+        capabilities: &[GitCapability],
+    ) -> Result<(), GitInnerError> {
+        for capability in capabilities {
+            match capability {
+                GitCapability::ObjectFormat(format) => {
+                    self.check_object_format(format.name()).await?
+                }
+                GitCapability::Unknown(s) => {
+                    if let Some(format) = s.strip_prefix("object-format=") {
+                        self.check_object_format(format).await?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capability::enums::parse_capability_list;
+    use crate::error::GitInnerError;
+    use crate::repository::testing::{memory_repository, transaction};
+    use crate::sha::HashVersion;
+
+    #[tokio::test]
+    async fn test_matching_object_format() {
+        let repo = memory_repository();
+        let txn = transaction(&repo);
+        let caps = parse_capability_list("report-status object-format=sha1 agent=git/2.45");
+        txn.check_capability_object_format(&caps).await.unwrap();
+        txn.check_capability_object_format(&parse_capability_list("report-status"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_object_format() {
+        let repo = memory_repository();
+        let txn = transaction(&repo);
+        for line in ["report-status object-format=sha256", "object-format=md5"] {
+            let caps = parse_capability_list(line);
+            assert!(matches!(
+                txn.check_capability_object_format(&caps).await,
+                Err(GitInnerError::ObjectFormatMismatch(_))
+            ));
+            let packet = txn.call_back.receive.lock().await.recv().await.unwrap();
+            assert!(packet[4..].starts_with(b"ERR object-format mismatch"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_advertise_object_format() {
+        let mut repo = memory_repository();
+        repo.hash_version = HashVersion::Sha256;
+        let txn = transaction(&repo);
+        txn.write_refs_head_info().await.unwrap();
+        let packet = txn.call_back.receive.lock().await.recv().await.unwrap();
+        let line = String::from_utf8_lossy(&packet).to_string();
+        assert!(line.contains("object-format=sha256"));
+    }
+}
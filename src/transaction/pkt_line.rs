@@ -0,0 +1,197 @@
+use crate::error::GitInnerError;
+use bytes::Bytes;
+
+/// Largest pkt-line git allows, length header included.
+pub const MAX_PKT_LEN: usize = 65520;
+
+/// One packet of a pkt-line stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PktLine {
+    /// `0000`
+    Flush,
+    /// `0001`, the protocol v2 section delimiter.
+    Delim,
+    /// `0002`, the protocol v2 end of a stateless response.
+    ResponseEnd,
+    /// The payload of a data packet, without its length header.
+    Data(Bytes),
+}
+
+/// Decode the packet at the start of `input`, returning it and how many bytes it took, or
+/// `None` when `input` ends before the packet does. A length header that is not four hex
+/// digits, is 3 or is over [`MAX_PKT_LEN`] is [`GitInnerError::InvalidData`].
+pub fn parse_pkt_line(input: &[u8]) -> Result<Option<(PktLine, usize)>, GitInnerError> {
+    let Some(head) = input.get(..4) else {
+        return Ok(None);
+    };
+    // from_str_radix 会接受 "+fff" 这样的前缀，这里只认十六进制数字
+    if !head.iter().all(u8::is_ascii_hexdigit) {
+        return Err(GitInnerError::InvalidData);
+    }
+    let len = head.iter().fold(0usize, |len, x| {
+        len * 16 + (*x as char).to_digit(16).unwrap_or(0) as usize
+    });
+    let line = match len {
+        0 => PktLine::Flush,
+        1 => PktLine::Delim,
+        2 => PktLine::ResponseEnd,
+        3 => return Err(GitInnerError::InvalidData),
+        len if len > MAX_PKT_LEN => return Err(GitInnerError::InvalidData),
+        len => match input.get(4..len) {
+            Some(payload) => {
+                return Ok(Some((PktLine::Data(Bytes::copy_from_slice(payload)), len)));
+            }
+            None => return Ok(None),
+        },
+    };
+    Ok(Some((line, 4)))
+}
+
+/// Decode a complete pkt-line stream. Never panics: a malformed header is
+/// [`GitInnerError::InvalidData`] and a stream cut off inside a packet
+/// [`GitInnerError::UnexpectedEof`].
+pub fn parse_all_pkt_lines(input: &[u8]) -> Result<Vec<PktLine>, GitInnerError> {
+    let mut lines = vec![];
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (line, len) = parse_pkt_line(rest)?.ok_or(GitInnerError::UnexpectedEof)?;
+        lines.push(line);
+        rest = &rest[len..];
+    }
+    Ok(lines)
+}
+
+/// Fuzzing entry point: run arbitrary bytes through the pkt-line decoder and every
+/// negotiation parser that consumes its output. Errors are expected; only a panic is a bug.
+/// Build with `RUSTFLAGS="--cfg fuzz"` to expose it to a `cargo-fuzz` target.
+#[cfg(any(test, fuzz))]
+pub fn fuzz_negotiation(data: &[u8]) {
+    use crate::capability::enums::parse_capability_list;
+    use crate::sha::HashVersion;
+    use crate::transaction::receive::command::ReceiveCommand;
+    use crate::transaction::upload::command::UploadCommandType;
+    use crate::transaction::version::{GitProtoVersion, first_pkt_line};
+
+    let _ = first_pkt_line(data);
+    let _ = GitProtoVersion::detect(data);
+    let _ = ReceiveCommand::from_pkt_line(data);
+    let Ok(lines) = parse_all_pkt_lines(data) else {
+        return;
+    };
+    for line in lines {
+        let PktLine::Data(payload) = line else {
+            continue;
+        };
+        let Ok(text) = std::str::from_utf8(&payload) else {
+            continue;
+        };
+        for hash_version in [HashVersion::Sha1, HashVersion::Sha256] {
+            let _ = UploadCommandType::from_one_line(text, hash_version);
+        }
+        if let Some((_, capabilities)) = text.split_once('\0') {
+            let _ = parse_capability_list(capabilities);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_all_pkt_lines() {
+        let input = b"000ahello\n00010000000ax\0y z\n0002";
+        assert_eq!(
+            parse_all_pkt_lines(input).unwrap(),
+            vec![
+                PktLine::Data(Bytes::from_static(b"hello\n")),
+                PktLine::Delim,
+                PktLine::Flush,
+                PktLine::Data(Bytes::from_static(b"x\0y z\n")),
+                PktLine::ResponseEnd,
+            ]
+        );
+        assert_eq!(
+            parse_all_pkt_lines(b"0004").unwrap(),
+            vec![PktLine::Data(Bytes::new())]
+        );
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        for input in [&b"0"[..], b"000", b"000ahel", b"0009abcd", b"0000000"] {
+            assert!(
+                matches!(
+                    parse_all_pkt_lines(input),
+                    Err(GitInnerError::UnexpectedEof)
+                ),
+                "{:?}",
+                input
+            );
+        }
+        assert!(parse_pkt_line(b"000ahel").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_garbage_input() {
+        for input in [
+            &b"zzzz"[..],
+            b"0003",
+            b"+fff",
+            b"-001",
+            b" 00a",
+            b"\xff\xfe\x00\x01",
+            b"fff1",
+            b"ffff",
+            b"0000xyz!",
+        ] {
+            assert!(
+                matches!(parse_all_pkt_lines(input), Err(GitInnerError::InvalidData)),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_fuzz_negotiation_never_panics() {
+        let mut corpus: Vec<Vec<u8>> = vec![
+            b"0032want 0123456789abcdef0123456789abcdef01234567\n".to_vec(),
+            b"0003".to_vec(),
+            b"0002".to_vec(),
+            b"0001".to_vec(),
+            b"0009\0\0\0\0\0".to_vec(),
+            b"0014command=ls-refs\n0001000cpeel\n0000".to_vec(),
+            b"000fwant \0 side\n".to_vec(),
+            b"0010have zzzzzzz\n".to_vec(),
+            b"0011deepen -1\n".to_vec(),
+            b"0018deepen-since abc\n".to_vec(),
+            b"00080 0 \n".to_vec(),
+            b"0014shallow \xff\xfe\n".to_vec(),
+        ];
+        // 确定性的伪随机输入：一半以合法长度开头，一半完全随机
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for i in 0..2000 {
+            let len = (next() % 64) as usize;
+            let mut input = (0..len).map(|_| next() as u8).collect::<Vec<_>>();
+            if i % 2 == 0 {
+                let mut framed = format!("{:04x}", len + 4).into_bytes();
+                framed.append(&mut input);
+                input = framed;
+            }
+            corpus.push(input);
+        }
+        for input in corpus {
+            fuzz_negotiation(&input);
+            for cut in 0..input.len() {
+                fuzz_negotiation(&input[..cut]);
+            }
+        }
+    }
+}
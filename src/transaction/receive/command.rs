@@ -29,15 +29,21 @@ impl ReceiveCommand {
         let _len = u32::from_str_radix(len_str, 16).map_err(|_| {
             GitInnerError::ConversionError("Invalid pkt-line length format".to_string())
         })?;
-        if _len == 0 {
+        // 0000 flush、0001 delim、0002 response-end 都不是命令
+        if _len <= 2 {
             return Ok(None);
         }
+        if _len < 4 {
+            return Err(GitInnerError::InvalidData);
+        }
         if line.len() < _len as usize {
             return Ok(None);
         }
 
         let line_str = std::str::from_utf8(&line[4.._len as usize])
             .map_err(|_| GitInnerError::ConversionError("Invalid UTF-8 in pkt-line".to_string()))?;
+        // 第一条命令在 NUL 之后携带能力列表，不属于 ref 名
+        let line_str = line_str.split('\0').next().unwrap_or_default();
         let parts: Vec<&str> = line_str.trim().split(' ').collect();
 
         if parts.len() < 3 {
@@ -69,13 +75,14 @@ impl ReceiveCommand {
         Ok(Some(ReceiveCommand {
             old: old_hash,
             new: new_hash,
-            ref_name: ref_name.to_string().replace("\0", ""),
+            ref_name: ref_name.to_string(),
         }))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::error::GitInnerError;
     use crate::transaction::receive::command::ReceiveCommand;
     #[test]
     fn test_from_pkt_line_create_command() {
@@ -102,6 +109,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_pkt_line_strips_capabilities() {
+        let pkt_line = b"00810000000000000000000000000000000000000000 cdfdb42577e2506715f8cfeacdbabc092bf63e8d refs/heads/main\0report-status side-band-64k";
+        let command = ReceiveCommand::from_pkt_line(pkt_line).unwrap().unwrap();
+        assert_eq!(command.ref_name, "refs/heads/main");
+    }
+
     #[test]
     fn test_from_pkt_line_update_command() {
         let pkt_line = b"0067ca82a6dff817ec66f44342007202690a93763949 15027957951b64cf874c3557a0f3547bd83b3ff6 refs/heads/master";
@@ -161,6 +175,15 @@ mod tests {
         assert!(result.unwrap().is_none());
     }
 
+    #[test]
+    fn test_from_pkt_line_short_length() {
+        assert!(ReceiveCommand::from_pkt_line(b"0001").unwrap().is_none());
+        assert!(matches!(
+            ReceiveCommand::from_pkt_line(b"0003"),
+            Err(GitInnerError::InvalidData)
+        ));
+    }
+
     #[test]
     fn test_from_pkt_line() {
         let invalid_pkt = b"00a50000000000000000000000000000000000000000 56d999ae43df4c597dc240b39a77f64a5d8efbb4 refs/heads/main";
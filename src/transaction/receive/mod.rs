@@ -1,56 +1,129 @@
-use crate::capability::enums::GitCapability;
+use crate::capability::enums::{GitCapability, parse_capability_list};
 use crate::error::GitInnerError;
 use crate::odb::OdbTransaction;
+use crate::odb::guard::TransactionGuard;
+use crate::serve::maintenance::MAINTENANCE_MESSAGE;
 use crate::transaction::Transaction;
+use crate::transaction::pkt_line::{PktLine, parse_pkt_line};
 use crate::transaction::receive::command::ReceiveCommand;
-use crate::transaction::version::GitProtoVersion;
+use crate::transaction::timeout::TimeoutStream;
+use crate::transaction::version::{GitProtoVersion, first_pkt_line};
+use crate::write_pkt_line;
 use bstr::ByteSlice;
 use bytes::{Bytes, BytesMut};
 use futures_util::StreamExt;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio_stream::Stream;
-use tracing::log::warn;
+use tracing::warn;
 
 pub mod command;
 pub mod parse_objects;
 pub mod parse_receive_object;
+pub mod ref_update;
 pub mod zlib_decode;
 
+/// The client's side of a push as receive-pack reads it. It is `Send` so a push can run on
+/// any runtime worker.
+pub type ReceiveStream = Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>> + Send>>;
+
 #[derive(Clone)]
 pub struct ReceivePackTransaction {
     pub transaction: Transaction,
     pub ref_upload: Vec<ReceiveCommand>,
     pub capabilities: Vec<GitCapability>,
+    /// `git push -o` values, passed on to the receive hooks.
+    pub push_options: Vec<String>,
     pub version: GitProtoVersion,
     pub pack_size: usize,
 }
 
 impl Transaction {
-    pub async fn receive_pack(
+    pub async fn receive_pack(&mut self, stream: ReceiveStream) -> Result<(), GitInnerError> {
+        if self.maintenance.is_enabled() {
+            self.call_back
+                .send(Bytes::from(write_pkt_line(format!(
+                    "ERR {}\n",
+                    MAINTENANCE_MESSAGE
+                ))))
+                .await?;
+            return Err(GitInnerError::ReadOnly);
+        }
+        let _permit = self.push_limiter.acquire(self.repository.id).await;
+        // 提前返回时守卫在析构时回滚，不会遗留会话和暂存对象
+        let txn: Arc<Box<dyn OdbTransaction>> = Arc::new(Box::new(TransactionGuard::new(
+            self.repository.odb.begin_transaction().await?,
+        )));
+        let stream = Box::pin(TimeoutStream::new(stream, self.timeouts));
+        let result = self.receive_pack_with(stream, txn.clone()).await;
+        if result.is_err()
+            && let Err(err) = txn.abort().await
+        {
+            warn!("abort receive pack transaction failed: {:?}", err);
+        }
+        if result.is_ok() {
+            self.metrics.record_push();
+        }
+        result
+    }
+
+    async fn receive_pack_with(
         &mut self,
-        mut stream: Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>>>>,
+        mut stream: ReceiveStream,
+        txn: Arc<Box<dyn OdbTransaction>>,
     ) -> Result<(), GitInnerError> {
         let mut head = BytesMut::new();
-        let txn = self.repository.odb.begin_transaction().await?;
-        while let Some(pack) = stream.next().await {
-            let pack = pack?;
-            if pack == "0000" {
-                tokio::task::yield_now().await;
-                continue;
+        // 按 pkt-line 解析命令，pack 从最后一个 flush 之后的第一个字节开始；
+        // 声明了 push-options 时，命令之后还有一段以 flush 结束的 push-option
+        let mut parsed = 0usize;
+        let mut sections = 1;
+        let mut flushes = 0;
+        loop {
+            while flushes < sections
+                && let Some((line, len)) = parse_pkt_line(&head[parsed..])?
+            {
+                match line {
+                    PktLine::Flush => flushes += 1,
+                    PktLine::Data(payload) if parsed == 0 => {
+                        if let Some(idx) = payload.find_byte(0)
+                            && parse_capability_list(&String::from_utf8_lossy(&payload[idx + 1..]))
+                                .contains(&GitCapability::PushOptions)
+                        {
+                            sections = 2;
+                        }
+                    }
+                    _ => {}
+                }
+                parsed += len;
             }
-            if let Some(idx) = pack.find(b"PACK") {
-                head.extend_from_slice(&pack[..idx]);
-                let input =
-                    tokio_stream::iter(vec![Ok(Bytes::from(pack[idx..].to_vec()))]).chain(stream);
-                stream = Box::pin(input);
+            if flushes == sections {
                 break;
-            } else {
-                head.extend_from_slice(&pack);
             }
+            if head.len() > self.pack_limits.max_command_bytes {
+                return Err(GitInnerError::CommandsTooLarge);
+            }
+            match stream.next().await {
+                Some(chunk) => head.extend_from_slice(&chunk?),
+                None => break,
+            }
+        }
+        if parsed > self.pack_limits.max_command_bytes {
+            return Err(GitInnerError::CommandsTooLarge);
         }
+        let rest = head.split_off(parsed);
+        if !rest.is_empty() {
+            stream = Box::pin(tokio_stream::iter(vec![Ok(rest.freeze())]).chain(stream));
+        }
+        let push_options = parse_push_options(&head);
         let (refs, caps) = self.parse_receive_request(head).await?;
-        self.parse_receive_head(refs, caps, stream, txn).await?;
+        self.check_capability_object_format(&caps).await?;
+        let push_options = if caps.contains(&GitCapability::PushOptions) {
+            push_options
+        } else {
+            vec![]
+        };
+        self.parse_receive_head(refs, caps, push_options, stream, txn)
+            .await?;
         Ok(())
     }
     pub async fn parse_receive_request(
@@ -59,25 +132,19 @@ impl Transaction {
     ) -> Result<(Vec<ReceiveCommand>, Vec<GitCapability>), GitInnerError> {
         let mut refs = vec![];
         let mut capabilities = vec![];
-        for line in head.lines() {
-            let str = line
-                .to_str()
-                .map_err(|_| GitInnerError::InvalidUtf8)?
-                .to_string();
-            if let Some(idx) = str.find("\0") {
-                if let Ok(Some(pkt_line)) = ReceiveCommand::from_pkt_line(&str.as_bytes()) {
-                    refs.push(pkt_line);
-                }
-                let caps = str[idx + 1..]
-                    .split(' ')
-                    .map(|s| GitCapability::from_str(s))
-                    .collect::<Vec<_>>();
-                capabilities = caps;
-            } else {
-                if let Ok(Some(pkt_line)) = ReceiveCommand::from_pkt_line(&str.as_bytes()) {
-                    refs.push(pkt_line);
-                }
+        // 按 pkt-line 切分：命令行不一定以换行结尾，flush 之后是 push-option
+        let mut rest = &head[..];
+        while let Some((PktLine::Data(line), len)) = first_pkt_line(rest) {
+            if let Some(idx) = line.find_byte(0) {
+                let caps = line[idx + 1..]
+                    .to_str()
+                    .map_err(|_| GitInnerError::InvalidUtf8)?;
+                capabilities = parse_capability_list(caps);
+            }
+            if let Ok(Some(command)) = ReceiveCommand::from_pkt_line(&rest[..len]) {
+                refs.push(command);
             }
+            rest = &rest[len..];
         }
         Ok((refs, capabilities))
     }
@@ -86,11 +153,13 @@ impl Transaction {
         &mut self,
         refs: Vec<ReceiveCommand>,
         caps: Vec<GitCapability>,
-        mut stream: Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>>>>,
-        txn: Box<dyn OdbTransaction>,
+        push_options: Vec<String>,
+        mut stream: ReceiveStream,
+        txn: Arc<Box<dyn OdbTransaction>>,
     ) -> Result<(), GitInnerError> {
         let mut head = BytesMut::with_capacity(12);
         let mut remaining = 12;
+        // 流结束后重试；stream 已包装为 TimeoutStream，过了整体期限会返回 Timeout
         let mut retry = 12;
         while remaining > 0 {
             if let Some(next) = stream.next().await {
@@ -114,7 +183,8 @@ impl Transaction {
                 }
             }
         }
-        if head.len() != 12 {
+        // 命令之后必须紧接着 pack
+        if head.len() != 12 || &head[..4] != b"PACK" {
             return Err(GitInnerError::InvalidData);
         }
         let version = (head[4] as usize) << 24
@@ -129,19 +199,748 @@ impl Transaction {
             transaction: self.clone(),
             ref_upload: refs,
             capabilities: caps,
+            push_options,
             version: GitProtoVersion::from_u32(version as u32),
             pack_size,
         };
         match receive_pack_request.version {
-            GitProtoVersion::V0 | GitProtoVersion::V1 | GitProtoVersion::V2 => {
-                receive_pack_request
-                    .process_receive_pack(stream, Arc::from(txn))
-                    .await?;
+            // v0/v1 和 v2 的 receive-pack 目前没有区别，v2 有差异时在这里分开
+            GitProtoVersion::V0 | GitProtoVersion::V1 => {
+                receive_pack_request.receive(stream, txn).await?;
+            }
+            GitProtoVersion::V2 => {
+                receive_pack_request.receive(stream, txn).await?;
             }
             GitProtoVersion::Unknown => {
-                dbg!();
+                // 调用方 receive_pack 收到错误后会回滚 ODB 事务
+                return Err(GitInnerError::UnsupportedProtocolVersion(version as u32));
             }
         }
         Ok(())
     }
 }
+
+/// The push-option section of a receive-pack request: the pkt-lines between the flush that
+/// ends the command list and the next flush.
+fn parse_push_options(head: &[u8]) -> Vec<String> {
+    let mut options = vec![];
+    let mut rest = head;
+    let mut section = 0;
+    while section < 2
+        && let Some((pkt, len)) = first_pkt_line(rest)
+    {
+        rest = &rest[len..];
+        match pkt {
+            PktLine::Flush => section += 1,
+            PktLine::Data(line) if section == 1 => {
+                options.push(String::from_utf8_lossy(&line).trim_end().to_string());
+            }
+            _ => {}
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::GitInnerError;
+    use crate::hooks::{PushEvent, ReceiveHook};
+    use crate::objects::blob::Blob;
+    use crate::objects::ref_delta::RefDelta;
+    use crate::objects::tag::Tag;
+    use crate::objects::tree::TreeItemMode;
+    use crate::refs::memory::MemoryRefsManager;
+    use crate::repository::Repository;
+    use crate::repository::testing::{
+        memory_repository, memory_repository_with_odb, output, pack, put_blob, put_commit,
+        put_tree, receive_transaction,
+    };
+    use crate::sha::HashValue;
+    use crate::sha::HashVersion;
+    use crate::transaction::Transaction;
+    use crate::transaction::limits::PackLimits;
+    use crate::transaction::timeout::Timeouts;
+    use crate::transaction::upload::recursion::Object;
+    use crate::write_pkt_line;
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+    use tracing::Instrument;
+
+    #[tokio::test]
+    async fn test_pack_signature_split_across_chunks() {
+        let repo = memory_repository();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status",
+            HashVersion::Sha1.default(),
+            blob.id
+        ));
+        body.extend_from_slice(b"0000");
+        let split = body.len() + 2;
+        body.extend_from_slice(&pack(&[Object::Blob(blob.clone())]));
+        let body = body.freeze();
+        assert_eq!(&body[split - 2..split + 2], b"PACK");
+
+        let chunks: Vec<Result<Bytes, GitInnerError>> =
+            vec![Ok(body.slice(..split)), Ok(body.slice(split..))];
+        let mut txn = receive_transaction(&repo);
+        txn.receive_pack(Box::pin(tokio_stream::iter(chunks)))
+            .await
+            .unwrap();
+        assert!(repo.odb.has_blob(&blob.id).await.unwrap());
+        assert_eq!(
+            repo.refs
+                .get_value_refs("refs/heads/main".to_string())
+                .await
+                .unwrap(),
+            blob.id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ref_name_containing_pack() {
+        let repo = memory_repository();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/PACKAGE\0report-status",
+            HashVersion::Sha1.default(),
+            blob.id
+        ));
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&pack(&[Object::Blob(blob.clone())]));
+        // 每个字节单独到达，命令只能按 pkt-line 解析
+        let chunks = body
+            .freeze()
+            .iter()
+            .map(|x| Ok(Bytes::copy_from_slice(&[*x])))
+            .collect::<Vec<Result<Bytes, GitInnerError>>>();
+        let mut txn = receive_transaction(&repo);
+        txn.receive_pack(Box::pin(tokio_stream::iter(chunks)))
+            .await
+            .unwrap();
+        assert!(output(&txn).await.contains("ok refs/heads/PACKAGE"));
+        assert!(repo.odb.has_blob(&blob.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_command_bytes_limit() {
+        let repo = memory_repository();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let mut txn = receive_transaction(&repo);
+        txn.pack_limits.max_command_bytes = 64;
+        // 没有 flush 的命令流不会被无限缓存
+        let line = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status",
+            HashVersion::Sha1.default(),
+            blob.id
+        ))
+        .freeze();
+        let chunks = vec![Ok(line.clone()); 4];
+        let result = txn.receive_pack(Box::pin(tokio_stream::iter(chunks))).await;
+        assert!(matches!(result, Err(GitInnerError::CommandsTooLarge)));
+    }
+
+    #[tokio::test]
+    async fn test_receive_rejected_in_maintenance_mode() {
+        let (repo, odb) = memory_repository_with_odb();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let mut txn = receive_transaction(&repo);
+        txn.maintenance.set(true);
+        let result = txn
+            .receive_pack(Box::pin(tokio_stream::iter(vec![Ok(push_body(&[
+                blob.clone()
+            ]))])))
+            .await;
+        assert!(matches!(result, Err(GitInnerError::ReadOnly)));
+        assert!(
+            output(&txn)
+                .await
+                .contains("ERR repository is in read-only maintenance mode")
+        );
+        assert!(!repo.odb.has_blob(&blob.id).await.unwrap());
+        assert!(
+            !repo
+                .refs
+                .exists_refs("refs/heads/main".to_string())
+                .await
+                .unwrap()
+        );
+        assert_eq!(odb.aborts.load(Ordering::Relaxed), 0);
+    }
+
+    fn push_body(blobs: &[Blob]) -> Bytes {
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status",
+            HashVersion::Sha1.default(),
+            blobs[0].id
+        ));
+        body.extend_from_slice(b"0000");
+        let objects = blobs.iter().cloned().map(Object::Blob).collect::<Vec<_>>();
+        body.extend_from_slice(&pack(&objects));
+        body.freeze()
+    }
+
+    async fn push_with_limits(
+        repo: &Repository,
+        body: Bytes,
+        limits: PackLimits,
+    ) -> Result<(), GitInnerError> {
+        let mut txn = receive_transaction(repo);
+        txn.pack_limits = limits;
+        txn.receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body)])))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_pack_object_limit() {
+        let repo = memory_repository();
+        let blobs = [
+            Blob::parse(Bytes::from("one\n"), HashVersion::Sha1),
+            Blob::parse(Bytes::from("two\n"), HashVersion::Sha1),
+        ];
+        let limits = PackLimits {
+            max_objects: 1,
+            ..Default::default()
+        };
+        let result = push_with_limits(&repo, push_body(&blobs), limits).await;
+        assert!(matches!(result, Err(GitInnerError::PackTooLarge)));
+        assert!(!repo.odb.has_blob(&blobs[0].id).await.unwrap());
+
+        let limits = PackLimits {
+            max_objects: 2,
+            ..Default::default()
+        };
+        push_with_limits(&repo, push_body(&blobs), limits)
+            .await
+            .unwrap();
+        assert!(repo.odb.has_blob(&blobs[1].id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_pack_byte_limit() {
+        let repo = memory_repository();
+        let blobs = [
+            Blob::parse(Bytes::from("one\n"), HashVersion::Sha1),
+            Blob::parse(Bytes::from("two\n"), HashVersion::Sha1),
+        ];
+        let limits = PackLimits {
+            max_bytes: 6,
+            ..Default::default()
+        };
+        let result = push_with_limits(&repo, push_body(&blobs), limits).await;
+        assert!(matches!(result, Err(GitInnerError::PackTooLarge)));
+        assert!(!repo.odb.has_blob(&blobs[0].id).await.unwrap());
+
+        let limits = PackLimits {
+            max_bytes: 8,
+            ..Default::default()
+        };
+        push_with_limits(&repo, push_body(&blobs), limits)
+            .await
+            .unwrap();
+        assert!(repo.odb.has_blob(&blobs[1].id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_ref_delta_against_committed_base() {
+        let repo = memory_repository();
+        let base = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let txn = repo.odb.begin_transaction().await.unwrap();
+        txn.put_blob(base.clone()).await.unwrap();
+        txn.commit().await.unwrap();
+
+        // 复制 base 的 6 个字节，再追加 "world\n"
+        let mut delta = vec![6, 12, 0x90, 6, 6];
+        delta.extend_from_slice(b"world\n");
+        let target = Blob::parse(Bytes::from("hello\nworld\n"), HashVersion::Sha1);
+        let delta = RefDelta {
+            id: target.id.clone(),
+            base_sha: base.id.clone(),
+            delta_data: Bytes::from(delta),
+        };
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status",
+            HashVersion::Sha1.default(),
+            target.id
+        ));
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&pack(&[Object::RefDelta(delta)]));
+
+        let mut txn = receive_transaction(&repo);
+        txn.receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body.freeze())])))
+            .await
+            .unwrap();
+        assert_eq!(
+            repo.odb.get_blob(&target.id).await.unwrap().data,
+            target.data
+        );
+    }
+
+    /// `main` at c2 on top of c1, plus `next` (child of c2) and `rewrite` (child of c1).
+    async fn branch_history(repo: &Repository) -> [HashValue; 4] {
+        let blob = put_blob(repo, "hello").await;
+        let tree = put_tree(repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let c1 = put_commit(repo, &tree, &[], "c1", 1).await;
+        let c2 = put_commit(repo, &tree, &[c1.clone()], "c2", 2).await;
+        let next = put_commit(repo, &tree, &[c2.clone()], "next", 3).await;
+        let rewrite = put_commit(repo, &tree, &[c1.clone()], "rewrite", 4).await;
+        repo.refs_insert("refs/heads/main".to_string(), c2.clone())
+            .await
+            .unwrap();
+        [c1, c2, next, rewrite]
+    }
+
+    async fn push_update(
+        repo: &Repository,
+        old: &HashValue,
+        new: &HashValue,
+        ref_name: &str,
+    ) -> String {
+        push_update_with(receive_transaction(repo), old, new, ref_name).await
+    }
+
+    /// Like [`push_update`], on a server that refuses non-fast-forward branch updates.
+    async fn push_update_denying_non_ff(
+        repo: &Repository,
+        old: &HashValue,
+        new: &HashValue,
+        ref_name: &str,
+    ) -> String {
+        let txn = Transaction {
+            deny_non_fast_forwards: true,
+            ..receive_transaction(repo)
+        };
+        push_update_with(txn, old, new, ref_name).await
+    }
+
+    async fn push_update_with(
+        mut txn: Transaction,
+        old: &HashValue,
+        new: &HashValue,
+        ref_name: &str,
+    ) -> String {
+        let mut body = write_pkt_line(format!("{} {} {}\0report-status", old, new, ref_name));
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&pack(&[]));
+        txn.receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body.freeze())])))
+            .await
+            .unwrap();
+        output(&txn).await
+    }
+
+    async fn main_value(repo: &Repository) -> HashValue {
+        repo.refs
+            .get_value_refs("refs/heads/main".to_string())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fast_forward_accepted() {
+        let repo = memory_repository();
+        let [_, c2, next, _] = branch_history(&repo).await;
+        let report = push_update(&repo, &c2, &next, "refs/heads/main").await;
+        assert!(report.contains("ok refs/heads/main"));
+        assert_eq!(main_value(&repo).await, next);
+    }
+
+    #[tokio::test]
+    async fn test_non_fast_forward_rejected() {
+        let repo = memory_repository();
+        let [_, c2, _, rewrite] = branch_history(&repo).await;
+        let report = push_update_denying_non_ff(&repo, &c2, &rewrite, "refs/heads/main").await;
+        assert!(report.contains("ng refs/heads/main non-fast-forward"));
+        assert_eq!(main_value(&repo).await, c2);
+    }
+
+    #[tokio::test]
+    async fn test_forced_update_accepted() {
+        // 默认不拒绝非快进更新，git push --force 可以改写分支
+        let repo = memory_repository();
+        let [_, c2, next, rewrite] = branch_history(&repo).await;
+        let report = push_update(&repo, &c2, &rewrite, "refs/heads/main").await;
+        assert!(report.contains("ok refs/heads/main"));
+        assert_eq!(main_value(&repo).await, rewrite);
+
+        // 开启后同样的更新被拒绝，快进仍然允许
+        repo.refs_update("refs/heads/main".to_string(), c2.clone())
+            .await
+            .unwrap();
+        let report = push_update_denying_non_ff(&repo, &c2, &rewrite, "refs/heads/main").await;
+        assert!(report.contains("ng refs/heads/main non-fast-forward"));
+        let report = push_update_denying_non_ff(&repo, &c2, &next, "refs/heads/main").await;
+        assert!(report.contains("ok refs/heads/main"));
+        assert_eq!(main_value(&repo).await, next);
+    }
+
+    #[tokio::test]
+    async fn test_annotated_tag_ref_update() {
+        let repo = memory_repository();
+        let [c1, c2, _, _] = branch_history(&repo).await;
+        let tag = |target: &HashValue, name: &str| {
+            let data = format!(
+                "object {}\ntype commit\ntag v1\ntagger A <a@example.com> 0 +0000\n\n{}\n",
+                target, name
+            );
+            Tag::parse(Bytes::from(data), HashVersion::Sha1).unwrap()
+        };
+        let (old, new) = (tag(&c1, "first"), tag(&c2, "second"));
+        let old = repo.odb.put_tag(&old).await.unwrap();
+        let new = repo.odb.put_tag(&new).await.unwrap();
+        repo.refs_insert("refs/tags/v1".to_string(), old.clone())
+            .await
+            .unwrap();
+
+        let report = push_update_denying_non_ff(&repo, &old, &new, "refs/tags/v1").await;
+        assert!(report.contains("ok refs/tags/v1"));
+        // 分支指向非提交对象时不做祖先检查，而是报告 ng
+        repo.refs_insert("refs/heads/tagged".to_string(), old.clone())
+            .await
+            .unwrap();
+        let report = push_update_denying_non_ff(&repo, &old, &new, "refs/heads/tagged").await;
+        assert!(report.contains("ng refs/heads/tagged bad ref"));
+    }
+
+    #[tokio::test]
+    async fn test_stale_old_rejected() {
+        let repo = memory_repository();
+        let [c1, c2, next, _] = branch_history(&repo).await;
+        // 客户端以为 main 还在 c1
+        let report = push_update(&repo, &c1, &next, "refs/heads/main").await;
+        assert!(report.contains("ng refs/heads/main stale info"));
+        assert_eq!(main_value(&repo).await, c2);
+
+        let report = push_update(&repo, &c1, &next, "refs/heads/missing").await;
+        assert!(report.contains("ng refs/heads/missing stale info"));
+    }
+
+    #[tokio::test]
+    async fn test_create_existing_ref_rejected() {
+        let repo = memory_repository();
+        let [_, c2, next, _] = branch_history(&repo).await;
+        let zero = HashVersion::Sha1.default();
+        let report = push_update(&repo, &zero, &next, "refs/heads/main").await;
+        assert!(report.contains("ng refs/heads/main already exists"));
+        assert_eq!(main_value(&repo).await, c2);
+        assert_eq!(repo.refs.refs().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_ref_write_reported_as_ng() {
+        let mut repo = memory_repository();
+        let refs = MemoryRefsManager::new("main", HashVersion::Sha1);
+        repo.refs = Arc::new(Box::new(refs.clone()));
+        let [_, c2, next, _] = branch_history(&repo).await;
+        refs.fail_writes.store(true, Ordering::SeqCst);
+        let zero = HashVersion::Sha1.default();
+
+        let report = push_update(&repo, &zero, &next, "refs/heads/feature").await;
+        assert!(report.contains("ng refs/heads/feature refs storage unavailable"));
+        let report = push_update(&repo, &c2, &next, "refs/heads/main").await;
+        assert!(report.contains("ng refs/heads/main refs storage unavailable"));
+        assert_eq!(main_value(&repo).await, c2);
+    }
+
+    #[tokio::test]
+    async fn test_delete_reported_as_ng() {
+        let repo = memory_repository();
+        let [_, c2, _, _] = branch_history(&repo).await;
+        let zero = HashVersion::Sha1.default();
+        let report = push_update(&repo, &c2, &zero, "refs/heads/main").await;
+        assert!(report.contains("ng refs/heads/main deleting refs is not supported"));
+        assert_eq!(main_value(&repo).await, c2);
+    }
+
+    #[tokio::test]
+    async fn test_reflog_records_pushes_in_order() {
+        let repo = memory_repository();
+        let [_, c2, next, _] = branch_history(&repo).await;
+        let zero = HashVersion::Sha1.default();
+        let report = push_update(&repo, &zero, &c2, "refs/heads/dev").await;
+        assert!(report.contains("ok refs/heads/dev"));
+        let report = push_update(&repo, &c2, &next, "refs/heads/dev").await;
+        assert!(report.contains("ok refs/heads/dev"));
+        // 被拒绝的更新不写入审计日志
+        push_update(&repo, &c2, &next, "refs/heads/dev").await;
+
+        let reflog = repo.reflog("refs/heads/dev".to_string()).await.unwrap();
+        let changes = reflog
+            .iter()
+            .map(|x| (x.old.clone(), x.new.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec![(zero, c2.clone()), (c2, next)]);
+        assert!(reflog.iter().all(|x| x.timestamp > 0 && x.actor.is_none()));
+        assert!(
+            repo.reflog("refs/heads/main".to_string())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stalled_push_times_out_and_aborts() {
+        let (repo, odb) = memory_repository_with_odb();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status",
+            HashVersion::Sha1.default(),
+            blob.id
+        ));
+        body.extend_from_slice(b"0000");
+        let pack = pack(&[Object::Blob(blob.clone())]);
+        // 对象只发送了一半，之后客户端不再发送数据
+        body.extend_from_slice(&pack[..14]);
+
+        let stream =
+            tokio_stream::iter(vec![Ok(body.freeze())]).chain(futures_util::stream::pending());
+        let mut txn = receive_transaction(&repo);
+        txn.timeouts = Timeouts {
+            read: Duration::from_millis(50),
+            deadline: Duration::from_secs(60),
+        };
+        let started = Instant::now();
+        let result = txn.receive_pack(Box::pin(stream)).await;
+        assert!(matches!(result, Err(GitInnerError::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(odb.aborts.load(Ordering::Relaxed), 1);
+        assert!(!repo.odb.has_blob(&blob.id).await.unwrap());
+        assert!(
+            !repo
+                .refs
+                .exists_refs("refs/heads/main".to_string())
+                .await
+                .unwrap()
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingHook {
+        events: Arc<std::sync::Mutex<Vec<(&'static str, PushEvent)>>>,
+        decline: Option<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl ReceiveHook for RecordingHook {
+        async fn pre_receive(&self, event: &PushEvent) -> Result<(), String> {
+            self.events.lock().unwrap().push(("pre", event.clone()));
+            match &self.decline {
+                Some(reason) => Err(reason.clone()),
+                None => Ok(()),
+            }
+        }
+        async fn post_receive(&self, event: &PushEvent) {
+            self.events.lock().unwrap().push(("post", event.clone()));
+        }
+    }
+
+    fn push_body_with_options(blob: &Blob, options: &[&str]) -> Bytes {
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status push-options",
+            HashVersion::Sha1.default(),
+            blob.id
+        ));
+        body.extend_from_slice(b"0000");
+        for option in options {
+            body.extend_from_slice(&write_pkt_line(format!("{}\n", option)));
+        }
+        body.extend_from_slice(b"0000");
+        body.extend_from_slice(&pack(&[Object::Blob(blob.clone())]));
+        body.freeze()
+    }
+
+    #[tokio::test]
+    async fn test_push_options_reach_hooks() {
+        let repo = memory_repository();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let hook = RecordingHook::default();
+        let events = hook.events.clone();
+        let mut txn = receive_transaction(&repo);
+        txn.hooks = Some(Arc::new(Box::new(hook)));
+        let body = push_body_with_options(&blob, &["ci.skip=true", "reviewer=alice"]);
+        txn.receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body)])))
+            .await
+            .unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            events.iter().map(|(stage, _)| *stage).collect::<Vec<_>>(),
+            vec!["pre", "post"]
+        );
+        for (_, event) in events.iter() {
+            assert_eq!(event.push_options, vec!["ci.skip=true", "reviewer=alice"]);
+            assert_eq!(event.commands[0].ref_name, "refs/heads/main");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_option_containing_pack() {
+        let repo = memory_repository();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let hook = RecordingHook::default();
+        let events = hook.events.clone();
+        let mut txn = receive_transaction(&repo);
+        txn.hooks = Some(Arc::new(Box::new(hook)));
+        let body = push_body_with_options(&blob, &["PACKAGE=1"]);
+        txn.receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body)])))
+            .await
+            .unwrap();
+        assert!(repo.odb.has_blob(&blob.id).await.unwrap());
+        assert_eq!(events.lock().unwrap()[0].1.push_options, vec!["PACKAGE=1"]);
+    }
+
+    #[tokio::test]
+    async fn test_pre_receive_decline_keeps_nothing() {
+        let (repo, odb) = memory_repository_with_odb();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let hook = RecordingHook {
+            decline: Some("ci.skip not allowed".to_string()),
+            ..Default::default()
+        };
+        let mut txn = receive_transaction(&repo);
+        txn.hooks = Some(Arc::new(Box::new(hook)));
+        let body = push_body_with_options(&blob, &["ci.skip=true"]);
+        let result = txn
+            .receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body)])))
+            .await;
+        assert!(matches!(result, Err(GitInnerError::HookDeclined(_))));
+        assert_eq!(odb.aborts.load(Ordering::Relaxed), 1);
+        assert!(!repo.odb.has_blob(&blob.id).await.unwrap());
+        assert!(
+            output(&txn)
+                .await
+                .contains("ng refs/heads/main ci.skip not allowed")
+        );
+    }
+
+    #[test]
+    fn test_push_options_need_their_own_section() {
+        let body = b"0008want00000011ci.skip=true\n0000PACK";
+        assert_eq!(super::parse_push_options(body), vec!["ci.skip=true"]);
+        assert!(super::parse_push_options(b"0008want0000PACK").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_blob_rejected() {
+        let (repo, odb) = memory_repository_with_odb();
+        let blobs = [
+            Blob::parse(Bytes::from("small\n"), HashVersion::Sha1),
+            Blob::parse(Bytes::from("this blob is too large\n"), HashVersion::Sha1),
+        ];
+        let mut txn = receive_transaction(&repo);
+        txn.pack_limits = PackLimits {
+            max_blob_bytes: 8,
+            ..Default::default()
+        };
+        let result = txn
+            .receive_pack(Box::pin(tokio_stream::iter(vec![Ok(push_body(&blobs))])))
+            .await;
+        assert!(matches!(result, Err(GitInnerError::BlobTooLarge)));
+        assert_eq!(odb.aborts.load(Ordering::Relaxed), 1);
+        assert!(!repo.odb.has_blob(&blobs[0].id).await.unwrap());
+        assert!(!repo.odb.has_blob(&blobs[1].id).await.unwrap());
+        assert!(
+            output(&txn)
+                .await
+                .contains("ng refs/heads/main blob exceeds size limit")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_pack_version_aborts() {
+        let (repo, odb) = memory_repository_with_odb();
+        let blob = Blob::parse(Bytes::from("hello\n"), HashVersion::Sha1);
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status",
+            HashVersion::Sha1.default(),
+            blob.id
+        ));
+        body.extend_from_slice(b"0000");
+        let mut pack = pack(&[Object::Blob(blob.clone())]).to_vec();
+        pack[4..8].copy_from_slice(&3u32.to_be_bytes());
+        body.extend_from_slice(&pack);
+
+        let mut txn = receive_transaction(&repo);
+        let result = txn
+            .receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body.freeze())])))
+            .await;
+        assert!(matches!(
+            result,
+            Err(GitInnerError::UnsupportedProtocolVersion(3))
+        ));
+        assert_eq!(odb.aborts.load(Ordering::Relaxed), 1);
+        assert!(!repo.odb.has_blob(&blob.id).await.unwrap());
+        assert!(
+            !repo
+                .refs
+                .exists_refs("refs/heads/main".to_string())
+                .await
+                .unwrap()
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for Captured {
+        type Writer = Captured;
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive_logs_carry_span_fields() {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(captured.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let repo = memory_repository();
+        let mut body = write_pkt_line(format!(
+            "{} {} refs/heads/main\0report-status",
+            repo.hash_version.default(),
+            put_blob(&repo, "x").await
+        ));
+        body.extend_from_slice(b"0000");
+        // pack 头不完整，读到流结束后会重试
+        body.extend_from_slice(b"PACK\0\0");
+        let mut txn = receive_transaction(&repo);
+        let span = txn.span("ns", "repo");
+        let result = txn
+            .receive_pack(Box::pin(tokio_stream::iter(vec![Ok(body.freeze())])))
+            .instrument(span)
+            .await;
+        assert!(matches!(result, Err(GitInnerError::UnexpectedEof)));
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let line = logs
+            .lines()
+            .find(|x| x.contains("retry receive pack"))
+            .expect("retry was not logged");
+        for field in [
+            "request_id=",
+            "namespace=\"ns\"",
+            "repo=\"repo\"",
+            "protocol=\"http\"",
+            "service=ReceivePack",
+        ] {
+            assert!(line.contains(field), "missing {} in {}", field, line);
+        }
+    }
+}
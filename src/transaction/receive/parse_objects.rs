@@ -55,6 +55,10 @@ impl Transaction {
         data: &[u8],
         txn: Arc<Box<dyn OdbTransaction>>,
     ) -> Result<HashValue, GitInnerError> {
+        // 在写入 ODB 之前检查，超限的 blob 不落盘
+        if data.len() as u64 > self.pack_limits.max_blob_bytes {
+            return Err(GitInnerError::BlobTooLarge);
+        }
         let bytes = bytes::Bytes::from(data.to_vec());
         let blob = crate::objects::blob::Blob::parse(bytes, self.repository.hash_version.clone());
         let hash = blob.id.clone();
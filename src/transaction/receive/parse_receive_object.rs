@@ -1,24 +1,67 @@
 use crate::callback::sidebend::{SideBend, bend_pkt_flush};
 use crate::capability::enums::GitCapability;
 use crate::error::GitInnerError;
+use crate::hooks::PushEvent;
 use crate::objects::ref_delta::RefDelta;
 use crate::objects::types::ObjectType;
 use crate::odb::OdbTransaction;
+use crate::refs::ReflogEntry;
 use crate::sha::HashValue;
 use crate::transaction::receive::ReceivePackTransaction;
+use crate::transaction::receive::ReceiveStream;
 use crate::transaction::receive::zlib_decode::decompress_object_data;
 use crate::write_pkt_line;
 use bytes::{Buf, Bytes, BytesMut};
-use futures_util::Stream;
 use futures_util::StreamExt;
 use std::collections::{BTreeMap, HashMap};
-use std::pin::Pin;
 use std::sync::Arc;
+use tracing::{debug, warn};
 
 impl ReceivePackTransaction {
+    /// Unpack the pack and apply the ref updates. A pack refused for an oversized blob is
+    /// reported to the client as `ng` on every ref before the error is returned.
+    pub async fn receive(
+        &mut self,
+        stream: ReceiveStream,
+        txn: Arc<Box<dyn OdbTransaction>>,
+    ) -> Result<(), GitInnerError> {
+        let result = self.process_receive_pack(stream, txn).await;
+        if let Err(err @ GitInnerError::BlobTooLarge) = &result {
+            let reason = err.to_string();
+            self.reject_all(&reason, &reason).await?;
+        }
+        result
+    }
+
+    /// Report the whole push as refused: `unpack <unpack>` and `ng <ref> <reason>` per ref.
+    async fn reject_all(&self, unpack: &str, reason: &str) -> Result<(), GitInnerError> {
+        let call_back = &self.transaction.call_back;
+        call_back
+            .send_side_pkt_line(
+                Bytes::from(write_pkt_line(format!("unpack {}\n", unpack))),
+                SideBend::SidebandPrimary,
+            )
+            .await?;
+        let sidebend = self.capabilities.contains(&GitCapability::SideBand)
+            || self.capabilities.contains(&GitCapability::SideBand64k);
+        for command in &self.ref_upload {
+            let status = write_pkt_line(format!("ng {} {}\n", command.ref_name, reason));
+            if sidebend {
+                call_back
+                    .send_side_pkt_line(Bytes::from(status), SideBend::SidebandPrimary)
+                    .await?;
+            } else {
+                call_back.send(Bytes::from(status)).await?;
+            }
+        }
+        call_back.send(bend_pkt_flush().into()).await?;
+        call_back.send(Bytes::new()).await?;
+        Ok(())
+    }
+
     pub async fn process_receive_pack(
         &mut self,
-        mut stream: Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>>>>,
+        mut stream: ReceiveStream,
         txn: Arc<Box<dyn OdbTransaction>>,
     ) -> Result<(), GitInnerError> {
         let mut buffer = BytesMut::new();
@@ -28,7 +71,7 @@ impl ReceivePackTransaction {
         let mut resolved_ofs: BTreeMap<u64, (HashValue, Bytes, ObjectType)> = BTreeMap::new();
         async fn ensure_buf(
             buffer: &mut BytesMut,
-            stream: &mut Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>>>>,
+            stream: &mut ReceiveStream,
             n: usize,
         ) -> Result<(), GitInnerError> {
             while buffer.len() < n {
@@ -40,6 +83,12 @@ impl ReceivePackTransaction {
             }
             Ok(())
         }
+        let limits = self.transaction.pack_limits;
+        if self.pack_size > limits.max_objects {
+            return Err(GitInnerError::PackTooLarge);
+        }
+        // 累计解压后的字节数，超过上限立即中止
+        let mut unpacked_bytes = 0u64;
         while pack_count < self.pack_size {
             let obj_start = current_offset;
             ensure_buf(&mut buffer, &mut stream, 1).await?;
@@ -57,23 +106,25 @@ impl ReceivePackTransaction {
                 shift += 7;
             }
 
-            let type_id = (first >> 4) & 0x07;
-            let object_type = match type_id {
-                1 => ObjectType::Commit,
-                2 => ObjectType::Tree,
-                3 => ObjectType::Blob,
-                4 => ObjectType::Tag,
-                6 => ObjectType::OfsDelta,
-                7 => ObjectType::RefDelta,
-                _ => return Err(GitInnerError::InvalidData),
-            };
+            let object_type = ObjectType::from_pack_type((first >> 4) & 0x07)?;
 
             buffer.advance(consumed);
             current_offset += consumed;
+            // 先按对象头声明的大小检查，避免解压超大对象
+            if unpacked_bytes + size as u64 > limits.max_bytes {
+                return Err(GitInnerError::PackTooLarge);
+            }
+            if object_type == ObjectType::Blob && size as u64 > limits.max_blob_bytes {
+                return Err(GitInnerError::BlobTooLarge);
+            }
 
             match object_type {
                 ObjectType::Commit | ObjectType::Tree | ObjectType::Blob | ObjectType::Tag => {
                     let obj_bytes = decompress_object_data(&mut buffer, &mut stream, size).await?;
+                    unpacked_bytes += obj_bytes.len() as u64;
+                    if unpacked_bytes > limits.max_bytes {
+                        return Err(GitInnerError::PackTooLarge);
+                    }
                     let hash = self
                         .transaction
                         .process_object_data(object_type, &obj_bytes, txn.clone())
@@ -92,6 +143,10 @@ impl ReceivePackTransaction {
                         .ok_or(GitInnerError::InvalidHash)?;
                     let delta_bytes =
                         decompress_object_data(&mut buffer, &mut stream, size).await?;
+                    unpacked_bytes += delta_bytes.len() as u64;
+                    if unpacked_bytes > limits.max_bytes {
+                        return Err(GitInnerError::PackTooLarge);
+                    }
                     ref_delta.insert(obj_start as u64, (base_hash, delta_bytes));
                 }
 
@@ -101,7 +156,7 @@ impl ReceivePackTransaction {
                         .send(Bytes::from(write_pkt_line(
                             "ERR Unsupported object type\n".to_string(),
                         )))
-                        .await;
+                        .await?;
                 }
             }
             pack_count += 1;
@@ -139,6 +194,11 @@ impl ReceivePackTransaction {
                 unresolved.remove(&k);
             }
             let progress = (ref_total - remaining_count) as f64 * 100.0 / ref_total as f64;
+            debug!(
+                "resolved {}/{} deltas",
+                ref_total - remaining_count + resolved_in_round_count,
+                ref_total
+            );
             if sidebend {
                 self.transaction
                     .call_back
@@ -151,7 +211,7 @@ impl ReceivePackTransaction {
                         )),
                         SideBend::SidebandMessage,
                     )
-                    .await;
+                    .await?;
             } else {
                 self.transaction
                     .call_back
@@ -161,7 +221,7 @@ impl ReceivePackTransaction {
                         ref_total - remaining_count + resolved_in_round_count,
                         ref_total
                     ))))
-                    .await;
+                    .await?;
             }
             if resolved_count == 0 {
                 break;
@@ -170,66 +230,97 @@ impl ReceivePackTransaction {
         if !unresolved.is_empty() {
             return Err(GitInnerError::MissingBaseObject);
         }
+        let hooks = self.transaction.hooks.clone();
+        if let Some(hooks) = &hooks
+            && let Err(reason) = hooks.pre_receive(&self.push_event()).await
+        {
+            self.reject_all("ok", &reason).await?;
+            return Err(GitInnerError::HookDeclined(reason));
+        }
         self.transaction
             .call_back
             .send_side_pkt_line(
                 Bytes::from(write_pkt_line("unpack ok\n".to_string())),
                 SideBend::SidebandPrimary,
             )
-            .await;
+            .await?;
 
         txn.commit().await?;
-        let mut ok = false;
         for idx in self.ref_upload.clone() {
-            if idx.is_create() {
-                if self
-                    .transaction
-                    .repository
-                    .refs
-                    .create_refs(idx.ref_name.clone(), idx.new)
-                    .await
-                    .is_ok()
-                {
-                    ok = true;
+            let refs = &self.transaction.repository.refs;
+            let status = match self.check_ref_update(&idx).await? {
+                Some(reason) => format!("ng {} {}\n", idx.ref_name, reason),
+                None if idx.is_create() => {
+                    match refs
+                        .create_refs(idx.ref_name.clone(), idx.new.clone())
+                        .await
+                    {
+                        Ok(()) => format!("ok {}\n", idx.ref_name),
+                        // 检查之后被其它推送抢先创建
+                        Err(GitInnerError::RefAlreadyExists(_)) => {
+                            format!("ng {} already exists\n", idx.ref_name)
+                        }
+                        Err(err) => format!("ng {} {}\n", idx.ref_name, err),
+                    }
                 }
-            } else if idx.is_update() {
-                if self
-                    .transaction
-                    .repository
-                    .refs
-                    .update_refs(idx.ref_name.clone(), idx.new)
+                // 比较并交换，检查之后 ref 被改动时不会覆盖
+                None if idx.is_update() => match refs
+                    .cas_update(idx.ref_name.clone(), idx.old.clone(), idx.new.clone())
                     .await
-                    .is_ok()
                 {
-                    ok = true;
+                    Ok(true) => format!("ok {}\n", idx.ref_name),
+                    Ok(false) => format!("ng {} stale info\n", idx.ref_name),
+                    Err(err) => format!("ng {} {}\n", idx.ref_name, err),
+                },
+                // 没有声明 delete-refs，客户端仍然发来删除时明确拒绝
+                None => format!("ng {} deleting refs is not supported\n", idx.ref_name),
+            };
+            if status.starts_with("ok ") {
+                let entry = ReflogEntry {
+                    ref_name: idx.ref_name.clone(),
+                    old: idx.old.clone(),
+                    new: idx.new.clone(),
+                    actor: self.transaction.actor.clone(),
+                    timestamp: chrono::Utc::now().timestamp(),
+                };
+                // 审计日志写入失败不影响已经更新的 ref
+                if let Err(err) = refs.append_reflog(entry).await {
+                    warn!("append reflog for {} failed: {:?}", idx.ref_name, err);
                 }
             }
-            if ok {
-                if sidebend {
-                    self.transaction
-                        .call_back
-                        .send_side_pkt_line(
-                            Bytes::from(write_pkt_line(format!("ok {}\n", idx.ref_name))),
-                            SideBend::SidebandPrimary,
-                        )
-                        .await;
-                } else {
-                    self.transaction
-                        .call_back
-                        .send(Bytes::from(write_pkt_line(format!(
-                            "ok {}\n",
-                            idx.ref_name
-                        ))))
-                        .await;
-                }
+            if sidebend {
+                self.transaction
+                    .call_back
+                    .send_side_pkt_line(
+                        Bytes::from(write_pkt_line(status)),
+                        SideBend::SidebandPrimary,
+                    )
+                    .await?;
+            } else {
+                self.transaction
+                    .call_back
+                    .send(Bytes::from(write_pkt_line(status)))
+                    .await?;
             }
         }
+        if let Some(hooks) = &hooks {
+            hooks.post_receive(&self.push_event()).await;
+        }
         self.transaction
             .call_back
             .send(bend_pkt_flush().into())
-            .await;
-        self.transaction.call_back.send(Bytes::new()).await;
+            .await?;
+        self.transaction.call_back.send(Bytes::new()).await?;
 
         Ok(())
     }
+
+    fn push_event(&self) -> PushEvent {
+        PushEvent {
+            repository: self.transaction.repository.id,
+            actor: self.transaction.actor.clone(),
+            commands: self.ref_upload.clone(),
+            push_options: self.push_options.clone(),
+        }
+    }
 }
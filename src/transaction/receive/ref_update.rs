@@ -0,0 +1,49 @@
+use crate::error::GitInnerError;
+use crate::transaction::receive::ReceivePackTransaction;
+use crate::transaction::receive::command::ReceiveCommand;
+
+impl ReceivePackTransaction {
+    /// Reason to refuse `command`, reported to the client as `ng <ref> <reason>`, or `None`
+    /// if the update may be applied. Creates are refused when the ref already exists, and
+    /// branch updates that are not fast-forwards are only refused when the server denies
+    /// non-fast-forwards.
+    pub async fn check_ref_update(
+        &self,
+        command: &ReceiveCommand,
+    ) -> Result<Option<&'static str>, GitInnerError> {
+        let repository = &self.transaction.repository;
+        let refs = &repository.refs;
+        if command.is_create() {
+            if refs.exists_refs(command.ref_name.clone()).await? {
+                return Ok(Some("already exists"));
+            }
+            return Ok(None);
+        }
+        if command.is_delete() {
+            return Ok(None);
+        }
+        // 客户端看到的旧值与当前 ref 不一致，说明期间有其它推送
+        let current = if refs.exists_refs(command.ref_name.clone()).await? {
+            Some(refs.get_value_refs(command.ref_name.clone()).await?)
+        } else {
+            None
+        };
+        if current.as_ref() != Some(&command.old) {
+            return Ok(Some("stale info"));
+        }
+        // 与 git 的 receive.denyNonFastForwards 一样，只检查分支
+        if !self.transaction.deny_non_fast_forwards || !command.ref_name.starts_with("refs/heads/")
+        {
+            return Ok(None);
+        }
+        let odb = &repository.odb;
+        if !odb.has_commit(&command.old).await? || !odb.has_commit(&command.new).await? {
+            return Ok(Some("bad ref"));
+        }
+        // 非快进：新提交不以旧提交为祖先
+        if !repository.is_ancestor(&command.old, &command.new).await? {
+            return Ok(Some("non-fast-forward"));
+        }
+        Ok(None)
+    }
+}
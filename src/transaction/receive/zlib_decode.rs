@@ -1,17 +1,25 @@
 use crate::error::GitInnerError;
+use crate::transaction::receive::ReceiveStream;
 use bytes::{Buf, Bytes, BytesMut};
 use flate2::{Decompress, FlushDecompress, Status};
-use futures_util::Stream;
 use futures_util::StreamExt;
-use std::pin::Pin;
 
+/// How far inflation may run past the declared object size before the object is rejected.
+pub const INFLATE_MARGIN: usize = 64;
+/// Largest up-front allocation made on the strength of a declared object size.
+const MAX_PREALLOC: usize = 1 << 20;
+
+/// Inflate one pack object whose header declared `expected_size` bytes.
+///
+/// Output beyond `expected_size + INFLATE_MARGIN` is rejected with
+/// [`GitInnerError::InvalidData`], and the final length must match the declared size.
 pub async fn decompress_object_data(
     buffer: &mut BytesMut,
-    stream: &mut Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>>>>,
+    stream: &mut ReceiveStream,
     expected_size: usize,
 ) -> Result<Bytes, GitInnerError> {
     let mut decomp = Decompress::new(true);
-    let mut object_data = Vec::with_capacity(expected_size);
+    let mut object_data = Vec::with_capacity(expected_size.min(MAX_PREALLOC));
     let mut tmp_out = [0u8; 8192];
 
     loop {
@@ -39,6 +47,10 @@ pub async fn decompress_object_data(
         if produced_out > 0 {
             object_data.extend_from_slice(&tmp_out[..produced_out]);
         }
+        // 解压结果超过声明大小，可能是解压炸弹
+        if object_data.len() > expected_size.saturating_add(INFLATE_MARGIN) {
+            return Err(GitInnerError::InvalidData);
+        }
 
         match status {
             Status::Ok => {
@@ -70,7 +82,7 @@ pub async fn decompress_object_data(
 }
 pub async fn decode_ofs_delta_offset(
     buffer: &mut BytesMut,
-    stream: &mut Pin<Box<dyn Stream<Item = Result<Bytes, GitInnerError>>>>,
+    stream: &mut ReceiveStream,
     current_offset: &mut usize,
     obj_start: u64,
 ) -> Result<u64, GitInnerError> {
@@ -127,3 +139,58 @@ pub async fn decode_ofs_delta_offset(
 
     Ok(base_offset)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    fn zlib(data: &[u8]) -> Bytes {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        Bytes::from(encoder.finish().unwrap())
+    }
+
+    async fn inflate(chunks: Vec<Bytes>, expected_size: usize) -> Result<Bytes, GitInnerError> {
+        let mut stream: ReceiveStream = Box::pin(tokio_stream::iter(chunks.into_iter().map(Ok)));
+        decompress_object_data(&mut BytesMut::new(), &mut stream, expected_size).await
+    }
+
+    #[tokio::test]
+    async fn test_inflate_declared_size() {
+        let data = b"hello world\n".repeat(100);
+        let compressed = zlib(&data);
+        let (a, b) = (compressed.slice(..10), compressed.slice(10..));
+        assert_eq!(inflate(vec![a, b], data.len()).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_inflate_truncated_stream() {
+        let data = b"hello world\n".repeat(100);
+        let compressed = zlib(&data);
+        let truncated = compressed.slice(..compressed.len() / 2);
+        assert!(matches!(
+            inflate(vec![truncated], data.len()).await,
+            Err(GitInnerError::UnexpectedEof)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_inflate_over_produces() {
+        let data = vec![0u8; 1 << 20];
+        assert!(matches!(
+            inflate(vec![zlib(&data)], 16).await,
+            Err(GitInnerError::InvalidData)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_inflate_under_produces() {
+        let data = b"short";
+        assert!(matches!(
+            inflate(vec![zlib(data)], 4096).await,
+            Err(GitInnerError::DecompressionError)
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::config::transaction::TransactionConfig;
+use crate::error::GitInnerError;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+use tokio_stream::Stream;
+
+/// Bounds on how long a pack transaction may wait for its client.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// Longest wait for a single chunk of the request body.
+    pub read: Duration,
+    /// Longest the whole transaction may take, retries included.
+    pub deadline: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        (&TransactionConfig::default()).into()
+    }
+}
+
+impl From<&TransactionConfig> for Timeouts {
+    fn from(config: &TransactionConfig) -> Self {
+        Self {
+            read: Duration::from_secs(config.read_timeout_secs),
+            deadline: Duration::from_secs(config.deadline_secs),
+        }
+    }
+}
+
+/// Wraps a request body so that a stalled read or an expired deadline yields
+/// [`GitInnerError::Timeout`] instead of waiting forever.
+///
+/// Once the inner stream has ended, further polls keep returning `None` until the deadline
+/// passes, so callers that retry on end of stream are bounded by the deadline as well.
+pub struct TimeoutStream<S> {
+    inner: S,
+    read_timeout: Duration,
+    deadline: Instant,
+    sleep: Pin<Box<Sleep>>,
+    ended: bool,
+    timed_out: bool,
+}
+
+impl<S> TimeoutStream<S> {
+    pub fn new(inner: S, timeouts: Timeouts) -> Self {
+        let now = Instant::now();
+        let deadline = now + timeouts.deadline;
+        Self {
+            inner,
+            read_timeout: timeouts.read,
+            deadline,
+            sleep: Box::pin(tokio::time::sleep_until(
+                (now + timeouts.read).min(deadline),
+            )),
+            ended: false,
+            timed_out: false,
+        }
+    }
+}
+
+impl<S, T> Stream for TimeoutStream<S>
+where
+    S: Stream<Item = Result<T, GitInnerError>> + Unpin,
+{
+    type Item = Result<T, GitInnerError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.timed_out {
+            return Poll::Ready(None);
+        }
+        if Instant::now() >= self.deadline {
+            self.timed_out = true;
+            return Poll::Ready(Some(Err(GitInnerError::Timeout)));
+        }
+        if self.ended {
+            return Poll::Ready(None);
+        }
+        match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(item) => {
+                self.ended = item.is_none();
+                let next = (Instant::now() + self.read_timeout).min(self.deadline);
+                self.sleep.as_mut().reset(next);
+                Poll::Ready(item)
+            }
+            Poll::Pending => match self.sleep.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    self.timed_out = true;
+                    Poll::Ready(Some(Err(GitInnerError::Timeout)))
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_stalled_read_times_out() {
+        let timeouts = Timeouts {
+            read: Duration::from_millis(20),
+            deadline: Duration::from_secs(60),
+        };
+        let inner = tokio_stream::iter(vec![Ok(Bytes::from("0000"))])
+            .chain(futures_util::stream::pending());
+        let mut stream = TimeoutStream::new(Box::pin(inner), timeouts);
+        assert_eq!(stream.next().await.unwrap().unwrap(), "0000");
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(GitInnerError::Timeout))
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ended_stream_is_bounded_by_deadline() {
+        let timeouts = Timeouts {
+            read: Duration::from_secs(60),
+            deadline: Duration::from_millis(20),
+        };
+        let inner = tokio_stream::iter(Vec::<Result<Bytes, GitInnerError>>::new());
+        let mut stream = TimeoutStream::new(inner, timeouts);
+        assert!(stream.next().await.is_none());
+        assert!(stream.next().await.is_none());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(GitInnerError::Timeout))
+        ));
+    }
+}
@@ -10,19 +10,19 @@ impl Transaction {
             crate::sha::HashVersion::Sha256 => "sha256",
         };
         let object_format = format!("object-format={}\n", sha_version);
-        let fetch = "fetch=shallow filter wait-for-done\n";
+        let fetch = "fetch=shallow filter wait-for-done resumable\n";
         let server_option = "server-option\n";
         let ls_refs = "ls-refs=unborn\n";
-        self.call_back.send_pkt_line(Bytes::from(agent)).await;
-        self.call_back.send_pkt_line(Bytes::from(ls_refs)).await;
-        self.call_back.send_pkt_line(Bytes::from(fetch)).await;
+        self.call_back.send_pkt_line(Bytes::from(agent)).await?;
+        self.call_back.send_pkt_line(Bytes::from(ls_refs)).await?;
+        self.call_back.send_pkt_line(Bytes::from(fetch)).await?;
         self.call_back
             .send_pkt_line(Bytes::from(server_option))
-            .await;
+            .await?;
         self.call_back
             .send_pkt_line(Bytes::from(object_format))
-            .await;
-        self.call_back.send(Bytes::from("0000")).await;
+            .await?;
+        self.call_back.send(Bytes::from("0000")).await?;
         Ok(())
     }
 }
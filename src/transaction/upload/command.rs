@@ -1,4 +1,4 @@
-use crate::capability::enums::GitCapability;
+use crate::capability::enums::{GitCapability, parse_capability_list};
 use crate::error::GitInnerError;
 use crate::sha::{HashValue, HashVersion};
 
@@ -9,6 +9,8 @@ pub enum UploadCommandType {
     Done,
     Shallow(HashValue),
     Deepen(i32),
+    DeepenSince(u64),
+    DeepenNot(String),
     Capabilities(Vec<GitCapability>),
     Flush,
 
@@ -26,6 +28,10 @@ pub enum UploadCommandType {
     Peel,
     ThinPack,
     OfsDelta,
+    /// Ask the server to keep the negotiation for a later `resume`.
+    Resumable,
+    /// Skip negotiation and rebuild the pack of an earlier `resumable` fetch.
+    Resume(String),
 }
 
 impl UploadCommandType {
@@ -37,15 +43,14 @@ impl UploadCommandType {
         if line_str.is_empty() {
             return Ok(vec![]);
         }
-        if line_str.starts_with("want ") {
-            let parts: Vec<&str> = line_str[5..].split_whitespace().collect();
-            if parts.is_empty() {
+        if let Some(rest) = line_str.strip_prefix("want ") {
+            let (hash_str, capabilities) = rest.split_once(' ').unwrap_or((rest, ""));
+            if hash_str.is_empty() {
                 return Err(GitInnerError::ConversionError(
                     "Missing hash after 'want'".into(),
                 ));
             }
 
-            let hash_str = parts[0];
             if hash_str.len() < hash_version.len() {
                 return Err(GitInnerError::ConversionError("Invalid hash length".into()));
             }
@@ -53,14 +58,7 @@ impl UploadCommandType {
             let hash = HashValue::from_str(hash_str)
                 .ok_or(GitInnerError::ConversionError("Invalid hash value".into()))?;
 
-            let capabilities = if parts.len() > 1 {
-                parts[1..]
-                    .iter()
-                    .filter_map(|s| Option::from(GitCapability::from_str(s)))
-                    .collect::<Vec<_>>()
-            } else {
-                vec![]
-            };
+            let capabilities = parse_capability_list(capabilities);
 
             let mut res = vec![];
             if !capabilities.is_empty() {
@@ -87,6 +85,22 @@ impl UploadCommandType {
             ))?;
             return Ok(vec![UploadCommandType::Shallow(hash)]);
         }
+        if let Some(timestamp) = line_str.strip_prefix("deepen-since ") {
+            let timestamp = timestamp
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| GitInnerError::ConversionError("Invalid deepen-since value".into()))?;
+            return Ok(vec![UploadCommandType::DeepenSince(timestamp)]);
+        }
+        if let Some(name) = line_str.strip_prefix("deepen-not ") {
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(GitInnerError::ConversionError(
+                    "Missing ref after 'deepen-not'".into(),
+                ));
+            }
+            return Ok(vec![UploadCommandType::DeepenNot(name.to_string())]);
+        }
         if line_str.starts_with("deepen ") {
             let depth = line_str[7..]
                 .parse::<i32>()
@@ -124,6 +138,12 @@ impl UploadCommandType {
         if line_str == "ofs-delta" {
             return Ok(vec![UploadCommandType::OfsDelta]);
         }
+        if line_str == "resumable" {
+            return Ok(vec![UploadCommandType::Resumable]);
+        }
+        if let Some(token) = line_str.strip_prefix("resume ") {
+            return Ok(vec![UploadCommandType::Resume(token.trim().to_string())]);
+        }
         if line_str == "0000" {
             return Ok(vec![UploadCommandType::Flush]);
         }
@@ -134,3 +154,23 @@ impl UploadCommandType {
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_deepen_since_and_not() {
+        assert_eq!(
+            UploadCommandType::from_one_line("deepen-since 1700000000", HashVersion::Sha1).unwrap(),
+            vec![UploadCommandType::DeepenSince(1700000000)]
+        );
+        assert_eq!(
+            UploadCommandType::from_one_line("deepen-not refs/heads/old", HashVersion::Sha1)
+                .unwrap(),
+            vec![UploadCommandType::DeepenNot("refs/heads/old".to_string())]
+        );
+        assert!(UploadCommandType::from_one_line("deepen-since soon", HashVersion::Sha1).is_err());
+        assert!(UploadCommandType::from_one_line("deepen-not ", HashVersion::Sha1).is_err());
+    }
+}
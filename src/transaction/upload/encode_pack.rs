@@ -1,182 +1,355 @@
+use crate::capability::enums::GitCapability;
 use crate::error::GitInnerError;
+use crate::objects::tree::TreeItemMode;
+use crate::objects::types::ObjectType;
+use crate::sha::HashValue;
 use crate::sha::Sha;
+use crate::transaction::GitProtoVersion;
 use crate::transaction::upload::UploadPackTransaction;
-use crate::transaction::upload::recursion::Object;
-use bstr::ByteSlice;
+use crate::transaction::upload::recursion::{Object, PackEntry, pack_object_header, zlib_compress};
 use bytes::{BufMut, Bytes, BytesMut};
-use log::trace;
-use std::collections::HashSet;
-use std::sync::Arc;
+use flate2::Compression;
+use std::collections::{HashMap, HashSet};
 use tokio::task;
+use tracing::trace;
 
 const MAX_PKT_LINE: usize = 0xfff0;
 const MAX_PAYLOAD_PER_PKT: usize = MAX_PKT_LINE - 4 - 1;
-const TARGET_PACK_BYTES: usize = usize::MAX;
 const PACK_HEADER_LEN: usize = 12;
+/// Objects between two band-2 progress messages while enumerating or compressing.
+pub(super) const PROGRESS_INTERVAL: usize = 256;
 
 impl UploadPackTransaction {
     pub async fn upload_pack_encode(&self) -> Result<(), GitInnerError> {
         trace!("[upload_pack_encode] start");
+        self.txn.metrics.record_fetch();
         let wants = self.want.clone();
         let mut objs = Vec::new();
         let mut visited = HashSet::new();
+        let mut shallow = HashSet::new();
 
-        self.txn
-            .call_back
-            .send_pkt_line(Bytes::from_static(b"packfile\n"))
-            .await;
+        // shallow-info has to precede the packfile section, so with a deepen request the
+        // walk runs before the packfile header and cannot report progress
+        let early_packfile = !self.shallow_info_needed();
+        if early_packfile {
+            self.send_packfile_header().await?;
+        }
 
         for want in &wants {
-            self.recursion_pack_pool_found_iter(&mut objs, &mut visited, want.clone())
+            self.recursion_pack_pool_found_iter(
+                &mut objs,
+                &mut visited,
+                &mut shallow,
+                want.clone(),
+            )
+            .await?;
+        }
+        if self.include_tag {
+            self.include_tags(&mut objs, &mut visited).await?;
+        }
+        if early_packfile {
+            self.send_progress(&format!("Enumerating objects: {}, done.\n", objs.len()))
                 .await?;
         }
 
+        if self.thin {
+            self.thin_pack_deltas(&mut objs).await?;
+        }
+
+        if !early_packfile {
+            if !shallow.is_empty() {
+                self.txn
+                    .call_back
+                    .send_pkt_line(Bytes::from_static(b"shallow-info\n"))
+                    .await?;
+                self.send_shallow_info(&shallow).await?;
+                self.txn.call_back.send(Bytes::from_static(b"0001")).await?;
+            }
+            self.send_packfile_header().await?;
+        }
+
         if self.sideband {
-            let payload = format!("find pack {}\n", objs.len());
-            let pkt = build_sideband_pkt(2, payload.as_bytes());
-            self.txn.call_back.send(pkt).await;
+            self.send_progress(&format!("find pack {}\n", objs.len()))
+                .await?;
         } else {
             self.txn
                 .call_back
                 .send_pkt_line(Bytes::from(format!("find pack {}\n", objs.len())))
-                .await;
+                .await?;
         }
 
         if objs.is_empty() {
-            self.txn.call_back.send(Bytes::from_static(b"0000")).await;
+            self.txn.call_back.send(Bytes::from_static(b"0000")).await?;
             return Ok(());
         }
 
+        // 客户端支持 ofs-delta 时，基对象已在同一个 pack 里的 delta 改用偏移引用
+        let ofs_delta = self.capabilities.contains(&GitCapability::OfsDelta);
+        let level = self.txn.pack_limits.compression();
         let concurrency = 8usize;
-        let objs_arc = Arc::new(objs);
-        let mut compressed_list: Vec<(Object, Bytes)> = Vec::with_capacity(objs_arc.len());
-        let mut index = 0usize;
-
-        while index < objs_arc.len() {
-            let mut handles = Vec::new();
-            for i in index..(index + concurrency).min(objs_arc.len()) {
-                let o = objs_arc[i].clone();
-                let handle =
+        let total = objs.len();
+        let mut writer = PackWriter::start(self, total).await?;
+        let mut offsets: HashMap<HashValue, usize> = HashMap::new();
+        let mut written = 0usize;
+
+        // 每批对象读取后并行压缩并立即写出，同时在内存里的只有一批对象
+        for batch in objs.chunks(concurrency) {
+            let mut loaded = Vec::with_capacity(batch.len());
+            for entry in batch {
+                loaded.push(self.load_entry(entry).await?);
+            }
+            let handles = loaded
+                .into_iter()
+                .map(|(o, base)| {
                     task::spawn_blocking(move || -> Result<(Object, Bytes), GitInnerError> {
-                        let bytes = o.zlib()?;
+                        let o = o.thin(base.as_ref());
+                        let bytes = o.zlib(level)?;
                         Ok((o, bytes))
-                    });
-                handles.push(handle);
-            }
+                    })
+                })
+                .collect::<Vec<_>>();
             for h in handles {
-                match h.await {
-                    Ok(Ok((o, b))) => {
-                        compressed_list.push((o, b));
-                    }
+                let (obj, compressed) = match h.await {
+                    Ok(Ok(x)) => x,
                     Ok(Err(e)) => return Err(e),
                     Err(e) => {
                         return Err(GitInnerError::Other(format!("compress join error: {}", e)));
                     }
-                }
+                };
+                let entry = match &obj {
+                    Object::RefDelta(delta) if ofs_delta => match offsets.get(&delta.base_sha) {
+                        Some(base) => encode_ofs_delta(
+                            (writer.offset - base) as u64,
+                            &delta.delta_data,
+                            level,
+                        )?,
+                        None => compressed,
+                    },
+                    _ => compressed,
+                };
+                offsets.insert(obj.id().clone(), writer.offset);
+                writer.write(&entry).await?;
+                written += 1;
+            }
+            if written % PROGRESS_INTERVAL < concurrency {
+                self.send_progress(&format!("Writing objects: {}/{}\r", written, total))
+                    .await?;
             }
-            index += concurrency;
         }
 
-        let mut pos = 0usize;
-        let total = compressed_list.len();
-        let mut pack_idx = 1usize;
-        let mut any_segment_sent = false;
+        let pack_len = writer.finish().await?;
+        trace!("pack streamed: {} objects, {} bytes total", total, pack_len);
+        self.send_progress(&format!("Writing objects: {}/{}, done.\n", total, total))
+            .await?;
+        self.txn.call_back.send(Bytes::from_static(b"0000")).await?;
 
-        while pos < total {
-            let mut temp_objs_bytes: Vec<Bytes> = Vec::new();
-            let mut segment_objects = 0usize;
-            let mut seg_est = PACK_HEADER_LEN;
+        Ok(())
+    }
+}
 
-            while pos < total {
-                let cand_len = compressed_list[pos].1.len();
-                if segment_objects > 0 && seg_est + cand_len > TARGET_PACK_BYTES {
-                    break;
-                }
-                temp_objs_bytes.push(compressed_list[pos].1.clone());
-                seg_est += cand_len;
-                segment_objects += 1;
-                pos += 1;
-            }
+impl UploadPackTransaction {
+    /// Protocol v2 requires a shallow-info section ahead of the packfile for deepen requests.
+    fn shallow_info_needed(&self) -> bool {
+        self.txn.version == GitProtoVersion::V2
+            && (self.depth.is_some() || self.deepen_since.is_some() || !self.deepen_not.is_empty())
+    }
 
-            if segment_objects == 0 && pos < total {
-                temp_objs_bytes.push(compressed_list[pos].1.clone());
-                segment_objects = 1;
-                seg_est += temp_objs_bytes.last().unwrap().len();
-                pos += 1;
-            }
+    pub(super) fn progress_enabled(&self) -> bool {
+        self.sideband && !self.no_progress
+    }
 
-            let mut seg_buf = BytesMut::with_capacity(seg_est + 64);
-            let mut header = BytesMut::new();
-            header.extend_from_slice(b"PACK");
-            header.put_u32(2u32); // version 2
-            header.put_u32(segment_objects as u32);
-            seg_buf.extend_from_slice(&header);
+    async fn send_packfile_header(&self) -> Result<(), GitInnerError> {
+        self.txn
+            .call_back
+            .send_pkt_line(Bytes::from_static(b"packfile\n"))
+            .await
+    }
 
-            let mut hash = self.txn.repository.hash_version.default();
+    /// Send a band-2 progress message unless sideband is off or the client sent no-progress.
+    pub(super) async fn send_progress(&self, message: &str) -> Result<(), GitInnerError> {
+        if self.progress_enabled() {
+            self.txn
+                .call_back
+                .send(build_sideband_pkt(2, message.as_bytes()))
+                .await?;
+        }
+        Ok(())
+    }
 
-            // 包含 header
-            hash.update(&header[..]);
+    /// Whether the object walk may write progress, i.e. the packfile section has started.
+    pub(super) fn enumeration_progress(&self) -> bool {
+        self.progress_enabled() && !self.shallow_info_needed()
+    }
 
-            for b in &temp_objs_bytes {
-                hash.update(&b[..]);
-                seg_buf.extend_from_slice(&b[..]);
+    /// Give blobs the blob the client already has at the same path in one of its `have`
+    /// commits as a thin-pack delta base. Bases are not added to the pack; whether the
+    /// delta is used is decided when the blob is written.
+    pub async fn thin_pack_deltas(&self, objs: &mut [PackEntry]) -> Result<(), GitInnerError> {
+        let odb = &self.txn.repository.odb;
+        let mut have_paths: HashMap<String, HashValue> = HashMap::new();
+        for have in &self.have {
+            if !odb.has_commit(have).await? {
+                continue;
+            }
+            let Some(tree) = odb.get_commit(have).await?.tree else {
+                continue;
+            };
+            let mut stack = vec![(String::new(), tree)];
+            while let Some((prefix, tree)) = stack.pop() {
+                for item in odb.get_tree(&tree).await?.tree_items {
+                    let path = format!("{}{}", prefix, item.name);
+                    match item.mode {
+                        TreeItemMode::Tree => stack.push((format!("{}/", path), item.id)),
+                        TreeItemMode::Blob | TreeItemMode::BlobExecutable => {
+                            have_paths.entry(path).or_insert(item.id);
+                        }
+                        _ => {}
+                    }
+                }
             }
+        }
+        if have_paths.is_empty() {
+            return Ok(());
+        }
 
-            let final_hash = hash.finalize();
-            seg_buf.extend_from_slice(final_hash.as_bytes());
-
-            trace!(
-                "pack segment {} built: {} objects, {} bytes total",
-                pack_idx,
-                segment_objects,
-                seg_buf.len()
-            );
-
-            let raw = seg_buf.split().freeze();
-
-            if self.sideband {
-                let mut offset = 0usize;
-                while offset < raw.len() {
-                    let chunk_size = (raw.len() - offset).min(MAX_PAYLOAD_PER_PKT);
-                    let chunk = raw.slice(offset..offset + chunk_size);
-                    let pkt_len = 4 + 1 + chunk.len();
-                    let mut pkt = BytesMut::with_capacity(pkt_len);
-                    pkt.extend_from_slice(format!("{:04x}", pkt_len).as_bytes());
-                    pkt.put_u8(1);
-                    pkt.extend_from_slice(&chunk);
-                    self.txn.call_back.send(pkt.freeze()).await;
-                    offset += chunk_size;
+        // 只沿着要发送的树找路径，树在需要时重新读取
+        let trees = objs
+            .iter()
+            .filter(|x| x.object_type == ObjectType::Tree)
+            .map(|x| x.id.clone())
+            .collect::<HashSet<_>>();
+        let mut bases: HashMap<HashValue, HashValue> = HashMap::new();
+        for entry in objs.iter() {
+            if entry.object_type != ObjectType::Commit {
+                continue;
+            }
+            let commit = odb.get_commit(&entry.id).await?;
+            let mut stack = commit
+                .tree
+                .into_iter()
+                .map(|x| (String::new(), x))
+                .collect::<Vec<_>>();
+            while let Some((prefix, tree)) = stack.pop() {
+                if !trees.contains(&tree) {
+                    continue;
+                }
+                for item in odb.get_tree(&tree).await?.tree_items {
+                    let path = format!("{}{}", prefix, item.name);
+                    match item.mode {
+                        TreeItemMode::Tree => stack.push((format!("{}/", path), item.id)),
+                        TreeItemMode::Blob | TreeItemMode::BlobExecutable => {
+                            if let Some(base) = have_paths.get(&path)
+                                && *base != item.id
+                            {
+                                bases.entry(item.id).or_insert(base.clone());
+                            }
+                        }
+                        _ => {}
+                    }
                 }
-            } else {
-                self.txn.call_back.send(Bytes::from(raw)).await;
             }
+        }
 
-            if self.sideband {
-                let percent = ((pos) * 100 / total).min(100);
-                let progress_payload =
-                    format!("pack segment {} progress: {}%\n", pack_idx, percent);
-                let pkt = build_sideband_pkt(2, progress_payload.as_bytes());
-                self.txn.call_back.send(pkt).await;
-            } else {
-                self.txn
-                    .call_back
-                    .send_pkt_line(Bytes::from(format!(
-                        "pack segment {} progress: {}%\n",
-                        pack_idx,
-                        (pos * 100 / total)
-                    )))
-                    .await;
+        for entry in objs.iter_mut() {
+            if entry.object_type == ObjectType::Blob {
+                entry.delta_base = bases.get(&entry.id).cloned();
             }
+        }
+        Ok(())
+    }
+}
 
-            any_segment_sent = true;
-            pack_idx += 1;
+/// Pack entry for an OFS_DELTA object whose base starts `base_offset_delta` bytes before
+/// this entry: the type and size header, the offset in git's negative-offset varint, then
+/// the delta zlib-compressed at `level`. The inverse of [`crate::objects::ofs_delta::OfsDelta::parse`].
+pub fn encode_ofs_delta(
+    base_offset_delta: u64,
+    delta: &Bytes,
+    level: Compression,
+) -> Result<Bytes, GitInnerError> {
+    let mut entry = pack_object_header(ObjectType::OfsDelta, delta.len());
+    let mut ofs = base_offset_delta;
+    let mut varint = vec![(ofs & 0x7F) as u8];
+    ofs >>= 7;
+    while ofs != 0 {
+        ofs -= 1;
+        varint.push(0x80 | (ofs & 0x7F) as u8);
+        ofs >>= 7;
+    }
+    varint.reverse();
+    entry.extend_from_slice(&varint);
+    entry.extend_from_slice(&zlib_compress(delta, level)?);
+    Ok(Bytes::from(entry))
+}
+
+/// Streams one pack to the client. Bytes are hashed as they pass through and leave in
+/// pkt-line sized chunks, so no more than one chunk of the pack is buffered at a time.
+struct PackWriter<'a> {
+    request: &'a UploadPackTransaction,
+    hash: HashValue,
+    pending: BytesMut,
+    /// Bytes of the pack written so far, i.e. the offset of the next entry.
+    offset: usize,
+}
+
+impl<'a> PackWriter<'a> {
+    /// Write the pack header announcing `objects` entries.
+    async fn start(
+        request: &'a UploadPackTransaction,
+        objects: usize,
+    ) -> Result<PackWriter<'a>, GitInnerError> {
+        let mut writer = PackWriter {
+            request,
+            hash: request.txn.repository.hash_version.default(),
+            pending: BytesMut::with_capacity(MAX_PAYLOAD_PER_PKT),
+            offset: 0,
+        };
+        let mut header = BytesMut::with_capacity(PACK_HEADER_LEN);
+        header.extend_from_slice(b"PACK");
+        header.put_u32(2u32); // version 2
+        header.put_u32(objects as u32);
+        writer.write(&header).await?;
+        Ok(writer)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), GitInnerError> {
+        self.hash.update(data);
+        self.offset += data.len();
+        self.append(data).await
+    }
+
+    async fn append(&mut self, mut data: &[u8]) -> Result<(), GitInnerError> {
+        while !data.is_empty() {
+            let take = (MAX_PAYLOAD_PER_PKT - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() == MAX_PAYLOAD_PER_PKT {
+                self.flush().await?;
+            }
         }
+        Ok(())
+    }
 
-        if any_segment_sent {
-            self.txn.call_back.send(Bytes::from_static(b"0000")).await;
+    async fn flush(&mut self) -> Result<(), GitInnerError> {
+        if self.pending.is_empty() {
+            return Ok(());
         }
+        let chunk = self.pending.split().freeze();
+        let txn = &self.request.txn;
+        txn.metrics.record_bytes_served(chunk.len());
+        if self.request.sideband {
+            txn.call_back.send(build_sideband_pkt(1, &chunk)).await
+        } else {
+            txn.call_back.send(chunk).await
+        }
+    }
 
-        Ok(())
+    /// Append the trailing checksum and send what is left; returns the pack length.
+    async fn finish(mut self) -> Result<usize, GitInnerError> {
+        let checksum = self.hash.finalize();
+        self.append(&checksum).await?;
+        self.flush().await?;
+        Ok(self.offset + checksum.len())
     }
 }
 
@@ -188,3 +361,285 @@ fn build_sideband_pkt(band: u8, payload: &[u8]) -> Bytes {
     pkt.extend_from_slice(payload);
     pkt.freeze()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callback::CallBack;
+    use crate::callback::sidebend::{DemuxOutput, demux};
+    use crate::objects::ofs_delta::OfsDelta;
+    use crate::objects::ref_delta::RefDelta;
+    use crate::objects::tag::Tag;
+    use crate::repository::Repository;
+    use crate::repository::testing::{
+        memory_repository, memory_repository_with_odb, put_blob, put_commit, put_tree, transaction,
+    };
+    use crate::sha::HashVersion;
+    use std::sync::atomic::Ordering;
+
+    /// Demultiplex everything written to the callback.
+    async fn read_output(request: &UploadPackTransaction) -> DemuxOutput {
+        let mut receive = request.txn.call_back.receive.lock().await;
+        let mut out = Vec::new();
+        while let Ok(bytes) = receive.try_recv() {
+            out.extend_from_slice(&bytes);
+        }
+        demux(&out).unwrap()
+    }
+
+    #[test]
+    fn test_ofs_delta_round_trip() {
+        let delta = RefDelta::encode_delta(b"hello world\n", b"hello there world\n");
+        for distance in [1u64, 127, 128, 16511, 16512, 1 << 30] {
+            let entry = encode_ofs_delta(distance, &delta, Compression::default()).unwrap();
+            // 跳过类型和大小头
+            assert_eq!((entry[0] >> 4) & 0x07, ObjectType::OfsDelta.to_pack_type());
+            let header_len = entry.iter().position(|x| x & 0x80 == 0).unwrap() + 1;
+            let varint_len = entry[header_len..]
+                .iter()
+                .position(|x| x & 0x80 == 0)
+                .unwrap()
+                + 1;
+            let mut decoder = flate2::read::ZlibDecoder::new(&entry[header_len + varint_len..]);
+            let mut body = Vec::new();
+            std::io::Read::read_to_end(&mut decoder, &mut body).unwrap();
+
+            let mut input = BytesMut::from(&entry[header_len..header_len + varint_len]);
+            input.extend_from_slice(&body);
+            let current = (1u64 << 31) as usize;
+            let parsed =
+                OfsDelta::parse(input, current, |x| HashVersion::Sha1.hash(x.clone())).unwrap();
+            assert_eq!(parsed.base_offset, current as u64 - distance);
+            assert_eq!(parsed.delta_data, delta);
+        }
+    }
+
+    async fn history(repo: &Repository) -> (HashValue, HashValue) {
+        let base = (0..500)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let base_blob = put_blob(repo, &base).await;
+        let tree = put_tree(repo, vec![(TreeItemMode::Blob, "file.txt", base_blob)]).await;
+        let old = put_commit(repo, &tree, &[], "old", 1).await;
+        let changed = format!("{}one more line\n", base);
+        let changed_blob = put_blob(repo, &changed).await;
+        let tree = put_tree(repo, vec![(TreeItemMode::Blob, "file.txt", changed_blob)]).await;
+        let new = put_commit(repo, &tree, &[old.clone()], "new", 2).await;
+        (old, new)
+    }
+
+    async fn fetch(repo: &Repository, old: &HashValue, new: &HashValue, thin: bool) -> Vec<u8> {
+        let mut request = UploadPackTransaction::new(transaction(repo));
+        request.want.push(new.clone());
+        request.have.push(old.clone());
+        request.sideband = true;
+        request.thin = thin;
+        request.upload_pack_encode().await.unwrap();
+        read_output(&request).await.pack
+    }
+
+    #[tokio::test]
+    async fn test_thin_pack_uses_ref_delta() {
+        let repo = crate::repository::testing::memory_repository();
+        let (old, new) = history(&repo).await;
+        let full = fetch(&repo, &old, &new, false).await;
+        let thin = fetch(&repo, &old, &new, true).await;
+
+        assert_eq!(&thin[..4], b"PACK");
+        // commit, tree and the changed blob are sent either way; the base blob never is
+        assert_eq!(u32::from_be_bytes(thin[8..12].try_into().unwrap()), 3);
+        assert_eq!(u32::from_be_bytes(full[8..12].try_into().unwrap()), 3);
+        assert!(thin.len() < full.len());
+
+        let mut delta_bases = vec![];
+        let mut objs = vec![];
+        let mut shallow = HashSet::new();
+        let mut request = UploadPackTransaction::new(transaction(&repo));
+        request.have.push(old.clone());
+        request
+            .recursion_pack_pool_found_iter(&mut objs, &mut HashSet::new(), &mut shallow, new)
+            .await
+            .unwrap();
+        request.thin_pack_deltas(&mut objs).await.unwrap();
+        for entry in &objs {
+            if let Some(base) = &entry.delta_base {
+                delta_bases.push(base.clone());
+            }
+        }
+        let old_tree = repo.odb.get_commit(&old).await.unwrap().tree.unwrap();
+        let base = repo.odb.get_tree(&old_tree).await.unwrap().tree_items[0]
+            .id
+            .clone();
+        assert_eq!(delta_bases, vec![base]);
+    }
+
+    #[tokio::test]
+    async fn test_include_tag_sends_annotated_tag() {
+        let repo = memory_repository();
+        let (_, new) = history(&repo).await;
+        let data = format!(
+            "object {}\ntype commit\ntag v1.0\ntagger T <t@example.com> 1700000000 +0000\n\nv1.0\n",
+            new
+        );
+        let tag = Tag::parse(Bytes::from(data), HashVersion::Sha1).unwrap();
+        let tag_id = repo.odb.put_tag(&tag).await.unwrap();
+        repo.refs_insert("refs/tags/v1.0".to_string(), tag_id)
+            .await
+            .unwrap();
+
+        let mut counts = vec![];
+        for include_tag in [false, true] {
+            let mut request = UploadPackTransaction::new(transaction(&repo));
+            request.want.push(new.clone());
+            request.sideband = true;
+            request.include_tag = include_tag;
+            request.upload_pack_encode().await.unwrap();
+            let pack = read_output(&request).await.pack;
+            counts.push(u32::from_be_bytes(pack[8..12].try_into().unwrap()));
+        }
+        // 两个提交、两棵树、两个 blob，再加上标签对象
+        assert_eq!(counts, vec![6, 7]);
+    }
+
+    async fn many_blobs_request(no_progress: bool) -> UploadPackTransaction {
+        let repo = crate::repository::testing::memory_repository();
+        let mut items = vec![];
+        for i in 0..600 {
+            items.push((
+                TreeItemMode::Blob,
+                format!("f{}", i),
+                put_blob(&repo, &format!("blob {}", i)).await,
+            ));
+        }
+        let tree = put_tree(
+            &repo,
+            items
+                .iter()
+                .map(|(mode, name, id)| (*mode, name.as_str(), id.clone()))
+                .collect(),
+        )
+        .await;
+        let commit = put_commit(&repo, &tree, &[], "many", 1).await;
+        let mut request = UploadPackTransaction::new(transaction(&repo));
+        request.want.push(commit);
+        request.sideband = true;
+        request.no_progress = no_progress;
+        request
+    }
+
+    #[tokio::test]
+    async fn test_progress_before_pack_data() {
+        let request = many_blobs_request(false).await;
+        request.upload_pack_encode().await.unwrap();
+        let packets = read_output(&request).await.lines;
+        let first_data = packets.iter().position(|x| x.first() == Some(&1)).unwrap();
+        let progress = packets[..first_data]
+            .iter()
+            .filter(|x| x.first() == Some(&2) && x[1..].starts_with(b"Enumerating objects: "))
+            .count();
+        assert!(progress >= 2);
+        assert_eq!(packets[0], b"packfile\n");
+    }
+
+    #[tokio::test]
+    async fn test_no_progress_suppresses_band_two() {
+        let request = many_blobs_request(true).await;
+        request.upload_pack_encode().await.unwrap();
+        let output = read_output(&request).await;
+        assert!(!output.pack.is_empty());
+        assert!(output.progress.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_encode_stops_when_client_gone() {
+        let repo = crate::repository::testing::memory_repository();
+        let (_, new) = history(&repo).await;
+        let mut request = UploadPackTransaction::new(transaction(&repo));
+        request.want.push(new);
+        request.sideband = true;
+        drop(request.txn.call_back.subscribe().await);
+        assert!(matches!(
+            request.upload_pack_encode().await,
+            Err(GitInnerError::ClientDisconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pack_streams_in_bounded_chunks() {
+        let (repo, odb) = memory_repository_with_odb();
+        let mut seed = HashVersion::Sha1.hash(Bytes::from_static(b"seed"));
+        let mut blobs = vec![];
+        for _ in 0..300 {
+            // 哈希串几乎不可压缩，让 pack 远大于单个 pkt-line
+            let content = (0..100)
+                .map(|_| {
+                    seed = HashVersion::Sha1.hash(Bytes::from(seed.to_string()));
+                    seed.to_string()
+                })
+                .collect::<String>();
+            blobs.push(put_blob(&repo, &content).await);
+        }
+        let names = (0..blobs.len())
+            .map(|i| format!("f{}", i))
+            .collect::<Vec<_>>();
+        let items = names
+            .iter()
+            .zip(&blobs)
+            .map(|(name, blob)| (TreeItemMode::Blob, name.as_str(), blob.clone()))
+            .collect();
+        let tree = put_tree(&repo, items).await;
+        let commit = put_commit(&repo, &tree, &[], "big", 1).await;
+
+        let mut txn = transaction(&repo);
+        txn.call_back = CallBack::new(4);
+        let mut request = UploadPackTransaction::new(txn);
+        request.want.push(commit);
+        request.sideband = true;
+        let mut receive = request.txn.call_back.subscribe().await;
+        let reader = tokio::spawn(async move {
+            let mut packets = vec![];
+            while let Some(packet) = receive.recv().await {
+                packets.push(packet);
+            }
+            packets
+        });
+        request.upload_pack_encode().await.unwrap();
+        drop(request);
+        let packets = reader.await.unwrap();
+
+        // 同时读入内存的 blob 不超过一批，而不是整个仓库
+        let blob_len = 100 * 40;
+        assert_eq!(odb.live_blob_bytes.load(Ordering::SeqCst), 0);
+        assert!(odb.peak_blob_bytes.load(Ordering::SeqCst) <= 8 * blob_len);
+        assert!(odb.peak_blob_bytes.load(Ordering::SeqCst) >= blob_len);
+
+        // 每次发送的数据都不超过一个 pkt-line
+        assert!(packets.iter().all(|x| x.len() <= MAX_PKT_LINE));
+        let pack = demux(&packets.concat()).unwrap().pack;
+        assert!(pack.len() > 8 * MAX_PKT_LINE);
+
+        assert_eq!(&pack[..4], b"PACK");
+        let count = u32::from_be_bytes(pack[8..12].try_into().unwrap()) as usize;
+        assert_eq!(count, blobs.len() + 2);
+        let (body, checksum) = pack.split_at(pack.len() - 20);
+        assert_eq!(
+            HashVersion::Sha1
+                .hash(Bytes::copy_from_slice(body))
+                .as_bytes(),
+            checksum
+        );
+        // 逐个解压条目，最后一个正好结束在校验和之前
+        let mut pos = PACK_HEADER_LEN;
+        for _ in 0..count {
+            pos += body[pos..].iter().position(|x| x & 0x80 == 0).unwrap() + 1;
+            let mut inflate = flate2::Decompress::new(true);
+            let mut out = vec![0u8; 1 << 16];
+            let status = inflate
+                .decompress(&body[pos..], &mut out, flate2::FlushDecompress::Finish)
+                .unwrap();
+            assert_eq!(status, flate2::Status::StreamEnd);
+            pos += inflate.total_in() as usize;
+        }
+        assert_eq!(pos, body.len());
+    }
+}
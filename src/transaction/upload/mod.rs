@@ -1,6 +1,9 @@
 use crate::capability::enums::GitCapability;
+use crate::error::GitInnerError;
+use crate::repository::walk::visit;
 use crate::sha::HashValue;
 use crate::transaction::Transaction;
+use std::collections::HashSet;
 
 #[derive(Clone)]
 pub struct UploadPackTransaction {
@@ -10,6 +13,10 @@ pub struct UploadPackTransaction {
     pub sideband: bool,
     pub thin: bool,
     pub depth: Option<u32>,
+    /// `deepen-since`: commits with an older committer timestamp are not sent.
+    pub deepen_since: Option<u64>,
+    /// `deepen-not`: commits reachable from these refs are not sent.
+    pub deepen_not: HashSet<HashValue>,
     pub no_progress: bool,
     pub no_done: bool,
     pub include_tag: bool,
@@ -26,6 +33,8 @@ impl UploadPackTransaction {
             sideband: false,
             thin: false,
             depth: None,
+            deepen_since: None,
+            deepen_not: HashSet::new(),
             no_progress: false,
             no_done: false,
             include_tag: false,
@@ -35,9 +44,30 @@ impl UploadPackTransaction {
     }
 }
 
+impl UploadPackTransaction {
+    /// Resolve a `deepen-not` ref and exclude every commit reachable from it. Fails with
+    /// `GraphTooLarge` once the excluded commits of all `deepen-not` refs together exceed
+    /// `max_walk_nodes`.
+    pub async fn add_deepen_not(&mut self, name: &str) -> Result<(), GitInnerError> {
+        let repository = &self.txn.repository;
+        let limit = self.txn.pack_limits.max_walk_nodes;
+        let hash = repository.resolve_rev(name).await?;
+        let mut stack = vec![repository.peel_to_commit(&hash).await?];
+        while let Some(hash) = stack.pop() {
+            if !visit(&mut self.deepen_not, &hash, limit)? {
+                continue;
+            }
+            stack.extend(repository.odb.get_commit(&hash).await?.parents);
+        }
+        Ok(())
+    }
+}
+
 pub mod advertise_v2;
 pub mod command;
 pub mod encode_pack;
 pub mod recursion;
+pub mod resume;
+pub mod upload_archive;
 pub mod upload_pack;
 pub mod upload_pack_v2;
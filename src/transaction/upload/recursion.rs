@@ -2,12 +2,17 @@ use crate::error::GitInnerError;
 use crate::objects::ObjectTrait;
 use crate::objects::blob::Blob;
 use crate::objects::commit::Commit;
+use crate::objects::ref_delta::RefDelta;
 use crate::objects::tag::Tag;
-use crate::objects::tree::Tree;
+use crate::objects::tree::{Tree, TreeItemMode};
+use crate::objects::types::ObjectType;
+use crate::repository::walk::visit;
 use crate::sha::HashValue;
 use crate::transaction::upload::UploadPackTransaction;
+use crate::transaction::upload::encode_pack::PROGRESS_INTERVAL;
 use crate::write_pkt_line;
 use bytes::Bytes;
+use flate2::Compression;
 use flate2::write::ZlibEncoder;
 use std::collections::HashSet;
 use std::io::Write;
@@ -18,34 +23,89 @@ pub enum Object {
     Tree(Tree),
     Blob(Blob),
     Tag(Tag),
+    /// Thin-pack entry: the object is sent as a delta against a base the client has.
+    RefDelta(RefDelta),
 }
+
+/// An object chosen for a pack. Enumeration keeps only its id and type; the object itself
+/// is loaded when its entry is written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackEntry {
+    pub id: HashValue,
+    pub object_type: ObjectType,
+    /// Thin packs: a blob the client already has that this blob may be sent as a delta of.
+    pub delta_base: Option<HashValue>,
+}
+
+impl PackEntry {
+    pub fn new(id: HashValue, object_type: ObjectType) -> Self {
+        Self {
+            id,
+            object_type,
+            delta_base: None,
+        }
+    }
+}
+
 impl UploadPackTransaction {
-    pub async fn find_object(&self, hash: HashValue) -> Result<Option<Object>, GitInnerError> {
-        if let Ok(commit) = self.txn.repository.odb.get_commit(&hash).await {
-            return Ok(Some(Object::Commit(commit)));
+    /// Type of the stored object `hash`. Commits, trees and tags are small and recognised by
+    /// reading them; blobs only by an existence check, so their data is not loaded.
+    pub async fn object_type(&self, hash: &HashValue) -> Result<Option<ObjectType>, GitInnerError> {
+        let odb = &self.txn.repository.odb;
+        if odb.get_commit(hash).await.is_ok() {
+            return Ok(Some(ObjectType::Commit));
         }
-        if let Ok(tree) = self.txn.repository.odb.get_tree(&hash).await {
-            return Ok(Some(Object::Tree(tree)));
+        if odb.get_tree(hash).await.is_ok() {
+            return Ok(Some(ObjectType::Tree));
         }
-        if let Ok(tag) = self.txn.repository.odb.get_tag(&hash).await {
-            return Ok(Some(Object::Tag(tag)));
+        if odb.get_tag(hash).await.is_ok() {
+            return Ok(Some(ObjectType::Tag));
         }
-        if let Ok(blob) = self.txn.repository.odb.get_blob(&hash).await {
-            return Ok(Some(Object::Blob(blob)));
+        if odb.has_blob(hash).await? {
+            return Ok(Some(ObjectType::Blob));
         }
-
         Ok(None)
     }
 
+    /// Load the object behind `entry`, with the thin-pack base blob if it has one.
+    pub async fn load_entry(
+        &self,
+        entry: &PackEntry,
+    ) -> Result<(Object, Option<Blob>), GitInnerError> {
+        let odb = &self.txn.repository.odb;
+        let object = match entry.object_type {
+            ObjectType::Commit => Object::Commit(odb.get_commit(&entry.id).await?),
+            ObjectType::Tree => Object::Tree(odb.get_tree(&entry.id).await?),
+            ObjectType::Tag => Object::Tag(odb.get_tag(&entry.id).await?),
+            ObjectType::Blob => Object::Blob(odb.get_blob(&entry.id).await?),
+            _ => return Err(GitInnerError::InvalidData),
+        };
+        let base = match &entry.delta_base {
+            Some(base) => Some(odb.get_blob(base).await?),
+            None => None,
+        };
+        Ok((object, base))
+    }
+
     pub async fn recursion_pack_pool_found_iter(
         &self,
-        objs: &mut Vec<Object>,
+        objs: &mut Vec<PackEntry>,
         visited: &mut HashSet<HashValue>,
+        shallow: &mut HashSet<HashValue>,
         root: HashValue,
     ) -> Result<(), GitInnerError> {
-        let mut stack = vec![(root, 0usize)];
-        while let Some((hash, depth)) = stack.pop() {
-            if !visited.insert(hash.clone()) || self.have.contains(&hash) {
+        let odb = &self.txn.repository.odb;
+        let progress = self.enumeration_progress();
+        // 树条目的模式已经给出类型，其余对象要先查询类型
+        let mut stack = vec![(root, 0usize, None)];
+        while let Some((hash, depth, hint)) = stack.pop() {
+            // 客户端已断开，继续遍历没有意义
+            if self.txn.call_back.is_closed() {
+                return Err(GitInnerError::ClientDisconnected);
+            }
+            if !visit(visited, &hash, self.txn.pack_limits.max_walk_nodes)?
+                || self.have.contains(&hash)
+            {
                 continue;
             }
             if let Some(max_depth) = self.depth {
@@ -53,40 +113,136 @@ impl UploadPackTransaction {
                     continue;
                 }
             }
-            let obj_opt = self.find_object(hash.clone()).await?;
-            let Some(obj) = obj_opt else {
-                continue;
+            let object_type = match hint {
+                Some(object_type) => object_type,
+                None => match self.object_type(&hash).await? {
+                    Some(object_type) => object_type,
+                    None => continue,
+                },
             };
-            match obj {
-                Object::Commit(commit) => {
+            let before = objs.len();
+            match object_type {
+                ObjectType::Commit => {
+                    let Ok(commit) = odb.get_commit(&hash).await else {
+                        continue;
+                    };
+                    if !self.deepen_includes(&commit) {
+                        continue;
+                    }
                     if let Some(tree) = commit.tree.clone() {
-                        stack.push((tree, depth));
+                        stack.push((tree, depth, Some(ObjectType::Tree)));
                     }
+                    let mut boundary = false;
                     for parent in commit.parents.clone() {
-                        stack.push((parent, depth + 1));
+                        if self
+                            .depth
+                            .is_some_and(|max_depth| depth + 1 >= max_depth as usize)
+                        {
+                            boundary = true;
+                            continue;
+                        }
+                        if self.deepen_since.is_some() || !self.deepen_not.is_empty() {
+                            let parent_commit = odb.get_commit(&parent).await?;
+                            if !self.deepen_includes(&parent_commit) {
+                                boundary = true;
+                                continue;
+                            }
+                        }
+                        stack.push((parent, depth + 1, Some(ObjectType::Commit)));
                     }
-                    objs.push(Object::Commit(commit));
+                    if boundary {
+                        shallow.insert(commit.hash.clone());
+                    }
+                    objs.push(PackEntry::new(hash, ObjectType::Commit));
                 }
-                Object::Tree(tree) => {
-                    for entry in tree.tree_items.clone() {
-                        stack.push((entry.id.clone(), depth));
+                ObjectType::Tree => {
+                    let Ok(tree) = odb.get_tree(&hash).await else {
+                        continue;
+                    };
+                    for entry in tree.tree_items {
+                        let hint = match entry.mode {
+                            TreeItemMode::Tree => Some(ObjectType::Tree),
+                            TreeItemMode::Blob
+                            | TreeItemMode::BlobExecutable
+                            | TreeItemMode::Link => Some(ObjectType::Blob),
+                            // 子模块提交通常不在本仓库里
+                            TreeItemMode::Commit => None,
+                        };
+                        stack.push((entry.id, depth, hint));
                     }
-                    objs.push(Object::Tree(tree));
+                    objs.push(PackEntry::new(hash, ObjectType::Tree));
                 }
-                Object::Tag(tag) => {
+                ObjectType::Tag => {
+                    let Ok(tag) = odb.get_tag(&hash).await else {
+                        continue;
+                    };
                     if self.include_tag {
-                        stack.push((tag.object_hash.clone(), depth));
+                        stack.push((tag.object_hash.clone(), depth, None));
                     }
-                    objs.push(Object::Tag(tag));
-                }
-                Object::Blob(blob) => {
-                    objs.push(Object::Blob(blob));
+                    objs.push(PackEntry::new(hash, ObjectType::Tag));
                 }
+                // blob 内容到写出时才读取，缺失的 blob 在那时报错
+                ObjectType::Blob => objs.push(PackEntry::new(hash, ObjectType::Blob)),
+                _ => continue,
+            }
+            if progress && objs.len() != before && objs.len().is_multiple_of(PROGRESS_INTERVAL) {
+                self.send_progress(&format!("Enumerating objects: {}\r", objs.len()))
+                    .await?;
             }
         }
         Ok(())
     }
 
+    /// `include-tag`: add the annotated tags whose target is already in `objs`, so tags
+    /// pointing at fetched commits reach the client without being asked for.
+    pub async fn include_tags(
+        &self,
+        objs: &mut Vec<PackEntry>,
+        visited: &mut HashSet<HashValue>,
+    ) -> Result<(), GitInnerError> {
+        let odb = &self.txn.repository.odb;
+        let mut pending = vec![];
+        for item in self.txn.repository.refs_list().await? {
+            if !item.name.starts_with("refs/tags/")
+                || visited.contains(&item.value)
+                || self.have.contains(&item.value)
+                || !odb.has_tag(&item.value).await?
+            {
+                continue;
+            }
+            pending.push(odb.get_tag(&item.value).await?);
+        }
+        let mut packed = objs.iter().map(|x| x.id.clone()).collect::<HashSet<_>>();
+        // 标签也可能指向另一个标签，反复扫描直到没有新的标签加入
+        loop {
+            let before = pending.len();
+            let mut rest = vec![];
+            for tag in pending {
+                if !packed.contains(&tag.object_hash) {
+                    rest.push(tag);
+                } else if visited.insert(tag.id.clone()) {
+                    packed.insert(tag.id.clone());
+                    objs.push(PackEntry::new(tag.id, ObjectType::Tag));
+                }
+            }
+            if rest.len() == before {
+                return Ok(());
+            }
+            pending = rest;
+        }
+    }
+
+    /// Whether `commit` survives the `deepen-since` / `deepen-not` cut.
+    fn deepen_includes(&self, commit: &Commit) -> bool {
+        if self
+            .deepen_since
+            .is_some_and(|since| (commit.committer.timestamp as u64) < since)
+        {
+            return false;
+        }
+        !self.deepen_not.contains(&commit.hash)
+    }
+
     pub async fn send_shallow_info(
         &self,
         shallow_commits: &HashSet<HashValue>,
@@ -95,53 +251,191 @@ impl UploadPackTransaction {
             self.txn
                 .call_back
                 .send(write_pkt_line(format!("shallow {}\n", hash)).freeze())
-                .await;
+                .await?;
         }
         Ok(())
     }
 }
 
 impl Object {
-    pub fn zlib(&self) -> Result<Bytes, GitInnerError> {
-        let body = match self {
-            Object::Blob(blob) => blob.get_data(),
-            Object::Tree(tree) => tree.get_data(),
-            Object::Commit(commit) => commit.get_data(),
-            Object::Tag(tag) => tag.get_data(),
+    pub fn id(&self) -> &HashValue {
+        match self {
+            Object::Blob(blob) => &blob.id,
+            Object::Tree(tree) => &tree.id,
+            Object::Commit(commit) => &commit.hash,
+            Object::Tag(tag) => &tag.id,
+            Object::RefDelta(delta) => &delta.id,
+        }
+    }
+
+    /// With a thin-pack `base`, the blob as a REF_DELTA against it when that is smaller.
+    pub fn thin(self, base: Option<&Blob>) -> Object {
+        let (Object::Blob(blob), Some(base)) = (&self, base) else {
+            return self;
         };
+        let delta_data = RefDelta::encode_delta(&base.data, &blob.data);
+        if delta_data.len() >= blob.data.len() {
+            return self;
+        }
+        Object::RefDelta(RefDelta {
+            id: blob.id.clone(),
+            base_sha: base.id.clone(),
+            delta_data,
+        })
+    }
 
-        let type_code = match self {
-            Object::Commit(_) => 1u8,
-            Object::Tree(_) => 2u8,
-            Object::Blob(_) => 3u8,
-            Object::Tag(_) => 4u8,
+    /// The object as a pack entry, its body compressed at `level`.
+    pub fn zlib(&self, level: Compression) -> Result<Bytes, GitInnerError> {
+        let object: &dyn ObjectTrait = match self {
+            Object::Blob(blob) => blob,
+            Object::Tree(tree) => tree,
+            Object::Commit(commit) => commit,
+            Object::Tag(tag) => tag,
+            Object::RefDelta(delta) => delta,
         };
+        let body = object.get_data();
+        let mut result = pack_object_header(object.get_type(), body.len());
+        if let Object::RefDelta(delta) = self {
+            result.extend_from_slice(&delta.base_sha.raw());
+        }
+        result.extend_from_slice(&zlib_compress(&body, level)?);
+        Ok(Bytes::from(result))
+    }
+}
 
-        let mut header = vec![];
-        let mut size = body.len();
-        let mut first_byte = ((size & 0x0F) as u8) | (type_code << 4);
-        size >>= 4;
+/// Type and size header of a pack entry: 3 type bits and the size as a little-endian varint.
+pub(super) fn pack_object_header(object_type: ObjectType, size: usize) -> Vec<u8> {
+    let mut header = vec![];
+    let mut size = size;
+    let mut first_byte = ((size & 0x0F) as u8) | (object_type.to_pack_type() << 4);
+    size >>= 4;
 
+    if size != 0 {
+        first_byte |= 0x80;
+    }
+    header.push(first_byte);
+    while size != 0 {
+        let mut byte = (size & 0x7F) as u8;
+        size >>= 7;
         if size != 0 {
-            first_byte |= 0x80;
+            byte |= 0x80;
         }
-        header.push(first_byte);
-        while size != 0 {
-            let mut byte = (size & 0x7F) as u8;
-            size >>= 7;
-            if size != 0 {
-                byte |= 0x80;
-            }
-            header.push(byte);
+        header.push(byte);
+    }
+    header
+}
+
+pub(super) fn zlib_compress(body: &[u8], level: Compression) -> Result<Vec<u8>, GitInnerError> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), level);
+    encoder
+        .write_all(body)
+        .map_err(|_| GitInnerError::ZlibError)?;
+    encoder.finish().map_err(|_| GitInnerError::ZlibError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::testing::{
+        memory_repository, put_blob, put_commit, put_tree, transaction,
+    };
+    use std::io::Read;
+
+    /// Ten commits on `main` with committer timestamps 1..=10.
+    async fn linear_request() -> (UploadPackTransaction, Vec<HashValue>) {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "x").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "x", blob)]).await;
+        let mut commits: Vec<HashValue> = vec![];
+        for i in 1..=10 {
+            let parents = commits.last().cloned().into_iter().collect::<Vec<_>>();
+            commits.push(put_commit(&repo, &tree, &parents, &format!("c{}", i), i).await);
         }
-        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
-        encoder
-            .write_all(&body)
-            .map_err(|_| GitInnerError::ZlibError)?;
-        let compressed_body = encoder.finish().map_err(|_| GitInnerError::ZlibError)?;
-        let mut result = header;
-        result.extend_from_slice(&compressed_body);
+        repo.refs_insert("refs/heads/main".to_string(), commits[9].clone())
+            .await
+            .unwrap();
+        repo.refs_insert("refs/heads/old".to_string(), commits[4].clone())
+            .await
+            .unwrap();
+        let mut request = UploadPackTransaction::new(transaction(&repo));
+        request.want.push(commits[9].clone());
+        (request, commits)
+    }
 
-        Ok(Bytes::from(result))
+    async fn walk(request: &UploadPackTransaction) -> (HashSet<HashValue>, HashSet<HashValue>) {
+        let mut objs = vec![];
+        let mut shallow = HashSet::new();
+        request
+            .recursion_pack_pool_found_iter(
+                &mut objs,
+                &mut HashSet::new(),
+                &mut shallow,
+                request.want[0].clone(),
+            )
+            .await
+            .unwrap();
+        let commits = objs
+            .into_iter()
+            .filter(|x| x.object_type == ObjectType::Commit)
+            .map(|x| x.id)
+            .collect();
+        (commits, shallow)
+    }
+
+    #[tokio::test]
+    async fn test_deepen_since_cuts_history() {
+        let (mut request, commits) = linear_request().await;
+        request.deepen_since = Some(6);
+        let (sent, shallow) = walk(&request).await;
+        assert_eq!(sent, commits[5..].iter().cloned().collect());
+        assert_eq!(shallow, HashSet::from([commits[5].clone()]));
+    }
+
+    #[tokio::test]
+    async fn test_deepen_not_excludes_ref() {
+        let (mut request, commits) = linear_request().await;
+        request.add_deepen_not("old").await.unwrap();
+        let (sent, shallow) = walk(&request).await;
+        assert_eq!(sent, commits[5..].iter().cloned().collect());
+        assert_eq!(shallow, HashSet::from([commits[5].clone()]));
+    }
+
+    #[tokio::test]
+    async fn test_deepen_not_walk_limit() {
+        let (mut request, _) = linear_request().await;
+        request.txn.pack_limits.max_walk_nodes = 3;
+        assert!(matches!(
+            request.add_deepen_not("old").await,
+            Err(GitInnerError::GraphTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_depth_boundary() {
+        let (mut request, commits) = linear_request().await;
+        request.depth = Some(3);
+        let (sent, shallow) = walk(&request).await;
+        assert_eq!(sent, commits[7..].iter().cloned().collect());
+        assert_eq!(shallow, HashSet::from([commits[7].clone()]));
+    }
+
+    #[tokio::test]
+    async fn test_compression_levels_round_trip() {
+        let repo = memory_repository();
+        let hash = put_blob(&repo, &"hello world\n".repeat(512)).await;
+        let object = Object::Blob(repo.odb.get_blob(&hash).await.unwrap());
+        let fast = object.zlib(Compression::new(1)).unwrap();
+        let best = object.zlib(Compression::new(9)).unwrap();
+        let inflate = |entry: &Bytes| {
+            // 跳过类型和大小头
+            let header_len = entry.iter().position(|x| x & 0x80 == 0).unwrap() + 1;
+            let mut data = Vec::new();
+            flate2::read::ZlibDecoder::new(&entry[header_len..])
+                .read_to_end(&mut data)
+                .unwrap();
+            data
+        };
+        assert_eq!(inflate(&fast), inflate(&best));
+        assert_eq!(inflate(&best), "hello world\n".repeat(512).into_bytes());
     }
 }
@@ -0,0 +1,91 @@
+use crate::sha::HashValue;
+use crate::transaction::upload::UploadPackTransaction;
+use lru::LruCache;
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a negotiated fetch stays resumable.
+pub const RESUME_TTL: Duration = Duration::from_secs(600);
+/// Negotiated fetches kept at once; the least recently used is dropped first.
+const RESUME_CAPACITY: usize = 1024;
+
+/// Outcome of a fetch negotiation: everything needed to build the same pack again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchState {
+    /// Repository the fetch was negotiated against.
+    pub repository: Uuid,
+    pub want: Vec<HashValue>,
+    /// Haves the server acknowledged as common.
+    pub have: Vec<HashValue>,
+    pub shallow: Vec<HashValue>,
+    pub depth: Option<u32>,
+    pub deepen_since: Option<u64>,
+    pub deepen_not: HashSet<HashValue>,
+}
+
+/// Server-wide store of negotiated fetches under short-lived tokens. Clones share the
+/// entries, so a fetch resumed over a new connection finds the state of the first one.
+#[derive(Clone)]
+pub struct FetchResumeStore(Arc<Mutex<LruCache<String, (Instant, FetchState)>>>);
+
+impl Default for FetchResumeStore {
+    fn default() -> Self {
+        Self::new(NonZeroUsize::new(RESUME_CAPACITY).unwrap())
+    }
+}
+
+impl FetchResumeStore {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self(Arc::new(Mutex::new(LruCache::new(capacity))))
+    }
+
+    /// Keep `state` and return the token a later fetch resumes it with.
+    pub fn save(&self, state: FetchState) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        self.0
+            .lock()
+            .unwrap()
+            .put(token.clone(), (Instant::now(), state));
+        token
+    }
+
+    /// The state saved under `token`, unless it is unknown or older than [`RESUME_TTL`].
+    /// A token can be resumed any number of times until it expires.
+    pub fn get(&self, token: &str) -> Option<FetchState> {
+        let mut entries = self.0.lock().unwrap();
+        let (saved, state) = entries.get(token)?;
+        if saved.elapsed() > RESUME_TTL {
+            entries.pop(token);
+            return None;
+        }
+        Some(state.clone())
+    }
+}
+
+impl UploadPackTransaction {
+    /// The negotiated part of this fetch, as saved for a later resume.
+    pub fn fetch_state(&self) -> FetchState {
+        FetchState {
+            repository: self.txn.repository.id,
+            want: self.want.clone(),
+            have: self.have.clone(),
+            shallow: self.shallow.clone(),
+            depth: self.depth,
+            deepen_since: self.deepen_since,
+            deepen_not: self.deepen_not.clone(),
+        }
+    }
+
+    /// Take over the negotiation of an earlier fetch instead of negotiating again.
+    pub fn resume(&mut self, state: FetchState) {
+        self.want = state.want;
+        self.have = state.have;
+        self.shallow = state.shallow;
+        self.depth = state.depth;
+        self.deepen_since = state.deepen_since;
+        self.deepen_not = state.deepen_not;
+    }
+}
@@ -0,0 +1,254 @@
+use crate::callback::sidebend::SideBend;
+use crate::error::GitInnerError;
+use crate::repository::archive::ArchiveFormat;
+use crate::transaction::Transaction;
+use crate::transaction::pkt_line::{PktLine, parse_pkt_line};
+use crate::transaction::timeout::TimeoutStream;
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::StreamExt;
+use std::pin::Pin;
+use tokio_stream::wrappers::ReceiverStream;
+
+// 与 git 的 upload-archive 一致，最多接受 64 个参数
+const MAX_ARGUMENTS: usize = 64;
+const MAX_PAYLOAD_PER_PKT: usize = 0xfff0 - 4 - 1;
+
+/// What a `git archive --remote` client asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveRequest {
+    pub rev: String,
+    pub format: ArchiveFormat,
+    pub prefix: String,
+}
+
+impl ArchiveRequest {
+    /// Build a request from the `argument` lines the client sent. Only the options that
+    /// change the archive itself are accepted; path limits and compression levels are not
+    /// supported.
+    pub fn from_arguments(arguments: &[String]) -> Result<Self, GitInnerError> {
+        let mut format = ArchiveFormat::Tar;
+        let mut prefix = String::new();
+        let mut rev = None;
+        for argument in arguments {
+            if let Some(value) = argument.strip_prefix("--format=") {
+                format = match value {
+                    "tar" => ArchiveFormat::Tar,
+                    "zip" => ArchiveFormat::Zip,
+                    _ => {
+                        return Err(GitInnerError::InvalidArgument(format!(
+                            "unknown archive format '{}'",
+                            value
+                        )));
+                    }
+                };
+            } else if let Some(value) = argument.strip_prefix("--prefix=") {
+                prefix = value.to_string();
+            } else if argument.starts_with('-') || rev.is_some() {
+                return Err(GitInnerError::InvalidArgument(format!(
+                    "unsupported archive argument '{}'",
+                    argument
+                )));
+            } else {
+                rev = Some(argument.clone());
+            }
+        }
+        let rev = rev.ok_or(GitInnerError::InvalidArgument(
+            "no tree-ish given".to_string(),
+        ))?;
+        Ok(ArchiveRequest {
+            rev,
+            format,
+            prefix,
+        })
+    }
+}
+
+impl Transaction {
+    /// Serve `git-upload-archive`: read `argument` pkt-lines up to a flush, answer `ACK` and
+    /// a flush, then stream the archive on sideband 1 and end with a flush. A request that
+    /// cannot be served is answered `NACK <reason>` instead; an error once the archive has
+    /// started goes to sideband 3.
+    pub async fn upload_archive(
+        &self,
+        stream: &mut Pin<Box<ReceiverStream<Result<Bytes, GitInnerError>>>>,
+    ) -> Result<(), GitInnerError> {
+        let arguments = self.read_archive_arguments(stream).await?;
+        let request = ArchiveRequest::from_arguments(&arguments);
+        let archive = match request {
+            Ok(request) => {
+                self.repository
+                    .archive(&request.rev, request.format, &request.prefix)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        let mut archive = match archive {
+            Ok(archive) => archive,
+            Err(err) => {
+                self.call_back
+                    .send_pkt_line(Bytes::from(format!("NACK {}\n", err)))
+                    .await?;
+                self.call_back.send(Bytes::from_static(b"0000")).await?;
+                return Err(err);
+            }
+        };
+        self.call_back
+            .send_pkt_line(Bytes::from_static(b"ACK\n"))
+            .await?;
+        self.call_back.send(Bytes::from_static(b"0000")).await?;
+        while let Some(chunk) = archive.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    self.call_back
+                        .send_side_pkt_line(
+                            Bytes::from(format!("{}\n", err)),
+                            SideBend::SidebandRemoteError,
+                        )
+                        .await?;
+                    return Err(err);
+                }
+            };
+            for data in chunk.chunks(MAX_PAYLOAD_PER_PKT) {
+                self.call_back
+                    .send_side_pkt_line(Bytes::copy_from_slice(data), SideBend::SidebandPrimary)
+                    .await?;
+            }
+        }
+        self.call_back
+            .send_side_pkt_line(Bytes::new(), SideBend::SidebandFlush)
+            .await
+    }
+
+    async fn read_archive_arguments(
+        &self,
+        stream: &mut Pin<Box<ReceiverStream<Result<Bytes, GitInnerError>>>>,
+    ) -> Result<Vec<String>, GitInnerError> {
+        let mut stream = TimeoutStream::new(stream, self.timeouts);
+        let mut buffer = BytesMut::new();
+        let mut arguments = vec![];
+        loop {
+            while let Some((pkt, len)) = parse_pkt_line(&buffer)? {
+                let payload = match pkt {
+                    PktLine::Flush => return Ok(arguments),
+                    PktLine::Data(payload) => payload,
+                    PktLine::Delim | PktLine::ResponseEnd => {
+                        return Err(GitInnerError::InvalidData);
+                    }
+                };
+                let line = std::str::from_utf8(&payload)
+                    .map_err(|_| GitInnerError::ConversionError("Invalid UTF-8 line".to_string()))?
+                    .trim_end_matches('\n');
+                let argument = line.strip_prefix("argument ").ok_or_else(|| {
+                    GitInnerError::InvalidArgument(format!(
+                        "'argument' token expected, got '{}'",
+                        line
+                    ))
+                })?;
+                if arguments.len() == MAX_ARGUMENTS {
+                    return Err(GitInnerError::InvalidArgument(
+                        "too many archive arguments".to_string(),
+                    ));
+                }
+                arguments.push(argument.to_string());
+                buffer.advance(len);
+            }
+            match stream.next().await {
+                Some(next) => buffer.extend_from_slice(&next?),
+                None => return Err(GitInnerError::UnexpectedEof),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callback::sidebend::demux;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{
+        memory_repository, put_blob, put_commit, put_tree, transaction,
+    };
+
+    fn pkt(line: &str) -> String {
+        format!("{:04x}{}", line.len() + 4, line)
+    }
+
+    async fn run(txn: &Transaction, body: String) -> (Result<(), GitInnerError>, Vec<u8>) {
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let result = txn
+            .upload_archive(&mut Box::pin(ReceiverStream::new(rx)))
+            .await;
+        let mut receive = txn.call_back.receive.lock().await;
+        let mut out = Vec::new();
+        while let Ok(bytes) = receive.try_recv() {
+            out.extend_from_slice(&bytes);
+        }
+        (result, out)
+    }
+
+    #[test]
+    fn test_archive_request_arguments() {
+        let arguments = ["--format=zip", "--prefix=app/", "main"].map(String::from);
+        assert_eq!(
+            ArchiveRequest::from_arguments(&arguments).unwrap(),
+            ArchiveRequest {
+                rev: "main".to_string(),
+                format: ArchiveFormat::Zip,
+                prefix: "app/".to_string(),
+            }
+        );
+        for arguments in [
+            vec!["--format=tgz", "main"],
+            vec!["--remote=evil", "main"],
+            vec!["main", "README"],
+            vec!["--format=tar"],
+        ] {
+            let arguments = arguments.into_iter().map(String::from).collect::<Vec<_>>();
+            assert!(ArchiveRequest::from_arguments(&arguments).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_archive_streams_tar() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello\n").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit)
+            .await
+            .unwrap();
+
+        let txn = transaction(&repo);
+        let body = pkt("argument --format=tar\n")
+            + &pkt("argument --prefix=app/\n")
+            + &pkt("argument main\n")
+            + "0000";
+        let (result, out) = run(&txn, body).await;
+        result.unwrap();
+        assert!(out.starts_with(b"0008ACK\n0000"));
+        assert!(out.ends_with(b"0000"));
+
+        let tar = demux(&out).unwrap().pack;
+        assert_eq!(tar.len() % (20 * 512), 0);
+        assert_eq!(&tar[..4], b"app/");
+        assert_eq!(tar[156], b'5');
+        let file = &tar[512..1024];
+        assert_eq!(&file[..11], b"app/README\0");
+        assert_eq!(file[156], b'0');
+        assert_eq!(&tar[1024..1030], b"hello\n");
+    }
+
+    #[tokio::test]
+    async fn test_upload_archive_unknown_ref_nacks() {
+        let repo = memory_repository();
+        let txn = transaction(&repo);
+        let (result, out) = run(&txn, pkt("argument missing\n") + "0000").await;
+        assert!(result.is_err());
+        let lines = demux(&out).unwrap().lines;
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with(b"NACK "));
+    }
+}
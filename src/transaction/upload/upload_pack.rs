@@ -1,7 +1,10 @@
 use crate::capability::enums::GitCapability;
 use crate::error::GitInnerError;
+use crate::transaction::pkt_line::PktLine;
+use crate::transaction::timeout::TimeoutStream;
 use crate::transaction::upload::UploadPackTransaction;
 use crate::transaction::upload::command::UploadCommandType;
+use crate::transaction::version::first_pkt_line;
 use crate::transaction::{GitProtoVersion, Transaction};
 use bytes::{Buf, Bytes, BytesMut};
 use futures_util::StreamExt;
@@ -13,13 +16,18 @@ impl Transaction {
         &self,
         stream: &mut Pin<Box<ReceiverStream<Result<Bytes, GitInnerError>>>>,
     ) -> Result<(), GitInnerError> {
+        if self.version == GitProtoVersion::Unknown {
+            return self.upload_pack_detect(stream).await;
+        }
         if self.version == GitProtoVersion::V2 {
             self.upload_pack_v2(stream).await?;
             return Ok(());
         }
+        let mut stream = TimeoutStream::new(stream, self.timeouts);
         let mut buffer = BytesMut::new();
         let mut commands = vec![];
-        while let Some(next) = stream.next().await {
+        // 有状态的连接（SSH）上客户端发完 done 就等 pack，不会先关闭输入
+        'read: while let Some(next) = stream.next().await {
             let next = next?;
             buffer.extend_from_slice(&next);
             loop {
@@ -54,12 +62,22 @@ impl Transaction {
                     line_str,
                     self.repository.hash_version.clone(),
                 )?;
+                for command in &parsed {
+                    if let UploadCommandType::Capabilities(capabilities) = command {
+                        self.check_capability_object_format(capabilities).await?;
+                    }
+                }
+                let done = parsed.contains(&UploadCommandType::Done);
                 commands.append(&mut parsed);
+                if done {
+                    break 'read;
+                }
             }
         }
 
         let mut request = UploadPackTransaction::new(self.clone());
         let mut found_common = false;
+        let mut last_common = None;
 
         for cmd in commands {
             match cmd {
@@ -73,22 +91,51 @@ impl Transaction {
                         || self.repository.odb.has_tag(&hash).await?;
 
                     if has_object {
-                        let ack_msg = format!("ACK {}\n", hash);
+                        let ack_msg = if request.multi_ack_detailed() {
+                            format!("ACK {} common\n", hash)
+                        } else {
+                            format!("ACK {}\n", hash)
+                        };
                         let pkt_line = format!("{:04x}{}", ack_msg.len() + 4, ack_msg);
-                        self.call_back.send(Bytes::from(pkt_line)).await;
+                        self.call_back.send(Bytes::from(pkt_line)).await?;
                         found_common = true;
+                        last_common = Some(hash.clone());
                         request.have.push(hash);
                     }
                 }
+                UploadCommandType::Flush => {
+                    // no-done：协商到 ready 后不用等客户端的 done，直接开始发 pack
+                    if request.no_done
+                        && request.multi_ack_detailed()
+                        && let Some(last) = &last_common
+                    {
+                        for msg in [
+                            format!("ACK {} ready\n", last),
+                            "NAK\n".to_string(),
+                            format!("ACK {}\n", last),
+                        ] {
+                            self.call_back.send_pkt_line(Bytes::from(msg)).await?;
+                        }
+                        break;
+                    }
+                }
                 UploadCommandType::Shallow(hash) => {
                     request.shallow.push(hash);
                 }
                 UploadCommandType::Deepen(depth) => {
                     request.depth = Some(depth as u32);
                 }
+                UploadCommandType::DeepenSince(timestamp) => {
+                    request.deepen_since = Some(timestamp);
+                }
+                UploadCommandType::DeepenNot(name) => {
+                    request.add_deepen_not(&name).await?;
+                }
                 UploadCommandType::Capabilities(capabilities) => {
                     for capability in capabilities {
-                        if capability == GitCapability::SideBand {
+                        if capability == GitCapability::SideBand
+                            || capability == GitCapability::SideBand64k
+                        {
                             request.sideband = true;
                         } else if capability == GitCapability::ThinPack {
                             request.thin = true;
@@ -106,7 +153,7 @@ impl Transaction {
                     if !found_common {
                         let nak_msg = "NAK\n";
                         let pkt_line = format!("{:04x}{}", nak_msg.len() + 4, nak_msg);
-                        self.call_back.send(Bytes::from(pkt_line)).await;
+                        self.call_back.send(Bytes::from(pkt_line)).await?;
                     }
                     break;
                 }
@@ -117,3 +164,173 @@ impl Transaction {
         Ok(())
     }
 }
+
+impl Transaction {
+    /// Upload-pack for transports that carry no version (SSH without `GIT_PROTOCOL`): the
+    /// first client pkt-line picks v0 or v2, then the buffered input is replayed to it.
+    async fn upload_pack_detect(
+        &self,
+        stream: &mut Pin<Box<ReceiverStream<Result<Bytes, GitInnerError>>>>,
+    ) -> Result<(), GitInnerError> {
+        let mut buffer = BytesMut::new();
+        let mut version = GitProtoVersion::Unknown;
+        {
+            let mut timed = TimeoutStream::new(&mut *stream, self.timeouts);
+            while version == GitProtoVersion::Unknown {
+                let Some(next) = timed.next().await else {
+                    break;
+                };
+                buffer.extend_from_slice(&next?);
+                version = GitProtoVersion::detect(&buffer);
+            }
+        }
+        if version == GitProtoVersion::Unknown {
+            // 既没有版本声明也不像 v0 请求，按 v0 处理
+            version = GitProtoVersion::V0;
+        }
+        // `version N` 只用来选协议，后面的解析器不认识这一行
+        if let Some((PktLine::Data(line), len)) = first_pkt_line(&buffer)
+            && line.starts_with(b"version ")
+        {
+            buffer.advance(len);
+        }
+        let mut txn = self.clone();
+        txn.version = version;
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        let forward = async move {
+            if !buffer.is_empty() && tx.send(Ok(buffer.freeze())).await.is_err() {
+                return;
+            }
+            while let Some(next) = stream.next().await {
+                if tx.send(next).await.is_err() {
+                    return;
+                }
+            }
+        };
+        let (_, result) = futures_util::future::join(
+            forward,
+            Box::pin(txn.upload_pack(&mut Box::pin(ReceiverStream::new(rx)))),
+        )
+        .await;
+        result
+    }
+}
+
+impl UploadPackTransaction {
+    fn multi_ack_detailed(&self) -> bool {
+        self.capabilities.contains(&GitCapability::MultiAckDetailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::callback::sidebend::demux;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{
+        memory_repository, output, put_blob, put_commit, put_tree, transaction,
+    };
+    use crate::transaction::GitProtoVersion;
+    use bytes::Bytes;
+    use tokio_stream::wrappers::ReceiverStream;
+
+    fn pkt(line: &str) -> String {
+        format!("{:04x}{}", line.len() + 4, line)
+    }
+
+    #[tokio::test]
+    async fn test_no_done_sends_pack_after_ready() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let old = put_commit(&repo, &tree, &[], "old", 1).await;
+        let new = put_commit(&repo, &tree, &[old.clone()], "new", 2).await;
+
+        // 客户端只发了 have 和 flush，没有 done
+        let body = pkt(&format!(
+            "want {} multi_ack_detailed no-done side-band\n",
+            new
+        )) + "0000"
+            + &pkt(&format!("have {}\n", old))
+            + "0000";
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let mut txn = transaction(&repo);
+        txn.version = GitProtoVersion::V1;
+        txn.upload_pack(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+
+        let mut receive = txn.call_back.receive.lock().await;
+        let mut out = Vec::new();
+        while let Ok(bytes) = receive.try_recv() {
+            out.extend_from_slice(&bytes);
+        }
+        let output = demux(&out).unwrap();
+        let lines = output.lines;
+        assert_eq!(lines[0], format!("ACK {} common\n", old).into_bytes());
+        assert_eq!(lines[1], format!("ACK {} ready\n", old).into_bytes());
+        assert_eq!(lines[2], b"NAK\n");
+        assert_eq!(lines[3], format!("ACK {}\n", old).into_bytes());
+        assert!(output.pack.starts_with(b"PACK"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_version_detects_v2_announcement() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+
+        let body = pkt("version 2\n") + &pkt("command=ls-refs\n") + "0001" + "0000";
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let mut txn = transaction(&repo);
+        txn.version = GitProtoVersion::Unknown;
+        txn.upload_pack(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+        assert!(
+            output(&txn)
+                .await
+                .contains(&format!("{} refs/heads/main", commit))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_version_detects_v0_want() {
+        let repo = memory_repository();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+
+        // 长度前缀拆在两个分片里，要攒够一整行才能判断
+        let body = pkt(&format!("want {} side-band\n", commit)) + "0000" + &pkt("done\n");
+        let (tx, rx) = tokio::sync::mpsc::channel(2);
+        tx.send(Ok(Bytes::from(body[..2].to_string())))
+            .await
+            .unwrap();
+        tx.send(Ok(Bytes::from(body[2..].to_string())))
+            .await
+            .unwrap();
+        drop(tx);
+        let mut txn = transaction(&repo);
+        txn.version = GitProtoVersion::Unknown;
+        txn.upload_pack(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+
+        let mut receive = txn.call_back.receive.lock().await;
+        let mut out = Vec::new();
+        while let Ok(bytes) = receive.try_recv() {
+            out.extend_from_slice(&bytes);
+        }
+        let output = demux(&out).unwrap();
+        assert_eq!(output.lines[0], b"NAK\n");
+        assert!(output.pack.starts_with(b"PACK"));
+    }
+}
@@ -1,8 +1,11 @@
 use crate::capability::enums::GitCapability;
 use crate::error::GitInnerError;
 use crate::transaction::Transaction;
+use crate::transaction::pkt_line::{PktLine, parse_pkt_line};
+use crate::transaction::timeout::TimeoutStream;
 use crate::transaction::upload::UploadPackTransaction;
 use crate::transaction::upload::command::UploadCommandType;
+use crate::write_pkt_line;
 use bytes::{Buf, Bytes, BytesMut};
 use futures_util::StreamExt;
 use std::pin::Pin;
@@ -13,43 +16,49 @@ impl Transaction {
         &self,
         stream: &mut Pin<Box<ReceiverStream<Result<Bytes, GitInnerError>>>>,
     ) -> Result<(), GitInnerError> {
+        let mut stream = TimeoutStream::new(stream, self.timeouts);
         let mut buffer = BytesMut::new();
         let mut commands = vec![];
+        let mut received = 0u64;
+        let mut issued = 0usize;
         while let Some(next) = stream.next().await {
             let next = next?;
+            received += next.len() as u64;
+            if received > self.pack_limits.max_session_bytes {
+                return self.session_too_large().await;
+            }
             buffer.extend_from_slice(&next);
-            loop {
-                if buffer.len() < 4 {
-                    break;
-                }
-                let len_str = std::str::from_utf8(&buffer[..4]).map_err(|_| {
-                    GitInnerError::ConversionError("Invalid pkt-line length".to_string())
-                })?;
-                let pkt_len = u32::from_str_radix(len_str, 16).map_err(|_| {
-                    GitInnerError::ConversionError("Invalid pkt-line length format".to_string())
-                })?;
-
-                if pkt_len == 0 || pkt_len == 1 {
-                    commands.push(UploadCommandType::Flush);
-                    buffer.advance(4);
-                    continue;
-                }
-
-                if buffer.len() < pkt_len as usize {
-                    break;
-                }
-
-                let line_bytes = buffer.split_to(pkt_len as usize);
-                if line_bytes.len() < pkt_len as usize {
-                    break;
-                }
-                let line_str = std::str::from_utf8(&line_bytes[4..])
+            while let Some((pkt, len)) = parse_pkt_line(&buffer)? {
+                buffer.advance(len);
+                let line_bytes = match pkt {
+                    PktLine::Data(line) => line,
+                    // 分隔符与结束符都只用来切分请求
+                    PktLine::Flush | PktLine::Delim | PktLine::ResponseEnd => {
+                        commands.push(UploadCommandType::Flush);
+                        continue;
+                    }
+                };
+                let line_str = std::str::from_utf8(&line_bytes)
                     .map_err(|_| GitInnerError::ConversionError("Invalid UTF-8 line".to_string()))?
                     .trim_end();
                 let mut parsed = UploadCommandType::from_one_line(
                     line_str,
                     self.repository.hash_version.clone(),
                 )?;
+                for command in &parsed {
+                    match command {
+                        UploadCommandType::ObjectFormat(format) => {
+                            self.check_object_format(format).await?;
+                        }
+                        UploadCommandType::Command(_) => {
+                            issued += 1;
+                            if issued > self.pack_limits.max_session_commands {
+                                return self.session_too_large().await;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
                 commands.append(&mut parsed);
             }
         }
@@ -60,32 +69,37 @@ impl Transaction {
                     "ls-refs" => {
                         self.write_refs_head_info_v2(
                             commands.contains(&UploadCommandType::Symrefs),
+                            commands.contains(&UploadCommandType::Unborn),
                         )
                         .await?;
-                        self.write_all_refs().await?;
-                        self.call_back.send(Bytes::from("0000")).await;
+                        self.write_all_refs_v2(commands.contains(&UploadCommandType::Peel))
+                            .await?;
+                        self.call_back.send(Bytes::from("0000")).await?;
                     }
                     "fetch" => {
                         let mut request = UploadPackTransaction::new(self.clone());
                         let mut found_common = false;
+                        let haves = commands
+                            .iter()
+                            .filter_map(|x| match x {
+                                UploadCommandType::Have(hash) => Some(hash.clone()),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>();
+                        let existing = self.repository.odb.has_objects(&haves).await?;
                         for cmd in commands.clone() {
                             match cmd {
                                 UploadCommandType::Want(hash) => {
                                     request.want.push(hash);
                                 }
-                                UploadCommandType::Have(hash) => {
-                                    let has_object = self.repository.odb.has_commit(&hash).await?
-                                        || self.repository.odb.has_tree(&hash).await?
-                                        || self.repository.odb.has_blob(&hash).await?
-                                        || self.repository.odb.has_tag(&hash).await?;
-                                    if has_object {
-                                        let ack_msg = format!("ACK {}\n", hash);
-                                        let pkt_line =
-                                            format!("{:04x}{}", ack_msg.len() + 4, ack_msg);
-                                        self.call_back.send(Bytes::from(pkt_line)).await;
-                                        found_common = true;
-                                        request.have.push(hash);
-                                    }
+                                UploadCommandType::Have(hash)
+                                    if existing.get(&hash).copied().unwrap_or(false) =>
+                                {
+                                    let ack_msg = format!("ACK {}\n", hash);
+                                    let pkt_line = format!("{:04x}{}", ack_msg.len() + 4, ack_msg);
+                                    self.call_back.send(Bytes::from(pkt_line)).await?;
+                                    found_common = true;
+                                    request.have.push(hash);
                                 }
                                 UploadCommandType::Shallow(hash) => {
                                     request.shallow.push(hash);
@@ -93,6 +107,12 @@ impl Transaction {
                                 UploadCommandType::Deepen(depth) => {
                                     request.depth = Some(depth as u32);
                                 }
+                                UploadCommandType::DeepenSince(timestamp) => {
+                                    request.deepen_since = Some(timestamp);
+                                }
+                                UploadCommandType::DeepenNot(name) => {
+                                    request.add_deepen_not(&name).await?;
+                                }
                                 UploadCommandType::Capabilities(capabilities) => {
                                     for capability in capabilities {
                                         if capability == GitCapability::SideBand {
@@ -124,11 +144,35 @@ impl Transaction {
                         }) {
                             found_common = true;
                         }
+                        let resume = commands.iter().find_map(|x| match x {
+                            UploadCommandType::Resume(token) => Some(token),
+                            _ => None,
+                        });
+                        if let Some(token) = resume {
+                            let state = self
+                                .fetch_resume
+                                .get(token)
+                                .filter(|x| x.repository == self.repository.id)
+                                .ok_or_else(|| {
+                                    GitInnerError::InvalidArgument(format!(
+                                        "unknown or expired resume token {}",
+                                        token
+                                    ))
+                                })?;
+                            request.resume(state);
+                            found_common = true;
+                        } else if found_common && commands.contains(&UploadCommandType::Resumable) {
+                            // 保存协商结果，连接中断后可以凭 token 直接重新生成同一个 pack
+                            let token = self.fetch_resume.save(request.fetch_state());
+                            self.call_back
+                                .send_pkt_line(Bytes::from(format!("resume-token {}\n", token)))
+                                .await?;
+                        }
                         request.sideband = true;
                         if !found_common {
                             let nak_msg = "NAK\n";
                             let pkt_line = format!("{:04x}{}", nak_msg.len() + 4, nak_msg);
-                            self.call_back.send(Bytes::from(pkt_line)).await;
+                            self.call_back.send(Bytes::from(pkt_line)).await?;
                         } else {
                             request.upload_pack_encode().await?;
                         }
@@ -139,4 +183,266 @@ impl Transaction {
         }
         Ok(())
     }
+
+    /// End a session that went past its command or byte budget, telling the client why.
+    async fn session_too_large(&self) -> Result<(), GitInnerError> {
+        let err = GitInnerError::SessionTooLarge;
+        self.call_back
+            .send(Bytes::from(write_pkt_line(format!("ERR {}\n", err))))
+            .await?;
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::GitInnerError;
+    use crate::objects::tree::TreeItemMode;
+    use crate::repository::testing::{
+        memory_repository_with_odb, output, put_blob, put_commit, put_tree, transaction,
+    };
+    use bytes::Bytes;
+    use std::sync::atomic::Ordering;
+    use tokio_stream::wrappers::ReceiverStream;
+
+    fn pkt(line: &str) -> String {
+        format!("{:04x}{}", line.len() + 4, line)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_haves_use_one_batched_lookup() {
+        let (repo, odb) = memory_repository_with_odb();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let mut commits = vec![put_commit(&repo, &tree, &[], "c0", 1).await];
+        for i in 1..25 {
+            let parent = commits[i - 1].clone();
+            commits.push(put_commit(&repo, &tree, &[parent], &format!("c{}", i), i).await);
+        }
+        let tip = put_commit(&repo, &tree, &[commits[24].clone()], "tip", 100).await;
+        // 另一个仓库里的 25 个提交，服务端没有
+        let (other, _) = memory_repository_with_odb();
+        let mut unknown = vec![];
+        for i in 0..25 {
+            unknown.push(put_commit(&other, &tree, &[], &format!("u{}", i), i).await);
+        }
+
+        let mut body = pkt("command=fetch\n") + "0001" + &pkt(&format!("want {}\n", tip));
+        for hash in commits.iter().chain(unknown.iter()) {
+            body += &pkt(&format!("have {}\n", hash));
+        }
+        body += &pkt("done\n");
+        body += "0000";
+
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let txn = transaction(&repo);
+        odb.exists_queries.store(0, Ordering::Relaxed);
+        txn.upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+
+        assert_eq!(odb.exists_queries.load(Ordering::Relaxed), 1);
+        let out = output(&txn).await;
+        assert_eq!(out.matches("ACK ").count(), 25);
+        assert!(out.contains(&format!("ACK {}", commits[0])));
+        assert!(!out.contains(&format!("ACK {}", unknown[0])));
+    }
+
+    #[tokio::test]
+    async fn test_upload_allowed_in_maintenance_mode() {
+        let (repo, _) = memory_repository_with_odb();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+
+        let body = pkt("command=ls-refs\n") + "0001" + "0000";
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let txn = transaction(&repo);
+        txn.maintenance.set(true);
+        txn.upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+        assert!(
+            output(&txn)
+                .await
+                .contains(&format!("{} refs/heads/main", commit))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resumed_fetch_produces_same_pack() {
+        let (repo, _) = memory_repository_with_odb();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let base = put_commit(&repo, &tree, &[], "base", 1).await;
+        let blob = put_blob(&repo, "hello again").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let tip = put_commit(&repo, &tree, &[base.clone()], "tip", 2).await;
+
+        let body = pkt("command=fetch\n")
+            + "0001"
+            + &pkt(&format!("want {}\n", tip))
+            + &pkt(&format!("have {}\n", base))
+            + &pkt("resumable\n")
+            + &pkt("done\n")
+            + "0000";
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let first = transaction(&repo);
+        first
+            .upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+        let out = output(&first).await;
+        let token = out
+            .split("resume-token ")
+            .nth(1)
+            .and_then(|x| x.split('\n').next())
+            .unwrap()
+            .to_string();
+        let pack = out.split_once("packfile\n").unwrap().1.to_string();
+
+        // 连接断开后换一个事务，只带 token，不再发送 want/have
+        let body = pkt("command=fetch\n")
+            + "0001"
+            + &pkt(&format!("resume {}\n", token))
+            + &pkt("done\n")
+            + "0000";
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let mut resumed = transaction(&repo);
+        resumed.fetch_resume = first.fetch_resume.clone();
+        resumed
+            .upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+        let out = output(&resumed).await;
+        assert!(!out.contains("resume-token "));
+        assert_eq!(out.split_once("packfile\n").unwrap().1, pack);
+    }
+
+    #[tokio::test]
+    async fn test_resume_with_unknown_token_fails() {
+        let (repo, _) = memory_repository_with_odb();
+        let body =
+            pkt("command=fetch\n") + "0001" + &pkt("resume nope\n") + &pkt("done\n") + "0000";
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let txn = transaction(&repo);
+        assert!(
+            txn.upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_command_limit() {
+        let (repo, _) = memory_repository_with_odb();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "init", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit.clone())
+            .await
+            .unwrap();
+
+        let body = (0..50)
+            .map(|_| pkt("command=ls-refs\n") + "0001" + "0000")
+            .collect::<String>();
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let mut txn = transaction(&repo);
+        txn.pack_limits.max_session_commands = 10;
+        let result = txn
+            .upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await;
+        assert!(matches!(result, Err(GitInnerError::SessionTooLarge)));
+        // 超限后不再处理任何命令
+        let out = output(&txn).await;
+        assert!(!out.contains("refs/heads/main"));
+        assert!(out.contains("ERR session exceeds"));
+    }
+
+    #[tokio::test]
+    async fn test_session_byte_limit() {
+        let (repo, _) = memory_repository_with_odb();
+        let body = pkt("command=ls-refs\n") + "0001" + "0000";
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        for _ in 0..4 {
+            tx.send(Ok(Bytes::from(body.clone()))).await.unwrap();
+        }
+        drop(tx);
+        let mut txn = transaction(&repo);
+        txn.pack_limits.max_session_bytes = body.len() as u64 * 2;
+        let result = txn
+            .upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await;
+        assert!(matches!(result, Err(GitInnerError::SessionTooLarge)));
+    }
+
+    #[tokio::test]
+    async fn test_response_end_ends_request() {
+        let (repo, _) = memory_repository_with_odb();
+        let blob = put_blob(&repo, "hello").await;
+        let tree = put_tree(&repo, vec![(TreeItemMode::Blob, "README", blob)]).await;
+        let commit = put_commit(&repo, &tree, &[], "c0", 1).await;
+        repo.refs_insert("refs/heads/main".to_string(), commit)
+            .await
+            .unwrap();
+        let body = pkt("command=ls-refs\n") + "0002";
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from(body))).await.unwrap();
+        drop(tx);
+        let txn = transaction(&repo);
+        txn.upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await
+            .unwrap();
+        assert!(output(&txn).await.contains("refs/heads/main"));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_pkt_length_is_invalid() {
+        let (repo, _) = memory_repository_with_odb();
+        for header in ["0003", "+fff", "00 8", "-001"] {
+            let body = pkt("command=ls-refs\n") + header + "0000";
+            let (tx, rx) = tokio::sync::mpsc::channel(1);
+            tx.send(Ok(Bytes::from(body))).await.unwrap();
+            drop(tx);
+            let txn = transaction(&repo);
+            let result = txn
+                .upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+                .await;
+            assert!(
+                matches!(result, Err(GitInnerError::InvalidData)),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_pkt_length_is_invalid() {
+        let (repo, _) = memory_repository_with_odb();
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        tx.send(Ok(Bytes::from_static(b"fff1command=ls-refs\n")))
+            .await
+            .unwrap();
+        drop(tx);
+        let txn = transaction(&repo);
+        let result = txn
+            .upload_pack_v2(&mut Box::pin(ReceiverStream::new(rx)))
+            .await;
+        assert!(matches!(result, Err(GitInnerError::InvalidData)));
+    }
 }
@@ -1,4 +1,6 @@
+use crate::error::GitInnerError;
 use crate::transaction::Transaction;
+use crate::transaction::pkt_line::{PktLine, parse_pkt_line};
 use bytes::Bytes;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -42,10 +44,44 @@ impl GitProtoVersion {
             GitProtoVersion::Unknown => 0,
         }
     }
+    /// Pick the protocol from the first client pkt-line, for transports without a
+    /// `Git-Protocol` header. A `version N` announcement names it, a `command=` request
+    /// implies v2 and the v0/v1 `want`/`have`/`shallow`/`deepen`/`done` lines or a flush
+    /// imply v0. `Unknown` while `pkt` does not hold a whole pkt-line yet.
+    pub fn detect(pkt: &[u8]) -> GitProtoVersion {
+        let line = match first_pkt_line(pkt) {
+            Some((PktLine::Data(line), _)) => line,
+            Some((PktLine::Flush, _)) => return GitProtoVersion::V0,
+            Some(_) | None => return GitProtoVersion::Unknown,
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        if let Some(version) = line.strip_prefix("version ") {
+            return GitProtoVersion::from_str(version.trim());
+        }
+        if line.starts_with("command=") {
+            return GitProtoVersion::V2;
+        }
+        if line.is_empty()
+            || ["want ", "have ", "shallow ", "deepen"]
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+            || line == "done"
+        {
+            return GitProtoVersion::V0;
+        }
+        GitProtoVersion::Unknown
+    }
+}
+
+/// The first pkt-line of `pkt` and its length, or `None` while `pkt` does not hold a whole
+/// one or starts with a malformed length header.
+pub(crate) fn first_pkt_line(pkt: &[u8]) -> Option<(PktLine, usize)> {
+    parse_pkt_line(pkt).ok().flatten()
 }
 
 impl Transaction {
-    pub async fn write_version(&self) {
+    pub async fn write_version(&self) -> Result<(), GitInnerError> {
         let version_str = match self.version {
             GitProtoVersion::V0 => "version 0\n",
             GitProtoVersion::V1 => "version 1\n",
@@ -56,6 +92,51 @@ impl Transaction {
         let len = version_str.len() + 4;
         pkt.extend_from_slice(format!("{:04x}", len).as_bytes());
         pkt.extend_from_slice(version_str.as_bytes());
-        self.call_back.send(Bytes::from(pkt)).await;
+        self.call_back.send(Bytes::from(pkt)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_v2_from_announcement() {
+        assert_eq!(
+            GitProtoVersion::detect(b"000eversion 2\n"),
+            GitProtoVersion::V2
+        );
+        assert_eq!(
+            GitProtoVersion::detect(b"0014command=ls-refs\n0000"),
+            GitProtoVersion::V2
+        );
+    }
+
+    #[test]
+    fn test_detect_v0_from_want() {
+        let want = "want 0123456789abcdef0123456789abcdef01234567 side-band-64k\n";
+        let pkt = format!("{:04x}{}", want.len() + 4, want);
+        assert_eq!(GitProtoVersion::detect(pkt.as_bytes()), GitProtoVersion::V0);
+        assert_eq!(GitProtoVersion::detect(b"0000"), GitProtoVersion::V0);
+    }
+
+    #[test]
+    fn test_detect_needs_whole_line() {
+        assert_eq!(
+            GitProtoVersion::detect(b"000eversi"),
+            GitProtoVersion::Unknown
+        );
+        assert_eq!(GitProtoVersion::detect(b"00"), GitProtoVersion::Unknown);
+    }
+
+    #[test]
+    fn test_first_pkt_line_special_packets() {
+        assert_eq!(first_pkt_line(b"0000"), Some((PktLine::Flush, 4)));
+        assert_eq!(first_pkt_line(b"0001"), Some((PktLine::Delim, 4)));
+        assert_eq!(first_pkt_line(b"0002"), Some((PktLine::ResponseEnd, 4)));
+        assert_eq!(first_pkt_line(b"0003"), None);
+        assert_eq!(first_pkt_line(b"+00a"), None);
+        // 只有 flush 意味着 v0
+        assert_eq!(GitProtoVersion::detect(b"0001"), GitProtoVersion::Unknown);
     }
 }